@@ -44,9 +44,11 @@ cask "terminal-remote" do
     install_dir = "#{Dir.home}/.terminal-remote"
     FileUtils.rm_rf(install_dir) if Dir.exist?(install_dir)
 
-    # Clean up Unix socket
-    socket = "/tmp/terminal-remote.sock"
-    FileUtils.rm_f(socket) if File.exist?(socket)
+    # Clean up Unix sockets (~/.terminal-remote/ipc.sock went with the
+    # install directory; /tmp is where older versions kept it)
+    sockets = ["/tmp/terminal-remote.sock"]
+    sockets << "#{ENV["XDG_RUNTIME_DIR"]}/terminal-remote.sock" if ENV["XDG_RUNTIME_DIR"]&.start_with?("/")
+    sockets.each { |socket| FileUtils.rm_f(socket) if File.exist?(socket) }
   end
 
   depends_on formula: "cloudflared"
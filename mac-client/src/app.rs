@@ -24,6 +24,14 @@ pub enum UiEvent {
     BrowserDisconnected(String),
     /// Error from relay
     RelayError(String),
+    /// Browsers currently watching, oldest first
+    Viewers(Vec<crate::protocol::BrowserEntry>),
+    /// Relay created a time-boxed share code
+    ShareCode(String),
+    /// Reconnect summary for the diagnostics menu entry
+    Reconnects(String),
+    /// Terminal data rate, averaged over the last few seconds
+    Throughput { up_bytes_per_sec: u64, down_bytes_per_sec: u64 },
 
     // From cloudflared tunnel
     /// Tunnel URL is available
@@ -40,6 +48,9 @@ pub enum UiEvent {
     ShellCountChanged(usize),
     /// Error from PTY manager
     PtyError(String),
+    /// The PTY manager stopped accepting commands; browser input no longer
+    /// reaches shells
+    PtyStopped,
 
     // Terminal data forwarding
     /// Terminal data from IPC (shell -> relay)
@@ -59,6 +70,8 @@ pub enum BackgroundCommand {
     SendToShell { session_id: String, data: Vec<u8> },
     /// Reconnect to relay to get a new session code
     ReconnectRelay,
+    /// Ask the relay for a share code that expires after `ttl`
+    CreateShare { ttl: std::time::Duration },
 }
 
 /// Application state holding current values and menu item references.
@@ -76,6 +89,8 @@ pub struct AppState {
     pub browser_count: usize,
     /// Current tunnel URL (None if not yet available)
     pub tunnel_url: Option<String>,
+    /// Whether the PTY manager has stopped (shown in place of relay status)
+    pub pty_stopped: bool,
 
     // Menu items that need dynamic updates
     /// Display item showing session code
@@ -88,6 +103,10 @@ pub struct AppState {
     pub url_item: MenuItem,
     /// Action item for copying URL (text changes for confirmation)
     pub copy_item: MenuItem,
+    /// Action item for copying the session code (text changes on failure)
+    pub copy_code_item: MenuItem,
+    /// Display item showing terminal data rates
+    pub throughput_item: MenuItem,
 }
 
 impl AppState {
@@ -98,6 +117,8 @@ impl AppState {
         count_item: MenuItem,
         url_item: MenuItem,
         copy_item: MenuItem,
+        copy_code_item: MenuItem,
+        throughput_item: MenuItem,
     ) -> Self {
         Self {
             session_code: None,
@@ -105,11 +126,14 @@ impl AppState {
             shell_count: 0,
             browser_count: 0,
             tunnel_url: None,
+            pty_stopped: false,
             code_item,
             status_item,
             count_item,
             url_item,
             copy_item,
+            copy_code_item,
+            throughput_item,
         }
     }
 
@@ -124,7 +148,9 @@ impl AppState {
 
     /// Update the status display menu item.
     pub fn update_status_display(&self) {
-        let status = if self.relay_connected {
+        let status = if self.pty_stopped {
+            "Terminal bridge stopped"
+        } else if self.relay_connected {
             "Connected"
         } else {
             "Disconnected"
@@ -146,6 +172,38 @@ impl AppState {
         };
         self.url_item.set_text(display);
     }
+
+    /// Update the throughput display menu item: output sent to browsers
+    /// (up) and input received from them (down).
+    pub fn update_throughput_display(&self, up_bytes_per_sec: u64, down_bytes_per_sec: u64) {
+        self.throughput_item.set_text(format!(
+            "\u{2191} {} \u{2193} {}",
+            format_rate(up_bytes_per_sec),
+            format_rate(down_bytes_per_sec)
+        ));
+    }
+}
+
+/// A rate such as "512 B/s", "12 KB/s" or "1.5 MB/s".
+fn format_rate(bytes_per_sec: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * 1024;
+    match bytes_per_sec {
+        0..KB => format!("{} B/s", bytes_per_sec),
+        KB..MB => format!("{} KB/s", bytes_per_sec / KB),
+        _ => format!("{:.1} MB/s", bytes_per_sec as f64 / MB as f64),
+    }
+}
+
+/// Title of the Viewers submenu, counting watch-only browsers separately
+/// once there are any: "Viewers (3)", "Viewers (1 controlling, 2 watching)".
+pub fn viewers_title(viewers: &[crate::protocol::BrowserEntry]) -> String {
+    let watching = viewers.iter().filter(|v| v.read_only).count();
+    if watching == 0 {
+        format!("Viewers ({})", viewers.len())
+    } else {
+        format!("Viewers ({} controlling, {} watching)", viewers.len() - watching, watching)
+    }
 }
 
 #[cfg(test)]
@@ -171,6 +229,11 @@ mod tests {
         };
         let _shell_count = UiEvent::ShellCountChanged(5);
         let _pty_error = UiEvent::PtyError("pty error".into());
+        let _pty_stopped = UiEvent::PtyStopped;
+        let _throughput = UiEvent::Throughput {
+            up_bytes_per_sec: 12 * 1024,
+            down_bytes_per_sec: 3 * 1024,
+        };
         let _terminal_from_shell = UiEvent::TerminalDataFromShell {
             session_id: "sess-1".into(),
             data: vec![0x1b, 0x5b, 0x41],
@@ -181,6 +244,30 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(0), "0 B/s");
+        assert_eq!(format_rate(1023), "1023 B/s");
+        assert_eq!(format_rate(12 * 1024 + 500), "12 KB/s");
+        assert_eq!(format_rate(3 * 1024 * 1024 / 2), "1.5 MB/s");
+    }
+
+    #[test]
+    fn test_viewers_title() {
+        let viewer = |read_only| crate::protocol::BrowserEntry {
+            id: "b1".into(),
+            connected_at: 0,
+            ip_prefix: "10.0.0.0/24".into(),
+            read_only,
+        };
+        assert_eq!(viewers_title(&[]), "Viewers (0)");
+        assert_eq!(viewers_title(&[viewer(false), viewer(false)]), "Viewers (2)");
+        assert_eq!(
+            viewers_title(&[viewer(false), viewer(true), viewer(true)]),
+            "Viewers (1 controlling, 2 watching)"
+        );
+    }
+
     #[test]
     fn test_background_command_variants() {
         let _shutdown = BackgroundCommand::Shutdown;
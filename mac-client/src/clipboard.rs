@@ -0,0 +1,137 @@
+//! OSC 52 clipboard bridge.
+//!
+//! Programs set the clipboard with `ESC ] 52 ; <targets> ; <base64> BEL`
+//! (or `ST` instead of BEL). When the bridge is enabled, such sequences in
+//! session output set the Mac clipboard, and clipboard text from the browser
+//! is delivered to the session as an OSC 52 response.
+//!
+//! Off by default: it lets anything printed to a terminal write the clipboard.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Start of an OSC 52 sequence
+const OSC52_START: &[u8] = b"\x1b]52;";
+
+/// Longest OSC 52 sequence we buffer across frames before giving up
+const MAX_SEQUENCE: usize = 1024 * 1024;
+
+/// Whether the clipboard bridge is enabled (`CLIPBOARD_BRIDGE=1`).
+pub fn bridge_enabled() -> bool {
+    matches!(
+        std::env::var("CLIPBOARD_BRIDGE").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// Build the OSC 52 sequence delivering `base64` clipboard data to a session.
+/// Returns None if `base64` isn't valid base64, so nothing else can be
+/// smuggled into the terminal.
+pub fn osc52_response(base64: &str) -> Option<Vec<u8>> {
+    let text = STANDARD.decode(base64).ok()?;
+    let mut seq = OSC52_START.to_vec();
+    seq.extend_from_slice(b"c;");
+    seq.extend_from_slice(STANDARD.encode(text).as_bytes());
+    seq.push(0x07);
+    Some(seq)
+}
+
+/// Finds OSC 52 clipboard writes in a session's output stream, including
+/// sequences split across output chunks.
+#[derive(Debug, Default)]
+pub struct Osc52Scanner {
+    /// Unterminated sequence (or possible start of one) from the last chunk
+    pending: Vec<u8>,
+}
+
+impl Osc52Scanner {
+    /// Feed a chunk of output; returns the clipboard text of each complete
+    /// OSC 52 write found. Queries (`?`) and undecodable payloads are skipped.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(data);
+
+        let mut found = Vec::new();
+        let mut pos = 0;
+        while let Some(start) = find(&buf[pos..], OSC52_START).map(|i| pos + i) {
+            let body_start = start + OSC52_START.len();
+            let Some((body_end, next)) = find_terminator(&buf[body_start..]) else {
+                if buf.len() - start <= MAX_SEQUENCE {
+                    self.pending = buf[start..].to_vec();
+                }
+                return found;
+            };
+            let body = &buf[body_start..body_start + body_end];
+            if let Some(text) = decode_body(body) {
+                found.push(text);
+            }
+            pos = body_start + next;
+        }
+
+        // Keep a trailing partial start sequence for the next chunk
+        let tail = &buf[pos..];
+        for len in (1..OSC52_START.len()).rev() {
+            if tail.len() >= len && tail.ends_with(&OSC52_START[..len]) {
+                self.pending = tail[tail.len() - len..].to_vec();
+                break;
+            }
+        }
+        found
+    }
+}
+
+/// `<targets>;<base64>` -> decoded text
+fn decode_body(body: &[u8]) -> Option<String> {
+    let split = body.iter().position(|&b| b == b';')?;
+    let payload = &body[split + 1..];
+    if payload == b"?" {
+        return None;
+    }
+    String::from_utf8(STANDARD.decode(payload).ok()?).ok()
+}
+
+/// Position of the terminator (BEL or ESC \) and the offset just past it.
+fn find_terminator(data: &[u8]) -> Option<(usize, usize)> {
+    for (i, &b) in data.iter().enumerate() {
+        if b == 0x07 {
+            return Some((i, i + 1));
+        }
+        if b == 0x1b && data.get(i + 1) == Some(&b'\\') {
+            return Some((i, i + 2));
+        }
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanner_finds_sequences() {
+        let mut scanner = Osc52Scanner::default();
+        // "hello" with BEL, "hi" with ST, and a query that must be ignored
+        let out = scanner.feed(b"ab\x1b]52;c;aGVsbG8=\x07cd\x1b]52;p;aGk=\x1b\\\x1b]52;c;?\x07");
+        assert_eq!(out, ["hello", "hi"]);
+    }
+
+    #[test]
+    fn test_scanner_split_across_chunks() {
+        let mut scanner = Osc52Scanner::default();
+        assert!(scanner.feed(b"output\x1b]5").is_empty());
+        assert!(scanner.feed(b"2;c;aGVs").is_empty());
+        assert_eq!(scanner.feed(b"bG8=\x07more"), ["hello"]);
+        assert!(scanner.pending.is_empty());
+    }
+
+    #[test]
+    fn test_osc52_response() {
+        assert_eq!(osc52_response("aGk=").unwrap(), b"\x1b]52;c;aGk=\x07");
+        // Not base64 - could otherwise inject escape sequences
+        assert!(osc52_response("\x07\x1b[2J").is_none());
+    }
+}
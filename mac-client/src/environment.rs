@@ -0,0 +1,86 @@
+//! Environment report for relay operators.
+//!
+//! With `REPORT_ENVIRONMENT=1` the mac-client tells the relay its macOS
+//! version, its shell and whether tmux and cloudflared are installed. The
+//! relay shows it in its admin view, to help debug a Mac whose sessions
+//! behave oddly. Off by default: the relay doesn't need it to work.
+
+use std::path::{Path, PathBuf};
+
+use crate::protocol::ClientEnvironment;
+
+/// Where Homebrew installs tools; not on the PATH of a menu bar app
+const HOMEBREW_BIN_DIRS: &[&str] = &["/opt/homebrew/bin", "/usr/local/bin"];
+
+/// Whether the environment is sent to the relay (`REPORT_ENVIRONMENT`).
+pub fn reporting_enabled() -> bool {
+    matches!(std::env::var("REPORT_ENVIRONMENT").as_deref(), Ok("1") | Ok("true"))
+}
+
+/// Describe this Mac. Runs `sw_vers`, so call it once rather than per
+/// connection.
+pub fn detect() -> ClientEnvironment {
+    let dirs = search_dirs();
+    ClientEnvironment {
+        os_version: os_version(),
+        shell: ["DEFAULT_SHELL", "SHELL"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|shell| !shell.is_empty()),
+        tmux: installed_in(&dirs, "tmux"),
+        cloudflared: installed_in(&dirs, "cloudflared"),
+        client_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+    }
+}
+
+/// macOS version, e.g. "14.5".
+fn os_version() -> Option<String> {
+    let output = std::process::Command::new("sw_vers").arg("-productVersion").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+/// Homebrew's directories, then `$PATH`.
+fn search_dirs() -> Vec<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    HOMEBREW_BIN_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .chain(std::env::split_paths(&path))
+        .collect()
+}
+
+/// Whether an executable `name` is in one of `dirs`.
+fn installed_in(dirs: &[PathBuf], name: &str) -> bool {
+    dirs.iter().any(|dir| is_executable(&dir.join(name)))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed_in() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("env-bin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tmux");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("cloudflared"), "not executable").unwrap();
+
+        let dirs = [PathBuf::from("/nonexistent"), dir.clone()];
+        assert!(installed_in(&dirs, "tmux"));
+        assert!(!installed_in(&dirs, "cloudflared"));
+        assert!(!installed_in(&dirs, "missing"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
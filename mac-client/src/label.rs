@@ -0,0 +1,42 @@
+//! The label browsers show for this Mac.
+//!
+//! `SESSION_LABEL` if set, otherwise the computer name from System Settings
+//! (Sharing), otherwise the hostname. It is re-read periodically, so renaming
+//! the Mac updates open browsers without a reconnect.
+
+use std::process::Command;
+
+/// The current label, or None if nothing usable is set.
+pub fn current_label() -> Option<String> {
+    std::env::var("SESSION_LABEL")
+        .ok()
+        .and_then(|label| non_empty(&label))
+        .or_else(|| command_output("scutil", &["--get", "ComputerName"]))
+        .or_else(|| command_output("hostname", &["-s"]))
+}
+
+/// First line of a command's output as a label, if it succeeded.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    non_empty(String::from_utf8_lossy(&output.stdout).lines().next()?)
+}
+
+/// The label trimmed, or None if blank. The relay strips control
+/// characters and caps the length of whatever is sent.
+fn non_empty(label: &str) -> Option<String> {
+    Some(label.trim().to_string()).filter(|l| !l.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_empty() {
+        assert_eq!(non_empty("  Studio Mac\t").as_deref(), Some("Studio Mac"));
+        assert_eq!(non_empty("   "), None);
+    }
+}
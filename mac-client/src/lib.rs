@@ -1,6 +1,17 @@
 // mac-client library root
 
 pub mod app;
+pub mod clipboard;
+pub mod environment;
+pub mod label;
+pub mod locale;
+pub mod login_item;
+pub mod network;
+pub mod paste;
+pub mod paths;
+pub mod profiles;
 pub mod protocol;
+pub mod qr;
 pub mod pty;
 pub mod relay;
+pub mod ssh;
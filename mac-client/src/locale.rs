@@ -0,0 +1,95 @@
+//! Browser locale and timezone for new sessions.
+//!
+//! Browsers may report their locale (a BCP 47 tag such as `de-CH`) and IANA
+//! timezone in their capabilities. With forwarding enabled, shells opened for
+//! that browser get matching `LANG`/`LC_ALL` and `TZ`, so `date` and other
+//! locale-aware output looks like it would on the viewer's machine.
+//!
+//! Off by default: it changes the environment of sessions opened from the
+//! browser. Values may end up on a shell command line (for Terminal
+//! windows), so only names that are installed on this Mac are used.
+
+use std::path::Path;
+
+/// Where macOS keeps locale definitions (`en_US.UTF-8`, ...)
+const LOCALE_DIR: &str = "/usr/share/locale";
+
+/// Where macOS keeps timezone definitions (`Europe/Zurich`, ...)
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Whether browser locale/timezone are applied (`FORWARD_BROWSER_LOCALE=1`).
+pub fn forwarding_enabled() -> bool {
+    matches!(
+        std::env::var("FORWARD_BROWSER_LOCALE").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// Environment variables for a new shell. Values that aren't valid or
+/// installed are left out.
+pub fn env(locale: Option<&str>, timezone: Option<&str>) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    if let Some(name) = locale.and_then(|tag| locale_name(tag, |name| Path::new(LOCALE_DIR).join(name).is_dir())) {
+        env.push(("LANG".to_string(), name.clone()));
+        env.push(("LC_ALL".to_string(), name));
+    }
+    if let Some(zone) = timezone.and_then(|tz| timezone_name(tz, |zone| Path::new(ZONEINFO_DIR).join(zone).is_file())) {
+        env.push(("TZ".to_string(), zone));
+    }
+    env
+}
+
+/// The UTF-8 POSIX locale for a BCP 47 tag, e.g. `en-US` -> `en_US.UTF-8`,
+/// if `installed`. Script and variant subtags are ignored; a tag without a
+/// region has no POSIX equivalent.
+fn locale_name(tag: &str, installed: impl Fn(&str) -> bool) -> Option<String> {
+    let mut subtags = tag.trim().split(['-', '_']);
+    let language = subtags.next()?;
+    if !(2..=3).contains(&language.len()) || !language.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    let region = subtags.find(|s| s.len() == 2 && s.bytes().all(|b| b.is_ascii_alphabetic()))?;
+    let name = format!("{}_{}.UTF-8", language.to_ascii_lowercase(), region.to_ascii_uppercase());
+    installed(&name).then_some(name)
+}
+
+/// An IANA timezone name such as `America/New_York`, if `installed`.
+fn timezone_name(name: &str, installed: impl Fn(&str) -> bool) -> Option<String> {
+    let name = name.trim();
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.split('/').all(|part| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'+'))
+        });
+    (valid && installed(name)).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_name() {
+        let installed = |name: &str| ["en_US.UTF-8", "de_CH.UTF-8", "zh_TW.UTF-8"].contains(&name);
+        assert_eq!(locale_name("en-US", installed).as_deref(), Some("en_US.UTF-8"));
+        assert_eq!(locale_name("DE_ch", installed).as_deref(), Some("de_CH.UTF-8"));
+        assert_eq!(locale_name("zh-Hant-TW", installed).as_deref(), Some("zh_TW.UTF-8"));
+        assert_eq!(locale_name("de", installed), None);
+        assert_eq!(locale_name("fr-FR", installed), None);
+        assert_eq!(locale_name("en-US;rm -rf", installed), None);
+    }
+
+    #[test]
+    fn test_timezone_name() {
+        let installed = |_: &str| true;
+        assert_eq!(timezone_name("Europe/Zurich", installed).as_deref(), Some("Europe/Zurich"));
+        assert_eq!(timezone_name("Etc/GMT+5", installed).as_deref(), Some("Etc/GMT+5"));
+        assert_eq!(timezone_name("UTC", installed).as_deref(), Some("UTC"));
+        assert_eq!(timezone_name("../../etc/passwd", installed), None);
+        assert_eq!(timezone_name("/etc/localtime", installed), None);
+        assert_eq!(timezone_name("Europe/Zurich; id", installed), None);
+        assert_eq!(timezone_name("Mars/Olympus", |_| false), None);
+    }
+}
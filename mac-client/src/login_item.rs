@@ -0,0 +1,165 @@
+//! "Start at Login" support.
+//!
+//! Prefers SMAppService (macOS 13+, signed .app bundle). When that is
+//! unavailable - older macOS, or running the raw binary during development -
+//! falls back to a LaunchAgent plist in `~/Library/LaunchAgents`, loaded via
+//! `launchctl`. The label matches the agent `scripts/install.sh` creates, so
+//! both paths agree on whether the app starts at login.
+
+use smappservice_rs::{AppService, ServiceStatus, ServiceType};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+/// LaunchAgent label (shared with scripts/install.sh)
+const LAUNCH_AGENT_LABEL: &str = "com.terminal-remote.app";
+
+/// First macOS major version with SMAppService
+const SMAPPSERVICE_MIN_MACOS: u32 = 13;
+
+/// Check if the app is currently set to start at login by either mechanism.
+pub fn is_enabled() -> bool {
+    let service = AppService::new(ServiceType::MainApp);
+    matches!(service.status(), ServiceStatus::Enabled)
+        || launch_agent_path().is_some_and(|path| path.exists())
+}
+
+/// Enable or disable starting at login.
+///
+/// Enabling tries SMAppService first (on macOS 13+) and falls back to a
+/// LaunchAgent if registration fails. Disabling removes both.
+pub fn configure(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if enable {
+        if macos_major_version().is_some_and(|v| v >= SMAPPSERVICE_MIN_MACOS) {
+            match register_app_service() {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("SMAppService registration failed ({}), using LaunchAgent", e),
+            }
+        } else {
+            info!("SMAppService unavailable on this macOS, using LaunchAgent");
+        }
+        install_launch_agent()
+    } else {
+        let service = AppService::new(ServiceType::MainApp);
+        if !matches!(service.status(), ServiceStatus::NotRegistered | ServiceStatus::NotFound) {
+            service.unregister()?;
+            info!("Unregistered as login item");
+        }
+        remove_launch_agent()
+    }
+}
+
+fn register_app_service() -> Result<(), Box<dyn std::error::Error>> {
+    let service = AppService::new(ServiceType::MainApp);
+    service.register()?;
+    info!("Registered as login item");
+
+    // Check if user approval is needed
+    if matches!(service.status(), ServiceStatus::RequiresApproval) {
+        info!("Login item requires user approval in System Settings > Login Items");
+        AppService::open_system_settings_login_items();
+    }
+    Ok(())
+}
+
+fn install_launch_agent() -> Result<(), Box<dyn std::error::Error>> {
+    let path = launch_agent_path().ok_or("HOME is not set")?;
+    let exe = std::env::current_exe()?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, launch_agent_plist(&exe))?;
+    launchctl("load", &path)?;
+    info!("Installed LaunchAgent at {}", path.display());
+    Ok(())
+}
+
+fn remove_launch_agent() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = launch_agent_path() else {
+        return Ok(());
+    };
+    if !path.exists() {
+        return Ok(());
+    }
+    // Unloading fails if the agent isn't loaded, which is fine
+    if let Err(e) = launchctl("unload", &path) {
+        debug!("launchctl unload: {}", e);
+    }
+    std::fs::remove_file(&path)?;
+    info!("Removed LaunchAgent at {}", path.display());
+    Ok(())
+}
+
+fn launchctl(action: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("launchctl").arg(action).arg(path).status()?;
+    if !status.success() {
+        return Err(format!("launchctl {} exited with {}", action, status).into());
+    }
+    Ok(())
+}
+
+fn launch_agent_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(
+        PathBuf::from(home)
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", LAUNCH_AGENT_LABEL)),
+    )
+}
+
+/// Major version from `sw_vers -productVersion`, e.g. 14 for "14.2.1".
+fn macos_major_version() -> Option<u32> {
+    let output = Command::new("sw_vers").arg("-productVersion").output().ok()?;
+    parse_major_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_major_version(version: &str) -> Option<u32> {
+    version.trim().split('.').next()?.parse().ok()
+}
+
+fn launch_agent_plist(exe: &Path) -> String {
+    let exe = exe
+        .to_string_lossy()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        LAUNCH_AGENT_LABEL, exe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_major_version() {
+        assert_eq!(parse_major_version("14.2.1\n"), Some(14));
+        assert_eq!(parse_major_version("12.7"), Some(12));
+        assert_eq!(parse_major_version(""), None);
+    }
+
+    #[test]
+    fn test_launch_agent_plist() {
+        let plist = launch_agent_plist(Path::new("/tmp/R&D/mac-client"));
+        assert!(plist.contains("<string>com.terminal-remote.app</string>"));
+        assert!(plist.contains("<string>/tmp/R&amp;D/mac-client</string>"));
+        assert!(plist.contains("<key>RunAtLoad</key>"));
+    }
+}
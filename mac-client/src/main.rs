@@ -7,12 +7,23 @@
 //! We use winit's EventLoop to drive the main thread.
 
 use image::ImageReader;
-use mac_client::app::{AppState, BackgroundCommand, UiEvent};
-use mac_client::pty::{PtyCommand, PtyEvent, PtyManager};
-use mac_client::relay::{RelayClient, RelayCommand, RelayEvent};
-use muda::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
-use smappservice_rs::{AppService, ServiceStatus, ServiceType};
-use std::io::{BufRead, BufReader, Cursor};
+use mac_client::app::{viewers_title, AppState, BackgroundCommand, UiEvent};
+use mac_client::clipboard::{self, Osc52Scanner};
+use mac_client::label;
+use mac_client::locale;
+use mac_client::login_item;
+use mac_client::network;
+use mac_client::paste::{self, PasteGuard};
+use mac_client::paths;
+use mac_client::profiles;
+use mac_client::protocol::{parse_relay_version, PROTOCOL_VERSION};
+use mac_client::qr;
+use mac_client::pty::{self, lock_sessions, PtyCommand, PtyEvent, PtyManager, SessionList, ALLOWED_TERMS};
+use mac_client::relay::{ByteCounters, MovingAverage, RelayClient, RelayCommand, RelayEvent};
+use mac_client::ssh::SshTarget;
+use muda::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Cursor, Read};
 use std::process::{Child, Command, Stdio};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{mpsc, Arc};
@@ -23,21 +34,103 @@ use tray_icon::{TrayIcon, TrayIconBuilder};
 use tracing::{debug, error, info, warn};
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
-use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
 use winit::window::WindowId;
 
 // Menu item IDs
 const ID_REGEN_CODE: &str = "regen_code";
 const ID_COPY_URL: &str = "copy_url";
 const ID_COPY_CODE: &str = "copy_code";
+const ID_SHARE_CODE: &str = "share_code";
+const ID_SHOW_QR: &str = "show_qr";
+const ID_PAUSE_MIRRORING: &str = "pause_mirroring";
 const ID_LOGIN_ITEM: &str = "login_item";
 const ID_QUIT: &str = "quit";
 
+/// How often to report liveness and load to the relay
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the label (computer name or SESSION_LABEL) is re-read
+const LABEL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the session list is checked against the PTY manager's, in
+/// case an Attached/Detached event was missed
+const SESSION_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often interface addresses are checked for a network change
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait for each step of shutdown before moving on
+const SHUTDOWN_STEP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often reconnect counts are logged and refreshed in the menu
+const RECONNECT_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the throughput entry in the menu is refreshed
+const THROUGHPUT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lifetime of the watch-only share codes from "Copy 1-Hour Share Code"
+const SHARE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Label of the share menu item
+const SHARE_LABEL: &str = "Copy 1-Hour Share Code";
+
+/// Label of the QR code menu item
+const QR_LABEL: &str = "Show QR Code";
+
+/// How long a copy menu item shows "Copy failed" before its label resets
+const COPY_FEEDBACK_DURATION: Duration = Duration::from_secs(2);
+
+/// How long `relay-server --version` may take. Binaries from before the
+/// flag existed start a server instead, and are killed after this.
+const RELAY_VERSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// relay-server stderr lines printed this soon after launch are startup
+/// failures (e.g. port in use) and are surfaced in the mac-client log
+const RELAY_STARTUP_WINDOW: Duration = Duration::from_secs(5);
+
+/// Minimum time between browser-requested new sessions, so a buggy or
+/// malicious client can't open dozens of shells
+const CREATE_SESSION_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Banner used when JOIN_BANNER is set to "1" rather than a template
+const DEFAULT_JOIN_BANNER: &str = "[viewer connected at {time}]";
+
 /// Custom events for our application
 #[derive(Debug)]
 enum AppEvent {
     TrayIconEvent(tray_icon::TrayIconEvent),
     MenuEvent(muda::MenuEvent),
+    /// A UiEvent was sent; drain the channel
+    Wake,
+}
+
+/// Sends UiEvents to the main thread and wakes its event loop, so events
+/// are handled immediately instead of on a polling tick.
+#[derive(Clone)]
+struct UiSender {
+    tx: mpsc::Sender<UiEvent>,
+    proxy: EventLoopProxy<AppEvent>,
+}
+
+/// Where background tasks send UiEvents: a UiSender, or a plain channel
+/// in tests, which have no event loop to wake.
+trait UiSink {
+    fn send(&self, event: UiEvent) -> Result<(), mpsc::SendError<UiEvent>>;
+}
+
+impl UiSink for UiSender {
+    fn send(&self, event: UiEvent) -> Result<(), mpsc::SendError<UiEvent>> {
+        self.tx.send(event)?;
+        let _ = self.proxy.send_event(AppEvent::Wake);
+        Ok(())
+    }
+}
+
+impl UiSink for mpsc::Sender<UiEvent> {
+    fn send(&self, event: UiEvent) -> Result<(), mpsc::SendError<UiEvent>> {
+        mpsc::Sender::send(self, event)
+    }
 }
 
 /// Main application state
@@ -45,6 +138,15 @@ struct App {
     tray_icon: Option<TrayIcon>,
     app_state: Option<AppState>,
     login_item: Option<CheckMenuItem>,
+    share_item: Option<MenuItem>,
+    qr_item: Option<MenuItem>,
+    /// Checked while output is kept from browsers
+    mirroring_item: Option<CheckMenuItem>,
+    /// Diagnostics entry showing recent reconnects
+    reconnects_item: Option<MenuItem>,
+    viewers_menu: Option<Submenu>,
+    /// Entries currently shown in the Viewers submenu
+    viewer_items: Vec<MenuItem>,
     bg_tx: Option<mpsc::Sender<BackgroundCommand>>,
     ui_rx: Option<mpsc::Receiver<UiEvent>>,
     bg_handle: Option<thread::JoinHandle<()>>,
@@ -60,6 +162,12 @@ impl App {
             tray_icon: None,
             app_state: None,
             login_item: None,
+            share_item: None,
+            qr_item: None,
+            mirroring_item: None,
+            reconnects_item: None,
+            viewers_menu: None,
+            viewer_items: Vec::new(),
             bg_tx: None,
             ui_rx: None,
             bg_handle: None,
@@ -83,9 +191,12 @@ impl App {
             ID_COPY_URL => {
                 if let Some(app_state) = &self.app_state {
                     if let Some(url) = &app_state.tunnel_url {
-                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                            if clipboard.set_text(url.clone()).is_ok() {
-                                info!("Tunnel URL copied to clipboard: {}", url);
+                        match copy_to_clipboard(url) {
+                            Ok(()) => info!("Tunnel URL copied to clipboard: {}", url),
+                            Err(e) => {
+                                warn!("Failed to copy tunnel URL: {}", e);
+                                app_state.copy_item.set_text("Copy failed");
+                                self.copy_reset_time = Some(Instant::now() + COPY_FEEDBACK_DURATION);
                             }
                         }
                     }
@@ -94,20 +205,74 @@ impl App {
             ID_COPY_CODE => {
                 if let Some(app_state) = &self.app_state {
                     if let Some(code) = &app_state.session_code {
-                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                            if clipboard.set_text(code.clone()).is_ok() {
-                                info!("Session code copied to clipboard: {}", code);
+                        match copy_to_clipboard(code) {
+                            Ok(()) => info!("Session code copied to clipboard: {}", code),
+                            Err(e) => {
+                                warn!("Failed to copy session code: {}", e);
+                                app_state.copy_code_item.set_text("Copy failed");
+                                self.copy_reset_time = Some(Instant::now() + COPY_FEEDBACK_DURATION);
                             }
                         }
                     }
                 }
             }
+            ID_SHARE_CODE => {
+                // The code is copied when the relay replies (UiEvent::ShareCode)
+                info!("Share code requested");
+                if let Some(bg_tx) = &self.bg_tx {
+                    let _ = bg_tx.send(BackgroundCommand::CreateShare { ttl: SHARE_TTL });
+                }
+            }
+            ID_SHOW_QR => {
+                let Some(app_state) = &self.app_state else {
+                    return;
+                };
+                let feedback = match (&app_state.tunnel_url, &app_state.session_code) {
+                    (Some(url), Some(code)) => {
+                        let path = qr::image_path();
+                        match qr::write_png(&qr::login_url(url, code), &path) {
+                            Ok(()) => match Command::new("open").arg(&path).spawn() {
+                                Ok(_) => {
+                                    info!("Showing QR code for the tunnel URL");
+                                    None
+                                }
+                                Err(e) => {
+                                    warn!("Failed to open QR code: {}", e);
+                                    Some("QR code failed")
+                                }
+                            },
+                            Err(e) => {
+                                warn!("Failed to write QR code: {}", e);
+                                Some("QR code failed")
+                            }
+                        }
+                    }
+                    // Both arrive shortly after startup
+                    _ => {
+                        info!("QR code requested before the tunnel URL and code are known");
+                        Some("No URL or code yet")
+                    }
+                };
+                if let (Some(text), Some(qr_item)) = (feedback, &self.qr_item) {
+                    qr_item.set_text(text);
+                    self.copy_reset_time = Some(Instant::now() + COPY_FEEDBACK_DURATION);
+                }
+            }
+            ID_PAUSE_MIRRORING => {
+                if let (Some(item), Some(pty_cmd_tx)) = (&self.mirroring_item, &self.pty_cmd_tx) {
+                    let paused = !item.is_checked();
+                    item.set_checked(paused);
+                    info!("Mirroring to browsers {}", if paused { "paused" } else { "resumed" });
+                    // Shells that start while paused are muted from their first output
+                    let _ = pty_cmd_tx.send(PtyCommand::PauseMirroring { paused });
+                }
+            }
             ID_LOGIN_ITEM => {
                 if let Some(login_item) = &self.login_item {
                     let current = login_item.is_checked();
                     let new_state = !current;
 
-                    match configure_login_item(new_state) {
+                    match login_item::configure(new_state) {
                         Ok(()) => {
                             login_item.set_checked(new_state);
                             info!(
@@ -118,15 +283,20 @@ impl App {
                         }
                         Err(e) => {
                             error!("Failed to configure login item: {}", e);
-                            warn!(
-                                "Login item registration may require running from a proper .app bundle"
-                            );
                         }
                     }
                 }
             }
             ID_QUIT => {
                 info!("Quit requested, exiting");
+                // Let the background tasks close the relay connection and
+                // stop their children in order
+                if let (Some(bg_tx), Some(handle)) = (self.bg_tx.take(), self.bg_handle.take()) {
+                    let _ = bg_tx.send(BackgroundCommand::Shutdown);
+                    if handle.join().is_err() {
+                        error!("Background thread panicked during shutdown");
+                    }
+                }
                 let pid = self.cloudflared_pid.load(Ordering::Relaxed);
                 if pid != 0 {
                     info!("Killing cloudflared (pid {})", pid);
@@ -145,7 +315,35 @@ impl App {
         }
     }
 
+    /// Replace the Viewers submenu entries with `viewers`.
+    fn show_viewers(&mut self, viewers: &[mac_client::protocol::BrowserEntry]) {
+        let Some(menu) = &self.viewers_menu else {
+            return;
+        };
+        for item in self.viewer_items.drain(..) {
+            let _ = menu.remove(&item);
+        }
+        menu.set_text(viewers_title(viewers));
+        self.viewer_items = if viewers.is_empty() {
+            vec![MenuItem::new("No viewers", false, None)]
+        } else {
+            viewers
+                .iter()
+                .map(|v| {
+                    let since = local_time_at(v.connected_at as libc::time_t);
+                    let role = if v.read_only { ", watching" } else { "" };
+                    MenuItem::new(format!("{} since {}{}", v.ip_prefix, since, role), false, None)
+                })
+                .collect()
+        };
+        for item in &self.viewer_items {
+            let _ = menu.append(item);
+        }
+    }
+
     fn handle_ui_events(&mut self) {
+        // Applied after draining events, since rebuilding the menu needs &mut self
+        let mut viewers = None;
         if let Some(ui_rx) = &self.ui_rx {
             while let Ok(event) = ui_rx.try_recv() {
                 debug!("UI event: {:?}", event);
@@ -163,6 +361,11 @@ impl App {
                             app_state.session_code = None;
                             app_state.update_status_display();
                             app_state.update_code_display();
+                            viewers = Some(Vec::new());
+                        }
+                        UiEvent::Viewers(list) => {
+                            debug!("{} viewers", list.len());
+                            viewers = Some(list);
                         }
                         UiEvent::SessionCode(code) => {
                             info!("Received session code: {}", code);
@@ -185,6 +388,24 @@ impl App {
                         UiEvent::RelayError(msg) => {
                             error!("Relay error: {}", msg);
                         }
+                        UiEvent::Reconnects(summary) => {
+                            if let Some(item) = &self.reconnects_item {
+                                item.set_text(summary);
+                            }
+                        }
+                        UiEvent::Throughput { up_bytes_per_sec, down_bytes_per_sec } => {
+                            app_state.update_throughput_display(up_bytes_per_sec, down_bytes_per_sec);
+                        }
+                        UiEvent::ShareCode(code) => match copy_to_clipboard(&code) {
+                            Ok(()) => info!("Share code copied to clipboard"),
+                            Err(e) => {
+                                warn!("Failed to copy share code: {}", e);
+                                if let Some(share_item) = &self.share_item {
+                                    share_item.set_text("Copy failed");
+                                }
+                                self.copy_reset_time = Some(Instant::now() + COPY_FEEDBACK_DURATION);
+                            }
+                        },
                         UiEvent::ShellConnected { session_id, name } => {
                             info!("Shell connected: {} ({})", name, session_id);
                             app_state.shell_count += 1;
@@ -206,6 +427,11 @@ impl App {
                         UiEvent::PtyError(msg) => {
                             error!("PTY error: {}", msg);
                         }
+                        UiEvent::PtyStopped => {
+                            error!("PTY manager stopped, terminal input is no longer delivered");
+                            app_state.pty_stopped = true;
+                            app_state.update_status_display();
+                        }
                         UiEvent::TerminalDataFromShell { session_id, data } => {
                             debug!(
                                 "Terminal data from shell {}: {} bytes",
@@ -225,11 +451,22 @@ impl App {
             }
         }
 
+        if let Some(list) = viewers {
+            self.show_viewers(&list);
+        }
+
         // Reset copy button text after 2 seconds
         if let Some(reset_time) = self.copy_reset_time {
             if Instant::now() >= reset_time {
                 if let Some(app_state) = &self.app_state {
                     app_state.copy_item.set_text("Copy URL");
+                    app_state.copy_code_item.set_text("Copy Session Code");
+                }
+                if let Some(share_item) = &self.share_item {
+                    share_item.set_text(SHARE_LABEL);
+                }
+                if let Some(qr_item) = &self.qr_item {
+                    qr_item.set_text(QR_LABEL);
                 }
                 self.copy_reset_time = None;
             }
@@ -244,12 +481,7 @@ impl ApplicationHandler<AppEvent> for App {
         debug!("Application resumed");
     }
 
-    fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: winit::event::StartCause) {
-        // Set up polling - we need to check our channels periodically
-        event_loop.set_control_flow(ControlFlow::WaitUntil(
-            Instant::now() + Duration::from_millis(16), // ~60fps
-        ));
-
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: winit::event::StartCause) {
         // Handle wake-ups and polls
         if matches!(
             cause,
@@ -269,6 +501,9 @@ impl ApplicationHandler<AppEvent> for App {
             AppEvent::MenuEvent(e) => {
                 self.handle_menu_event(e);
             }
+            AppEvent::Wake => {
+                self.handle_ui_events();
+            }
         }
     }
 
@@ -281,10 +516,18 @@ impl ApplicationHandler<AppEvent> for App {
         // We don't have any windows, but winit requires this
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         // Called right before the event loop goes to sleep
         // Good time to check our channels one more time
         self.handle_ui_events();
+
+        // Sleep until woken by AppEvent::Wake, or until a pending copy reset.
+        // Set here rather than in new_events so a reset scheduled by this
+        // iteration's menu event is honored.
+        event_loop.set_control_flow(match self.copy_reset_time {
+            Some(reset) => ControlFlow::WaitUntil(reset),
+            None => ControlFlow::Wait,
+        });
     }
 }
 
@@ -296,10 +539,16 @@ fn main() {
 
     info!("Starting mac-client menu bar application");
 
+    // Over SSH the window server is unreachable, and AppKit fails with
+    // opaque errors or aborts outright, so check before touching it
+    if in_gui_session() == Some(false) {
+        exit_without_gui("Not running in a GUI login session", "no window server connection");
+    }
+
     // Create the event loop FIRST (required on macOS)
     let event_loop = EventLoop::<AppEvent>::with_user_event()
         .build()
-        .expect("Failed to create event loop");
+        .unwrap_or_else(|e| exit_without_gui("Failed to create event loop", e));
 
     // Set up event handlers to forward events to our event loop
     let proxy = event_loop.create_proxy();
@@ -322,28 +571,34 @@ fn main() {
     // Spawn relay-server as a child process
     let relay_server_pid = Arc::new(AtomicU32::new(0));
     {
-        // Find relay-server binary: next to our binary, or in ~/.terminal-remote/bin/
+        // Its startup errors arrive while the event loop may be idle
+        let ui_tx = UiSender { tx: ui_tx.clone(), proxy: event_loop.create_proxy() };
+        // Find relay-server binary: next to our binary, or in the data bin/ dir
         let relay_bin = std::env::current_exe()
             .ok()
             .and_then(|p| p.parent().map(|d| d.join("relay-server")))
             .filter(|p| p.exists())
             .or_else(|| {
-                let home = std::env::var("HOME").ok()?;
-                let p = std::path::PathBuf::from(home).join(".terminal-remote/bin/relay-server");
+                let p = paths::bin_dir()?.join("relay-server");
                 p.exists().then_some(p)
             });
 
         match relay_bin {
             Some(bin) => {
                 info!("Starting relay-server from: {}", bin.display());
-                match Command::new(&bin).spawn() {
+                // A stale relay would otherwise fail later with confusing protocol errors
+                match check_relay_version(&bin).and_then(|()| {
+                    spawn_relay_server(&bin, ui_tx.clone())
+                        .map_err(|e| format!("failed to spawn: {}", e))
+                }) {
                     Ok(child) => {
                         let pid = child.id();
                         info!("relay-server started (pid {})", pid);
                         relay_server_pid.store(pid, Ordering::Relaxed);
                     }
-                    Err(e) => {
-                        error!("Failed to spawn relay-server: {}", e);
+                    Err(msg) => {
+                        error!("Not starting relay-server: {}", msg);
+                        let _ = ui_tx.send(UiEvent::RelayError(msg));
                     }
                 }
             }
@@ -390,9 +645,10 @@ fn main() {
 
     // Spawn background thread with Tokio runtime
     let ui_tx_bg = ui_tx.clone();
+    let proxy_bg = event_loop.create_proxy();
     let cloudflared_pid_bg = cloudflared_pid.clone();
     let bg_handle = thread::spawn(move || {
-        run_background_tasks(ui_tx_bg, bg_rx, pty_cmd_rx, cloudflared_pid_bg);
+        run_background_tasks(ui_tx_bg, proxy_bg, bg_rx, pty_cmd_rx, cloudflared_pid_bg);
     });
 
     // Load icon from embedded bytes
@@ -417,14 +673,20 @@ fn main() {
     let code_item = MenuItem::new("Code: ------", false, None);
     let status_item = MenuItem::new("Status: Connecting...", false, None);
     let sessions_item = MenuItem::new("Sessions: 0", false, None);
+    let reconnects_item = MenuItem::new("No reconnects", false, None);
+    let throughput_item = MenuItem::new("\u{2191} 0 B/s \u{2193} 0 B/s", false, None);
 
     // Action items
     let regen_code_item = MenuItem::with_id(ID_REGEN_CODE, "Regenerate Code", true, None);
     let copy_url_item = MenuItem::with_id(ID_COPY_URL, "Copy URL", true, None);
     let copy_code_item = MenuItem::with_id(ID_COPY_CODE, "Copy Session Code", true, None);
+    let share_item = MenuItem::with_id(ID_SHARE_CODE, SHARE_LABEL, true, None);
+    let qr_item = MenuItem::with_id(ID_SHOW_QR, QR_LABEL, true, None);
+    let mirroring_item = CheckMenuItem::with_id(ID_PAUSE_MIRRORING, "Pause Mirroring", true, false, None);
+    let viewers_menu = Submenu::new("Viewers (0)", true);
 
     // Check current login item status and set initial checkbox state
-    let is_login_enabled = is_login_item_enabled();
+    let is_login_enabled = login_item::is_enabled();
     let login_item =
         CheckMenuItem::with_id(ID_LOGIN_ITEM, "Start at Login", true, is_login_enabled, None);
     debug!("Login item initial state: {}", is_login_enabled);
@@ -432,29 +694,29 @@ fn main() {
     let quit_item = MenuItem::with_id(ID_QUIT, "Quit", true, None);
 
     // Assemble menu
-    menu.append(&url_item).expect("Failed to add url item");
-    menu.append(&code_item).expect("Failed to add code item");
-    menu.append(&status_item)
-        .expect("Failed to add status item");
-    menu.append(&sessions_item)
-        .expect("Failed to add sessions item");
-    menu.append(&PredefinedMenuItem::separator())
-        .expect("Failed to add separator");
-    menu.append(&copy_url_item)
-        .expect("Failed to add copy url item");
-    menu.append(&copy_code_item)
-        .expect("Failed to add copy code item");
-    menu.append(&regen_code_item)
-        .expect("Failed to add regen code item");
-    menu.append(&PredefinedMenuItem::separator())
-        .expect("Failed to add separator");
-    menu.append(&login_item)
-        .expect("Failed to add login item");
-    menu.append(&PredefinedMenuItem::separator())
-        .expect("Failed to add separator");
-    menu.append(&quit_item).expect("Failed to add quit item");
-
-    debug!("Menu constructed with {} items", 9);
+    menu.append_items(&[
+        &url_item,
+        &code_item,
+        &status_item,
+        &sessions_item,
+        &throughput_item,
+        &viewers_menu,
+        &reconnects_item,
+        &PredefinedMenuItem::separator(),
+        &copy_url_item,
+        &copy_code_item,
+        &share_item,
+        &qr_item,
+        &regen_code_item,
+        &mirroring_item,
+        &PredefinedMenuItem::separator(),
+        &login_item,
+        &PredefinedMenuItem::separator(),
+        &quit_item,
+    ])
+    .unwrap_or_else(|e| exit_without_gui("Failed to build menu", e));
+
+    debug!("Menu constructed with {} items", 11);
 
     // Create app state with menu item references
     let app_state = AppState::new(
@@ -463,6 +725,8 @@ fn main() {
         sessions_item,
         url_item,
         copy_url_item.clone(),
+        copy_code_item.clone(),
+        throughput_item,
     );
 
     // Create tray icon
@@ -472,7 +736,7 @@ fn main() {
         .with_icon_as_template(true)
         .with_tooltip("Terminal Remote")
         .build()
-        .expect("Failed to create tray icon");
+        .unwrap_or_else(|e| exit_without_gui("Failed to create tray icon", e));
 
     info!("Tray icon created successfully");
 
@@ -481,6 +745,12 @@ fn main() {
     app.tray_icon = Some(tray_icon);
     app.app_state = Some(app_state);
     app.login_item = Some(login_item);
+    app.share_item = Some(share_item);
+    app.qr_item = Some(qr_item);
+    app.mirroring_item = Some(mirroring_item);
+    app.reconnects_item = Some(reconnects_item);
+    app.viewers_menu = Some(viewers_menu);
+    app.show_viewers(&[]);
     app.bg_tx = Some(bg_tx);
     app.ui_rx = Some(ui_rx);
     app.bg_handle = Some(bg_handle);
@@ -491,7 +761,9 @@ fn main() {
     info!("Entering main event loop");
 
     // Run the event loop - this blocks until the app exits
-    event_loop.run_app(&mut app).expect("Event loop failed");
+    if let Err(e) = event_loop.run_app(&mut app) {
+        exit_without_gui("Event loop failed", e);
+    }
 
     // Clean up
     info!("Waiting for background thread to finish...");
@@ -504,50 +776,41 @@ fn main() {
     info!("Application exiting");
 }
 
-/// Check if the app is currently registered as a login item.
-///
-/// Returns true if enabled, false otherwise (not registered, requires approval, or not found).
-fn is_login_item_enabled() -> bool {
-    let service = AppService::new(ServiceType::MainApp);
-    matches!(service.status(), ServiceStatus::Enabled)
-}
-
-/// Configure the app as a login item (register or unregister).
-///
-/// This uses SMAppService which requires:
-/// - macOS 13.0+
-/// - App must be properly signed and bundled
-/// - May require user approval in System Settings > Login Items
-fn configure_login_item(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let service = AppService::new(ServiceType::MainApp);
-
-    if enable {
-        service.register()?;
-        info!("Registered as login item");
-
-        // Check if user approval is needed
-        if matches!(service.status(), ServiceStatus::RequiresApproval) {
-            info!("Login item requires user approval in System Settings > Login Items");
-            // Optionally open System Settings
-            AppService::open_system_settings_login_items();
-        }
-    } else {
-        service.unregister()?;
-        info!("Unregistered as login item");
+/// Whether this process is in a GUI login session, going by launchd's
+/// session type ("Aqua" for the desktop, "Background" or "StandardIO" over
+/// SSH). None if that can't be determined.
+fn in_gui_session() -> Option<bool> {
+    let output = Command::new("launchctl").arg("managername").output().ok()?;
+    if !output.status.success() {
+        return None;
     }
+    Some(String::from_utf8_lossy(&output.stdout).trim() == "Aqua")
+}
 
-    Ok(())
+/// Exit with guidance instead of a panic when the menu bar can't be set up,
+/// which almost always means the app was started without a GUI session.
+fn exit_without_gui(what: &str, err: impl std::fmt::Display) -> ! {
+    error!("{}: {}", what, err);
+    eprintln!("mac-client: {}: {}", what, err);
+    eprintln!(
+        "This app must run in a macOS GUI session. Start it from Finder or with \
+         `open`, or from Terminal on the Mac itself, not over SSH."
+    );
+    std::process::exit(1);
 }
 
 /// Run background tasks (relay client and PTY manager) on a Tokio runtime.
 fn run_background_tasks(
     ui_tx: mpsc::Sender<UiEvent>,
+    proxy: EventLoopProxy<AppEvent>,
     bg_rx: mpsc::Receiver<BackgroundCommand>,
     pty_cmd_rx: tokio::sync::mpsc::UnboundedReceiver<PtyCommand>,
     cloudflared_pid: Arc<AtomicU32>,
 ) {
     info!("Background thread starting");
 
+    let ui_tx = UiSender { tx: ui_tx, proxy };
+
     let rt = Runtime::new().expect("Failed to create Tokio runtime");
 
     rt.block_on(async {
@@ -570,11 +833,15 @@ fn run_background_tasks(
         let relay_cmd_tx_for_relay = relay_cmd_tx.clone();
 
         // Shared session list for browser sync
-        let session_list: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>> =
-            std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let session_list: SessionList = Arc::default();
         let session_list_for_pty = session_list.clone();
         let session_list_for_relay = session_list.clone();
 
+        // Terminal bytes relayed each way, for the menu's throughput entry
+        let byte_counters = Arc::new(ByteCounters::default());
+        let byte_counters_for_pty = byte_counters.clone();
+        let byte_counters_for_relay = byte_counters.clone();
+
         // Create PTY manager (replaces both TmuxManager and IpcServer)
         let (_pty_manager, mut pty_event_rx, pty_internal_cmd_tx) = PtyManager::new();
 
@@ -582,34 +849,64 @@ fn run_background_tasks(
 
         // Clone for relay forwarding (before move)
         let pty_cmd_tx_for_relay = pty_internal_cmd_tx.clone();
+        let pty_cmd_tx_for_reconcile = pty_internal_cmd_tx.clone();
+        let pty_cmd_tx_for_shutdown = pty_internal_cmd_tx.clone();
 
         // Forward pty commands from main thread to pty manager
         let mut pty_cmd_rx = pty_cmd_rx;
+        let ui_tx_forward = ui_tx.clone();
         let pty_forward_handle = tokio::spawn(async move {
             while let Some(cmd) = pty_cmd_rx.recv().await {
                 if pty_internal_cmd_tx.send(cmd).is_err() {
-                    break;
+                    // The manager's command processor is gone, so nothing
+                    // reaches the shells any more - don't fail silently
+                    error!("PTY manager stopped accepting commands");
+                    let _ = ui_tx_forward.send(UiEvent::PtyStopped);
+                    return;
                 }
             }
+            debug!("PTY command channel closed, forwarder exiting");
         });
 
         // Spawn cloudflared tunnel
         let ui_tx_tunnel = ui_tx.clone();
-        let cloudflared_pid = cloudflared_pid.clone();
-        let tunnel_handle = tokio::task::spawn_blocking(move || {
-            run_cloudflared_tunnel(ui_tx_tunnel, cloudflared_pid);
+        let cloudflared_pid_tunnel = cloudflared_pid.clone();
+        let mut tunnel_handle = tokio::task::spawn_blocking(move || {
+            run_cloudflared_tunnel(ui_tx_tunnel, cloudflared_pid_tunnel);
         });
 
         // Forward PTY events to relay (output -> browser)
         let ui_tx_pty = ui_tx.clone();
-        let pty_event_handle = tokio::spawn(async move {
-            while let Some(event) = pty_event_rx.recv().await {
+        // At shutdown, events already queued are still forwarded, then it stops
+        let (pty_event_stop_tx, mut pty_event_stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let mut pty_event_handle = tokio::spawn(async move {
+            let clipboard_bridge = clipboard::bridge_enabled();
+            // Local keystrokes include passwords the shell never echoes, so
+            // they only reach browsers when explicitly enabled
+            let forward_local_input = matches!(
+                std::env::var("FORWARD_LOCAL_INPUT").as_deref(),
+                Ok("1") | Ok("true")
+            );
+            let mut osc52_scanners: std::collections::HashMap<String, Osc52Scanner> =
+                std::collections::HashMap::new();
+            loop {
+                let event = tokio::select! {
+                    biased;
+                    event = pty_event_rx.recv() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                    _ = &mut pty_event_stop_rx => break,
+                };
                 match event {
                     PtyEvent::Attached { session_id, session_name } => {
                         info!("pty-proxy session connected: {} ({})", session_name, session_id);
-                        // Update session list
+                        // Update session list (a reconcile may have added it already)
                         {
-                            let mut list = session_list_for_pty.lock().unwrap();
+                            let mut list = lock_sessions(&session_list_for_pty);
+                            if list.iter().any(|(id, _)| id == &session_id) {
+                                continue;
+                            }
                             list.push((session_id.clone(), session_name.clone()));
                         }
                         // Notify relay to send to browser
@@ -625,25 +922,46 @@ fn run_background_tasks(
                     }
                     PtyEvent::Detached { session_id } => {
                         info!("pty-proxy session disconnected: {}", session_id);
-                        // Update session list
+                        // Update session list (a reconcile may have removed it already)
                         {
-                            let mut list = session_list_for_pty.lock().unwrap();
+                            let mut list = lock_sessions(&session_list_for_pty);
+                            let before = list.len();
                             list.retain(|(id, _)| id != &session_id);
+                            if list.len() == before {
+                                continue;
+                            }
                         }
                         // Notify relay to send to browser
                         let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionDisconnected {
                             session_id: session_id.clone(),
                         });
+                        osc52_scanners.remove(&session_id);
                         // Notify UI
                         let _ = ui_tx_pty.send(UiEvent::ShellDisconnected { session_id });
                     }
                     PtyEvent::Output { session_id, data } => {
+                        // OSC 52 clipboard writes from the session set the Mac clipboard
+                        if clipboard_bridge {
+                            let scanner = osc52_scanners.entry(session_id.clone()).or_default();
+                            for text in scanner.feed(&data) {
+                                match arboard::Clipboard::new().and_then(|mut c| c.set_text(text)) {
+                                    Ok(()) => info!(session_id = %session_id, "Clipboard set via OSC 52"),
+                                    Err(e) => warn!("Failed to set clipboard: {}", e),
+                                }
+                            }
+                        }
                         // Forward pty output to relay for browser
+                        byte_counters_for_pty.add_up(data.len());
                         let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendTerminalData {
                             session_id,
                             data,
                         });
                     }
+                    PtyEvent::Input { session_id, data } => {
+                        if forward_local_input {
+                            let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendLocalInput { session_id, data });
+                        }
+                    }
                     PtyEvent::SessionResize { session_id, cols, rows } => {
                         // Forward mac terminal resize to browser (one-way: mac -> UI)
                         let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionResize {
@@ -652,6 +970,52 @@ fn run_background_tasks(
                             rows,
                         });
                     }
+                    PtyEvent::Encoding { session_id, encoding } => {
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionEncoding { session_id, encoding });
+                    }
+                    PtyEvent::Cwd { session_id, path } => {
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendCwd { session_id, path });
+                    }
+                    PtyEvent::SessionList { sessions } => {
+                        let (added, removed) =
+                            pty::reconcile_sessions(&mut lock_sessions(&session_list_for_pty), sessions);
+                        for (session_id, name) in added {
+                            warn!("Session list was missing {} ({}), adding it", name, session_id);
+                            let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionConnected {
+                                session_id: session_id.clone(),
+                                name: name.clone(),
+                            });
+                            let _ = ui_tx_pty.send(UiEvent::ShellConnected { session_id, name });
+                        }
+                        for session_id in removed {
+                            warn!("Session list had stale {}, removing it", session_id);
+                            let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionDisconnected {
+                                session_id: session_id.clone(),
+                            });
+                            osc52_scanners.remove(&session_id);
+                            let _ = ui_tx_pty.send(UiEvent::ShellDisconnected { session_id });
+                        }
+                    }
+                    PtyEvent::State { session_id, browser_id, state } => {
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendState { session_id, browser_id, state });
+                    }
+                    PtyEvent::UnknownSession { session_id } => {
+                        // Browser is typing into a session that's gone - tell it,
+                        // and drop the session so it stops sending input there
+                        warn!("Input for unknown session: {}", session_id);
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendNotice {
+                            session_id: Some(session_id.clone()),
+                            message: "Session ended".into(),
+                        });
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionDisconnected { session_id });
+                    }
+                    PtyEvent::SpawnFailed(reason) => {
+                        error!("Could not start a shell for a browser: {}", reason);
+                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendNotice {
+                            session_id: None,
+                            message: "Could not open a new terminal".into(),
+                        });
+                    }
                     PtyEvent::Error(msg) => {
                         error!("PTY error: {}", msg);
                     }
@@ -659,20 +1023,130 @@ fn run_background_tasks(
             }
         });
 
+        // Periodically report session count and load to the relay
+        let relay_cmd_tx_for_heartbeat = relay_cmd_tx.clone();
+        let session_list_for_heartbeat = session_list.clone();
+        let heartbeat_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let sessions = lock_sessions(&session_list_for_heartbeat).len();
+                let _ = relay_cmd_tx_for_heartbeat.send(RelayCommand::SendHeartbeat {
+                    sessions,
+                    load: load_average(),
+                });
+            }
+        });
+
+        // Keep the relay's label in step with the Mac's name
+        let relay_cmd_tx_for_label = relay_cmd_tx.clone();
+        let label_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(LABEL_CHECK_INTERVAL);
+            let mut current: Option<String> = None;
+            loop {
+                interval.tick().await;
+                let Ok(label) = tokio::task::spawn_blocking(label::current_label).await else {
+                    continue;
+                };
+                if label != current {
+                    info!("Session label: {}", label.as_deref().unwrap_or("(none)"));
+                    current.clone_from(&label);
+                    let _ = relay_cmd_tx_for_label.send(RelayCommand::SetLabel { label });
+                }
+            }
+        });
+
+        // Correct the session list if an Attached/Detached event went missing
+        let reconcile_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_RECONCILE_INTERVAL);
+            // The first tick is immediate; nothing can have drifted yet
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let _ = pty_cmd_tx_for_reconcile.send(PtyCommand::QuerySessions);
+            }
+        });
+
+        // Reconnect as soon as the network changes instead of waiting for the
+        // broken connection to time out
+        let relay_cmd_tx_for_network = relay_cmd_tx.clone();
+        let network_enabled = network::reconnect_enabled();
+        let network_handle = tokio::spawn(async move {
+            if !network_enabled {
+                return;
+            }
+            let mut interval = tokio::time::interval(NETWORK_CHECK_INTERVAL);
+            let mut current = network::fingerprint();
+            loop {
+                interval.tick().await;
+                let fingerprint = network::fingerprint();
+                if fingerprint != current {
+                    info!("Network changed, reconnecting to relay");
+                    debug!("Network: {:?}", fingerprint);
+                    current = fingerprint;
+                    let _ = relay_cmd_tx_for_network.send(RelayCommand::Reconnect);
+                }
+            }
+        });
+
+        // Periodically log reconnect counts and refresh the diagnostics entry
+        let reconnect_stats = relay.reconnect_stats();
+        let ui_tx_stats = ui_tx.clone();
+        let stats_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RECONNECT_SUMMARY_INTERVAL);
+            loop {
+                interval.tick().await;
+                let (summary, recent) = {
+                    let stats = reconnect_stats.lock().unwrap();
+                    let now = Instant::now();
+                    (stats.summary(now), stats.in_last_hour(now))
+                };
+                if recent > 0 {
+                    info!("Relay link: {}", summary);
+                } else {
+                    debug!("Relay link: {}", summary);
+                }
+                let _ = ui_tx_stats.send(UiEvent::Reconnects(summary));
+            }
+        });
+
+        // Refresh the throughput entry from the bytes relayed each second
+        let ui_tx_throughput = ui_tx.clone();
+        let throughput_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(THROUGHPUT_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut average = MovingAverage::default();
+            let mut shown = (0, 0);
+            loop {
+                interval.tick().await;
+                let (up, down) = byte_counters.take();
+                let rates = average.push(up, down);
+                // The menu only needs an update when the reading changes
+                if rates != shown {
+                    shown = rates;
+                    let _ = ui_tx_throughput.send(UiEvent::Throughput {
+                        up_bytes_per_sec: rates.0,
+                        down_bytes_per_sec: rates.1,
+                    });
+                }
+            }
+        });
+
         // Spawn relay client task
-        let relay_handle = tokio::spawn(async move {
+        let mut relay_handle = tokio::spawn(async move {
             relay.run().await;
         });
 
         // Spawn event forwarding task
         let ui_tx_relay = ui_tx.clone();
-        let relay_forward_handle = tokio::task::spawn_blocking(move || {
+        let mut relay_forward_handle = tokio::task::spawn_blocking(move || {
             forward_relay_events(
                 relay_event_rx,
                 ui_tx_relay,
                 pty_cmd_tx_for_relay,
                 relay_cmd_tx_for_relay,
                 session_list_for_relay,
+                byte_counters_for_relay,
             );
         });
 
@@ -691,6 +1165,9 @@ fn run_background_tasks(
                     // Forward terminal data to shell via PTY manager
                     let _ = relay_cmd_tx.send(RelayCommand::SendTerminalData { session_id, data });
                 }
+                Ok(BackgroundCommand::CreateShare { ttl }) => {
+                    let _ = relay_cmd_tx.send(RelayCommand::CreateShare { ttl_secs: ttl.as_secs() });
+                }
                 Ok(BackgroundCommand::ReconnectRelay) => {
                     info!("Reconnecting relay to regenerate session code");
                     let _ = relay_cmd_tx.send(RelayCommand::Reconnect);
@@ -706,12 +1183,50 @@ fn run_background_tasks(
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
 
-        // Abort tasks (they run forever, so we need to abort them)
-        relay_handle.abort();
-        relay_forward_handle.abort();
+        // Tear down in order, so no task sends into one that's already gone.
+        // First stop taking input: menu commands and the periodic tasks.
         pty_forward_handle.abort();
-        pty_event_handle.abort();
-        tunnel_handle.abort();
+        heartbeat_handle.abort();
+        label_handle.abort();
+        reconcile_handle.abort();
+        network_handle.abort();
+        stats_handle.abort();
+        throughput_handle.abort();
+
+        // Forward the output sessions already produced...
+        let _ = pty_event_stop_tx.send(());
+        if tokio::time::timeout(SHUTDOWN_STEP_TIMEOUT, &mut pty_event_handle).await.is_err() {
+            warn!("PTY events still pending at shutdown, dropping them");
+            pty_event_handle.abort();
+        }
+
+        // ...and close the relay connection once it has been sent. The relay
+        // event forwarder stops when the relay client is gone.
+        let _ = relay_cmd_tx.send(RelayCommand::Shutdown);
+        if tokio::time::timeout(SHUTDOWN_STEP_TIMEOUT, &mut relay_handle).await.is_err() {
+            warn!("Relay connection did not close in time");
+            relay_handle.abort();
+            let _ = (&mut relay_handle).await;
+        }
+        if tokio::time::timeout(SHUTDOWN_STEP_TIMEOUT, &mut relay_forward_handle).await.is_err() {
+            warn!("Relay event forwarder did not stop in time");
+        }
+
+        // Hang up the shells the PTY manager started; it stops once done
+        let _ = pty_cmd_tx_for_shutdown.send(PtyCommand::Shutdown);
+        if tokio::time::timeout(SHUTDOWN_STEP_TIMEOUT, pty_cmd_tx_for_shutdown.closed()).await.is_err() {
+            warn!("PTY manager did not stop in time");
+        }
+
+        // Finally the tunnel: its thread ends when cloudflared does
+        let pid = cloudflared_pid.swap(0, Ordering::Relaxed);
+        if pid != 0 {
+            info!("Stopping cloudflared (pid {})", pid);
+            unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+        }
+        if tokio::time::timeout(SHUTDOWN_STEP_TIMEOUT, &mut tunnel_handle).await.is_err() {
+            warn!("cloudflared did not exit in time");
+        }
 
         info!("Background tasks shut down");
     });
@@ -719,6 +1234,106 @@ fn run_background_tasks(
     info!("Background thread exiting");
 }
 
+/// Check that the relay-server binary speaks our protocol version, using
+/// its `--version` output.
+fn check_relay_version(bin: &std::path::Path) -> Result<(), String> {
+    let mut child = Command::new(bin)
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run relay-server --version: {}", e))?;
+
+    let deadline = Instant::now() + RELAY_VERSION_TIMEOUT;
+    while child.try_wait().ok().flatten().is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err("relay-server is outdated (no --version support), reinstall it".into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut output);
+    }
+    match parse_relay_version(&output) {
+        Some(version) if version == PROTOCOL_VERSION => {
+            info!("{}", output.trim());
+            Ok(())
+        }
+        Some(version) => Err(format!(
+            "relay-server speaks protocol {} but this app needs {}, reinstall it",
+            version, PROTOCOL_VERSION
+        )),
+        None => Err(format!("unrecognized relay-server --version output: {:?}", output.trim())),
+    }
+}
+
+/// Spawn relay-server in the data directory with its output appended to
+/// relay-server.log in the log directory. Stderr is relayed through a thread
+/// so startup errors also show up in our own log and as a RelayError.
+fn spawn_relay_server(bin: &std::path::Path, ui_tx: UiSender) -> std::io::Result<Child> {
+    let not_found = || std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set");
+    let dir = paths::data_dir().ok_or_else(not_found)?;
+    let log_dir = paths::log_dir().ok_or_else(not_found)?;
+    std::fs::create_dir_all(&dir)?;
+    std::fs::create_dir_all(&log_dir)?;
+    let log_path = log_dir.join("relay-server.log");
+    let log = std::fs::OpenOptions::new().create(true).append(true).open(&log_path)?;
+    info!("relay-server log: {}", log_path.display());
+
+    let mut child = Command::new(bin)
+        .current_dir(&dir)
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(stderr) = child.stderr.take() {
+        let mut log = log;
+        let started = Instant::now();
+        thread::spawn(move || {
+            use std::io::Write;
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let _ = writeln!(log, "{}", line);
+                if started.elapsed() < RELAY_STARTUP_WINDOW {
+                    error!("relay-server: {}", line);
+                    let _ = ui_tx.send(UiEvent::RelayError(format!("relay-server: {}", line)));
+                }
+            }
+        });
+    }
+    Ok(child)
+}
+
+/// Put text on the system clipboard.
+fn copy_to_clipboard(text: &str) -> Result<(), arboard::Error> {
+    arboard::Clipboard::new()?.set_text(text.to_string())
+}
+
+/// 1-minute system load average (0.0 if unavailable).
+fn load_average() -> f32 {
+    let mut loads = [0.0f64; 3];
+    let n = unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) };
+    if n > 0 {
+        loads[0] as f32
+    } else {
+        0.0
+    }
+}
+
+/// Local wall-clock time as HH:MM:SS.
+fn local_time() -> String {
+    local_time_at(unsafe { libc::time(std::ptr::null_mut()) })
+}
+
+/// Local wall-clock time of a Unix timestamp as HH:MM:SS.
+fn local_time_at(time: libc::time_t) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
 
 /// Forward relay events to the UI channel.
 ///
@@ -727,29 +1342,76 @@ fn run_background_tasks(
 /// Also forwards terminal data from relay to PTY manager (browser -> shell).
 fn forward_relay_events(
     rx: mpsc::Receiver<RelayEvent>,
-    ui_tx: mpsc::Sender<UiEvent>,
+    ui_tx: impl UiSink,
     pty_cmd_tx: tokio::sync::mpsc::UnboundedSender<PtyCommand>,
     relay_cmd_tx: tokio::sync::mpsc::UnboundedSender<RelayCommand>,
-    session_list: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    session_list: SessionList,
+    byte_counters: Arc<ByteCounters>,
 ) {
     debug!("Relay event forwarder starting");
+    let clipboard_bridge = clipboard::bridge_enabled();
+    let confirm_paste = paste::confirm_enabled();
+    let forward_locale = locale::forwarding_enabled();
+    let terminal_windows = pty::terminal_window_sessions();
+    let mut held_pastes = PasteGuard::default();
+    // Sessions a browser locked against closing
+    let mut locked_sessions: HashSet<String> = HashSet::new();
+    let mut last_create: Option<Instant> = None;
+    let join_banner = std::env::var("JOIN_BANNER").ok().map(|v| match v.as_str() {
+        "1" | "true" => DEFAULT_JOIN_BANNER.to_string(),
+        _ => v,
+    });
     loop {
         match rx.recv() {
             Ok(event) => {
                 let ui_event = match event {
-                    RelayEvent::Connected => UiEvent::RelayConnected,
+                    RelayEvent::Connected => {
+                        // PTY sessions outlive the relay connection (e.g. after
+                        // "Regenerate Code"); re-advertise them on every connect
+                        // rather than waiting for a browser to join. Queued
+                        // commands are only sent after Register, so this can't
+                        // reach the relay before the new session code exists.
+                        let sessions = lock_sessions(&session_list).clone();
+                        info!("Relay connected, re-advertising {} sessions", sessions.len());
+                        let _ = relay_cmd_tx.send(RelayCommand::SendSessionList { sessions });
+                        let _ = relay_cmd_tx.send(RelayCommand::ListBrowsers);
+                        UiEvent::RelayConnected
+                    }
                     RelayEvent::Disconnected => UiEvent::RelayDisconnected,
                     RelayEvent::SessionCode(code) => UiEvent::SessionCode(code),
                     RelayEvent::BrowserConnected(id) => {
                         // Send session list to newly connected browser
-                        let sessions = session_list.lock().unwrap().clone();
+                        let sessions = lock_sessions(&session_list).clone();
                         info!("Browser connected, sending {} sessions", sessions.len());
                         let _ = relay_cmd_tx.send(RelayCommand::SendSessionList { sessions });
+                        for session_id in &locked_sessions {
+                            let _ = relay_cmd_tx.send(RelayCommand::SendSessionLock {
+                                session_id: session_id.clone(),
+                                locked: true,
+                            });
+                        }
+                        // Leave a record of the join in every shell's scrollback
+                        if let Some(template) = &join_banner {
+                            let text = format!("\r\n{}\r\n", template.replace("{time}", &local_time()));
+                            let _ = pty_cmd_tx.send(PtyCommand::Print { data: text.into_bytes() });
+                        }
                         UiEvent::BrowserConnected(id)
                     }
                     RelayEvent::BrowserDisconnected(id) => UiEvent::BrowserDisconnected(id),
                     RelayEvent::Error(msg) => UiEvent::RelayError(msg),
+                    RelayEvent::ShareCreated { code, .. } => UiEvent::ShareCode(code),
+                    RelayEvent::BrowserList(browsers) => UiEvent::Viewers(browsers),
                     RelayEvent::TerminalData { session_id, data } => {
+                        byte_counters.add_down(data.len());
+                        // Multiline input runs each line as a command; hold it
+                        // until a browser confirms
+                        if confirm_paste && paste::is_multiline(&data) {
+                            let preview = paste::preview(&data);
+                            let token = held_pastes.hold(&session_id, data, Instant::now());
+                            info!("Holding multiline input for session {} until confirmed", session_id);
+                            let _ = relay_cmd_tx.send(RelayCommand::SendConfirmPaste { session_id, token, preview });
+                            continue;
+                        }
                         // Forward to PTY manager (browser -> shell)
                         let _ = pty_cmd_tx.send(PtyCommand::Write {
                             session_id: session_id.clone(),
@@ -757,7 +1419,16 @@ fn forward_relay_events(
                         });
                         UiEvent::TerminalDataFromRelay { session_id, data }
                     }
-                    RelayEvent::CloseSession { session_id } => {
+                    RelayEvent::CloseSession { session_id, force } => {
+                        if locked_sessions.contains(&session_id) && !force {
+                            info!("Refusing to close locked session: {}", session_id);
+                            let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                session_id: Some(session_id),
+                                message: "Session is locked; unlock it or force close".into(),
+                            });
+                            continue;
+                        }
+                        locked_sessions.remove(&session_id);
                         // Kill the pty-proxy session
                         info!("Closing session: {}", session_id);
                         let _ = pty_cmd_tx.send(PtyCommand::KillSession {
@@ -766,12 +1437,135 @@ fn forward_relay_events(
                         // No UI event - session will emit Detached event
                         continue;
                     }
-                    RelayEvent::CreateSession => {
-                        info!("Creating new terminal session");
-                        match std::process::Command::new("osascript")
+                    RelayEvent::LockSession { session_id, locked } => {
+                        // Forget locks on sessions that have since ended
+                        let sessions = lock_sessions(&session_list).clone();
+                        locked_sessions.retain(|id| sessions.iter().any(|(s, _)| s == id));
+                        if !sessions.iter().any(|(id, _)| *id == session_id) {
+                            warn!("Lock request for unknown session: {}", session_id);
+                            continue;
+                        }
+                        if locked {
+                            locked_sessions.insert(session_id.clone());
+                        } else {
+                            locked_sessions.remove(&session_id);
+                        }
+                        info!("Session {} {}", session_id, if locked { "locked" } else { "unlocked" });
+                        let _ = relay_cmd_tx.send(RelayCommand::SendSessionLock { session_id, locked });
+                        continue;
+                    }
+                    RelayEvent::CreateSession { term, backend, host, user, capabilities, profile } => {
+                        if last_create.is_some_and(|t| t.elapsed() < CREATE_SESSION_INTERVAL) {
+                            warn!("Create session request throttled");
+                            let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                session_id: None,
+                                message: "Too many new sessions, try again in a moment".into(),
+                            });
+                            continue;
+                        }
+                        last_create = Some(Instant::now());
+                        let ssh = match backend.as_deref() {
+                            None | Some("pty") => None,
+                            Some("ssh") => match SshTarget::new(user.as_deref(), host.as_deref().unwrap_or_default()) {
+                                Ok(target) => Some(target),
+                                Err(reason) => {
+                                    warn!("Rejected SSH session request: {}", reason);
+                                    let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                        session_id: None,
+                                        message: reason,
+                                    });
+                                    continue;
+                                }
+                            },
+                            Some(other) => {
+                                warn!("Unsupported session backend requested: {}", other);
+                                let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                    session_id: None,
+                                    message: format!("Unsupported session type {}", other),
+                                });
+                                continue;
+                            }
+                        };
+                        let profile = match profile {
+                            Some(name) => match profiles::find(&profiles::load(), &name) {
+                                Some(profile) => Some(profile),
+                                None => {
+                                    warn!("Unknown profile requested: {}", name);
+                                    let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                        session_id: None,
+                                        message: format!("Unknown profile {}", name),
+                                    });
+                                    continue;
+                                }
+                            },
+                            None => None,
+                        };
+                        // Only allowlisted names, validated SSH targets and
+                        // profile commands from the Mac's own config reach the
+                        // new shell's environment and command line
+                        let mut env = Vec::new();
+                        let mut commands = Vec::new();
+                        match term.as_deref() {
+                            Some(term) if ALLOWED_TERMS.contains(&term) => {
+                                info!("New terminal session will use TERM={}", term);
+                                env.push(("TERM".to_string(), term.to_string()));
+                            }
+                            Some(term) => {
+                                warn!("Unsupported TERM requested: {}", term);
+                                let _ = relay_cmd_tx.send(RelayCommand::SendNotice {
+                                    session_id: None,
+                                    message: format!("Unsupported TERM {}, using the default", term),
+                                });
+                            }
+                            None => {}
+                        }
+                        if let Some(capabilities) = &capabilities {
+                            info!(
+                                "Requesting browser: {}x{}, truecolor {}, unicode {:?}",
+                                capabilities.cols, capabilities.rows, capabilities.truecolor, capabilities.unicode_version
+                            );
+                            if capabilities.truecolor {
+                                env.push(("COLORTERM".to_string(), "truecolor".to_string()));
+                            }
+                            if forward_locale {
+                                let locale_env = locale::env(
+                                    capabilities.locale.as_deref(),
+                                    capabilities.timezone.as_deref(),
+                                );
+                                info!(
+                                    "Browser locale {:?}, timezone {:?}: {:?}",
+                                    capabilities.locale, capabilities.timezone, locale_env
+                                );
+                                env.extend(locale_env);
+                            }
+                        }
+                        if let Some(profile) = &profile {
+                            info!("New terminal session uses profile {}", profile.name);
+                            commands.extend(profile.command.clone());
+                        }
+                        match &ssh {
+                            Some(target) => {
+                                info!("Creating SSH session to {}", target.destination());
+                                commands.push(target.command());
+                            }
+                            None => info!("Creating new terminal session"),
+                        }
+                        let size = capabilities.as_ref().map(|c| (c.cols, c.rows));
+                        if !terminal_windows {
+                            // Attached (or SpawnFailed) comes back from the PTY manager
+                            let _ = pty_cmd_tx.send(PtyCommand::SpawnShell { env, commands, size });
+                            continue;
+                        }
+                        // A Terminal window's shell can only be given them as exports
+                        let commands: Vec<String> = env
+                            .iter()
+                            .map(|(name, value)| format!("export {}={}", name, value))
+                            .chain(commands)
+                            .collect();
+                        let script = pty::new_window_script(&commands, size);
+                        let created = match std::process::Command::new("osascript")
                             .arg("-e")
-                            .arg(r#"tell application "Terminal" to do script ""
-"#)
+                            .arg(script)
                             .output()
                         {
                             Ok(output) => {
@@ -784,11 +1578,77 @@ fn forward_relay_events(
                                         String::from_utf8_lossy(&output.stderr)
                                     );
                                 }
+                                output.status.success()
                             }
                             Err(e) => {
                                 error!("Failed to run osascript for create: {}", e);
+                                false
+                            }
+                        };
+                        if !created {
+                            let message = match &ssh {
+                                Some(target) => format!("Could not open an SSH session to {}", target.destination()),
+                                None => "Could not open a new terminal".to_string(),
+                            };
+                            let _ = relay_cmd_tx.send(RelayCommand::SendNotice { session_id: None, message });
+                        }
+                        continue;
+                    }
+                    RelayEvent::Resize { session_id, cols, rows } => {
+                        debug!("Browser resized session {} to {}x{}", session_id, cols, rows);
+                        let _ = pty_cmd_tx.send(PtyCommand::Resize { session_id, cols, rows });
+                        continue;
+                    }
+                    RelayEvent::SetEncoding { session_id, encoding } => {
+                        let _ = pty_cmd_tx.send(PtyCommand::SetEncoding { session_id, encoding });
+                        continue;
+                    }
+                    RelayEvent::GetCwd { session_id } => {
+                        let _ = pty_cmd_tx.send(PtyCommand::GetCwd { session_id });
+                        continue;
+                    }
+                    RelayEvent::AutoFit { session_id } => {
+                        let _ = pty_cmd_tx.send(PtyCommand::AutoFit { session_id });
+                        continue;
+                    }
+                    RelayEvent::ListProfiles { browser_id } => {
+                        let profiles = profiles::load().iter().map(profiles::Profile::info).collect();
+                        let _ = relay_cmd_tx.send(RelayCommand::SendProfileList { browser_id, profiles });
+                        continue;
+                    }
+                    RelayEvent::GetState { session_id, browser_id } => {
+                        let _ = pty_cmd_tx.send(PtyCommand::GetState { session_id, browser_id });
+                        continue;
+                    }
+                    RelayEvent::ConfirmPasteAck { token, accept } => {
+                        match held_pastes.take(&token, Instant::now()) {
+                            Some((session_id, data)) if accept => {
+                                info!("Multiline input confirmed for session {}", session_id);
+                                let _ = pty_cmd_tx.send(PtyCommand::Write {
+                                    session_id: session_id.clone(),
+                                    data: data.clone(),
+                                });
+                                UiEvent::TerminalDataFromRelay { session_id, data }
+                            }
+                            Some((session_id, _)) => {
+                                info!("Multiline input declined for session {}", session_id);
+                                continue;
+                            }
+                            None => {
+                                // Already answered by another browser, or expired
+                                debug!("No held paste for confirmation token");
+                                continue;
                             }
                         }
+                    }
+                    RelayEvent::Clipboard { session_id, data } => {
+                        if !clipboard_bridge {
+                            debug!("Clipboard bridge disabled, ignoring browser clipboard");
+                        } else if let Some(seq) = clipboard::osc52_response(&data) {
+                            let _ = pty_cmd_tx.send(PtyCommand::Write { session_id, data: seq });
+                        } else {
+                            warn!("Ignoring clipboard with invalid base64 for session {}", session_id);
+                        }
                         continue;
                     }
                 };
@@ -824,7 +1684,7 @@ fn find_cloudflared() -> String {
     "cloudflared".to_string()
 }
 
-fn run_cloudflared_tunnel(ui_tx: mpsc::Sender<UiEvent>, pid_store: Arc<AtomicU32>) -> Option<Child> {
+fn run_cloudflared_tunnel(ui_tx: UiSender, pid_store: Arc<AtomicU32>) -> Option<Child> {
     let cloudflared = find_cloudflared();
     info!("Using cloudflared at: {}", cloudflared);
 
@@ -887,3 +1747,30 @@ fn extract_tunnel_url(line: &str) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_resize_reaches_pty_manager() {
+        let (relay_tx, relay_rx) = mpsc::channel();
+        let (ui_tx, ui_rx) = mpsc::channel();
+        let (pty_cmd_tx, mut pty_cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (relay_cmd_tx, _relay_cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        relay_tx.send(RelayEvent::Resize { session_id: "s1".into(), cols: 120, rows: 40 }).unwrap();
+        drop(relay_tx);
+        forward_relay_events(relay_rx, ui_tx, pty_cmd_tx, relay_cmd_tx, SessionList::default(), Arc::default());
+
+        match pty_cmd_rx.try_recv() {
+            Ok(PtyCommand::Resize { session_id, cols, rows }) => {
+                assert_eq!((session_id.as_str(), cols, rows), ("s1", 120, 40));
+            }
+            other => panic!("expected Resize, got {:?}", other),
+        }
+        assert!(pty_cmd_rx.try_recv().is_err());
+        // Resizes are the PTY manager's business only
+        assert!(ui_rx.try_recv().is_err());
+    }
+}
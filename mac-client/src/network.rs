@@ -0,0 +1,96 @@
+//! Network change detection.
+//!
+//! Switching Wi-Fi networks or connecting a VPN often breaks the relay
+//! WebSocket without a FIN, so the connection would only be noticed dead at
+//! the next timeout. Instead the interface addresses are polled and the relay
+//! client is told to reconnect as soon as they change. On by default;
+//! `RECONNECT_ON_NETWORK_CHANGE=0` turns it off.
+
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Whether network changes trigger a reconnect (`RECONNECT_ON_NETWORK_CHANGE`).
+pub fn reconnect_enabled() -> bool {
+    !matches!(
+        std::env::var("RECONNECT_ON_NETWORK_CHANGE").as_deref(),
+        Ok("0") | Ok("false")
+    )
+}
+
+/// Summary of the active network configuration; it differs whenever the
+/// Mac has moved networks. Empty if the interfaces can't be read.
+pub fn fingerprint() -> Vec<String> {
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Vec::new();
+    }
+
+    let mut addresses = Vec::new();
+    let mut cursor = ifap;
+    while !cursor.is_null() {
+        let ifa = unsafe { &*cursor };
+        cursor = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_flags & libc::IFF_UP as u32 == 0 {
+            continue;
+        }
+        let address = match unsafe { (*ifa.ifa_addr).sa_family } as i32 {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+        addresses.push((name, address));
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+
+    summarize(addresses)
+}
+
+/// Sorted `interface address` entries for IPv4, and bare interface names
+/// for IPv6: temporary IPv6 addresses rotate on their own, which shouldn't
+/// count as a network change. Loopback is left out.
+fn summarize(addresses: Vec<(String, IpAddr)>) -> Vec<String> {
+    let mut summary: Vec<String> = addresses
+        .into_iter()
+        .filter(|(_, address)| !address.is_loopback())
+        .map(|(name, address)| match address {
+            IpAddr::V4(v4) => format!("{} {}", name, v4),
+            IpAddr::V6(_) => name,
+        })
+        .collect();
+    summary.sort();
+    summary.dedup();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let home = vec![
+            ("lo0".to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ("en0".to_string(), "192.168.1.20".parse().unwrap()),
+            ("en0".to_string(), "2001:db8::1".parse().unwrap()),
+            ("en0".to_string(), "2001:db8::2".parse().unwrap()),
+        ];
+        assert_eq!(summarize(home.clone()), ["en0", "en0 192.168.1.20"]);
+
+        // A rotated temporary IPv6 address is not a change
+        let mut rotated = home.clone();
+        rotated[3].1 = "2001:db8::3".parse().unwrap();
+        assert_eq!(summarize(rotated), summarize(home.clone()));
+
+        // Joining a VPN adds its interface
+        let mut vpn = home;
+        vpn.push(("utun3".to_string(), "fd00::5".parse().unwrap()));
+        assert_eq!(summarize(vpn), ["en0", "en0 192.168.1.20", "utun3"]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Confirmation of multiline browser input.
+//!
+//! A paste containing a newline runs every line as a command the moment it
+//! reaches the shell. When confirmation is enabled, such input is held back
+//! and browsers are sent a `ConfirmPaste` with a preview; the bytes are only
+//! written once a browser answers with a matching `ConfirmPasteAck`.
+//!
+//! Off by default: it changes how pastes behave for every browser.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Held pastes are dropped if not confirmed within this long
+pub const PASTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Most pastes held at once; the oldest is dropped to make room
+const MAX_PENDING: usize = 16;
+
+/// Longest preview sent to browsers, in characters
+const PREVIEW_CHARS: usize = 200;
+
+/// Whether multiline input needs confirmation (`CONFIRM_PASTE=1`).
+pub fn confirm_enabled() -> bool {
+    matches!(
+        std::env::var("CONFIRM_PASTE").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// Whether browser input spans more than one line. Newlines at the end
+/// don't count: a line typed or pasted with its Enter (`cmd\r`) runs a
+/// single command.
+pub fn is_multiline(data: &[u8]) -> bool {
+    let is_newline = |b: &u8| *b == b'\n' || *b == b'\r';
+    let end = data.iter().rposition(|b| !is_newline(b)).map_or(0, |last| last + 1);
+    data[..end].iter().any(is_newline)
+}
+
+/// Printable preview of held input, truncated to [`PREVIEW_CHARS`].
+pub fn preview(data: &[u8]) -> String {
+    let text = String::from_utf8_lossy(data);
+    let mut preview: String = text
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n')
+        .take(PREVIEW_CHARS)
+        .collect();
+    if text.chars().count() > PREVIEW_CHARS {
+        preview.push('…');
+    }
+    preview
+}
+
+#[derive(Debug)]
+struct PendingPaste {
+    session_id: String,
+    data: Vec<u8>,
+    held_at: Instant,
+}
+
+/// Multiline input waiting for a browser to confirm it, keyed by token.
+#[derive(Debug, Default)]
+pub struct PasteGuard {
+    pending: HashMap<String, PendingPaste>,
+}
+
+impl PasteGuard {
+    /// Hold `data` for `session_id`; returns the token a browser must ack.
+    pub fn hold(&mut self, session_id: &str, data: Vec<u8>, now: Instant) -> String {
+        self.expire(now);
+        if self.pending.len() >= MAX_PENDING {
+            if let Some(oldest) = self
+                .pending
+                .iter()
+                .min_by_key(|(_, p)| p.held_at)
+                .map(|(token, _)| token.clone())
+            {
+                tracing::debug!("Too many pastes awaiting confirmation, dropping oldest");
+                self.pending.remove(&oldest);
+            }
+        }
+
+        let token = uuid::Uuid::new_v4().to_string();
+        self.pending.insert(
+            token.clone(),
+            PendingPaste {
+                session_id: session_id.to_string(),
+                data,
+                held_at: now,
+            },
+        );
+        token
+    }
+
+    /// Take the held paste for `token` as (session id, bytes), if it exists
+    /// and hasn't expired. Each token can be used once.
+    pub fn take(&mut self, token: &str, now: Instant) -> Option<(String, Vec<u8>)> {
+        self.expire(now);
+        self.pending
+            .remove(token)
+            .map(|paste| (paste.session_id, paste.data))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.pending
+            .retain(|_, paste| now.saturating_duration_since(paste.held_at) < PASTE_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_multiline() {
+        assert!(is_multiline(b"ls\nrm -rf /tmp/x\n"));
+        assert!(is_multiline(b"ls\r\npwd"));
+        assert!(!is_multiline(b"echo hi\r"));
+        assert!(!is_multiline(b"echo hi\r\n"));
+        assert!(!is_multiline(b"\r"));
+        assert!(!is_multiline(b"ls"));
+        assert!(!is_multiline(b"\x1b[A"));
+    }
+
+    #[test]
+    fn test_held_paste_needs_matching_token() {
+        let mut guard = PasteGuard::default();
+        let now = Instant::now();
+        let token = guard.hold("s1", b"ls\npwd\n".to_vec(), now);
+
+        assert_eq!(guard.take("bogus", now), None);
+        assert_eq!(guard.take(&token, now), Some(("s1".to_string(), b"ls\npwd\n".to_vec())));
+        // Single use
+        assert_eq!(guard.take(&token, now), None);
+    }
+
+    #[test]
+    fn test_held_paste_expires() {
+        let mut guard = PasteGuard::default();
+        let now = Instant::now();
+        let token = guard.hold("s1", b"ls\npwd\n".to_vec(), now);
+        assert_eq!(guard.take(&token, now + PASTE_TIMEOUT), None);
+    }
+
+    #[test]
+    fn test_preview_strips_control_characters() {
+        assert_eq!(preview(b"ls\r\n\x1b[2Jpwd"), "ls\n[2Jpwd");
+        let long = vec![b'x'; PREVIEW_CHARS + 1];
+        assert_eq!(preview(&long).chars().count(), PREVIEW_CHARS + 1);
+        assert!(preview(&long).ends_with('…'));
+    }
+}
@@ -0,0 +1,147 @@
+//! Where the app keeps its files.
+//!
+//! Everything lives under `~/.terminal-remote` by default, where the install
+//! scripts also put `bin/`. `TERMINAL_REMOTE_HOME` relocates all of it (for
+//! tests or a second profile); otherwise `XDG_CONFIG_HOME` and
+//! `XDG_STATE_HOME` move config and logs/state respectively.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Directory name under the XDG base directories (dot-prefixed under HOME)
+const APP_DIR: &str = "terminal-remote";
+
+/// Which kind of file a directory holds, for picking its XDG override
+#[derive(Clone, Copy)]
+enum Base {
+    Data,
+    Config,
+    State,
+}
+
+/// Data directory: the relay-server's working directory, holding `bin/`.
+pub fn data_dir() -> Option<PathBuf> {
+    base_dir(Base::Data, env_var)
+}
+
+/// Installed binaries (relay-server).
+pub fn bin_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("bin"))
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir(Base::Config, env_var)
+}
+
+/// Logs of the processes the app spawns.
+pub fn log_dir() -> Option<PathBuf> {
+    base_dir(Base::State, env_var)
+}
+
+/// State persisted across restarts.
+pub fn state_file() -> Option<PathBuf> {
+    Some(base_dir(Base::State, env_var)?.join("state.json"))
+}
+
+/// Socket pty-proxy connects to, private to this user: `terminal-remote.sock`
+/// in `XDG_RUNTIME_DIR`, else `~/.terminal-remote/ipc.sock`. pty-proxy and the
+/// shell integration resolve it the same way, so `TERMINAL_REMOTE_HOME`
+/// doesn't move it.
+pub fn socket_path() -> PathBuf {
+    socket_path_from(env_var)
+}
+
+fn env_var(name: &str) -> Option<OsString> {
+    std::env::var_os(name)
+}
+
+/// Resolve a base directory, looking variables up through `var`. None only
+/// if HOME is needed and unset.
+fn base_dir(base: Base, var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let var = |name: &str| var(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if let Some(home) = var("TERMINAL_REMOTE_HOME") {
+        return Some(home);
+    }
+    let xdg = match base {
+        Base::Data => None,
+        Base::Config => Some("XDG_CONFIG_HOME"),
+        Base::State => Some("XDG_STATE_HOME"),
+    };
+    // The XDG spec says relative paths are invalid and must be ignored
+    if let Some(dir) = xdg.and_then(var).filter(|dir| dir.is_absolute()) {
+        return Some(dir.join(APP_DIR));
+    }
+    Some(var("HOME")?.join(format!(".{}", APP_DIR)))
+}
+
+fn socket_path_from(var: impl Fn(&str) -> Option<OsString>) -> PathBuf {
+    let var = |name: &str| var(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if let Some(dir) = var("XDG_RUNTIME_DIR").filter(|dir| dir.is_absolute()) {
+        return dir.join(format!("{}.sock", APP_DIR));
+    }
+    match var("HOME") {
+        Some(home) => home.join(format!(".{}", APP_DIR)).join("ipc.sock"),
+        None => PathBuf::from(format!("/tmp/{}-{}.sock", APP_DIR, unsafe { libc::getuid() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| OsString::from(value))
+        }
+    }
+
+    #[test]
+    fn test_default_is_dot_dir_in_home() {
+        let vars = [("HOME", "/Users/me")];
+        for base in [Base::Data, Base::Config, Base::State] {
+            assert_eq!(base_dir(base, env(&vars)), Some(PathBuf::from("/Users/me/.terminal-remote")));
+        }
+        assert_eq!(base_dir(Base::Data, env(&[])), None);
+    }
+
+    #[test]
+    fn test_xdg_overrides() {
+        let vars = [
+            ("HOME", "/Users/me"),
+            ("XDG_CONFIG_HOME", "/Users/me/.config"),
+            ("XDG_STATE_HOME", "relative/state"),
+        ];
+        assert_eq!(
+            base_dir(Base::Config, env(&vars)),
+            Some(PathBuf::from("/Users/me/.config/terminal-remote"))
+        );
+        assert_eq!(base_dir(Base::State, env(&vars)), Some(PathBuf::from("/Users/me/.terminal-remote")));
+        assert_eq!(base_dir(Base::Data, env(&vars)), Some(PathBuf::from("/Users/me/.terminal-remote")));
+    }
+
+    #[test]
+    fn test_socket_path() {
+        let vars = [("HOME", "/Users/me"), ("TERMINAL_REMOTE_HOME", "/tmp/profile2")];
+        assert_eq!(socket_path_from(env(&vars)), PathBuf::from("/Users/me/.terminal-remote/ipc.sock"));
+
+        let vars = [("HOME", "/Users/me"), ("XDG_RUNTIME_DIR", "/run/user/501")];
+        assert_eq!(socket_path_from(env(&vars)), PathBuf::from("/run/user/501/terminal-remote.sock"));
+
+        let vars = [("HOME", "/Users/me"), ("XDG_RUNTIME_DIR", "relative")];
+        assert_eq!(socket_path_from(env(&vars)), PathBuf::from("/Users/me/.terminal-remote/ipc.sock"));
+    }
+
+    #[test]
+    fn test_terminal_remote_home_relocates_everything() {
+        let vars = [
+            ("HOME", "/Users/me"),
+            ("XDG_CONFIG_HOME", "/Users/me/.config"),
+            ("TERMINAL_REMOTE_HOME", "/tmp/profile2"),
+        ];
+        for base in [Base::Data, Base::Config, Base::State] {
+            assert_eq!(base_dir(base, env(&vars)), Some(PathBuf::from("/tmp/profile2")));
+        }
+    }
+}
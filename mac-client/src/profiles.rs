@@ -0,0 +1,133 @@
+//! Session profiles: preset environments browsers can start sessions in.
+//!
+//! Profiles live in `profiles.json` in the config directory, e.g.
+//!
+//! ```json
+//! [{"name": "Data science", "description": "Python 3.12 with pandas",
+//!   "icon": "python", "command": "source ~/venvs/ds/bin/activate"}]
+//! ```
+//!
+//! Browsers get the name, description and icon hint (ProfileList) and pick
+//! one by name in CreateSession. The `command` runs in the new shell first;
+//! it only ever comes from this file, never from a browser. The file is read
+//! on each request, so edits apply without a restart.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::paths;
+use crate::protocol::ProfileInfo;
+
+/// File name under the config directory
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Profiles beyond this many are ignored
+const MAX_PROFILES: usize = 64;
+
+/// A profile as configured on the Mac.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Run in the new shell once it starts
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+impl Profile {
+    /// What browsers are shown: everything but the command.
+    pub fn info(&self) -> ProfileInfo {
+        ProfileInfo {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            icon: self.icon.clone(),
+        }
+    }
+}
+
+/// The configured profiles; empty if there is no profiles file or it is
+/// invalid (logged).
+pub fn load() -> Vec<Profile> {
+    let Some(path) = paths::config_dir().map(|dir| dir.join(PROFILES_FILE)) else {
+        return Vec::new();
+    };
+    load_from(&path)
+}
+
+fn load_from(path: &Path) -> Vec<Profile> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("Cannot read {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+    match parse(&json) {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            tracing::warn!("Invalid {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// Profiles from JSON, skipping ones without a name and repeated names.
+fn parse(json: &str) -> Result<Vec<Profile>, serde_json::Error> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    for mut profile in serde_json::from_str::<Vec<Profile>>(json)? {
+        profile.name = profile.name.trim().to_string();
+        if profile.name.is_empty() || profiles.iter().any(|p| p.name == profile.name) {
+            continue;
+        }
+        profile.command = profile.command.filter(|c| !c.trim().is_empty());
+        profiles.push(profile);
+    }
+    profiles.truncate(MAX_PROFILES);
+    Ok(profiles)
+}
+
+/// The profile called `name`.
+pub fn find(profiles: &[Profile], name: &str) -> Option<Profile> {
+    profiles.iter().find(|p| p.name == name).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = r#"[
+            {"name": "Data science", "description": "Python 3.12 with pandas", "icon": "python",
+             "command": "source ~/venvs/ds/bin/activate"},
+            {"name": " Plain "},
+            {"name": "Plain", "command": "echo duplicate"},
+            {"name": "", "command": "echo unnamed"},
+            {"name": "Blank command", "command": "  "}
+        ]"#;
+        let profiles = parse(json).unwrap();
+        assert_eq!(
+            profiles.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            ["Data science", "Plain", "Blank command"]
+        );
+        assert_eq!(profiles[1].command, None);
+        assert_eq!(profiles[2].command, None);
+
+        let info = profiles[0].info();
+        assert_eq!(info.icon.as_deref(), Some("python"));
+        assert_eq!(find(&profiles, "Data science").unwrap().command.as_deref(), Some("source ~/venvs/ds/bin/activate"));
+        assert_eq!(find(&profiles, "data science"), None);
+
+        assert!(parse(r#"{"name": "not a list"}"#).is_err());
+    }
+
+    #[test]
+    fn test_missing_file_is_no_profiles() {
+        assert!(load_from(Path::new("/nonexistent/profiles.json")).is_empty());
+    }
+}
@@ -1,5 +1,22 @@
 use serde::{Deserialize, Serialize};
 
+/// Version of the relay control protocol this client speaks. Must match
+/// relay-server's PROTOCOL_VERSION.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Protocol version from `relay-server --version` output, e.g.
+/// "relay-server 0.1.0 (protocol 1)".
+pub fn parse_relay_version(output: &str) -> Option<u32> {
+    output
+        .split("(protocol ")
+        .nth(1)?
+        .split(')')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 /// Control messages sent as JSON over WebSocket Text frames.
 /// Terminal I/O is sent as Binary frames (not wrapped in ControlMessage).
 ///
@@ -8,29 +25,198 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ControlMessage {
     // Mac-client -> Relay
-    Register { client_id: String },
+    Register {
+        client_id: String,
+        /// Argon2 hash (PHC string) of a password browsers must send with Auth
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password_hash: Option<String>,
+        /// Fixed code to use if valid and free; Registered has the code given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        requested_code: Option<String>,
+        /// Can handle flagged, possibly compressed binary frames (answered
+        /// with Compression)
+        #[serde(default)]
+        compression: bool,
+    },
+    Heartbeat { sessions: usize, load: f32 },
+    /// Name browsers show for this Mac (e.g. its computer name); resent
+    /// whenever it changes. Empty clears it.
+    SetLabel { label: String },
+    /// The Mac's OS version, shell and tools (only with REPORT_ENVIRONMENT),
+    /// sent after each Register
+    Environment { environment: ClientEnvironment },
+
+    /// Relay <-> Mac-client/Browser: every binary frame the sender sends
+    /// after this starts with a compression flag byte
+    Compression,
+
+    // Mac-client -> Relay (time-boxed code for sharing the session)
+    CreateShare { ttl_secs: u64 },
+
+    // Mac-client -> Relay (replied to with BrowserList)
+    ListBrowsers,
 
     // Relay -> Mac-client
     Registered { code: String },
+    ShareCreated { code: String, ttl_secs: u64 },
+    /// Connected browsers; also sent whenever one joins or leaves
+    BrowserList { browsers: Vec<BrowserEntry> },
     BrowserConnected { browser_id: String },
     BrowserDisconnected { browser_id: String },
 
     // Browser -> Relay (not used by mac-client but included for completeness)
-    Auth { session_code: String },
+    Auth {
+        session_code: String,
+        /// Lines of scrollback to replay per terminal session (None = all)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replay_lines: Option<usize>,
+        /// Required when the mac-client registered with a password
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        /// Watch only: the relay drops this browser's input. Browsers joining
+        /// with a share code are read-only regardless
+        #[serde(default)]
+        read_only: bool,
+        /// Can handle flagged, possibly compressed binary frames (answered
+        /// with Compression)
+        #[serde(default)]
+        compression: bool,
+    },
 
     // Relay -> Browser (not used by mac-client)
     AuthSuccess,
     AuthFailed { reason: String },
+    /// The session's label, after AuthSuccess and whenever it changes
+    Label { label: String },
 
     // Browser -> Relay -> Mac-client
-    CloseSession { session_id: String },
-    CreateSession,
+    CloseSession {
+        session_id: String,
+        /// Close even if the session is locked
+        #[serde(default)]
+        force: bool,
+    },
+    // Browser -> Relay -> Mac-client, echoed back to browsers once applied
+    LockSession { session_id: String, locked: bool },
+    /// Transcode the session's output to UTF-8 from `encoding` (e.g.
+    /// "latin1"), and input back to it; None for UTF-8 passthrough
+    SetEncoding {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+    },
+    CreateSession {
+        /// TERM for the new shell (must be in the mac-client's allowlist)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        term: Option<String>,
+        /// "ssh" for a session on a remote host (default: a local shell)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        backend: Option<String>,
+        /// Remote `host[:port]` for the ssh backend
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<String>,
+        /// Remote user for the ssh backend (default: ssh's own default)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        /// The requesting browser's terminal, filled in by the relay
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capabilities: Option<TerminalCapabilities>,
+        /// Name of a profile from ProfileList to start the session with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+
+    // Browser -> Relay (not used by mac-client; arrives attached to CreateSession)
+    BrowserCapabilities {
+        #[serde(flatten)]
+        capabilities: TerminalCapabilities,
+    },
+
+    // Browser -> Relay (not used by mac-client; the relay filters output per browser)
+    Subscribe { session_ids: Vec<String> },
+    Unsubscribe { session_ids: Vec<String> },
+
+    // Browser -> Relay -> Mac-client (base64 clipboard text, delivered via OSC 52)
+    Clipboard { session_id: String, data: String },
+
+    // Browser -> Relay -> Mac-client (replied to with Cwd)
+    GetCwd { session_id: String },
+
+    // Browser -> Relay -> Mac-client (re-fit the session to its terminal;
+    // answered with SessionResize)
+    AutoFit { session_id: String },
+
+    // Browser -> Relay -> Mac-client (replied to with ProfileList; the relay fills in browser_id)
+    ListProfiles {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+    },
+
+    // Browser -> Relay -> Mac-client (replied to with State for browser_id)
+    GetState {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+    },
+
+    // Browser -> Relay -> Mac-client (run a paste held by ConfirmPaste, or drop it)
+    ConfirmPasteAck { token: String, accept: bool },
+
+    // Browser -> Relay -> Mac-client (precede this session's output with
+    // OutputTimestamp while any browser wants it; off by default)
+    EnableTimestamps { session_id: String },
+    DisableTimestamps { session_id: String },
+
+    // Browser <-> Relay scrollback search (not used by mac-client)
+    Search { session_id: String, pattern: String },
+
+    SearchResults {
+        session_id: String,
+        matches: Vec<SearchMatch>,
+        truncated: bool,
+    },
 
     // Mac-client -> Relay -> Browser (session list on connect)
     SessionList { sessions: Vec<SessionInfo> },
     SessionConnected { session_id: String, name: String },
     SessionDisconnected { session_id: String },
     SessionResize { session_id: String, cols: u16, rows: u16 },
+    Cwd { session_id: String, path: String },
+    /// Session presets a browser can pass to CreateSession as `profile`,
+    /// for the browser that asked (all browsers if None)
+    ProfileList {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+        profiles: Vec<ProfileInfo>,
+    },
+    /// Unix milliseconds of the binary frame that follows: sent by us when
+    /// we send the frame; the relay adds its own receive time for browsers
+    OutputTimestamp {
+        session_id: String,
+        timestamp_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mac_timestamp_ms: Option<u64>,
+    },
+    /// Snapshot of a session's terminal for the browser that asked (all
+    /// browsers if None)
+    State {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+        state: TerminalState,
+    },
+    /// Keystrokes typed on the Mac (base64), distinct from output (opt-in)
+    LocalInput { session_id: String, data: String },
+    /// Multiline browser input held until a browser acks `token` (opt-in)
+    ConfirmPaste { session_id: String, token: String, preview: String },
+    Notice {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        message: String,
+    },
+
+    // Mac-client -> Relay -> Browser (pause/resume input while the mac uplink is backed up)
+    FlowControl { pause: bool },
 
     // Bidirectional
     Error { message: String },
@@ -42,6 +228,93 @@ pub struct SessionInfo {
     pub name: String,
 }
 
+/// A session preset as offered to browsers. `icon` is a hint for the
+/// picker (e.g. "python"); browsers fall back to a generic icon.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProfileInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// What a mac-client runs on, reported (opt-in) so relay operators can
+/// debug a Mac whose sessions behave oddly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ClientEnvironment {
+    /// macOS version, e.g. "14.5"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Shell new sessions start, e.g. "/bin/zsh"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub tmux: bool,
+    #[serde(default)]
+    pub cloudflared: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+}
+
+/// A connected browser as shown to the session owner. Only a network
+/// prefix of its address is included, for the viewer's privacy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BrowserEntry {
+    pub id: String,
+    /// Unix timestamp (seconds) of when the browser joined
+    pub connected_at: u64,
+    pub ip_prefix: String,
+    /// Joined watch-only; its input is dropped by the relay
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// What a browser's terminal can display (see relay-server).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TerminalCapabilities {
+    pub cols: u16,
+    pub rows: u16,
+    /// 24-bit color support
+    #[serde(default)]
+    pub truecolor: bool,
+    /// Unicode version of the browser's character width tables (e.g. 6 or 11)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unicode_version: Option<u32>,
+    /// Browser locale as a BCP 47 tag (e.g. "de-CH")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Browser timezone as an IANA name (e.g. "Europe/Zurich")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// A session's terminal as the mac-client last saw it, for a browser
+/// restoring its view on rejoin: writing `scrollback` then `screen` to a
+/// reset terminal of `cols`x`rows` reproduces it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TerminalState {
+    pub cols: u16,
+    pub rows: u16,
+    /// Cursor position (0-based), also restored by `screen`
+    pub cursor_row: u16,
+    pub cursor_col: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Base64 escape sequences writing the scrollback tail into history
+    pub scrollback: String,
+    /// Base64 escape sequences redrawing the screen, cursor and input modes
+    pub screen: String,
+}
+
+/// A scrollback search hit (see relay-server).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchMatch {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,12 +323,34 @@ mod tests {
     fn test_register_serialization() {
         let msg = ControlMessage::Register {
             client_id: "test".into(),
+            password_hash: None,
+            requested_code: None,
+            compression: false,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
         assert!(json.contains("\"client_id\":\"test\""));
     }
 
+    #[test]
+    fn test_heartbeat_serialization() {
+        let msg = ControlMessage::Heartbeat {
+            sessions: 2,
+            load: 0.5,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"heartbeat\""));
+        assert!(json.contains("\"sessions\":2"));
+        assert!(json.contains("\"load\":0.5"));
+    }
+
+    #[test]
+    fn test_flow_control_serialization() {
+        let msg = ControlMessage::FlowControl { pause: false };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, "{\"type\":\"flow_control\",\"pause\":false}");
+    }
+
     #[test]
     fn test_registered_deserialization() {
         let json = r#"{"type":"registered","code":"ABC123"}"#;
@@ -91,4 +386,111 @@ mod tests {
             _ => panic!("Expected Error message"),
         }
     }
+
+    #[test]
+    fn test_create_session_deserialization() {
+        let msg: ControlMessage = serde_json::from_str(r#"{"type":"create_session"}"#).unwrap();
+        assert!(matches!(msg, ControlMessage::CreateSession { term: None, backend: None, .. }));
+
+        let json = r#"{"type":"create_session","term":"xterm-direct"}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::CreateSession { term, .. } => {
+                assert_eq!(term.as_deref(), Some("xterm-direct"));
+            }
+            _ => panic!("Expected CreateSession message"),
+        }
+
+        let json = r#"{"type":"create_session","backend":"ssh","host":"build-01","user":"deploy"}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::CreateSession { backend, host, user, .. } => {
+                assert_eq!(backend.as_deref(), Some("ssh"));
+                assert_eq!(host.as_deref(), Some("build-01"));
+                assert_eq!(user.as_deref(), Some("deploy"));
+            }
+            _ => panic!("Expected CreateSession message"),
+        }
+
+        let json = r#"{"type":"create_session","capabilities":{"cols":120,"rows":40,"truecolor":true}}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::CreateSession { capabilities, .. } => {
+                let capabilities = capabilities.unwrap();
+                assert_eq!((capabilities.cols, capabilities.rows), (120, 40));
+                assert!(capabilities.truecolor);
+                assert_eq!(capabilities.unicode_version, None);
+            }
+            _ => panic!("Expected CreateSession message"),
+        }
+    }
+
+    #[test]
+    fn test_get_state_round_trip() {
+        let json = r#"{"type":"get_state","session_id":"s1","browser_id":"b1"}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::GetState { session_id, browser_id } => {
+                assert_eq!(session_id, "s1");
+                assert_eq!(browser_id.as_deref(), Some("b1"));
+            }
+            _ => panic!("Expected GetState message"),
+        }
+
+        let msg = ControlMessage::State {
+            session_id: "s1".into(),
+            browser_id: None,
+            state: TerminalState {
+                cols: 80,
+                rows: 24,
+                cursor_row: 0,
+                cursor_col: 2,
+                title: None,
+                scrollback: String::new(),
+                screen: "JCA=".into(),
+            },
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"state\""));
+        assert!(!json.contains("browser_id"));
+        assert!(!json.contains("title"));
+    }
+
+    #[test]
+    fn test_profile_list_serialization() {
+        let msg = ControlMessage::ProfileList {
+            browser_id: Some("b1".into()),
+            profiles: vec![ProfileInfo {
+                name: "Data science".into(),
+                description: Some("Python 3.12 with pandas".into()),
+                icon: None,
+            }],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"profile_list","browser_id":"b1","profiles":[{"name":"Data science","description":"Python 3.12 with pandas"}]}"#
+        );
+        let msg: ControlMessage = serde_json::from_str(r#"{"type":"list_profiles"}"#).unwrap();
+        assert!(matches!(msg, ControlMessage::ListProfiles { browser_id: None }));
+
+        let msg: ControlMessage = serde_json::from_str(r#"{"type":"create_session","profile":"Data science"}"#).unwrap();
+        assert!(matches!(msg, ControlMessage::CreateSession { profile: Some(p), .. } if p == "Data science"));
+    }
+
+    #[test]
+    fn test_browser_list_deserialization() {
+        let json = r#"{"type":"browser_list","browsers":[{"id":"b1","connected_at":1700000000,"ip_prefix":"203.0.113.0/24"}]}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::BrowserList { browsers } => {
+                assert_eq!(browsers.len(), 1);
+                assert_eq!(browsers[0].connected_at, 1_700_000_000);
+                assert_eq!(browsers[0].ip_prefix, "203.0.113.0/24");
+            }
+            _ => panic!("Expected BrowserList message"),
+        }
+    }
+
+    #[test]
+    fn test_parse_relay_version() {
+        assert_eq!(parse_relay_version("relay-server 0.1.0 (protocol 1)\n"), Some(1));
+        assert_eq!(parse_relay_version("relay-server 0.1.0"), None);
+        assert_eq!(parse_relay_version(""), None);
+    }
 }
@@ -0,0 +1,355 @@
+//! Sessions on PTYs created outside pty-proxy.
+//!
+//! A script that spawns its own process on a PTY can hand the master fd to
+//! the manager ([`PtyCommand::Adopt`](super::PtyCommand::Adopt), or over
+//! the socket with `pty-proxy --adopt <fd> [name]`), and shells
+//! browsers open are started headless on a PTY of our own
+//! ([`PtyCommand::SpawnShell`](super::PtyCommand::SpawnShell)). The master
+//! is bridged onto one end of a socket pair speaking the pty-proxy protocol,
+//! and the other end is handled like any proxy connection, so the session
+//! looks the same to browsers. These sessions have no Terminal.app window:
+//! closing one hangs up its process group.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncReadExt, Interest};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+
+use super::{send_frame, EXTENDED_CONTROLS_VERSION};
+
+/// Largest frame accepted from the manager
+const MAX_FRAME: usize = 1024 * 1024;
+
+/// Shells tried when neither `DEFAULT_SHELL` nor `$SHELL` is usable
+const FALLBACK_SHELLS: &[&str] = &["/bin/zsh", "/bin/bash", "/bin/sh"];
+
+/// TERM for headless shells, which have no terminal emulator to set one
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Variables a headless shell inherits from the mac-client. Anything else,
+/// notably `SESSION_PASSWORD` and other settings the mac-client reads, stays
+/// out of reach of the browser user running `env`.
+const INHERITED_ENV: &[&str] = &["HOME", "USER", "LOGNAME", "PATH", "LANG", "TMPDIR", "SSH_AUTH_SOCK"];
+
+/// Messages the manager sends a proxy (the subset of pty-proxy's that
+/// applies without a local terminal).
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    Input { data: Vec<u8> },
+    Resize { cols: u16, rows: u16 },
+    Close,
+    Print { data: Vec<u8> },
+    Fit,
+    Redraw,
+}
+
+/// Fill `buf` from `stream`, keeping a file descriptor passed along with the
+/// bytes (SCM_RIGHTS), as `pty-proxy --adopt` sends its PTY master. Any
+/// further descriptors are closed.
+pub(super) async fn read_with_fd(stream: &UnixStream, buf: &mut [u8]) -> io::Result<Option<OwnedFd>> {
+    let mut passed = None;
+    let mut filled = 0;
+    while filled < buf.len() {
+        let (n, fd) = stream
+            .async_io(Interest::READABLE, || recv_with_fd(stream.as_raw_fd(), &mut buf[filled..]))
+            .await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        filled += n;
+        passed = passed.or(fd);
+    }
+    Ok(passed)
+}
+
+/// One recvmsg on `socket` into `buf`, with the first descriptor received.
+fn recv_with_fd(socket: RawFd, buf: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+    // u64s keep the buffer aligned for cmsghdr; room for a few descriptors
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+    let n = unsafe { libc::recvmsg(socket, &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut first = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                for i in 0..count {
+                    let fd = OwnedFd::from_raw_fd(data.add(i).read_unaligned());
+                    libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+                    first.get_or_insert(fd);
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((n as usize, first))
+}
+
+/// Check that `fd` is a PTY master with a process on it, returning the
+/// foreground process group, which stands in for pty-proxy's shell pid.
+pub(super) fn validate_master(fd: &OwnedFd) -> io::Result<u32> {
+    let raw = fd.as_raw_fd();
+    // Only a master has a slave name. ptsname isn't reentrant, but commands
+    // are processed one at a time
+    if unsafe { libc::ptsname(raw) }.is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a PTY master"));
+    }
+    let pgrp = unsafe { libc::tcgetpgrp(raw) };
+    if pgrp <= 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no process on the PTY"));
+    }
+    Ok(pgrp as u32)
+}
+
+/// Relay between `master` and the manager's end of a socket pair until
+/// either side closes, or the manager asks to close. Dropping the master
+/// then hangs up the PTY.
+pub(super) async fn bridge(master: OwnedFd, name: String, pid: u32, stream: UnixStream) -> io::Result<()> {
+    set_nonblocking(&master)?;
+    let master = Arc::new(AsyncFd::new(master)?);
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+
+    let registration = serde_json::json!({
+        "name": name,
+        "shell": "",
+        "pid": pid,
+        "tty": "",
+        "proxy_version": EXTENDED_CONTROLS_VERSION,
+    });
+    send_frame(&mut *writer.lock().await, registration.to_string().as_bytes()).await?;
+    if let Some((cols, rows)) = window_size(&master) {
+        let resize = serde_json::json!({ "type": "resize", "cols": cols, "rows": rows });
+        send_frame(&mut *writer.lock().await, resize.to_string().as_bytes()).await?;
+    }
+
+    let output = {
+        let master = master.clone();
+        let writer = writer.clone();
+        async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = read_master(&master, &mut buf).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let mut frame = Vec::with_capacity(1 + n);
+                frame.push(b'O');
+                frame.extend_from_slice(&buf[..n]);
+                send_frame(&mut *writer.lock().await, &frame).await?;
+            }
+        }
+    };
+
+    let control = async move {
+        loop {
+            let len = match reader.read_u32().await {
+                Ok(len) => len as usize,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if len > MAX_FRAME {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+            }
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload).await?;
+            match serde_json::from_slice(&payload) {
+                Ok(ControlMessage::Input { data }) => write_master(&master, &data).await?,
+                Ok(ControlMessage::Resize { cols, rows }) => set_window_size(&master, cols, rows),
+                Ok(ControlMessage::Close) => return Ok(()),
+                Ok(ControlMessage::Print { data }) => {
+                    // No local terminal to print to: only browsers see it
+                    let mut frame = Vec::with_capacity(1 + data.len());
+                    frame.push(b'O');
+                    frame.extend_from_slice(&data);
+                    send_frame(&mut *writer.lock().await, &frame).await?;
+                }
+                Ok(ControlMessage::Fit) => {
+                    if let Some((cols, rows)) = window_size(&master) {
+                        let resize = serde_json::json!({ "type": "resize", "cols": cols, "rows": rows });
+                        send_frame(&mut *writer.lock().await, resize.to_string().as_bytes()).await?;
+                    }
+                    redraw(&master);
+                }
+                Ok(ControlMessage::Redraw) => redraw(&master),
+                Err(_) => write_master(&master, &payload).await?,
+            }
+        }
+    };
+
+    tokio::select! {
+        result = output => result,
+        result = control => result,
+    }
+}
+
+/// Start a login shell on a new PTY of `size` (cols, rows) with `env` added
+/// to its environment. `commands` run first, through `-c`, so they neither
+/// show on screen nor land in the shell's history; the shell then replaces
+/// itself with an interactive one. Returns the master, a session name and
+/// the shell process, which the caller must reap.
+pub(super) fn spawn_shell(
+    env: &[(String, String)],
+    commands: &[String],
+    size: (u16, u16),
+) -> io::Result<(OwnedFd, String, tokio::process::Child)> {
+    let shell = detect_shell().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no usable shell"))?;
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/".to_string());
+
+    let winsize = libc::winsize { ws_col: size.0, ws_row: size.1, ws_xpixel: 0, ws_ypixel: 0 };
+    let (mut master, mut slave) = (0, 0);
+    let ret = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &winsize) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    let basename = shell.rsplit('/').next().unwrap_or(&shell).to_string();
+    let mut command = tokio::process::Command::new(&shell);
+    command
+        // A leading dash makes it a login shell, as in a new Terminal window
+        .arg0(format!("-{}", basename))
+        .current_dir(&home)
+        .env_clear()
+        .envs(INHERITED_ENV.iter().filter_map(|name| Some((*name, std::env::var_os(name)?))))
+        .env("SHELL", &shell)
+        .env("TERM", DEFAULT_TERM)
+        // Keeps shell integration from wrapping it in pty-proxy as well
+        .env("PTY_PROXY_ACTIVE", "1")
+        .envs(env.iter().map(|(name, value)| (name, value)));
+    if !commands.is_empty() {
+        command.arg("-c").arg(format!("{}; exec {} -l", commands.join("; "), shell_quote(&shell)));
+    }
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            // Lead a new session with the PTY as controlling terminal, so the
+            // shell gets job control and hangs up with it
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    // Close our copies of the slave, so the master sees the shell exit
+    drop(command);
+
+    Ok((master, format!("{} - {}", shell, home), child))
+}
+
+/// `value` in single quotes for a POSIX shell command line.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// `DEFAULT_SHELL`, then `$SHELL`, then the first of [`FALLBACK_SHELLS`],
+/// as pty-proxy picks; each must be an executable file.
+fn detect_shell() -> Option<String> {
+    ["DEFAULT_SHELL", "SHELL"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .chain(FALLBACK_SHELLS.iter().map(|shell| shell.to_string()))
+        .find(|shell| is_executable(shell))
+}
+
+fn is_executable(path: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    let raw = fd.as_raw_fd();
+    let flags = unsafe { libc::fcntl(raw, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(raw, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Read output; 0 once the slave side is gone.
+async fn read_master(master: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = master.readable().await?;
+        let result = guard.try_io(|fd| {
+            let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        match result {
+            Ok(Ok(n)) => return Ok(n),
+            // Linux reports a closed slave as EIO rather than EOF
+            Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+            Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Ok(Err(e)) => return Err(e),
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+async fn write_master(master: &AsyncFd<OwnedFd>, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let mut guard = master.writable().await?;
+        let result = guard.try_io(|fd| {
+            let n = unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        match result {
+            Ok(Ok(n)) => data = &data[n..],
+            Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+            Ok(Err(e)) => return Err(e),
+            Err(_would_block) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The PTY's size as (cols, rows), if one has been set.
+fn window_size(master: &AsyncFd<OwnedFd>) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+    (ret == 0 && size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col, size.ws_row))
+}
+
+/// Send SIGWINCH to the foreground process group, so a full-screen app
+/// redraws at the current size even if it hasn't changed.
+fn redraw(master: &AsyncFd<OwnedFd>) {
+    let pgrp = unsafe { libc::tcgetpgrp(master.as_raw_fd()) };
+    if pgrp > 1 {
+        unsafe { libc::killpg(pgrp, libc::SIGWINCH) };
+    }
+}
+
+fn set_window_size(master: &AsyncFd<OwnedFd>, cols: u16, rows: u16) {
+    let size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+    unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+}
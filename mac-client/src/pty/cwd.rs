@@ -0,0 +1,70 @@
+//! Working directory lookup for pty-proxy sessions.
+//!
+//! Resolves the cwd of the terminal's foreground process (e.g. vim, or the
+//! shell itself at a prompt) from the shell's pid, without needing shell
+//! integration to report directory changes.
+
+use std::path::PathBuf;
+
+/// Current working directory of the foreground process in the shell's
+/// terminal, falling back to the shell's own cwd.
+pub fn foreground_cwd(shell_pid: u32) -> Option<PathBuf> {
+    let pid = shell_pid as i32;
+    foreground_pid(pid)
+        .and_then(process_cwd)
+        .or_else(|| process_cwd(pid))
+}
+
+#[cfg(target_os = "macos")]
+fn foreground_pid(pid: i32) -> Option<i32> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as i32;
+    let n = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    (n == size && info.e_tpgid > 0).then_some(info.e_tpgid as i32)
+}
+
+#[cfg(target_os = "macos")]
+fn process_cwd(pid: i32) -> Option<PathBuf> {
+    use std::ffi::CStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as i32;
+    let n = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDVNODEPATHINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if n != size {
+        return None;
+    }
+    let path = unsafe { CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr() as *const libc::c_char) };
+    let path = std::ffi::OsStr::from_bytes(path.to_bytes());
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn foreground_pid(pid: i32) -> Option<i32> {
+    // Fields after "(comm)": state ppid pgrp session tty_nr tpgid
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = &stat[stat.rfind(')')? + 1..];
+    let tpgid: i32 = fields.split_whitespace().nth(5)?.parse().ok()?;
+    (tpgid > 0).then_some(tpgid)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn process_cwd(pid: i32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
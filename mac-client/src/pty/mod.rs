@@ -10,16 +10,82 @@
 //!
 //! We forward output to relay (-> browser) and inject browser input back.
 
+mod adopt;
+mod cwd;
+mod screen;
+mod throttle;
+
+use crate::protocol::TerminalState;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use screen::ScreenModel;
+use throttle::{repeated_suffix, ErrorThrottle};
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::sync::{mpsc, Mutex};
 use tracing::{debug, error, info, warn};
 
-/// Socket path for pty-proxy connections.
-pub const SOCKET_PATH: &str = "/tmp/terminal-remote.sock";
+/// Permissions of the pty-proxy socket unless `PTY_SOCKET_MODE` is set:
+/// owner-only, so other users on the machine can't inject sessions
+const DEFAULT_SOCKET_MODE: u32 = 0o600;
+
+/// Minimum interval between reports of the same error for a session
+/// (including UnknownSession events), so a browser typing into a dead
+/// session doesn't flood the relay or the log.
+const ERROR_THROTTLE: Duration = Duration::from_secs(5);
+
+/// TERM values a browser may request for new sessions, all standard
+/// terminfo entries shipped with macOS.
+pub const ALLOWED_TERMS: &[&str] = &[
+    "xterm-256color",
+    "xterm-direct",
+    "xterm",
+    "screen-256color",
+    "tmux-256color",
+    "vt100",
+];
+
+/// Bounds for a browser-requested window size (cols, rows)
+const MIN_WINDOW_SIZE: (u16, u16) = (20, 5);
+const MAX_WINDOW_SIZE: (u16, u16) = (500, 200);
+
+/// Whether sessions browsers create open a Terminal.app window through
+/// osascript (`OPEN_TERMINAL_WINDOW`), rather than a headless shell.
+pub fn terminal_window_sessions() -> bool {
+    matches!(std::env::var("OPEN_TERMINAL_WINDOW").as_deref(), Ok("1") | Ok("true"))
+}
+
+/// AppleScript opening a Terminal window that runs `commands`. With `size`
+/// (cols, rows), the window and so the new PTY start out at the requesting
+/// browser's dimensions.
+pub fn new_window_script(commands: &[String], size: Option<(u16, u16)>) -> String {
+    // Profile commands may contain quotes and backslashes; unescaped they
+    // would end the AppleScript string
+    let command = commands.join("; ").replace('\\', "\\\\").replace('"', "\\\"");
+    match size {
+        None => format!(r#"tell application "Terminal" to do script "{}""#, command),
+        Some((cols, rows)) => {
+            let cols = cols.clamp(MIN_WINDOW_SIZE.0, MAX_WINDOW_SIZE.0);
+            let rows = rows.clamp(MIN_WINDOW_SIZE.1, MAX_WINDOW_SIZE.1);
+            format!(
+                "tell application \"Terminal\"\n\
+                 \tset newTab to do script \"{}\"\n\
+                 \tset number of columns of newTab to {}\n\
+                 \tset number of rows of newTab to {}\n\
+                 end tell",
+                command, cols, rows
+            )
+        }
+    }
+}
+
+/// How long a looked-up working directory is reused before looking again
+const CWD_CACHE_TTL: Duration = Duration::from_secs(1);
 
 /// Information about a connected pty-proxy session.
 #[derive(Debug, Clone)]
@@ -47,12 +113,45 @@ pub enum PtyEvent {
         session_id: String,
         data: Vec<u8>,
     },
+    /// Keystrokes typed into the session's local terminal, as raw bytes
+    /// (unlike output, this includes input the shell doesn't echo).
+    Input {
+        session_id: String,
+        data: Vec<u8>,
+    },
     /// Terminal resized on mac (pty-proxy SIGWINCH → browser).
     SessionResize {
         session_id: String,
         cols: u16,
         rows: u16,
     },
+    /// Input was sent to a session that no longer exists (throttled).
+    UnknownSession {
+        session_id: String,
+    },
+    /// Working directory of a session's foreground process (reply to GetCwd).
+    Cwd {
+        session_id: String,
+        path: String,
+    },
+    /// Every connected session as (id, name), sorted by id (reply to
+    /// QuerySessions).
+    SessionList {
+        sessions: Vec<(String, String)>,
+    },
+    /// Snapshot of a session's terminal (reply to GetState).
+    State {
+        session_id: String,
+        browser_id: Option<String>,
+        state: TerminalState,
+    },
+    /// A session's encoding, after SetEncoding (None = UTF-8 passthrough).
+    Encoding {
+        session_id: String,
+        encoding: Option<String>,
+    },
+    /// A SpawnShell command failed; no session was created.
+    SpawnFailed(String),
     /// Error occurred.
     Error(String),
 }
@@ -69,7 +168,70 @@ pub enum PtyCommand {
     KillSession {
         session_id: String,
     },
-    /// Shutdown the PTY manager.
+    /// Look up a session's working directory (replies with PtyEvent::Cwd).
+    GetCwd {
+        session_id: String,
+    },
+    /// Set a session's terminal size (browser resized its view).
+    Resize {
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    /// Stop (or resume) relaying a session's output and local input to
+    /// browsers, e.g. while typing a password. The shell keeps running;
+    /// unmuting has its foreground app redraw so browsers catch up.
+    MuteOutput {
+        session_id: String,
+        muted: bool,
+    },
+    /// Mute (or unmute) every session, and have sessions attaching
+    /// meanwhile start out muted ("Pause Mirroring")
+    PauseMirroring {
+        paused: bool,
+    },
+    /// Fit a session to its terminal: the proxy reports the PTY's real size
+    /// (sent on as SessionResize) and signals the foreground app to redraw.
+    AutoFit {
+        session_id: String,
+    },
+    /// Transcode a session's output from `encoding` to UTF-8, and browser
+    /// input back to it (None = UTF-8 passthrough). Unknown labels leave
+    /// the encoding as it was. Replies with PtyEvent::Encoding.
+    SetEncoding {
+        session_id: String,
+        encoding: Option<String>,
+    },
+    /// Snapshot a session's terminal for a browser (replies with PtyEvent::State).
+    GetState {
+        session_id: String,
+        browser_id: Option<String>,
+    },
+    /// Print text into every session's terminal (shown locally and relayed
+    /// as output, never fed to the shell).
+    Print {
+        data: Vec<u8>,
+    },
+    /// List the connected sessions (replies with PtyEvent::SessionList).
+    QuerySessions,
+    /// Take over a PTY master created elsewhere (e.g. by a script) and run
+    /// it as a session. A PtyEvent::Error is sent if `fd` isn't a PTY master
+    /// with a process on it. Scripts hand one over with `pty-proxy --adopt`.
+    Adopt {
+        fd: std::os::fd::OwnedFd,
+        name: String,
+    },
+    /// Start a headless login shell as a new session, with `env` added to
+    /// its environment and `commands` run before it turns interactive. With
+    /// `size` (cols, rows) the PTY starts at the requesting browser's
+    /// dimensions. Replies with Attached, or SpawnFailed.
+    SpawnShell {
+        env: Vec<(String, String)>,
+        commands: Vec<String>,
+        size: Option<(u16, u16)>,
+    },
+    /// Stop processing commands, hanging up the sessions the manager started
+    /// or adopted. Sessions behind pty-proxy are left running.
     Shutdown,
 }
 
@@ -80,16 +242,62 @@ struct Registration {
     shell: String,
     pid: u32,
     tty: String,
+    /// Output encoding label when the session isn't UTF-8 (e.g. "latin1")
+    #[serde(default)]
+    encoding: Option<String>,
+    /// Protocol version of the proxy (0 if it doesn't say)
+    #[serde(default)]
+    proxy_version: u8,
+}
+
+/// First frame on the socket: a pty-proxy registering, or `pty-proxy
+/// --adopt` handing over the PTY master passed along with it
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Hello {
+    Adopt { adopt: String },
+    Proxy(Registration),
 }
 
+/// First pty-proxy version that understands fit, print and redraw. Older
+/// proxies would write them to the shell as input, so they don't get them.
+const EXTENDED_CONTROLS_VERSION: u8 = 2;
+
 /// Manages pty-proxy connections.
 /// Exists to own the Drop impl that cleans up the socket file.
-pub struct PtyManager;
+pub struct PtyManager {
+    socket_path: PathBuf,
+}
+
+/// Numbers proxy connections, so a stale connection's cleanup can tell
+/// whether its session id has since been taken over by a reconnect
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
 
 /// Handle for writing to a connected pty-proxy.
 struct SessionHandle {
+    /// Which proxy connection this handle belongs to
+    connection: u64,
     info: PtySessionInfo,
     writer: tokio::net::unix::OwnedWriteHalf,
+    /// Decodes output from the session's encoding, whose encoder also
+    /// handles browser input (None = UTF-8 passthrough)
+    decoder: Arc<std::sync::Mutex<Option<Decoder>>>,
+    /// The session's terminal as seen through its output
+    screen: Arc<std::sync::Mutex<ScreenModel>>,
+    /// Last size set by a Resize command or reported by the proxy (None
+    /// until the first one)
+    size: Arc<std::sync::Mutex<Option<(u16, u16)>>>,
+    /// Output and local input are kept from browsers (MuteOutput)
+    muted: Arc<AtomicBool>,
+    /// Whether the proxy understands fit, print and redraw
+    extended_controls: bool,
+}
+
+impl SessionHandle {
+    /// Encoding browser input is transcoded to (None = UTF-8 passthrough)
+    fn input_encoding(&self) -> Option<&'static Encoding> {
+        self.decoder.lock().unwrap().as_ref().map(Decoder::encoding)
+    }
 }
 
 /// Shared TTY map: session_id -> tty path.
@@ -107,6 +315,20 @@ impl PtyManager {
         mpsc::UnboundedReceiver<PtyEvent>,
         mpsc::UnboundedSender<PtyCommand>,
     ) {
+        Self::with_socket_path(crate::paths::socket_path())
+    }
+
+    /// Like [`new`](Self::new), listening on another socket (pty-proxy only
+    /// connects to [`paths::socket_path`](crate::paths::socket_path), so this
+    /// is for tests and tools).
+    pub fn with_socket_path(
+        socket_path: impl Into<PathBuf>,
+    ) -> (
+        Self,
+        mpsc::UnboundedReceiver<PtyEvent>,
+        mpsc::UnboundedSender<PtyCommand>,
+    ) {
+        let socket_path = socket_path.into();
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let (command_tx, command_rx) = mpsc::unbounded_channel();
 
@@ -116,39 +338,55 @@ impl PtyManager {
         // TTY map persists across session lifecycle for late close handling
         let tty_map: TtyMap = Arc::new(Mutex::new(HashMap::new()));
 
+        // Set by PauseMirroring; read when a session attaches, so its first
+        // output is already muted
+        let paused = Arc::new(AtomicBool::new(false));
+
         // Start command processor
         let sessions_cmd = sessions.clone();
         let tty_map_cmd = tty_map.clone();
+        let event_tx_cmd = event_tx.clone();
+        let paused_cmd = paused.clone();
         tokio::spawn(async move {
-            process_commands(command_rx, sessions_cmd, tty_map_cmd).await;
+            process_commands(command_rx, sessions_cmd, tty_map_cmd, event_tx_cmd, paused_cmd).await;
         });
 
         // Start Unix socket listener
         let event_tx_listen = event_tx.clone();
+        let path = socket_path.clone();
         tokio::spawn(async move {
-            if let Err(e) = run_listener(sessions, event_tx_listen, tty_map).await {
-                error!("PTY listener failed: {}", e);
+            if let Err(e) = run_listener(&path, sessions, event_tx_listen.clone(), tty_map, paused).await {
+                // No pty-proxy can connect; the consumer reports it
+                let _ = event_tx_listen.send(PtyEvent::Error(format!("listener on {} failed: {}", path.display(), e)));
             }
         });
 
-        (Self, event_rx, command_tx)
+        (Self { socket_path }, event_rx, command_tx)
     }
 }
 
 /// Listen for pty-proxy connections on Unix socket.
 async fn run_listener(
+    path: &std::path::Path,
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     event_tx: mpsc::UnboundedSender<PtyEvent>,
     tty_map: TtyMap,
+    paused: Arc<AtomicBool>,
 ) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
     // Remove stale socket
-    if std::path::Path::new(SOCKET_PATH).exists() {
-        warn!("Removing stale socket at {}", SOCKET_PATH);
-        std::fs::remove_file(SOCKET_PATH)?;
+    if path.exists() {
+        warn!("Removing stale socket at {}", path.display());
+        std::fs::remove_file(path)?;
     }
 
-    let listener = UnixListener::bind(SOCKET_PATH)?;
-    info!("PTY manager listening on {}", SOCKET_PATH);
+    let listener = UnixListener::bind(path)?;
+    let mode = socket_mode();
+    restrict_socket(path, mode)?;
+    info!("PTY manager listening on {} (mode {:o})", path.display(), mode);
 
     loop {
         match listener.accept().await {
@@ -156,8 +394,9 @@ async fn run_listener(
                 let sessions = sessions.clone();
                 let event_tx = event_tx.clone();
                 let tty_map = tty_map.clone();
+                let paused = paused.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = handle_proxy_connection(stream, sessions, event_tx, tty_map).await {
+                    if let Err(e) = handle_proxy_connection(stream, sessions, event_tx, tty_map, paused).await {
                         debug!("Proxy connection ended: {}", e);
                     }
                 });
@@ -170,19 +409,58 @@ async fn run_listener(
     }
 }
 
-/// Handle a single pty-proxy connection.
+/// Socket permissions from `PTY_SOCKET_MODE` (octal, e.g. `660` to let the
+/// socket's group connect), defaulting to [`DEFAULT_SOCKET_MODE`].
+fn socket_mode() -> u32 {
+    let Ok(value) = std::env::var("PTY_SOCKET_MODE") else {
+        return DEFAULT_SOCKET_MODE;
+    };
+    match parse_socket_mode(&value) {
+        Some(mode) => {
+            if mode & 0o007 != 0 {
+                warn!("PTY_SOCKET_MODE {:o} lets any user on this Mac connect", mode);
+            }
+            mode
+        }
+        None => {
+            warn!("Invalid PTY_SOCKET_MODE {:?}, using {:o}", value, DEFAULT_SOCKET_MODE);
+            DEFAULT_SOCKET_MODE
+        }
+    }
+}
+
+/// Parse an octal permission mode such as "600" or "0660".
+fn parse_socket_mode(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.trim(), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+/// Set the socket's permissions right after bind, before any connection is
+/// accepted.
+fn restrict_socket(path: &std::path::Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+/// Handle a single pty-proxy connection. A new session starts out muted
+/// while mirroring is `paused`.
 async fn handle_proxy_connection(
     stream: UnixStream,
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     event_tx: mpsc::UnboundedSender<PtyEvent>,
     tty_map: TtyMap,
+    paused: Arc<AtomicBool>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let session_id = uuid::Uuid::new_v4().to_string();
-    let (mut reader, writer) = stream.into_split();
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
 
-    // Read registration frame: 4 bytes length + JSON
-    let reg: Registration = {
-        let len = reader.read_u32().await?;
+    // Read registration frame: 4 bytes length + JSON, with a PTY master
+    // passed along if it is an adopt request
+    let mut len = [0u8; 4];
+    let passed_fd = adopt::read_with_fd(&stream, &mut len).await?;
+    let (mut reader, mut writer) = stream.into_split();
+    let hello: Hello = {
+        let len = u32::from_be_bytes(len);
         if len > 65536 {
             return Err("Registration too large".into());
         }
@@ -190,9 +468,25 @@ async fn handle_proxy_connection(
         reader.read_exact(&mut buf).await?;
         serde_json::from_slice(&buf)?
     };
+    let reg = match hello {
+        Hello::Proxy(reg) => reg,
+        Hello::Adopt { adopt: name } => {
+            let result = passed_fd
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file descriptor passed"))
+                .and_then(|fd| adopt_pty(fd, name, &sessions, &event_tx, &tty_map, &paused));
+            let reply = match result {
+                Ok(session_id) => serde_json::json!({ "session_id": session_id }),
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            };
+            send_frame(&mut writer, reply.to_string().as_bytes()).await?;
+            return Ok(());
+        }
+    };
 
+    let session_id = stable_id(reg.pid);
     let session_name = reg.name.clone();
     let tty = reg.tty.clone();
+    let encoding = resolve_encoding(reg.encoding.as_deref());
     info!(
         session_id = %session_id,
         name = %reg.name,
@@ -209,36 +503,66 @@ async fn handle_proxy_connection(
         tty: reg.tty,
     };
 
-    // Store session and TTY mapping
-    {
+    // Store session and TTY mapping. A proxy reconnecting before its old
+    // connection was noticed as closed takes over the existing entry.
+    let (replaced, screen, size, muted, decoder) = {
         let mut sessions_guard = sessions.lock().await;
-        sessions_guard.insert(
-            session_id.clone(),
-            SessionHandle { info, writer },
-        );
-    }
+        // A takeover keeps the screen model, size, mute and encoding: the
+        // terminal itself didn't change
+        let (screen, size, muted, decoder) = match sessions_guard.get(&session_id) {
+            Some(s) => (s.screen.clone(), s.size.clone(), s.muted.clone(), s.decoder.clone()),
+            None => (
+                Arc::default(),
+                Arc::default(),
+                Arc::new(AtomicBool::new(paused.load(Ordering::Relaxed))),
+                Arc::new(std::sync::Mutex::new(encoding.map(|e| e.new_decoder_without_bom_handling()))),
+            ),
+        };
+        let handle = SessionHandle {
+            connection,
+            info,
+            writer,
+            decoder: decoder.clone(),
+            screen: screen.clone(),
+            size: size.clone(),
+            muted: muted.clone(),
+            extended_controls: reg.proxy_version >= EXTENDED_CONTROLS_VERSION,
+        };
+        let replaced = sessions_guard.insert(session_id.clone(), handle).is_some();
+        (replaced, screen, size, muted, decoder)
+    };
     {
         let mut tty_guard = tty_map.lock().await;
         tty_guard.insert(session_id.clone(), tty.clone());
     }
 
-    // Notify: session attached
-    let _ = event_tx.send(PtyEvent::Attached {
-        session_id: session_id.clone(),
-        session_name,
-    });
+    // Notify: session attached (already announced if it was taken over)
+    if !replaced {
+        let _ = event_tx.send(PtyEvent::Attached {
+            session_id: session_id.clone(),
+            session_name,
+        });
+    }
 
     // Read frames from pty-proxy
-    let result = read_proxy_frames(&mut reader, &session_id, &event_tx).await;
+    let result = read_proxy_frames(&mut reader, &session_id, &event_tx, &decoder, &screen, &size, &muted).await;
 
-    // Cleanup on disconnect
-    {
+    // Cleanup on disconnect, unless a newer connection has the session now
+    let current = {
         let mut sessions_guard = sessions.lock().await;
-        sessions_guard.remove(&session_id);
+        let current = sessions_guard
+            .get(&session_id)
+            .is_some_and(|s| s.connection == connection);
+        if current {
+            sessions_guard.remove(&session_id);
+        }
+        current
+    };
+    if current {
+        let _ = event_tx.send(PtyEvent::Detached {
+            session_id: session_id.clone(),
+        });
     }
-    let _ = event_tx.send(PtyEvent::Detached {
-        session_id: session_id.clone(),
-    });
     info!(session_id = %session_id, "pty-proxy disconnected");
 
     // Don't auto-close the Terminal.app window here. When the user types `exit`,
@@ -256,6 +580,10 @@ async fn read_proxy_frames(
     reader: &mut tokio::net::unix::OwnedReadHalf,
     session_id: &str,
     event_tx: &mpsc::UnboundedSender<PtyEvent>,
+    decoder: &std::sync::Mutex<Option<Decoder>>,
+    screen: &std::sync::Mutex<ScreenModel>,
+    size: &std::sync::Mutex<Option<(u16, u16)>>,
+    muted: &AtomicBool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     loop {
         // Read frame length
@@ -281,14 +609,29 @@ async fn read_proxy_frames(
         match payload[0] {
             b'O' => {
                 // Output from shell -> forward to browser
+                let data = match decoder.lock().unwrap().as_mut() {
+                    Some(decoder) => decode_output(decoder, &payload[1..]),
+                    None => payload[1..].to_vec(),
+                };
+                screen.lock().unwrap().process(&data);
+                if muted.load(Ordering::Relaxed) {
+                    continue;
+                }
                 let _ = event_tx.send(PtyEvent::Output {
                     session_id: session_id.to_string(),
-                    data: payload[1..].to_vec(),
+                    data,
                 });
             }
             b'I' => {
-                // Input echo from terminal — we don't need this for browser,
-                // the shell output already includes echo.
+                // Keystrokes typed locally; the output already includes any
+                // echo, so this is only forwarded when asked for
+                if muted.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let _ = event_tx.send(PtyEvent::Input {
+                    session_id: session_id.to_string(),
+                    data: payload[1..].to_vec(),
+                });
             }
             b'{' => {
                 // JSON control message (e.g., resize from terminal)
@@ -302,6 +645,11 @@ async fn read_proxy_frames(
                             json.get("cols").and_then(|c| c.as_u64()),
                             json.get("rows").and_then(|r| r.as_u64()),
                         ) {
+                            screen.lock().unwrap().resize(cols as u16, rows as u16);
+                            // The local window's size (SIGWINCH or a fit) is
+                            // the last size now, so a browser going back to
+                            // its own is reported again
+                            *size.lock().unwrap() = Some((cols as u16, rows as u16));
                             let _ = event_tx.send(PtyEvent::SessionResize {
                                 session_id: session_id.to_string(),
                                 cols: cols as u16,
@@ -318,6 +666,116 @@ async fn read_proxy_frames(
     }
 }
 
+/// Sessions (id, name) as last sent to browsers, shared by the background
+/// tasks that keep it up to date.
+pub type SessionList = Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+/// Lock `list`, recovering it if a holder panicked. It's only changed an
+/// entry at a time, so a poisoned list is still usable, and the periodic
+/// reconcile corrects anything the panic left out.
+pub fn lock_sessions(list: &SessionList) -> std::sync::MutexGuard<'_, Vec<(String, String)>> {
+    list.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Bring `list` (id, name) in line with the manager's `actual` sessions,
+/// keeping the order of entries that stay. Returns the entries added and the
+/// ids removed, which were missed Attached/Detached events.
+pub fn reconcile_sessions(
+    list: &mut Vec<(String, String)>,
+    actual: Vec<(String, String)>,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut removed = Vec::new();
+    list.retain(|(id, _)| {
+        let keep = actual.iter().any(|(actual_id, _)| actual_id == id);
+        if !keep {
+            removed.push(id.clone());
+        }
+        keep
+    });
+    let added: Vec<(String, String)> = actual
+        .into_iter()
+        .filter(|(id, _)| !list.iter().any(|(listed, _)| listed == id))
+        .collect();
+    list.extend(added.iter().cloned());
+    (added, removed)
+}
+
+/// Session id for a shell that stays the same across mac-client restarts
+/// (pty-proxy reconnects on its own), so browsers keep addressing the same
+/// shell. A pid is unique while the shell lives; the boot time keeps ids
+/// from repeating across reboots.
+fn stable_id(shell_pid: u32) -> String {
+    static BOOT_TIME: OnceLock<Option<u64>> = OnceLock::new();
+    match BOOT_TIME.get_or_init(boot_time) {
+        Some(boot) => format!("{:x}-{}", boot, shell_pid),
+        None => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+/// System boot time as Unix seconds.
+#[cfg(target_os = "macos")]
+fn boot_time() -> Option<u64> {
+    let mut tv: libc::timeval = unsafe { std::mem::zeroed() };
+    let mut size = std::mem::size_of::<libc::timeval>();
+    let ret = unsafe {
+        libc::sysctlbyname(
+            c"kern.boottime".as_ptr(),
+            &mut tv as *mut _ as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (ret == 0 && tv.tv_sec > 0).then_some(tv.tv_sec as u64)
+}
+
+/// System boot time as Unix seconds.
+#[cfg(not(target_os = "macos"))]
+fn boot_time() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Resolve a pty-proxy encoding label. None means UTF-8 passthrough,
+/// including for unknown labels.
+fn resolve_encoding(label: Option<&str>) -> Option<&'static Encoding> {
+    let label = label?;
+    match Encoding::for_label(label.as_bytes()) {
+        Some(encoding) if encoding == UTF_8 => None,
+        Some(encoding) => {
+            info!(encoding = encoding.name(), "Transcoding session output to UTF-8");
+            Some(encoding)
+        }
+        None => {
+            warn!(label = %label, "Unknown session encoding, passing output through");
+            None
+        }
+    }
+}
+
+/// Decode a chunk of session output to UTF-8. The decoder keeps state, so
+/// multi-byte sequences split across frames decode correctly.
+fn decode_output(decoder: &mut Decoder, data: &[u8]) -> Vec<u8> {
+    let mut out = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length(data.len())
+            .unwrap_or(data.len() * 3),
+    );
+    let _ = decoder.decode_to_string(data, &mut out, false);
+    out.into_bytes()
+}
+
+/// Encode UTF-8 browser input into the session's encoding.
+fn encode_input(encoding: &'static Encoding, data: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(data);
+    let (bytes, _, _) = encoding.encode(&text);
+    bytes.into_owned()
+}
+
 /// Send a length-prefixed frame atomically to a pty-proxy session.
 async fn send_frame(
     writer: &mut tokio::net::unix::OwnedWriteHalf,
@@ -331,17 +789,92 @@ async fn send_frame(
     Ok(())
 }
 
+/// A proxy connection being handled, as spawned for bridged sessions
+type ProxyConnection =
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+
+/// Run the PTY master `fd` as a session named `name` (see
+/// [`PtyCommand::Adopt`]), returning its session id.
+fn adopt_pty(
+    fd: std::os::fd::OwnedFd,
+    name: String,
+    sessions: &Arc<Mutex<HashMap<String, SessionHandle>>>,
+    event_tx: &mpsc::UnboundedSender<PtyEvent>,
+    tty_map: &TtyMap,
+    paused: &Arc<AtomicBool>,
+) -> std::io::Result<String> {
+    let pid = adopt::validate_master(&fd)?;
+    info!(pid = pid, name = %name, "Adopting PTY");
+    start_bridged_session(fd, name, pid, sessions, event_tx, tty_map, paused)?;
+    Ok(stable_id(pid))
+}
+
+/// Run the PTY behind `master` as a session, through a socket pair as if
+/// a pty-proxy had connected.
+fn start_bridged_session(
+    master: std::os::fd::OwnedFd,
+    name: String,
+    pid: u32,
+    sessions: &Arc<Mutex<HashMap<String, SessionHandle>>>,
+    event_tx: &mpsc::UnboundedSender<PtyEvent>,
+    tty_map: &TtyMap,
+    paused: &Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    let (manager_end, bridge_end) = UnixStream::pair()?;
+    let (sessions, event_tx, tty_map, paused) = (sessions.clone(), event_tx.clone(), tty_map.clone(), paused.clone());
+    // Boxed: a connection can adopt a PTY, so its future can't contain
+    // the one it spawns here
+    let connection: ProxyConnection = Box::pin(handle_proxy_connection(manager_end, sessions, event_tx, tty_map, paused));
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("Bridged session ended: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = adopt::bridge(master, name, pid, bridge_end).await {
+            debug!(pid = pid, error = %e, "PTY bridge failed");
+        }
+    });
+    Ok(())
+}
+
+/// Mute or unmute a session's output to browsers. Unmuting has the
+/// foreground app redraw at its current size, so browsers catch up.
+async fn set_muted(session_id: &str, session: &mut SessionHandle, muted: bool) {
+    if session.muted.swap(muted, Ordering::Relaxed) == muted {
+        return;
+    }
+    info!(session_id = %session_id, "Output to browsers {}", if muted { "muted" } else { "unmuted" });
+    if !muted && session.extended_controls {
+        let json = serde_json::to_vec(&serde_json::json!({ "type": "redraw" })).unwrap();
+        if let Err(e) = send_frame(&mut session.writer, &json).await {
+            warn!(session_id = %session_id, error = %e, "Redraw after unmute failed");
+        }
+    }
+}
+
 /// Process commands sent to the PTY manager.
 async fn process_commands(
     mut command_rx: mpsc::UnboundedReceiver<PtyCommand>,
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     tty_map: TtyMap,
+    event_tx: mpsc::UnboundedSender<PtyEvent>,
+    paused: Arc<AtomicBool>,
 ) {
+    // Repeated failures per session are logged (and UnknownSession emitted) once per window
+    let mut errors = ErrorThrottle::new(ERROR_THROTTLE);
+    // Recently looked-up working directories, per session id
+    let mut cwd_cache: HashMap<String, (PathBuf, Instant)> = HashMap::new();
+
     while let Some(cmd) = command_rx.recv().await {
         match cmd {
             PtyCommand::Write { session_id, data } => {
                 let mut sessions_guard = sessions.lock().await;
                 if let Some(session) = sessions_guard.get_mut(&session_id) {
+                    let data = match session.input_encoding() {
+                        Some(encoding) => encode_input(encoding, &data),
+                        None => data,
+                    };
                     // Send as JSON input message, length-prefixed
                     let msg = serde_json::json!({
                         "type": "input",
@@ -349,8 +882,13 @@ async fn process_commands(
                     });
                     let json = serde_json::to_vec(&msg).unwrap();
                     if let Err(e) = send_frame(&mut session.writer, &json).await {
-                        warn!(session_id = %session_id, error = %e, "Write failed");
+                        if let Some(repeated) = errors.check(&session_id, "write", Instant::now()) {
+                            warn!(session_id = %session_id, error = %e, "Write failed{}", repeated_suffix(repeated));
+                        }
                     }
+                } else if let Some(repeated) = errors.check(&session_id, "unknown", Instant::now()) {
+                    debug!(session_id = %session_id, "Write to unknown session{}", repeated_suffix(repeated));
+                    let _ = event_tx.send(PtyEvent::UnknownSession { session_id });
                 }
             }
             PtyCommand::KillSession { session_id } => {
@@ -360,7 +898,8 @@ async fn process_commands(
                 let tty = {
                     let tty_guard = tty_map.lock().await;
                     tty_guard.get(&session_id).cloned()
-                };
+                }
+                .filter(|tty| !tty.is_empty() && tty != "unknown");
 
                 if let Some(tty) = tty {
                     info!(session_id = %session_id, tty = %tty, "Closing terminal window first");
@@ -368,37 +907,224 @@ async fn process_commands(
                         close_terminal_window_force(&tty);
                     }).await.ok();
                 } else {
-                    // Fallback: send close message to pty-proxy directly
+                    // Fallback: hang up the shell ourselves and tell pty-proxy
+                    // to exit. The shell leads its own process group, so
+                    // children that share it go too instead of being orphaned;
+                    // the proxy disconnecting then reports Detached.
                     let mut sessions_guard = sessions.lock().await;
                     if let Some(session) = sessions_guard.get_mut(&session_id) {
                         let pid = session.info.pid;
-                        info!(session_id = %session_id, pid = pid, "No TTY found, sending close to pty-proxy");
+                        info!(session_id = %session_id, pid = pid, "No TTY found, hanging up shell and closing pty-proxy");
+                        hang_up(pid);
                         let msg = serde_json::json!({ "type": "close" });
                         let json = serde_json::to_vec(&msg).unwrap();
                         if let Err(e) = send_frame(&mut session.writer, &json).await {
-                            warn!(session_id = %session_id, error = %e, "Close message failed, killing by PID");
-                            unsafe { libc::kill(pid as i32, libc::SIGTERM); }
+                            warn!(session_id = %session_id, error = %e, "Close message failed");
                         }
                     } else {
                         info!(session_id = %session_id, "Session already disconnected, nothing to kill");
                     }
                 }
             }
-            PtyCommand::Shutdown => {
-                info!("PTY manager shutting down");
+            PtyCommand::GetCwd { session_id } => {
+                let cached = cwd_cache
+                    .get(&session_id)
+                    .filter(|(_, at)| at.elapsed() < CWD_CACHE_TTL)
+                    .map(|(path, _)| path.clone());
+                let path = match cached {
+                    Some(path) => Some(path),
+                    None => {
+                        let pid = sessions.lock().await.get(&session_id).map(|s| s.info.pid);
+                        let path = pid.and_then(cwd::foreground_cwd);
+                        match &path {
+                            Some(path) => {
+                                cwd_cache.insert(session_id.clone(), (path.clone(), Instant::now()));
+                            }
+                            None => {
+                                cwd_cache.remove(&session_id);
+                            }
+                        }
+                        path
+                    }
+                };
+                match path {
+                    Some(path) => {
+                        let _ = event_tx.send(PtyEvent::Cwd {
+                            session_id,
+                            path: path.to_string_lossy().into_owned(),
+                        });
+                    }
+                    None => debug!(session_id = %session_id, "Could not resolve cwd"),
+                }
+            }
+            PtyCommand::Resize { session_id, cols, rows } => {
+                let mut sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get_mut(&session_id) {
+                    // pty-proxy doesn't report sizes it was told to set
+                    session.screen.lock().unwrap().resize(cols, rows);
+                    if session.size.lock().unwrap().replace((cols, rows)) != Some((cols, rows)) {
+                        let _ = event_tx.send(PtyEvent::SessionResize { session_id: session_id.clone(), cols, rows });
+                    }
+                    let msg = serde_json::json!({
+                        "type": "resize",
+                        "cols": cols,
+                        "rows": rows,
+                    });
+                    let json = serde_json::to_vec(&msg).unwrap();
+                    if let Err(e) = send_frame(&mut session.writer, &json).await {
+                        if let Some(repeated) = errors.check(&session_id, "resize", Instant::now()) {
+                            warn!(session_id = %session_id, error = %e, "Resize failed{}", repeated_suffix(repeated));
+                        }
+                    }
+                } else {
+                    debug!(session_id = %session_id, "Resize for unknown session");
+                }
+            }
+            PtyCommand::MuteOutput { session_id, muted } => {
+                let mut sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get_mut(&session_id) {
+                    set_muted(&session_id, session, muted).await;
+                } else {
+                    debug!(session_id = %session_id, "Mute for unknown session");
+                }
+            }
+            PtyCommand::PauseMirroring { paused: pause } => {
+                // Under the sessions lock, so a session attaching meanwhile
+                // either sees the new setting or is muted below
+                let mut sessions_guard = sessions.lock().await;
+                paused.store(pause, Ordering::Relaxed);
+                for (session_id, session) in sessions_guard.iter_mut() {
+                    set_muted(session_id, session, pause).await;
+                }
+            }
+            PtyCommand::AutoFit { session_id } => {
+                let mut sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get_mut(&session_id).filter(|s| s.extended_controls) {
+                    let json = serde_json::to_vec(&serde_json::json!({ "type": "fit" })).unwrap();
+                    if let Err(e) = send_frame(&mut session.writer, &json).await {
+                        warn!(session_id = %session_id, error = %e, "Auto-fit failed");
+                    }
+                } else {
+                    debug!(session_id = %session_id, "Auto-fit for unknown session or older proxy");
+                }
+            }
+            PtyCommand::SetEncoding { session_id, encoding } => {
+                let sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get(&session_id) {
+                    let mut decoder = session.decoder.lock().unwrap();
+                    match encoding.as_deref().map(|label| (label, Encoding::for_label(label.as_bytes()))) {
+                        Some((label, None)) => warn!(session_id = %session_id, label = %label, "Unknown session encoding"),
+                        resolved => {
+                            let encoding = resolved.and_then(|(_, e)| e).filter(|e| *e != UTF_8);
+                            info!(session_id = %session_id, encoding = encoding.map_or("UTF-8", |e| e.name()), "Session encoding set");
+                            *decoder = encoding.map(|e| e.new_decoder_without_bom_handling());
+                        }
+                    }
+                    let encoding = decoder.as_ref().map(|d| d.encoding().name().to_string());
+                    let _ = event_tx.send(PtyEvent::Encoding { session_id, encoding });
+                } else {
+                    debug!(session_id = %session_id, "Encoding set for unknown session");
+                }
+            }
+            PtyCommand::GetState { session_id, browser_id } => {
+                // A muted session's screen is no more shown in a snapshot
+                // than in its output
+                let screen = sessions
+                    .lock()
+                    .await
+                    .get(&session_id)
+                    .filter(|s| !s.muted.load(Ordering::Relaxed))
+                    .map(|s| s.screen.clone());
+                match screen {
+                    Some(screen) => {
+                        let state = screen.lock().unwrap().snapshot();
+                        let _ = event_tx.send(PtyEvent::State { session_id, browser_id, state });
+                    }
+                    None => debug!(session_id = %session_id, "State requested for unknown or muted session"),
+                }
+            }
+            PtyCommand::Print { data } => {
                 let mut sessions_guard = sessions.lock().await;
-                for (id, session) in sessions_guard.drain() {
-                    info!(session_id = %id, pid = session.info.pid, "Killing session on shutdown");
-                    unsafe {
-                        libc::kill(session.info.pid as i32, libc::SIGTERM);
+                for (session_id, session) in sessions_guard.iter_mut().filter(|(_, s)| s.extended_controls) {
+                    let data = match session.input_encoding() {
+                        Some(encoding) => encode_input(encoding, &data),
+                        None => data.clone(),
+                    };
+                    let msg = serde_json::json!({
+                        "type": "print",
+                        "data": data,
+                    });
+                    let json = serde_json::to_vec(&msg).unwrap();
+                    if let Err(e) = send_frame(&mut session.writer, &json).await {
+                        if let Some(repeated) = errors.check(session_id, "print", Instant::now()) {
+                            warn!(session_id = %session_id, error = %e, "Print failed{}", repeated_suffix(repeated));
+                        }
                     }
                 }
+            }
+            PtyCommand::QuerySessions => {
+                let mut list: Vec<(String, String)> = sessions
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(id, session)| (id.clone(), session.info.name.clone()))
+                    .collect();
+                list.sort();
+                let _ = event_tx.send(PtyEvent::SessionList { sessions: list });
+            }
+            PtyCommand::Adopt { fd, name } => {
+                if let Err(e) = adopt_pty(fd, name.clone(), &sessions, &event_tx, &tty_map, &paused) {
+                    let _ = event_tx.send(PtyEvent::Error(format!("cannot adopt {}: {}", name, e)));
+                }
+            }
+            PtyCommand::SpawnShell { env, commands, size } => {
+                let size = size.map_or(screen::INITIAL_SIZE, |(cols, rows)| {
+                    (cols.clamp(MIN_WINDOW_SIZE.0, MAX_WINDOW_SIZE.0), rows.clamp(MIN_WINDOW_SIZE.1, MAX_WINDOW_SIZE.1))
+                });
+                let result = adopt::spawn_shell(&env, &commands, size).and_then(|(master, name, mut child)| {
+                    let pid = child.id().unwrap_or_default();
+                    info!(pid = pid, name = %name, "Started headless shell");
+                    tokio::spawn(async move { child.wait().await });
+                    start_bridged_session(master, name, pid, &sessions, &event_tx, &tty_map, &paused)
+                });
+                if let Err(e) = result {
+                    warn!(error = %e, "Could not start a shell");
+                    let _ = event_tx.send(PtyEvent::SpawnFailed(e.to_string()));
+                }
+            }
+            PtyCommand::Shutdown => {
+                info!("PTY manager shutting down");
+                // Shells behind pty-proxy belong to their terminal windows and
+                // reconnect when we're back. Bridged sessions (registered
+                // without a tty) would be orphaned, so they are hung up.
+                let sessions_guard = sessions.lock().await;
+                for (id, session) in sessions_guard.iter().filter(|(_, s)| s.info.tty.is_empty()) {
+                    info!(session_id = %id, pid = session.info.pid, "Hanging up session on shutdown");
+                    hang_up(session.info.pid);
+                }
                 break;
             }
         }
     }
 }
 
+/// Send SIGHUP to a shell's process group (SIGHUP rather than SIGTERM,
+/// which interactive zsh ignores).
+fn hang_up(shell_pid: u32) {
+    let Ok(pid) = i32::try_from(shell_pid) else {
+        return;
+    };
+    // A pid of 0 or 1 would signal ourselves or everything
+    if pid <= 1 {
+        return;
+    }
+    if unsafe { libc::killpg(pid, libc::SIGHUP) } != 0 {
+        let err = std::io::Error::last_os_error();
+        debug!(pid, error = %err, "Process group hang-up failed, signaling the shell alone");
+        unsafe { libc::kill(pid, libc::SIGHUP) };
+    }
+}
+
 /// Force-close a Terminal.app window by TTY — no `busy` check.
 /// Used when the browser explicitly requests closing a session.
 /// Closes the window first so Terminal.app kills the shell naturally,
@@ -438,10 +1164,707 @@ end tell"#,
 impl Drop for PtyManager {
     fn drop(&mut self) {
         info!("PTY manager dropped, cleaning up socket");
-        if let Err(e) = std::fs::remove_file(SOCKET_PATH) {
+        if let Err(e) = std::fs::remove_file(&self.socket_path) {
             if e.kind() != std::io::ErrorKind::NotFound {
                 warn!("Failed to remove socket: {}", e);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreground_cwd_of_own_process() {
+        assert!(cwd::foreground_cwd(std::process::id()).is_some());
+    }
+
+    #[test]
+    fn test_allowed_terms_are_shell_safe() {
+        // Requested TERMs end up in a shell command, so only plain names
+        for term in ALLOWED_TERMS {
+            assert!(term.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'));
+        }
+    }
+
+    #[test]
+    fn test_new_window_script() {
+        let commands = vec!["export TERM=xterm".to_string(), "ssh -- host".to_string()];
+        assert_eq!(
+            new_window_script(&commands, None),
+            r#"tell application "Terminal" to do script "export TERM=xterm; ssh -- host""#
+        );
+        let script = new_window_script(&[], Some((132, 1000)));
+        assert!(script.starts_with("tell application \"Terminal\"\n\tset newTab to do script \"\"\n"));
+        assert!(script.contains("set number of columns of newTab to 132\n"));
+        assert!(script.contains("set number of rows of newTab to 200\n"));
+        assert!(script.ends_with("end tell"));
+
+        // Quotes and backslashes stay inside the AppleScript string
+        let commands = vec![r#"echo "hi" \ "; do shell script "id"#.to_string()];
+        assert_eq!(
+            new_window_script(&commands, None),
+            r#"tell application "Terminal" to do script "echo \"hi\" \\ \"; do shell script \"id""#
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("600"), Some(0o600));
+        assert_eq!(parse_socket_mode("0660\n"), Some(0o660));
+        assert_eq!(parse_socket_mode("rw-------"), None);
+        assert_eq!(parse_socket_mode("800"), None);
+        assert_eq!(parse_socket_mode("4755"), None);
+    }
+
+    #[tokio::test]
+    async fn test_socket_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("pty-mode-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        restrict_socket(&path, DEFAULT_SOCKET_MODE).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_round_trip_over_socket() {
+        let path = std::env::temp_dir().join(format!("pty-round-trip-{}.sock", std::process::id()));
+        let (manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        // The listener binds in a spawned task
+        let pid = std::process::id();
+        let registration = format!(r#"{{"name":"zsh","shell":"/bin/zsh","pid":{},"tty":"/dev/ttys042"}}"#, pid);
+        let mut proxy = connect_proxy(&path, &registration).await;
+
+        let session_id = match next_event(&mut events).await {
+            Some(PtyEvent::Attached { session_id, session_name }) => {
+                assert_eq!(session_name, "zsh");
+                assert_eq!(session_id, stable_id(pid));
+                session_id
+            }
+            other => panic!("expected Attached, got {:?}", other),
+        };
+
+        send_raw_frame(&mut proxy, b"Ohello\r\n").await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::Output { session_id: id, data }) if id == session_id && data == b"hello\r\n"
+        ));
+
+        commands.send(PtyCommand::QuerySessions).unwrap();
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::SessionList { sessions }) if sessions == [(session_id.clone(), "zsh".to_string())]
+        ));
+
+        // Browser input reaches the proxy as a framed JSON input message
+        commands
+            .send(PtyCommand::Write { session_id: session_id.clone(), data: b"ls\r".to_vec() })
+            .unwrap();
+        let json = read_control(&mut proxy).await;
+        assert_eq!(json["type"], "input");
+        assert_eq!(json["data"], serde_json::json!(b"ls\r"));
+
+        drop(proxy);
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::Detached { session_id: id }) if id == session_id
+        ));
+
+        drop(manager);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_resize_reported_once_per_size() {
+        let path = std::env::temp_dir().join(format!("pty-resize-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        let (mut proxy, session_id) = attach_proxy(&path, &mut events, 0).await;
+
+        for (cols, rows) in [(100, 30), (100, 30), (120, 40)] {
+            commands.send(PtyCommand::Resize { session_id: session_id.clone(), cols, rows }).unwrap();
+        }
+        commands.send(PtyCommand::QuerySessions).unwrap();
+
+        let mut resizes = Vec::new();
+        loop {
+            match next_event(&mut events).await {
+                Some(PtyEvent::SessionResize { session_id: id, cols, rows }) if id == session_id => resizes.push((cols, rows)),
+                Some(PtyEvent::SessionList { .. }) => break,
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        assert_eq!(resizes, [(100, 30), (120, 40)]);
+
+        // Every resize still reaches the proxy
+        for cols in [100, 100, 120] {
+            let json = read_control(&mut proxy).await;
+            assert_eq!(json["type"], "resize");
+            assert_eq!(json["cols"], cols);
+        }
+
+        // The local window resizing is the last size too: only a browser
+        // asking for another one is reported
+        send_raw_frame(&mut proxy, br#"{"type":"resize","cols":80,"rows":24}"#).await;
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::SessionResize { cols: 80, rows: 24, .. })));
+        for (cols, rows) in [(80, 24), (120, 40)] {
+            commands.send(PtyCommand::Resize { session_id: session_id.clone(), cols, rows }).unwrap();
+        }
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::SessionResize { cols: 120, rows: 40, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_auto_fit_reports_proxy_size() {
+        let path = std::env::temp_dir().join(format!("pty-fit-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        let (mut proxy, session_id) = attach_proxy(&path, &mut events, 2).await;
+
+        commands.send(PtyCommand::Resize { session_id: session_id.clone(), cols: 100, rows: 30 }).unwrap();
+        commands.send(PtyCommand::AutoFit { session_id: session_id.clone() }).unwrap();
+        let mut frames = Vec::new();
+        for _ in 0..2 {
+            frames.push(read_control(&mut proxy).await["type"].clone());
+        }
+        assert_eq!(frames, ["resize", "fit"]);
+
+        // The proxy answers with its terminal's size, which browsers follow
+        send_raw_frame(&mut proxy, br#"{"type":"resize","cols":80,"rows":24}"#).await;
+        let mut resizes = Vec::new();
+        while resizes.len() < 2 {
+            match next_event(&mut events).await {
+                Some(PtyEvent::SessionResize { session_id: id, cols, rows }) if id == session_id => resizes.push((cols, rows)),
+                other => panic!("expected SessionResize, got {:?}", other),
+            }
+        }
+        assert_eq!(resizes, [(100, 30), (80, 24)]);
+
+        // Going back to the browser's size is reported again
+        commands.send(PtyCommand::Resize { session_id: session_id.clone(), cols: 100, rows: 30 }).unwrap();
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::SessionResize { cols: 100, rows: 30, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_older_proxy_gets_no_extended_controls() {
+        let path = std::env::temp_dir().join(format!("pty-old-proxy-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        let (mut proxy, session_id) = attach_proxy(&path, &mut events, 1).await;
+
+        // Fit, print and redraw would reach its shell as typed text
+        commands.send(PtyCommand::AutoFit { session_id: session_id.clone() }).unwrap();
+        commands.send(PtyCommand::Print { data: b"notice".to_vec() }).unwrap();
+        commands.send(PtyCommand::MuteOutput { session_id: session_id.clone(), muted: true }).unwrap();
+        commands.send(PtyCommand::MuteOutput { session_id: session_id.clone(), muted: false }).unwrap();
+        commands.send(PtyCommand::Resize { session_id: session_id.clone(), cols: 100, rows: 30 }).unwrap();
+        assert_eq!(read_control(&mut proxy).await["type"], "resize");
+    }
+
+    #[tokio::test]
+    async fn test_set_encoding_transcodes_session() {
+        let path = std::env::temp_dir().join(format!("pty-encoding-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        let (mut proxy, session_id) = attach_proxy(&path, &mut events, 2).await;
+        let set_encoding = |label: Option<&str>| {
+            let encoding = label.map(str::to_string);
+            commands.send(PtyCommand::SetEncoding { session_id: session_id.clone(), encoding }).unwrap();
+        };
+        async fn applied(events: &mut mpsc::UnboundedReceiver<PtyEvent>) -> Option<String> {
+            match next_event(events).await {
+                Some(PtyEvent::Encoding { encoding, .. }) => encoding,
+                other => panic!("expected Encoding, got {:?}", other),
+            }
+        }
+
+        set_encoding(Some("latin1"));
+        assert_eq!(applied(&mut events).await.as_deref(), Some("windows-1252"));
+        // Unknown labels leave the encoding as it was
+        set_encoding(Some("klingon"));
+        assert_eq!(applied(&mut events).await.as_deref(), Some("windows-1252"));
+
+        // Output is decoded for browsers, and their input encoded for the shell
+        send_raw_frame(&mut proxy, b"Ocaf\xe9").await;
+        match next_event(&mut events).await {
+            Some(PtyEvent::Output { data, .. }) => assert_eq!(data, "café".as_bytes()),
+            other => panic!("expected Output, got {:?}", other),
+        }
+        commands.send(PtyCommand::Write { session_id: session_id.clone(), data: "é".as_bytes().to_vec() }).unwrap();
+        assert_eq!(read_control(&mut proxy).await["data"], serde_json::json!([0xe9]));
+
+        set_encoding(None);
+        assert_eq!(applied(&mut events).await, None);
+        send_raw_frame(&mut proxy, b"Ocaf\xe9").await;
+        match next_event(&mut events).await {
+            Some(PtyEvent::Output { data, .. }) => assert_eq!(data, b"caf\xe9"),
+            other => panic!("expected Output, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_muted_output_not_relayed() {
+        let path = std::env::temp_dir().join(format!("pty-mute-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        let (mut proxy, session_id) = attach_proxy(&path, &mut events, 2).await;
+
+        commands.send(PtyCommand::MuteOutput { session_id: session_id.clone(), muted: true }).unwrap();
+        // No snapshot of a muted session either
+        commands.send(PtyCommand::GetState { session_id: session_id.clone(), browser_id: None }).unwrap();
+        commands.send(PtyCommand::QuerySessions).unwrap();
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::SessionList { .. })));
+
+        send_raw_frame(&mut proxy, b"Osecret").await;
+        send_raw_frame(&mut proxy, b"Ipassword").await;
+        // Control messages still get through, which shows the frames above were read
+        send_raw_frame(&mut proxy, br#"{"type":"resize","cols":80,"rows":24}"#).await;
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::SessionResize { .. })));
+
+        // Unmuting asks the proxy for a redraw, and output flows again
+        commands.send(PtyCommand::MuteOutput { session_id: session_id.clone(), muted: false }).unwrap();
+        assert_eq!(read_control(&mut proxy).await["type"], "redraw");
+        send_raw_frame(&mut proxy, b"Ovisible").await;
+        match next_event(&mut events).await {
+            Some(PtyEvent::Output { data, .. }) => assert_eq!(data, b"visible"),
+            other => panic!("expected Output, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paused_mirroring_mutes_new_sessions() {
+        let path = std::env::temp_dir().join(format!("pty-pause-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+        commands.send(PtyCommand::PauseMirroring { paused: true }).unwrap();
+
+        let (mut proxy, _) = attach_proxy(&path, &mut events, 2).await;
+
+        // The very first output is kept back
+        send_raw_frame(&mut proxy, b"Osecret").await;
+        send_raw_frame(&mut proxy, br#"{"type":"resize","cols":80,"rows":24}"#).await;
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::SessionResize { .. })));
+
+        commands.send(PtyCommand::PauseMirroring { paused: false }).unwrap();
+        assert_eq!(read_control(&mut proxy).await["type"], "redraw");
+        send_raw_frame(&mut proxy, b"Ovisible").await;
+        match next_event(&mut events).await {
+            Some(PtyEvent::Output { data, .. }) => assert_eq!(data, b"visible"),
+            other => panic!("expected Output, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listener_failure_reported() {
+        let (_manager, mut events, _commands) = PtyManager::with_socket_path("/dev/null/pty.sock");
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::Error(msg)) if msg.contains("/dev/null/pty.sock")));
+    }
+
+    #[tokio::test]
+    async fn test_socket_created_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("pty-socket-mode-{}", std::process::id()));
+        let path = dir.join("runtime").join("ipc.sock");
+        let (manager, _events, _commands) = PtyManager::with_socket_path(&path);
+
+        // The listener binds in a spawned task, creating the directory first
+        while UnixStream::connect(&path).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(manager);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kill_session_hangs_up_process_group() {
+        use std::os::unix::process::CommandExt;
+
+        let path = std::env::temp_dir().join(format!("pty-kill-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        // A "shell" leading its own process group, with a child in it, as
+        // pty-proxy's setsid leaves real shells
+        let mut shell = std::process::Command::new("sh")
+            .args(["-c", "sleep 30 & wait"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pgid = shell.id() as i32;
+
+        let registration = format!(r#"{{"name":"sh","shell":"/bin/sh","pid":{},"tty":"unknown"}}"#, pgid);
+        let mut proxy = connect_proxy(&path, &registration).await;
+        let Some(PtyEvent::Attached { session_id, .. }) = next_event(&mut events).await else {
+            panic!("expected Attached");
+        };
+
+        commands.send(PtyCommand::KillSession { session_id: session_id.clone() }).unwrap();
+
+        // The proxy is told to close...
+        assert_eq!(read_control(&mut proxy).await["type"], "close");
+
+        // ...and the whole group was hung up, not just the shell
+        let status = tokio::task::spawn_blocking(move || shell.wait()).await.unwrap().unwrap();
+        assert_eq!(std::os::unix::process::ExitStatusExt::signal(&status), Some(libc::SIGHUP));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while unsafe { libc::killpg(pgid, 0) } == 0 {
+            assert!(Instant::now() < deadline, "background child survived");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // pty-proxy exits after closing, which detaches the session
+        drop(proxy);
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::Detached { session_id: id }) if id == session_id
+        ));
+    }
+
+    /// `cat` on a PTY of its own, as a script would set one up. Returns the
+    /// master.
+    fn cat_on_pty() -> (std::os::fd::OwnedFd, std::process::Child) {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::os::unix::process::CommandExt;
+
+        let (mut master, mut slave) = (0, 0);
+        let ret = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+        };
+        assert_eq!(ret, 0);
+        let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        let mut cat = std::process::Command::new("cat");
+        cat.stdin(slave.try_clone().unwrap()).stdout(slave.try_clone().unwrap());
+        unsafe {
+            cat.pre_exec(|| {
+                libc::setsid();
+                libc::ioctl(0, libc::TIOCSCTTY as _, 0);
+                Ok(())
+            });
+        }
+        (master, cat.spawn().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_adopted_pty_runs_as_session() {
+        let path = std::env::temp_dir().join(format!("pty-adopt-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+        let (master, mut cat) = cat_on_pty();
+
+        commands.send(PtyCommand::Adopt { fd: master, name: "script".to_string() }).unwrap();
+        let session_id = match next_event(&mut events).await {
+            Some(PtyEvent::Attached { session_id, session_name }) => {
+                assert_eq!(session_name, "script");
+                assert_eq!(session_id, stable_id(cat.id()));
+                session_id
+            }
+            other => panic!("expected Attached, got {:?}", other),
+        };
+
+        // Browser input reaches the process and its output comes back
+        commands
+            .send(PtyCommand::Write { session_id: session_id.clone(), data: b"ping\n".to_vec() })
+            .unwrap();
+        let mut output = Vec::new();
+        while output.windows(4).filter(|w| w == b"ping").count() < 2 {
+            match next_event(&mut events).await {
+                Some(PtyEvent::Output { session_id: id, data }) if id == session_id => output.extend(data),
+                other => panic!("expected Output, got {:?}", other),
+            }
+        }
+
+        // Closing it from the browser hangs up the process
+        commands.send(PtyCommand::KillSession { session_id: session_id.clone() }).unwrap();
+        assert!(matches!(
+            next_event(&mut events).await,
+            Some(PtyEvent::Detached { session_id: id }) if id == session_id
+        ));
+        let status = tokio::task::spawn_blocking(move || cat.wait()).await.unwrap().unwrap();
+        assert!(!status.success());
+
+        // Anything but a PTY master is refused
+        let (socket, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        commands.send(PtyCommand::Adopt { fd: socket.into(), name: "socket".to_string() }).unwrap();
+        assert!(matches!(next_event(&mut events).await, Some(PtyEvent::Error(msg)) if msg.contains("not a PTY master")));
+    }
+
+    #[tokio::test]
+    async fn test_pty_adopted_over_socket() {
+        use std::os::fd::AsRawFd;
+
+        let path = std::env::temp_dir().join(format!("pty-adopt-socket-{}.sock", std::process::id()));
+        let (_manager, mut events, _commands) = PtyManager::with_socket_path(&path);
+        let (master, mut cat) = cat_on_pty();
+
+        // As `pty-proxy --adopt` sends it: the fd rides on the first frame
+        let mut client = connect_proxy(&path, "").await;
+        let payload = br#"{"adopt":"script"}"#;
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        let mut iov = libc::iovec { iov_base: frame.as_mut_ptr().cast(), iov_len: frame.len() };
+        let mut control = [0u64; 4];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(4) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(4) as _;
+            (libc::CMSG_DATA(cmsg) as *mut i32).write_unaligned(master.as_raw_fd());
+        }
+        assert_eq!(unsafe { libc::sendmsg(client.as_raw_fd(), &msg, 0) }, frame.len() as isize);
+        drop(master);
+
+        // The client learns the session id, which browsers see attach
+        assert_eq!(read_control(&mut client).await["session_id"], stable_id(cat.id()));
+        match next_event(&mut events).await {
+            Some(PtyEvent::Attached { session_id, session_name }) => {
+                assert_eq!(session_name, "script");
+                assert_eq!(session_id, stable_id(cat.id()));
+            }
+            other => panic!("expected Attached, got {:?}", other),
+        }
+        cat.kill().unwrap();
+        cat.wait().unwrap();
+
+        // Without a descriptor there is nothing to adopt
+        let mut client = connect_proxy(&path, r#"{"adopt":"script"}"#).await;
+        assert_eq!(read_control(&mut client).await["error"], "no file descriptor passed");
+    }
+
+    #[tokio::test]
+    async fn test_spawned_shell_runs_as_session() {
+        let path = std::env::temp_dir().join(format!("pty-spawn-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        // Cargo sets CARGO_PKG_NAME for us, but it isn't passed on
+        let probe = "echo spawned-$((6 * 7))-[$CARGO_PKG_NAME]-$TERM".to_string();
+        let env = vec![("TERM".to_string(), "xterm-direct".to_string())];
+        commands
+            .send(PtyCommand::SpawnShell { env, commands: vec![probe], size: Some((100, 30)) })
+            .unwrap();
+        let session_id = match next_event(&mut events).await {
+            Some(PtyEvent::Attached { session_id, .. }) => session_id,
+            other => panic!("expected Attached, got {:?}", other),
+        };
+
+        // The command runs in the shell without being typed into it
+        let mut output = Vec::new();
+        while !String::from_utf8_lossy(&output).contains("spawned-42-[]-xterm-direct") {
+            match next_event(&mut events).await {
+                Some(PtyEvent::Output { session_id: id, data }) if id == session_id => output.extend(data),
+                Some(PtyEvent::SessionResize { cols, rows, .. }) => assert_eq!((cols, rows), (100, 30)),
+                other => panic!("expected Output, got {:?}", other),
+            }
+        }
+
+        assert!(!String::from_utf8_lossy(&output).contains("echo"));
+
+        commands.send(PtyCommand::KillSession { session_id: session_id.clone() }).unwrap();
+        loop {
+            match next_event(&mut events).await {
+                Some(PtyEvent::Detached { session_id: id }) if id == session_id => break,
+                Some(PtyEvent::Output { .. }) => {}
+                other => panic!("expected Detached, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truecolor_output_passes_through() {
+        let path = std::env::temp_dir().join(format!("pty-color-{}.sock", std::process::id()));
+        let (_manager, mut events, commands) = PtyManager::with_socket_path(&path);
+
+        // As main builds them: COLORTERM only for a truecolor browser
+        let probe = r"printf '\033[38;2;255;100;0mcc-[%s]\033[m\n' $COLORTERM".to_string();
+        for (env, expected) in [
+            (vec![], &b"\x1b[38;2;255;100;0mcc-[]\x1b[m"[..]),
+            (
+                vec![("COLORTERM".to_string(), "truecolor".to_string())],
+                &b"\x1b[38;2;255;100;0mcc-[truecolor]\x1b[m"[..],
+            ),
+        ] {
+            commands.send(PtyCommand::SpawnShell { env, commands: vec![probe.clone()], size: None }).unwrap();
+            let session_id = match next_event(&mut events).await {
+                Some(PtyEvent::Attached { session_id, .. }) => session_id,
+                other => panic!("expected Attached, got {:?}", other),
+            };
+
+            // The 24-bit SGR sequence arrives byte for byte
+            let mut output = Vec::new();
+            while !output.windows(expected.len()).any(|w| w == expected) {
+                match next_event(&mut events).await {
+                    Some(PtyEvent::Output { session_id: id, data }) if id == session_id => output.extend(data),
+                    Some(PtyEvent::SessionResize { .. }) => {}
+                    other => panic!("expected Output, got {:?}", other),
+                }
+            }
+
+            commands.send(PtyCommand::KillSession { session_id: session_id.clone() }).unwrap();
+            loop {
+                match next_event(&mut events).await {
+                    Some(PtyEvent::Detached { session_id: id }) if id == session_id => break,
+                    Some(PtyEvent::Output { .. }) => {}
+                    other => panic!("expected Detached, got {:?}", other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reconcile_sessions() {
+        let entry = |id: &str| (id.to_string(), format!("shell {}", id));
+        let mut list = vec![entry("c"), entry("a"), entry("gone")];
+        let (added, removed) = reconcile_sessions(&mut list, vec![entry("a"), entry("b"), entry("c")]);
+        assert_eq!(added, vec![entry("b")]);
+        assert_eq!(removed, vec!["gone".to_string()]);
+        assert_eq!(list, vec![entry("c"), entry("a"), entry("b")]);
+
+        // Already in step: nothing changes
+        let (added, removed) = reconcile_sessions(&mut list, vec![entry("a"), entry("b"), entry("c")]);
+        assert!(added.is_empty() && removed.is_empty());
+        assert_eq!(list.len(), 3);
+    }
+
+    #[test]
+    fn test_lock_sessions_after_panic() {
+        let list: SessionList = Arc::new(std::sync::Mutex::new(vec![("s1".into(), "zsh".into())]));
+        let holder = list.clone();
+        let result = std::thread::spawn(move || {
+            let _guard = holder.lock().unwrap();
+            panic!("holder panicked");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(list.is_poisoned());
+
+        lock_sessions(&list).push(("s2".into(), "bash".into()));
+        assert_eq!(lock_sessions(&list).len(), 2);
+    }
+
+    async fn next_event(events: &mut mpsc::UnboundedReceiver<PtyEvent>) -> Option<PtyEvent> {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap()
+    }
+
+    async fn send_raw_frame(stream: &mut UnixStream, payload: &[u8]) {
+        stream.write_u32(payload.len() as u32).await.unwrap();
+        stream.write_all(payload).await.unwrap();
+    }
+
+    /// Connect to the manager at `path` as a pty-proxy, once its listener
+    /// is bound (in a spawned task), and register (unless `registration`
+    /// is empty).
+    async fn connect_proxy(path: &std::path::Path, registration: &str) -> UnixStream {
+        let mut proxy = loop {
+            match UnixStream::connect(path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        if !registration.is_empty() {
+            send_raw_frame(&mut proxy, registration.as_bytes()).await;
+        }
+        proxy
+    }
+
+    /// A zsh pty-proxy of `proxy_version` attached to the manager at `path`,
+    /// with its session id.
+    async fn attach_proxy(
+        path: &std::path::Path,
+        events: &mut mpsc::UnboundedReceiver<PtyEvent>,
+        proxy_version: u8,
+    ) -> (UnixStream, String) {
+        let registration = format!(
+            r#"{{"name":"zsh","shell":"/bin/zsh","pid":{},"tty":"/dev/ttys042","proxy_version":{}}}"#,
+            std::process::id(),
+            proxy_version
+        );
+        let proxy = connect_proxy(path, &registration).await;
+        match next_event(events).await {
+            Some(PtyEvent::Attached { session_id, .. }) => (proxy, session_id),
+            other => panic!("expected Attached, got {:?}", other),
+        }
+    }
+
+    /// The next control message the manager sent a proxy.
+    async fn read_control(proxy: &mut UnixStream) -> serde_json::Value {
+        let len = tokio::time::timeout(Duration::from_secs(5), proxy.read_u32()).await.unwrap().unwrap();
+        let mut frame = vec![0u8; len as usize];
+        proxy.read_exact(&mut frame).await.unwrap();
+        serde_json::from_slice(&frame).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_input_and_output_frames_distinguished() {
+        let (mut proxy, manager) = UnixStream::pair().unwrap();
+        for payload in [&b"Oprompt$ "[..], b"Ils\r", br#"{"type":"resize","cols":100,"rows":30}"#] {
+            proxy.write_u32(payload.len() as u32).await.unwrap();
+            proxy.write_all(payload).await.unwrap();
+        }
+        drop(proxy);
+
+        let (mut reader, _writer) = manager.into_split();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let screen = std::sync::Mutex::new(ScreenModel::default());
+        read_proxy_frames(&mut reader, "s1", &event_tx, &Default::default(), &screen, &Default::default(), &AtomicBool::new(false)).await.unwrap();
+
+        assert!(matches!(event_rx.recv().await, Some(PtyEvent::Output { data, .. }) if data == b"prompt$ "));
+        assert!(matches!(event_rx.recv().await, Some(PtyEvent::Input { data, .. }) if data == b"ls\r"));
+
+        // Only output reaches the screen model, which follows resizes
+        let state = screen.lock().unwrap().snapshot();
+        assert_eq!((state.cols, state.rows), (100, 30));
+        assert_eq!((state.cursor_row, state.cursor_col), (0, 8));
+    }
+
+    #[test]
+    fn test_stable_id() {
+        // Same shell, same id; fits the one-byte session id length in frames
+        assert_eq!(stable_id(4242), stable_id(4242));
+        assert_ne!(stable_id(4242), stable_id(4243));
+        assert!(stable_id(4242).len() < 256);
+    }
+
+    #[test]
+    fn test_resolve_encoding() {
+        assert!(resolve_encoding(None).is_none());
+        assert!(resolve_encoding(Some("UTF-8")).is_none());
+        assert!(resolve_encoding(Some("no-such-encoding")).is_none());
+        assert!(resolve_encoding(Some("latin1")).is_some());
+        assert!(resolve_encoding(Some("ISO8859-1")).is_some());
+    }
+
+    #[test]
+    fn test_latin1_round_trip() {
+        let encoding = resolve_encoding(Some("latin1")).unwrap();
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+
+        // "café" in latin-1
+        assert_eq!(decode_output(&mut decoder, b"caf\xe9"), "café".as_bytes());
+        assert_eq!(encode_input(encoding, "é".as_bytes()), b"\xe9");
+    }
+
+    #[test]
+    fn test_decode_split_multibyte() {
+        let encoding = resolve_encoding(Some("shift_jis")).unwrap();
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+
+        // "あ" is 0x82 0xA0 in Shift_JIS, split across two frames
+        let mut out = decode_output(&mut decoder, b"\x82");
+        out.extend(decode_output(&mut decoder, b"\xa0"));
+        assert_eq!(out, "あ".as_bytes());
+    }
+}
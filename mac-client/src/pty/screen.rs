@@ -0,0 +1,157 @@
+//! Per-session terminal model.
+//!
+//! Session output is fed through a vt100 parser as it is relayed, so the
+//! current screen can be serialized for a browser that rejoins instead of
+//! replaying raw output from the relay's scrollback.
+
+use base64::Engine;
+
+use crate::protocol::TerminalState;
+
+/// Lines of scrollback kept per session, all included in a snapshot
+const SCROLLBACK_LINES: usize = 200;
+
+/// Longest window title kept
+const MAX_TITLE_LEN: usize = 256;
+
+/// Size until pty-proxy reports the real one (it does right after registering),
+/// and of headless shells no browser gave a size for
+pub(super) const INITIAL_SIZE: (u16, u16) = (80, 24);
+
+/// Remembers the window title (OSC 0 / OSC 2), which vt100 leaves to callbacks.
+#[derive(Default)]
+struct TitleTracker {
+    title: Option<String>,
+}
+
+impl vt100::Callbacks for TitleTracker {
+    fn set_window_title(&mut self, _: &mut vt100::Screen, title: &[u8]) {
+        let title: String = String::from_utf8_lossy(title).chars().take(MAX_TITLE_LEN).collect();
+        self.title = Some(title).filter(|t| !t.is_empty());
+    }
+}
+
+/// A session's screen, cursor, scrollback tail and title.
+pub struct ScreenModel {
+    parser: vt100::Parser<TitleTracker>,
+}
+
+impl Default for ScreenModel {
+    fn default() -> Self {
+        Self::new(INITIAL_SIZE.0, INITIAL_SIZE.1)
+    }
+}
+
+impl ScreenModel {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self {
+            parser: vt100::Parser::new_with_callbacks(rows, cols, SCROLLBACK_LINES, TitleTracker::default()),
+        }
+    }
+
+    /// Apply session output (UTF-8, as relayed).
+    pub fn process(&mut self, data: &[u8]) {
+        self.parser.process(data);
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        if cols > 0 && rows > 0 {
+            self.parser.screen_mut().set_size(rows, cols);
+        }
+    }
+
+    /// Serialize the terminal for a browser to restore.
+    pub fn snapshot(&mut self) -> TerminalState {
+        let scrollback = self.scrollback_formatted();
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        let (cursor_row, cursor_col) = screen.cursor_position();
+        let engine = base64::engine::general_purpose::STANDARD;
+        TerminalState {
+            cols,
+            rows,
+            cursor_row,
+            cursor_col,
+            title: self.parser.callbacks().title.clone(),
+            scrollback: engine.encode(scrollback),
+            screen: engine.encode(screen.state_formatted()),
+        }
+    }
+
+    /// Scrollback lines, oldest first, followed by enough newlines to push
+    /// them all off the screen (clearing the screen doesn't move lines into
+    /// history).
+    fn scrollback_formatted(&mut self) -> Vec<u8> {
+        let screen = self.parser.screen_mut();
+        let (rows, cols) = screen.size();
+        screen.set_scrollback(usize::MAX);
+        let mut offset = screen.scrollback();
+        if offset == 0 {
+            return Vec::new();
+        }
+
+        let mut out = Vec::new();
+        while offset > 0 {
+            // With the view scrolled back by `offset`, the top rows are the
+            // scrollback lines from that point on
+            screen.set_scrollback(offset);
+            let take = offset.min(rows as usize);
+            for row in screen.rows_formatted(0, cols).take(take) {
+                out.extend_from_slice(&row);
+                out.extend_from_slice(b"\x1b[m\r\n");
+            }
+            offset -= take;
+        }
+        screen.set_scrollback(0);
+        out.extend(std::iter::repeat_n(b'\n', rows.saturating_sub(1) as usize));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD.decode(data).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_restores_screen_and_cursor() {
+        let mut model = ScreenModel::new(20, 4);
+        model.process(b"\x1b]2;build log\x07");
+        model.process(b"one\r\ntwo\r\nthree\r\nfour\r\nfive\r\n\x1b[1;31msix\x1b[m\x1b[2;3H");
+
+        let state = model.snapshot();
+        assert_eq!((state.cols, state.rows), (20, 4));
+        assert_eq!((state.cursor_row, state.cursor_col), (1, 2));
+        assert_eq!(state.title.as_deref(), Some("build log"));
+
+        // Replaying the snapshot into a fresh terminal gives the same view
+        let mut restored = vt100::Parser::new(state.rows, state.cols, SCROLLBACK_LINES);
+        restored.process(&decode(&state.scrollback));
+        restored.process(&decode(&state.screen));
+        let original = model.parser.screen();
+        assert_eq!(restored.screen().contents(), original.contents());
+        assert_eq!(restored.screen().cursor_position(), (1, 2));
+        assert!(restored.screen().cell(3, 0).unwrap().fgcolor() == vt100::Color::Idx(1));
+
+        restored.screen_mut().set_scrollback(usize::MAX);
+        assert_eq!(restored.screen().scrollback(), 2);
+        assert!(restored.screen().contents().starts_with("one\ntwo\nthree\nfour"));
+    }
+
+    #[test]
+    fn test_resize_and_empty_scrollback() {
+        let mut model = ScreenModel::default();
+        model.process(b"$ ");
+        model.resize(100, 30);
+        model.resize(0, 0);
+
+        let state = model.snapshot();
+        assert_eq!((state.cols, state.rows), (100, 30));
+        assert_eq!((state.cursor_row, state.cursor_col), (0, 2));
+        assert_eq!(state.scrollback, "");
+        assert_eq!(state.title, None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Coalescing of repeated per-session errors.
+//!
+//! A dead or wedged session can fail on every keystroke or output chunk.
+//! Instead of logging each failure, the first one in a window is reported
+//! along with how many identical failures were swallowed since the last
+//! report ("repeated 42 times").
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Entries are pruned once the map grows past this many
+const PRUNE_THRESHOLD: usize = 256;
+
+/// Tracks when each (session, error) pair was last reported.
+#[derive(Debug)]
+pub struct ErrorThrottle {
+    window: Duration,
+    /// (session id, error) -> (last reported, suppressed since)
+    last: HashMap<(String, String), (Instant, u32)>,
+}
+
+impl ErrorThrottle {
+    /// Report each distinct error at most once per `window` per session.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Record an occurrence of `error` for `session_id`. Returns None if it
+    /// should be suppressed, or Some(n) to report it, where n is how many
+    /// times it was suppressed since it was last reported.
+    pub fn check(&mut self, session_id: &str, error: &str, now: Instant) -> Option<u32> {
+        if self.last.len() > PRUNE_THRESHOLD {
+            let window = self.window;
+            self.last
+                .retain(|_, (at, _)| now.saturating_duration_since(*at) < window);
+        }
+
+        let key = (session_id.to_string(), error.to_string());
+        match self.last.get_mut(&key) {
+            Some((at, suppressed)) if now.saturating_duration_since(*at) < self.window => {
+                *suppressed += 1;
+                None
+            }
+            Some((at, suppressed)) => {
+                let repeated = std::mem::take(suppressed);
+                *at = now;
+                Some(repeated)
+            }
+            None => {
+                self.last.insert(key, (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+/// Suffix for a reported error that was suppressed `repeated` times.
+pub fn repeated_suffix(repeated: u32) -> String {
+    match repeated {
+        0 => String::new(),
+        n => format!(" (repeated {} times)", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_are_coalesced() {
+        let mut throttle = ErrorThrottle::new(Duration::from_secs(5));
+        let start = Instant::now();
+
+        assert_eq!(throttle.check("s1", "Write failed", start), Some(0));
+        for i in 1..=42 {
+            let at = start + Duration::from_millis(i);
+            assert_eq!(throttle.check("s1", "Write failed", at), None);
+        }
+        // Other sessions and other errors are tracked separately
+        assert_eq!(throttle.check("s2", "Write failed", start), Some(0));
+        assert_eq!(throttle.check("s1", "Print failed", start), Some(0));
+
+        let later = start + Duration::from_secs(5);
+        assert_eq!(throttle.check("s1", "Write failed", later), Some(42));
+        assert_eq!(repeated_suffix(42), " (repeated 42 times)");
+        assert_eq!(repeated_suffix(0), "");
+    }
+}
@@ -0,0 +1,56 @@
+//! QR code for joining from a phone.
+//!
+//! "Show QR Code" in the menu encodes the tunnel URL's login page with the
+//! session code filled in, writes it to a PNG in the temp directory and
+//! opens it in Preview, so a phone camera can pick it up.
+
+use std::path::{Path, PathBuf};
+
+use image::Luma;
+use qrcode::QrCode;
+
+/// Smallest side of the rendered image, in pixels
+const MIN_SIZE: u32 = 320;
+
+/// Login page of `tunnel_url` with `code` filled in.
+pub fn login_url(tunnel_url: &str, code: &str) -> String {
+    format!("{}/login?code={}", tunnel_url.trim_end_matches('/'), code)
+}
+
+/// Where the QR image is written; overwritten each time.
+pub fn image_path() -> PathBuf {
+    std::env::temp_dir().join("terminal-remote-qr.png")
+}
+
+/// Render `payload` as a QR code PNG at `path`.
+pub fn write_png(payload: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let image = QrCode::new(payload.as_bytes())?
+        .render::<Luma<u8>>()
+        .min_dimensions(MIN_SIZE, MIN_SIZE)
+        .build();
+    image.save(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_url() {
+        assert_eq!(
+            login_url("https://calm-river.trycloudflare.com", "ABC123"),
+            "https://calm-river.trycloudflare.com/login?code=ABC123"
+        );
+        assert_eq!(login_url("https://example.com/", "ABC123"), "https://example.com/login?code=ABC123");
+    }
+
+    #[test]
+    fn test_write_png() {
+        let path = std::env::temp_dir().join(format!("qr-test-{}.png", std::process::id()));
+        write_png(&login_url("https://example.com", "ABC123"), &path).unwrap();
+        let image = image::open(&path).unwrap();
+        assert!(image.width() >= MIN_SIZE);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
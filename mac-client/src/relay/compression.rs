@@ -0,0 +1,79 @@
+//! Compressed terminal output (mirrors relay-server's `compression.rs`).
+//!
+//! Register asks for it; a relay that supports it answers with a
+//! `compression` message, after which its binary frames start with a flag
+//! byte. We answer with our own `compression` message and flag our frames
+//! from then on, gzipping the large ones: `cat` of a log or a full-screen
+//! redraw shrinks several times over, which matters on a phone's uplink.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Flag of a frame sent uncompressed
+pub const RAW: u8 = 0;
+
+/// Flag of a gzip-compressed frame
+pub const GZIP: u8 = 1;
+
+/// Frames shorter than this are sent raw: keystroke echo gains nothing
+pub const MIN_COMPRESSED_SIZE: usize = 512;
+
+/// Largest frame a gzip stream from the relay may inflate to
+const MAX_DECODED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Flag `frame`, compressing it if it is large enough and compresses well.
+pub fn encode(frame: &[u8]) -> Vec<u8> {
+    if frame.len() >= MIN_COMPRESSED_SIZE {
+        let mut encoder = GzEncoder::new(vec![GZIP], Compression::fast());
+        if let Ok(compressed) = encoder.write_all(frame).and_then(|()| encoder.finish()) {
+            if compressed.len() <= frame.len() {
+                return compressed;
+            }
+        }
+    }
+    let mut flagged = Vec::with_capacity(1 + frame.len());
+    flagged.push(RAW);
+    flagged.extend_from_slice(frame);
+    flagged
+}
+
+/// The frame within a flagged one, or None if it is malformed.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    match data.split_first()? {
+        (&RAW, frame) => Some(frame.to_vec()),
+        (&GZIP, compressed) => {
+            let mut frame = Vec::new();
+            GzDecoder::new(compressed)
+                .take(MAX_DECODED_SIZE as u64 + 1)
+                .read_to_end(&mut frame)
+                .ok()?;
+            (frame.len() <= MAX_DECODED_SIZE).then_some(frame)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // A full-screen TUI redraw: mostly escape sequences and padding
+        let mut frame = vec![2, b's', b'1'];
+        for row in 1..=50 {
+            frame.extend_from_slice(format!("\x1b[{};1H\x1b[44m{:<200}\x1b[0m", row, "│").as_bytes());
+        }
+        let encoded = encode(&frame);
+        assert_eq!(encoded[0], GZIP);
+        assert!(encoded.len() < frame.len() / 10, "{} of {} bytes", encoded.len(), frame.len());
+        assert_eq!(decode(&encoded).unwrap(), frame);
+
+        let echo = b"\x02s1l".to_vec();
+        assert_eq!(encode(&echo)[0], RAW);
+        assert_eq!(decode(&encode(&echo)).unwrap(), echo);
+        assert_eq!(decode(&[9, 1]), None);
+    }
+}
@@ -1,10 +1,23 @@
-use crate::protocol::ControlMessage;
+use crate::environment;
+use crate::protocol::{BrowserEntry, ClientEnvironment, ControlMessage, ProfileInfo, TerminalCapabilities, TerminalState};
+use super::compression;
+use super::rate_limit::OutputLimiter;
+use super::stats::ReconnectStats;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use futures_util::{SinkExt, StreamExt};
+use std::collections::{HashSet, VecDeque};
 use std::error::Error;
 use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
+/// Queued commands above which browsers are asked to pause input.
+const BACKPRESSURE_HIGH_WATER: usize = 512;
+/// Queued commands below which paused browsers are told to resume.
+const BACKPRESSURE_LOW_WATER: usize = 64;
+
 /// Events emitted by the RelayClient to the main thread.
 /// These are sent via std::sync::mpsc (not tokio::sync) for AppKit compatibility.
 #[derive(Debug, Clone)]
@@ -21,12 +34,44 @@ pub enum RelayEvent {
     BrowserDisconnected(String),
     /// Error message from relay
     Error(String),
+    /// Browsers currently connected to this session
+    BrowserList(Vec<BrowserEntry>),
+    /// Relay created a share code for this session
+    ShareCreated { code: String, ttl_secs: u64 },
     /// Terminal data received from relay (browser input -> shell)
     TerminalData { session_id: String, data: Vec<u8> },
-    /// Close session request from browser
-    CloseSession { session_id: String },
-    /// Create new session request from browser
-    CreateSession,
+    /// Close session request from browser (`force` overrides a lock)
+    CloseSession { session_id: String, force: bool },
+    /// Browser resized its view of a session
+    Resize { session_id: String, cols: u16, rows: u16 },
+    /// Browser locked or unlocked a session against closing
+    LockSession { session_id: String, locked: bool },
+    /// Browser set the encoding a session's output is transcoded from
+    /// (None = UTF-8 passthrough)
+    SetEncoding { session_id: String, encoding: Option<String> },
+    /// Create new session request from browser; `backend` "ssh" connects
+    /// to `user`@`host`. `capabilities` describes the requesting browser,
+    /// `profile` names a configured preset.
+    CreateSession {
+        term: Option<String>,
+        backend: Option<String>,
+        host: Option<String>,
+        user: Option<String>,
+        capabilities: Option<TerminalCapabilities>,
+        profile: Option<String>,
+    },
+    /// Clipboard text (base64) from browser for a session
+    Clipboard { session_id: String, data: String },
+    /// Browser asked for a session's working directory
+    GetCwd { session_id: String },
+    /// Browser asked for a session to be re-fitted to its terminal
+    AutoFit { session_id: String },
+    /// Browser asked for the session profiles
+    ListProfiles { browser_id: Option<String> },
+    /// Browser asked for a snapshot of a session's terminal
+    GetState { session_id: String, browser_id: Option<String> },
+    /// Browser answered a ConfirmPaste
+    ConfirmPasteAck { token: String, accept: bool },
 }
 
 /// Commands sent to RelayClient for sending data to relay.
@@ -42,8 +87,35 @@ pub enum RelayCommand {
     SendSessionDisconnected { session_id: String },
     /// Notify relay that a session resized (mac -> browser)
     SendSessionResize { session_id: String, cols: u16, rows: u16 },
+    /// Report liveness and load to the relay
+    SendHeartbeat { sessions: usize, load: f32 },
+    /// Set the label browsers show for this Mac (kept and resent after reconnects)
+    SetLabel { label: Option<String> },
+    /// Send an informational notice to browsers (e.g. "session ended")
+    SendNotice { session_id: Option<String>, message: String },
+    /// Send a session's working directory to browsers
+    SendCwd { session_id: String, path: String },
+    /// Send the session profiles (to one browser if given)
+    SendProfileList { browser_id: Option<String>, profiles: Vec<ProfileInfo> },
+    /// Send a snapshot of a session's terminal (to one browser if given)
+    SendState { session_id: String, browser_id: Option<String>, state: TerminalState },
+    /// Send keystrokes typed on the Mac to browsers, tagged as input
+    SendLocalInput { session_id: String, data: Vec<u8> },
+    /// Tell browsers a session was locked or unlocked
+    SendSessionLock { session_id: String, locked: bool },
+    /// Tell browsers which encoding a session is transcoded from
+    SendSessionEncoding { session_id: String, encoding: Option<String> },
+    /// Ask browsers to confirm multiline input held under `token`
+    SendConfirmPaste { session_id: String, token: String, preview: String },
+    /// Ask the relay for the connected browsers (answered with BrowserList)
+    ListBrowsers,
+    /// Ask the relay for a share code that expires after `ttl_secs`
+    CreateShare { ttl_secs: u64 },
     /// Disconnect and reconnect to get a new session code
     Reconnect,
+    /// Close the connection after everything queued before this, and stop
+    /// (`run` returns)
+    Shutdown,
 }
 
 /// WebSocket client for connecting to the relay server.
@@ -53,7 +125,35 @@ pub struct RelayClient {
     client_id: String,
     event_tx: Sender<RelayEvent>,
     command_rx: tokio::sync::mpsc::UnboundedReceiver<RelayCommand>,
+    /// Commands received while waiting to reconnect, handled before new ones
+    deferred: VecDeque<RelayCommand>,
     reconnect_attempts: u32,
+    /// Whether browsers have been told to pause input on this connection
+    input_paused: bool,
+    /// Aggregate output cap (None = unlimited). Queued output survives reconnects.
+    output_limiter: Option<OutputLimiter>,
+    /// Reconnect counters, shared with the diagnostics display
+    stats: Arc<Mutex<ReconnectStats>>,
+    /// Label for browsers, sent after each Register
+    label: Option<String>,
+    /// This Mac's environment, sent after each Register (REPORT_ENVIRONMENT)
+    environment: Option<ClientEnvironment>,
+    /// Argon2 hash of `SESSION_PASSWORD`, sent with Register
+    password_hash: Option<String>,
+    /// Fixed session code from `SESSION_CODE`, requested with Register
+    requested_code: Option<String>,
+    /// Code the relay last gave us, requested again on reconnect (unless
+    /// `requested_code` is set) so browsers don't need a new one after a blip
+    last_code: Option<String>,
+    /// A Shutdown command was received
+    shutting_down: bool,
+    /// The relay flags its binary frames on this connection (it sent Compression)
+    relay_compresses: bool,
+    /// We flag ours (we answered with Compression)
+    compress_output: bool,
+    /// Terminal sessions whose output frames we precede with an
+    /// OutputTimestamp (the relay sent EnableTimestamps on this connection)
+    timestamped: HashSet<String>,
 }
 
 impl RelayClient {
@@ -70,21 +170,63 @@ impl RelayClient {
     ) -> Self {
         let client_id = uuid::Uuid::new_v4().to_string();
         tracing::info!("Created RelayClient with client_id: {}", client_id);
+        let output_limiter = OutputLimiter::from_env();
+        if output_limiter.is_some() {
+            tracing::info!("Output to relay is rate limited (MAX_OUTPUT_RATE)");
+        }
+        let password_hash = std::env::var("SESSION_PASSWORD")
+            .ok()
+            .filter(|password| !password.is_empty())
+            .map(|password| hash_password(&password));
+        if password_hash.is_some() {
+            tracing::info!("Browsers must send the session password (SESSION_PASSWORD)");
+        }
+        let environment = environment::reporting_enabled().then(environment::detect);
+        if let Some(environment) = &environment {
+            tracing::info!("Reporting environment to the relay (REPORT_ENVIRONMENT): {:?}", environment);
+        }
+        let requested_code = std::env::var("SESSION_CODE")
+            .ok()
+            .map(|code| code.trim().to_ascii_uppercase())
+            .filter(|code| !code.is_empty());
 
         Self {
             relay_url,
             client_id,
             event_tx,
             command_rx,
+            deferred: VecDeque::new(),
             reconnect_attempts: 0,
+            input_paused: false,
+            output_limiter,
+            stats: Arc::new(Mutex::new(ReconnectStats::default())),
+            label: None,
+            environment,
+            password_hash,
+            requested_code,
+            last_code: None,
+            shutting_down: false,
+            relay_compresses: false,
+            compress_output: false,
+            timestamped: HashSet::new(),
         }
     }
 
+    /// Reconnect counters, updated by [`run`](Self::run).
+    pub fn reconnect_stats(&self) -> Arc<Mutex<ReconnectStats>> {
+        self.stats.clone()
+    }
+
     /// Main run loop. Connects to relay and auto-reconnects on disconnect.
-    /// This method runs forever (until the task is cancelled).
+    /// This method runs until a Shutdown command (or the task is cancelled).
     pub async fn run(&mut self) {
         loop {
-            match self.connect_and_run().await {
+            let result = self.connect_and_run().await;
+            if self.shutting_down {
+                tracing::info!("Relay client shut down");
+                return;
+            }
+            match result {
                 Ok(()) => {
                     // Clean disconnect, reconnect immediately
                     tracing::info!("Clean disconnect, reconnecting...");
@@ -94,17 +236,51 @@ impl RelayClient {
                 }
             }
 
+            self.stats.lock().unwrap().record_reconnect(std::time::Instant::now());
+
             // Notify main thread of disconnection
             let _ = self.event_tx.send(RelayEvent::Disconnected);
 
             // Exponential backoff: 1s, 2s, 4s, 8s, 16s, 32s max
             let delay_secs = (2u64).pow(self.reconnect_attempts.min(5));
             tracing::info!("Reconnecting in {}s...", delay_secs);
-            tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+            self.wait_to_reconnect(Duration::from_secs(delay_secs)).await;
+            if self.shutting_down {
+                tracing::info!("Relay client shut down");
+                return;
+            }
             self.reconnect_attempts += 1;
         }
     }
 
+    /// Sleep for `delay`, cut short by a Reconnect command (e.g. the network
+    /// changed). Other commands are kept for the next connection.
+    async fn wait_to_reconnect(&mut self, delay: Duration) {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return,
+                cmd = self.command_rx.recv() => match cmd {
+                    Some(RelayCommand::Reconnect) => {
+                        tracing::info!("Reconnect requested, not waiting");
+                        return;
+                    }
+                    Some(RelayCommand::Shutdown) => {
+                        // Deferred commands have no connection to go to
+                        self.shutting_down = true;
+                        return;
+                    }
+                    Some(cmd) => self.deferred.push_back(cmd),
+                    None => {
+                        sleep.await;
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
     /// Connect to relay, register, and handle messages until disconnected.
     async fn connect_and_run(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         tracing::info!("Connecting to relay: {}", self.relay_url);
@@ -118,30 +294,67 @@ impl RelayClient {
 
         // Reset reconnect attempts on successful connection
         self.reconnect_attempts = 0;
+        self.input_paused = false;
+        self.relay_compresses = false;
+        self.compress_output = false;
+        self.timestamped.clear();
 
         let (mut write, mut read) = ws_stream.split();
 
         // Send Register message
         let register_msg = ControlMessage::Register {
             client_id: self.client_id.clone(),
+            password_hash: self.password_hash.clone(),
+            requested_code: self.requested_code.clone().or_else(|| self.last_code.clone()),
+            compression: true,
         };
         let json = serde_json::to_string(&register_msg)?;
         tracing::debug!("Sending Register: {}", json);
         write.send(Message::Text(json.into())).await?;
+        if let Some(label) = self.label.clone() {
+            let json = serde_json::to_string(&ControlMessage::SetLabel { label })?;
+            write.send(Message::Text(json.into())).await?;
+        }
+        if let Some(environment) = self.environment.clone() {
+            let json = serde_json::to_string(&ControlMessage::Environment { environment })?;
+            write.send(Message::Text(json.into())).await?;
+        }
 
         // Message handling loop - select on both WebSocket and commands
         loop {
+            let next_send = self
+                .output_limiter
+                .as_ref()
+                .and_then(|limiter| limiter.next_ready(std::time::Instant::now()));
             tokio::select! {
+                // Send rate-limited output once the bucket has room
+                _ = tokio::time::sleep_until(next_send.unwrap_or_else(std::time::Instant::now).into()), if next_send.is_some() => {
+                    self.drain_output(&mut write).await;
+                }
+
                 // Handle incoming WebSocket messages
                 msg_result = read.next() => {
                     match msg_result {
                         Some(Ok(Message::Text(text))) => {
                             self.handle_text_message(&text)?;
+                            // The relay can take compressed output: switch ours over
+                            if self.relay_compresses && !self.compress_output {
+                                let json = serde_json::to_string(&ControlMessage::Compression)?;
+                                write.send(Message::Text(json.into())).await?;
+                                self.compress_output = true;
+                                tracing::debug!("Compressing output to relay");
+                            }
                         }
                         Some(Ok(Message::Binary(data))) => {
                             // Binary messages are terminal I/O from browser
                             // Frame format: 1 byte session_id length + session_id + data
-                            self.handle_binary_message(&data);
+                            if !self.relay_compresses {
+                                self.handle_binary_message(&data);
+                            } else if let Some(frame) = compression::decode(&data) {
+                                self.handle_binary_message(&frame);
+                            } else {
+                                tracing::warn!("Dropping malformed compressed frame ({} bytes)", data.len());
+                            }
                         }
                         Some(Ok(Message::Close(frame))) => {
                             tracing::info!("Received close frame: {:?}", frame);
@@ -169,10 +382,13 @@ impl RelayClient {
                 }
 
                 // Handle commands from IPC (send terminal data to relay)
-                cmd = self.command_rx.recv() => {
+                cmd = next_command(&mut self.deferred, &mut self.command_rx) => {
                     match cmd {
                         Some(RelayCommand::SendTerminalData { session_id, data }) => {
-                            if let Err(e) = Self::send_terminal_data(&mut write, &session_id, &data).await {
+                            if let Some(limiter) = self.output_limiter.as_mut() {
+                                limiter.push(&session_id, &data);
+                                self.drain_output(&mut write).await;
+                            } else if let Err(e) = Self::send_terminal_data(&mut write, &session_id, &data, self.compress_output, self.timestamped.contains(&session_id)).await {
                                 tracing::warn!("Failed to send terminal data: {}", e);
                             }
                         }
@@ -212,11 +428,104 @@ impl RelayClient {
                                 tracing::warn!("Failed to send session resize: {}", e);
                             }
                         }
+                        Some(RelayCommand::SendHeartbeat { sessions, load }) => {
+                            let msg = ControlMessage::Heartbeat { sessions, load };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::trace!("Sending Heartbeat: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send heartbeat: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SetLabel { label }) => {
+                            self.label.clone_from(&label);
+                            let msg = ControlMessage::SetLabel { label: label.unwrap_or_default() };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending SetLabel: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send label: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendNotice { session_id, message }) => {
+                            let msg = ControlMessage::Notice { session_id, message };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending Notice: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send notice: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendCwd { session_id, path }) => {
+                            let msg = ControlMessage::Cwd { session_id, path };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send cwd: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendProfileList { browser_id, profiles }) => {
+                            let msg = ControlMessage::ProfileList { browser_id, profiles };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send profile list: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendState { session_id, browser_id, state }) => {
+                            let msg = ControlMessage::State { session_id, browser_id, state };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send terminal state: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendLocalInput { session_id, data }) => {
+                            let msg = ControlMessage::LocalInput { session_id, data: STANDARD.encode(data) };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send local input: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendSessionLock { session_id, locked }) => {
+                            let msg = ControlMessage::LockSession { session_id, locked };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send session lock: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendSessionEncoding { session_id, encoding }) => {
+                            let msg = ControlMessage::SetEncoding { session_id, encoding };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send session encoding: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendConfirmPaste { session_id, token, preview }) => {
+                            let msg = ControlMessage::ConfirmPaste { session_id, token, preview };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send paste confirmation: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::ListBrowsers) => {
+                            let json = serde_json::to_string(&ControlMessage::ListBrowsers).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to request browser list: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::CreateShare { ttl_secs }) => {
+                            let msg = ControlMessage::CreateShare { ttl_secs };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to request share code: {}", e);
+                            }
+                        }
                         Some(RelayCommand::Reconnect) => {
                             tracing::info!("Reconnect requested, closing connection");
                             let _ = write.send(Message::Close(None)).await;
                             break;
                         }
+                        Some(RelayCommand::Shutdown) => {
+                            tracing::info!("Shutting down, closing connection");
+                            self.shutting_down = true;
+                            let _ = write.send(Message::Close(None)).await;
+                            break;
+                        }
                         None => {
                             tracing::info!("Command channel closed");
                             break;
@@ -224,27 +533,94 @@ impl RelayClient {
                     }
                 }
             }
+
+            // Queued output can drain without new output arriving (the
+            // limiter sends on a timer), so the pause is re-checked every time
+            self.update_flow_control(&mut write).await;
         }
 
         Ok(())
     }
 
+    /// Pause browser input while output queues faster than the uplink
+    /// drains it, and resume once it has caught up.
+    async fn update_flow_control<S>(&mut self, write: &mut S)
+    where
+        S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+    {
+        let queued = self.command_rx.len() + self.output_limiter.as_ref().map_or(0, OutputLimiter::len);
+        let pause = if self.input_paused {
+            queued > BACKPRESSURE_LOW_WATER
+        } else {
+            queued > BACKPRESSURE_HIGH_WATER
+        };
+        if pause != self.input_paused {
+            tracing::info!(queued, pause, "Relay uplink backpressure changed");
+            self.send_flow_control(write, pause).await;
+        }
+    }
+
+    /// Tell the relay to pause or resume browser input.
+    async fn send_flow_control<S>(&mut self, write: &mut S, pause: bool)
+    where
+        S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+    {
+        let msg = ControlMessage::FlowControl { pause };
+        let json = serde_json::to_string(&msg).unwrap();
+        tracing::debug!("Sending FlowControl: {}", json);
+        match write.send(Message::Text(json.into())).await {
+            Ok(()) => self.input_paused = pause,
+            Err(e) => tracing::warn!("Failed to send flow control: {}", e),
+        }
+    }
+
+    /// Send as much rate-limited output as the limiter allows right now.
+    async fn drain_output<S>(&mut self, write: &mut S)
+    where
+        S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+    {
+        let Some(limiter) = self.output_limiter.as_mut() else {
+            return;
+        };
+        while let Some((session_id, data)) = limiter.pop(std::time::Instant::now()) {
+            if let Err(e) = Self::send_terminal_data(write, &session_id, &data, self.compress_output, self.timestamped.contains(&session_id)).await {
+                tracing::warn!("Failed to send terminal data: {}", e);
+            }
+        }
+    }
+
     /// Send terminal data to relay for a specific session.
     ///
-    /// Frame format: 1 byte session_id length + session_id bytes + terminal data
+    /// Frame format: 1 byte session_id length + session_id bytes + terminal data,
+    /// flagged and possibly compressed with `compress`. With `stamp`, it is
+    /// preceded by an OutputTimestamp of when it was sent.
     async fn send_terminal_data<S>(
         write: &mut S,
         session_id: &str,
         data: &[u8],
+        compress: bool,
+        stamp: bool,
     ) -> Result<(), Box<dyn Error + Send + Sync>>
     where
         S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
     {
+        if stamp {
+            let msg = ControlMessage::OutputTimestamp {
+                session_id: session_id.to_string(),
+                timestamp_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as u64),
+                mac_timestamp_ms: None,
+            };
+            write.send(Message::Text(serde_json::to_string(&msg)?.into())).await?;
+        }
+
         // Frame format: 1 byte session_id length + session_id + data
         let mut frame = Vec::with_capacity(1 + session_id.len() + data.len());
         frame.push(session_id.len() as u8);
         frame.extend_from_slice(session_id.as_bytes());
         frame.extend_from_slice(data);
+        let frame = if compress { compression::encode(&frame) } else { frame };
 
         tracing::trace!(
             "Sending terminal data: session={}, {} bytes",
@@ -262,12 +638,16 @@ impl RelayClient {
     /// - Raw terminal input (keystrokes)
     /// - JSON control message (e.g., {"type":"resize","cols":80,"rows":24})
     fn handle_binary_message(&self, data: &[u8]) {
-        if data.len() < 2 {
-            tracing::warn!("Binary message too short: {} bytes", data.len());
+        let Some(&id_len) = data.first() else {
+            tracing::warn!("Empty binary message");
             return;
-        }
+        };
 
-        let id_len = data[0] as usize;
+        let id_len = id_len as usize;
+        if id_len == 0 {
+            tracing::warn!("Binary message has no session id ({} bytes)", data.len());
+            return;
+        }
         if data.len() < 1 + id_len {
             tracing::warn!(
                 "Binary message malformed: id_len={} but only {} bytes total",
@@ -280,6 +660,11 @@ impl RelayClient {
         let session_id = String::from_utf8_lossy(&data[1..1 + id_len]).to_string();
         let payload = &data[1 + id_len..];
 
+        if payload.is_empty() {
+            tracing::trace!("Dropping empty payload: session={}", session_id);
+            return;
+        }
+
         // Check if payload is a JSON control message (starts with '{')
         if payload.first() == Some(&b'{') {
             if let Ok(text) = std::str::from_utf8(payload) {
@@ -287,12 +672,26 @@ impl RelayClient {
                     let msg_type = json.get("type").and_then(|t| t.as_str());
 
                     if msg_type == Some("close_session") {
-                        tracing::info!("Received close_session: session={}", session_id);
+                        let force = json.get("force").and_then(|f| f.as_bool()).unwrap_or(false);
+                        tracing::info!("Received close_session: session={}, force={}", session_id, force);
                         let _ = self.event_tx.send(RelayEvent::CloseSession {
                             session_id,
+                            force,
                         });
                         return;
                     }
+
+                    if msg_type == Some("resize") {
+                        let dimension = |key| json.get(key).and_then(|v| v.as_u64()).and_then(|v| u16::try_from(v).ok());
+                        match (dimension("cols"), dimension("rows")) {
+                            (Some(cols), Some(rows)) if cols > 0 && rows > 0 => {
+                                tracing::debug!("Received resize: session={}, {}x{}", session_id, cols, rows);
+                                let _ = self.event_tx.send(RelayEvent::Resize { session_id, cols, rows });
+                            }
+                            _ => tracing::warn!("Invalid resize for session {}: {}", session_id, text),
+                        }
+                        return;
+                    }
                 }
             }
         }
@@ -311,7 +710,7 @@ impl RelayClient {
     }
 
     /// Handle a text message from the relay server.
-    fn handle_text_message(&self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    fn handle_text_message(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         tracing::debug!("Received text message: {}", text);
 
         let msg: ControlMessage = serde_json::from_str(text)?;
@@ -319,6 +718,12 @@ impl RelayClient {
         match msg {
             ControlMessage::Registered { code } => {
                 tracing::info!("Registered with session code: {}", code);
+                if let Some(requested) = self.requested_code.as_ref().filter(|requested| **requested != code) {
+                    tracing::warn!("Requested session code {} is invalid or in use, got {}", requested, code);
+                } else if let Some(last) = self.last_code.as_ref().filter(|last| **last != code) {
+                    tracing::warn!("Previous session code {} is in use, browsers need the new code {}", last, code);
+                }
+                self.last_code = Some(code.clone());
                 let _ = self.event_tx.send(RelayEvent::SessionCode(code));
             }
             ControlMessage::BrowserConnected { browser_id } => {
@@ -329,13 +734,61 @@ impl RelayClient {
                 tracing::info!("Browser disconnected: {}", browser_id);
                 let _ = self.event_tx.send(RelayEvent::BrowserDisconnected(browser_id));
             }
+            ControlMessage::BrowserList { browsers } => {
+                tracing::debug!("{} browsers connected", browsers.len());
+                let _ = self.event_tx.send(RelayEvent::BrowserList(browsers));
+            }
+            ControlMessage::ShareCreated { code, ttl_secs } => {
+                tracing::info!("Share code created, valid for {}s", ttl_secs);
+                let _ = self.event_tx.send(RelayEvent::ShareCreated { code, ttl_secs });
+            }
             ControlMessage::Error { message } => {
                 tracing::error!("Relay error: {}", message);
                 let _ = self.event_tx.send(RelayEvent::Error(message));
             }
-            ControlMessage::CreateSession => {
+            ControlMessage::CreateSession { term, backend, host, user, capabilities, profile } => {
                 tracing::info!("Received create_session request from browser");
-                let _ = self.event_tx.send(RelayEvent::CreateSession);
+                let _ = self.event_tx.send(RelayEvent::CreateSession { term, backend, host, user, capabilities, profile });
+            }
+            ControlMessage::Clipboard { session_id, data } => {
+                tracing::debug!("Received clipboard for session {}", session_id);
+                let _ = self.event_tx.send(RelayEvent::Clipboard { session_id, data });
+            }
+            ControlMessage::GetCwd { session_id } => {
+                let _ = self.event_tx.send(RelayEvent::GetCwd { session_id });
+            }
+            ControlMessage::AutoFit { session_id } => {
+                let _ = self.event_tx.send(RelayEvent::AutoFit { session_id });
+            }
+            ControlMessage::ListProfiles { browser_id } => {
+                let _ = self.event_tx.send(RelayEvent::ListProfiles { browser_id });
+            }
+            ControlMessage::GetState { session_id, browser_id } => {
+                tracing::debug!("Received get_state for session {}", session_id);
+                let _ = self.event_tx.send(RelayEvent::GetState { session_id, browser_id });
+            }
+            ControlMessage::LockSession { session_id, locked } => {
+                tracing::info!("Received lock_session: session={}, locked={}", session_id, locked);
+                let _ = self.event_tx.send(RelayEvent::LockSession { session_id, locked });
+            }
+            ControlMessage::SetEncoding { session_id, encoding } => {
+                tracing::info!("Received set_encoding: session={}, encoding={:?}", session_id, encoding);
+                let _ = self.event_tx.send(RelayEvent::SetEncoding { session_id, encoding });
+            }
+            ControlMessage::ConfirmPasteAck { token, accept } => {
+                let _ = self.event_tx.send(RelayEvent::ConfirmPasteAck { token, accept });
+            }
+            ControlMessage::EnableTimestamps { session_id } => {
+                tracing::debug!("Timestamping output of session {}", session_id);
+                self.timestamped.insert(session_id);
+            }
+            ControlMessage::DisableTimestamps { session_id } => {
+                tracing::debug!("No longer timestamping output of session {}", session_id);
+                self.timestamped.remove(&session_id);
+            }
+            ControlMessage::Compression => {
+                tracing::debug!("Relay compresses its frames");
+                self.relay_compresses = true;
             }
             // Other message types are for browser<->relay communication
             _ => {
@@ -347,6 +800,28 @@ impl RelayClient {
     }
 }
 
+/// The next command: deferred ones first, then new ones from the channel.
+async fn next_command(
+    deferred: &mut VecDeque<RelayCommand>,
+    command_rx: &mut tokio::sync::mpsc::UnboundedReceiver<RelayCommand>,
+) -> Option<RelayCommand> {
+    match deferred.pop_front() {
+        Some(cmd) => Some(cmd),
+        None => command_rx.recv().await,
+    }
+}
+
+/// Argon2id hash (PHC string) of the session password, with a fresh salt
+/// each run. Only the hash goes to the relay.
+fn hash_password(password: &str) -> String {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    let salt = SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes()).expect("16-byte salt");
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default Argon2 parameters")
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -384,4 +859,347 @@ mod tests {
         assert!(uuid::Uuid::parse_str(&client.client_id).is_ok());
         assert_eq!(client.reconnect_attempts, 0);
     }
+
+    fn test_client() -> (RelayClient, std::sync::mpsc::Receiver<RelayEvent>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        (RelayClient::new("ws://localhost:3000/ws".into(), tx, cmd_rx), rx)
+    }
+
+    #[test]
+    fn test_binary_message_degenerate_frames_dropped() {
+        let (client, rx) = test_client();
+
+        client.handle_binary_message(&[]);
+        // Just the length byte: zero-length id, or id bytes missing
+        client.handle_binary_message(&[0]);
+        client.handle_binary_message(&[5]);
+        // Session id but no payload
+        client.handle_binary_message(&[2, b's', b'1']);
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_binary_message_minimal_frame() {
+        let (client, rx) = test_client();
+
+        client.handle_binary_message(&[1, b's', b'x']);
+
+        match rx.try_recv() {
+            Ok(RelayEvent::TerminalData { session_id, data }) => {
+                assert_eq!(session_id, "s");
+                assert_eq!(data, b"x");
+            }
+            other => panic!("Expected TerminalData, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_binary_message_resize() {
+        let (client, rx) = test_client();
+
+        client.handle_binary_message(b"\x02s1{\"type\":\"resize\",\"cols\":120,\"rows\":40}");
+        match rx.try_recv() {
+            Ok(RelayEvent::Resize { session_id, cols, rows }) => {
+                assert_eq!(session_id, "s1");
+                assert_eq!((cols, rows), (120, 40));
+            }
+            other => panic!("Expected Resize, got {:?}", other),
+        }
+
+        // Bad dimensions are dropped, not typed into the shell
+        client.handle_binary_message(b"\x02s1{\"type\":\"resize\",\"cols\":0,\"rows\":40}");
+        client.handle_binary_message(b"\x02s1{\"type\":\"resize\",\"cols\":70000,\"rows\":40}");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_binary_message_close_session() {
+        let (client, rx) = test_client();
+
+        client.handle_binary_message(b"\x02s1{\"type\":\"close_session\"}");
+        client.handle_binary_message(b"\x02s1{\"type\":\"close_session\",\"force\":true}");
+
+        for expected in [false, true] {
+            match rx.try_recv() {
+                Ok(RelayEvent::CloseSession { session_id, force }) => {
+                    assert_eq!(session_id, "s1");
+                    assert_eq!(force, expected);
+                }
+                other => panic!("Expected CloseSession, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_password() {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+        let hash = hash_password("s3cret");
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("s3cret"), "salt is random");
+        let parsed = PasswordHash::new(&hash).unwrap();
+        assert!(argon2::Argon2::default().verify_password(b"s3cret", &parsed).is_ok());
+        assert!(argon2::Argon2::default().verify_password(b"wrong", &parsed).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_preserves_client_id() {
+        // Minimal relay: record the Register client_id, then close so the
+        // client has to reconnect.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let mut client_ids = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                if let Some(Ok(Message::Text(text))) = ws.next().await {
+                    if let Ok(ControlMessage::Register { client_id, .. }) = serde_json::from_str(&text) {
+                        client_ids.push(client_id);
+                    }
+                }
+                let _ = ws.close(None).await;
+            }
+            client_ids
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        let expected = client.client_id.clone();
+        let client_task = tokio::spawn(async move { client.run().await });
+
+        let client_ids = tokio::time::timeout(Duration::from_secs(10), relay)
+            .await
+            .expect("client did not reconnect in time")
+            .unwrap();
+        client_task.abort();
+
+        assert_eq!(client_ids, vec![expected.clone(), expected]);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_requests_last_code() {
+        // Minimal relay: record the code each Register asks for, grant a
+        // different one the second time (as if the old code were taken),
+        // then close so the client has to reconnect.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let mut requested = Vec::new();
+            for granted in ["AAA111", "BBB222", "BBB222"] {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                if let Some(Ok(Message::Text(text))) = ws.next().await {
+                    if let Ok(ControlMessage::Register { requested_code, .. }) = serde_json::from_str(&text) {
+                        requested.push(requested_code);
+                    }
+                }
+                let registered = ControlMessage::Registered { code: granted.into() };
+                ws.send(Message::Text(serde_json::to_string(&registered).unwrap().into())).await.unwrap();
+                let _ = ws.close(None).await;
+            }
+            requested
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        let client_task = tokio::spawn(async move { client.run().await });
+
+        let requested = tokio::time::timeout(Duration::from_secs(15), relay)
+            .await
+            .expect("client did not reconnect in time")
+            .unwrap();
+        client_task.abort();
+
+        // Reused when granted, replaced by the new code when not
+        assert_eq!(requested, [None, Some("AAA111".into()), Some("BBB222".into())]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_after_queued_output() {
+        // Minimal relay: record what arrives until the close frame
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut received = Vec::new();
+            while let Some(Ok(msg)) = ws.next().await {
+                match msg {
+                    Message::Binary(_) => received.push("output"),
+                    Message::Close(_) => {
+                        received.push("close");
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            received
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        cmd_tx.send(RelayCommand::SendTerminalData { session_id: "s".into(), data: b"bye".to_vec() }).unwrap();
+        cmd_tx.send(RelayCommand::Shutdown).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), client.run())
+            .await
+            .expect("client did not stop");
+        assert_eq!(relay.await.unwrap(), ["output", "close"]);
+    }
+
+    #[tokio::test]
+    async fn test_output_compressed_after_switch() {
+        // Minimal relay that supports compression: answer Register, then
+        // return the first output frame after the client switches over
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("Expected Register");
+            };
+            assert!(matches!(serde_json::from_str(&text), Ok(ControlMessage::Register { compression: true, .. })));
+            for msg in [ControlMessage::Registered { code: "ABC234".into() }, ControlMessage::Compression] {
+                let json = serde_json::to_string(&msg).unwrap();
+                ws.send(Message::Text(json.into())).await.unwrap();
+            }
+            let mut switched = false;
+            while let Some(Ok(msg)) = ws.next().await {
+                match msg {
+                    Message::Text(text) if text.contains("\"compression\"") => switched = true,
+                    Message::Binary(data) if switched => return data.to_vec(),
+                    _ => {}
+                }
+            }
+            panic!("connection closed");
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        let client_task = tokio::spawn(async move { client.run().await });
+
+        // A screenful of the same line, sent until the switch has happened
+        let data = b"drwxr-xr-x  12 user  staff   384 Oct 18 09:12 .\r\n".repeat(60);
+        let frame = loop {
+            cmd_tx.send(RelayCommand::SendTerminalData { session_id: "s1".into(), data: data.clone() }).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if relay.is_finished() {
+                break relay.await.unwrap();
+            }
+        };
+        client_task.abort();
+
+        assert_eq!(frame[0], compression::GZIP);
+        assert!(frame.len() < data.len() / 5);
+        assert_eq!(compression::decode(&frame).unwrap(), [b"\x02s1", &data[..]].concat());
+    }
+
+    #[tokio::test]
+    async fn test_output_timestamped_after_enable() {
+        // Minimal relay: answer Register and ask for s1's output to be
+        // stamped, then return what precedes the first frames of s1 and s2
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let Some(Ok(Message::Text(_register))) = ws.next().await else {
+                panic!("Expected Register");
+            };
+            let enable = ControlMessage::EnableTimestamps { session_id: "s1".into() };
+            for msg in [ControlMessage::Registered { code: "ABC234".into() }, enable] {
+                let json = serde_json::to_string(&msg).unwrap();
+                ws.send(Message::Text(json.into())).await.unwrap();
+            }
+            let mut stamp = None;
+            let mut unstamped_s2 = false;
+            while let Some(Ok(msg)) = ws.next().await {
+                match msg {
+                    Message::Text(text) => match serde_json::from_str(&text) {
+                        Ok(ControlMessage::OutputTimestamp { session_id, timestamp_ms, .. }) => {
+                            stamp = Some((session_id, timestamp_ms))
+                        }
+                        _ => stamp = None,
+                    },
+                    Message::Binary(data) if data[1..].starts_with(b"s1") => {
+                        if let Some(stamp) = stamp.take() {
+                            return (stamp, unstamped_s2);
+                        }
+                    }
+                    Message::Binary(_) => unstamped_s2 = stamp.take().is_none(),
+                    _ => {}
+                }
+            }
+            panic!("connection closed");
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        let client_task = tokio::spawn(async move { client.run().await });
+
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let ((session_id, timestamp_ms), unstamped_s2) = loop {
+            cmd_tx.send(RelayCommand::SendTerminalData { session_id: "s2".into(), data: b"b".to_vec() }).unwrap();
+            cmd_tx.send(RelayCommand::SendTerminalData { session_id: "s1".into(), data: b"a".to_vec() }).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if relay.is_finished() {
+                break relay.await.unwrap();
+            }
+        };
+        client_task.abort();
+
+        assert_eq!(session_id, "s1");
+        assert!(timestamp_ms >= before && timestamp_ms < before + 10_000, "{}", timestamp_ms);
+        assert!(unstamped_s2);
+    }
+
+    #[tokio::test]
+    async fn test_input_resumed_when_queued_output_drains() {
+        // Minimal relay: answer Register and record flow control changes
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let json = serde_json::to_string(&ControlMessage::Registered { code: "ABC234".into() }).unwrap();
+            ws.send(Message::Text(json.into())).await.unwrap();
+            let mut changes = Vec::new();
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Text(text) = msg {
+                    if let Ok(ControlMessage::FlowControl { pause }) = serde_json::from_str(&text) {
+                        changes.push(pause);
+                        if !pause {
+                            return changes;
+                        }
+                    }
+                }
+            }
+            panic!("connection closed");
+        });
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = RelayClient::new(format!("ws://{}/ws", addr), tx, cmd_rx);
+        // A one-second bucket of 512 chunks, so one burst of three times
+        // that leaves the queue well over the high-water mark
+        client.output_limiter = Some(OutputLimiter::new(2 * 1024 * 1024));
+        let client_task = tokio::spawn(async move { client.run().await });
+
+        // A single burst, then nothing: the limiter drains the rest on its own
+        cmd_tx.send(RelayCommand::SendTerminalData { session_id: "s1".into(), data: vec![b'x'; 6 * 1024 * 1024] }).unwrap();
+        let changes = tokio::time::timeout(Duration::from_secs(10), relay)
+            .await
+            .expect("input was never resumed")
+            .unwrap();
+        client_task.abort();
+        assert_eq!(changes, [true, false]);
+    }
 }
@@ -1,2 +1,8 @@
+mod compression;
 mod connection;
+mod rate_limit;
+mod stats;
+mod throughput;
 pub use connection::{RelayClient, RelayCommand, RelayEvent};
+pub use stats::ReconnectStats;
+pub use throughput::{ByteCounters, MovingAverage};
@@ -0,0 +1,268 @@
+//! Aggregate cap on terminal output sent to the relay.
+//!
+//! A token bucket limits total bytes/sec across all sessions, to keep busy
+//! sessions from saturating a metered uplink. While output is queued, the
+//! session that has sent the least recently goes next, so typing echo in one
+//! shell isn't stuck behind a bulk `cat` in another. Queued output is
+//! bounded: past [`MAX_QUEUED_BYTES`], the busiest session's oldest output
+//! is dropped and marked as skipped.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Output is queued and sent in chunks of at most this many bytes, so one
+/// large frame can't hold the bucket for long
+const MAX_CHUNK: usize = 4096;
+
+/// Most output bytes queued across all sessions. A shell printing faster
+/// than the cap (`yes`, `cat /dev/urandom`) would otherwise grow the
+/// queue without bound.
+const MAX_QUEUED_BYTES: usize = 8 * 1024 * 1024;
+
+/// Stands in for output dropped to stay within the queue budget
+const SKIPPED: &[u8] = b"\r\n[output skipped]\r\n";
+
+/// How quickly a session's recent-output count decays (halves every second)
+const RECENT_HALF_LIFE: Duration = Duration::from_secs(1);
+
+/// Token-bucket limiter with per-session queues.
+#[derive(Debug)]
+pub struct OutputLimiter {
+    /// Refill rate in bytes/sec
+    rate: f64,
+    /// Bucket size: one second of output, but at least one chunk
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+    /// Output waiting for tokens, per session id
+    queues: HashMap<String, VecDeque<Vec<u8>>>,
+    /// Bytes each session sent recently, decaying with [`RECENT_HALF_LIFE`]
+    recent: HashMap<String, f64>,
+    /// Total chunks across all queues
+    queued: usize,
+    /// Total bytes across all queues
+    queued_bytes: usize,
+    /// Most bytes queued before output is dropped
+    budget: usize,
+}
+
+impl OutputLimiter {
+    /// Limit total output to `bytes_per_sec`.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let capacity = rate.max(MAX_CHUNK as f64);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+            queues: HashMap::new(),
+            recent: HashMap::new(),
+            queued: 0,
+            queued_bytes: 0,
+            budget: MAX_QUEUED_BYTES,
+        }
+    }
+
+    /// Limiter configured by `MAX_OUTPUT_RATE` (bytes/sec), or None when
+    /// unset, meaning output is unlimited.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var("MAX_OUTPUT_RATE").ok()?;
+        match value.trim().parse::<u64>() {
+            Ok(rate) if rate > 0 => Some(Self::new(rate)),
+            _ => {
+                tracing::warn!("Invalid MAX_OUTPUT_RATE {:?}, output is unlimited", value);
+                None
+            }
+        }
+    }
+
+    /// Queue output for a session, dropping the oldest output of the
+    /// session with the most queued if that goes over the budget.
+    pub fn push(&mut self, session_id: &str, data: &[u8]) {
+        let queue = self.queues.entry(session_id.to_string()).or_default();
+        for chunk in data.chunks(MAX_CHUNK) {
+            queue.push_back(chunk.to_vec());
+            self.queued += 1;
+            self.queued_bytes += chunk.len();
+        }
+        while self.queued_bytes > self.budget && self.skip_oldest() {}
+    }
+
+    /// Number of queued chunks.
+    pub fn len(&self) -> usize {
+        self.queued
+    }
+
+    /// Drop the oldest chunk of the session with the most queued, leaving
+    /// a [`SKIPPED`] marker in front of its queue. Returns false when
+    /// there's nothing left to drop.
+    fn skip_oldest(&mut self) -> bool {
+        let Some((session_id, queue)) = self
+            .queues
+            .iter_mut()
+            .max_by_key(|(_, queue)| queue.iter().map(Vec::len).sum::<usize>())
+        else {
+            return false;
+        };
+        // Keep the marker and the newest chunk
+        let skip = usize::from(queue.front().is_some_and(|chunk| chunk == SKIPPED));
+        if queue.len() <= skip + 1 {
+            return false;
+        }
+        let dropped = queue.remove(skip).unwrap_or_default();
+        self.queued -= 1;
+        self.queued_bytes -= dropped.len();
+        if skip == 0 {
+            tracing::warn!(session_id = %session_id, "Output queue over budget, skipping the oldest output");
+            queue.push_front(SKIPPED.to_vec());
+            self.queued += 1;
+            self.queued_bytes += SKIPPED.len();
+        }
+        true
+    }
+
+    /// Take the next chunk to send, if the bucket has room for it at `now`.
+    /// Picks the queued session that has sent the least recently.
+    pub fn pop(&mut self, now: Instant) -> Option<(String, Vec<u8>)> {
+        self.refill(now);
+
+        let session_id = self.next_session()?.to_string();
+        let queue = self.queues.get_mut(&session_id)?;
+        let len = queue.front()?.len() as f64;
+        if self.tokens < len {
+            return None;
+        }
+
+        let chunk = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&session_id);
+        }
+        self.queued -= 1;
+        self.queued_bytes -= chunk.len();
+        self.tokens -= len;
+        *self.recent.entry(session_id.clone()).or_default() += len;
+        Some((session_id, chunk))
+    }
+
+    /// When the next queued chunk can be sent (None if nothing is queued).
+    pub fn next_ready(&self, now: Instant) -> Option<Instant> {
+        // Decay scales every session equally, so the pick won't change by then
+        let needed = self.queues.get(self.next_session()?)?.front()?.len() as f64;
+        let tokens = self.tokens_at(now);
+        if tokens >= needed {
+            return Some(now);
+        }
+        Some(now + Duration::from_secs_f64((needed - tokens) / self.rate))
+    }
+
+    /// Queued session that has sent the least recently
+    fn next_session(&self) -> Option<&str> {
+        self.queues
+            .keys()
+            .min_by(|a, b| self.recent_bytes(a).total_cmp(&self.recent_bytes(b)))
+            .map(String::as_str)
+    }
+
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * self.rate).min(self.capacity)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.tokens = self.tokens_at(now);
+
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        let decay = 0.5f64.powf(elapsed / RECENT_HALF_LIFE.as_secs_f64());
+        let queues = &self.queues;
+        self.recent.retain(|id, bytes| {
+            *bytes *= decay;
+            *bytes >= 1.0 || queues.contains_key(id)
+        });
+        self.last_refill = now;
+    }
+
+    fn recent_bytes(&self, session_id: &str) -> f64 {
+        self.recent.get(session_id).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_limits_rate() {
+        let mut limiter = OutputLimiter::new(8192);
+        let start = Instant::now();
+        limiter.push("a", &[b'x'; 3 * MAX_CHUNK]);
+        assert_eq!(limiter.len(), 3);
+
+        // A full bucket allows one second's worth immediately
+        assert!(limiter.pop(start).is_some());
+        assert!(limiter.pop(start).is_some());
+        assert!(limiter.pop(start).is_none());
+
+        let ready = limiter.next_ready(start).unwrap();
+        assert_eq!(ready - start, Duration::from_millis(500));
+        assert!(limiter.pop(ready).is_some());
+        assert_eq!(limiter.len(), 0);
+        assert!(limiter.next_ready(ready).is_none());
+    }
+
+    #[test]
+    fn test_interactive_output_goes_first() {
+        let mut limiter = OutputLimiter::new(MAX_CHUNK as u64);
+        let start = Instant::now();
+
+        // Bulk session drains the bucket, then keeps queuing
+        limiter.push("bulk", &[b'x'; 4 * MAX_CHUNK]);
+        assert_eq!(limiter.pop(start).unwrap().0, "bulk");
+
+        // A keystroke echo queued behind it is sent next
+        limiter.push("typing", b"l");
+        let ready = limiter.next_ready(start).unwrap();
+        assert!(ready - start < Duration::from_millis(1));
+        assert_eq!(limiter.pop(ready).unwrap(), ("typing".to_string(), b"l".to_vec()));
+    }
+
+    #[test]
+    fn test_queue_stays_within_budget() {
+        let mut limiter = OutputLimiter::new(1);
+        limiter.budget = 16 * MAX_CHUNK;
+
+        // `yes` far outpacing the cap, next to an interactive session
+        limiter.push("typing", b"$ ");
+        for i in 0..1000u32 {
+            limiter.push("bulk", &[b'0' + (i % 10) as u8; MAX_CHUNK]);
+            assert!(limiter.queued_bytes <= limiter.budget);
+        }
+
+        // Only the bulk session lost output: a marker, then its newest chunks
+        let mut sent: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        let mut at = Instant::now();
+        while let Some(ready) = limiter.next_ready(at) {
+            at = ready;
+            let (session_id, chunk) = limiter.pop(at).unwrap();
+            sent.entry(session_id).or_default().push(chunk);
+        }
+        assert_eq!(sent["typing"], [b"$ ".to_vec()]);
+        let bulk = &sent["bulk"];
+        assert_eq!(bulk[0], SKIPPED);
+        assert_eq!(bulk.len(), 16);
+        assert_eq!(bulk.last().unwrap()[0], b'9');
+        assert_eq!(limiter.queued_bytes, 0);
+        assert_eq!(limiter.len(), 0);
+    }
+
+    #[test]
+    fn test_session_output_stays_in_order() {
+        let mut limiter = OutputLimiter::new(1_000_000);
+        let now = Instant::now();
+        limiter.push("a", b"one");
+        limiter.push("a", b"two");
+
+        assert_eq!(limiter.pop(now).unwrap().1, b"one");
+        assert_eq!(limiter.pop(now).unwrap().1, b"two");
+    }
+}
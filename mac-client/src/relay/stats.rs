@@ -0,0 +1,113 @@
+//! Reconnect counters for diagnosing flaky links.
+//!
+//! `RelayClient` records every reconnect; the menu and a periodic log line
+//! show how many happened in the last hour and when the link last dropped,
+//! so a user can tell a flapping tunnel or wifi from a one-off blip.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Reconnects older than this drop out of the count
+pub const RECONNECT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Reconnects of the relay connection.
+#[derive(Debug, Default)]
+pub struct ReconnectStats {
+    /// When each reconnect within [`RECONNECT_WINDOW`] happened, oldest first
+    recent: VecDeque<Instant>,
+    /// Reconnects since startup
+    total: u64,
+    last_disconnect: Option<Instant>,
+}
+
+impl ReconnectStats {
+    /// Record that the connection dropped at `now` and is being re-established.
+    pub fn record_reconnect(&mut self, now: Instant) {
+        self.prune(now);
+        self.recent.push_back(now);
+        self.total += 1;
+        self.last_disconnect = Some(now);
+    }
+
+    /// Reconnects within [`RECONNECT_WINDOW`] of `now`.
+    pub fn in_last_hour(&self, now: Instant) -> usize {
+        self.recent
+            .iter()
+            .filter(|at| now.saturating_duration_since(**at) < RECONNECT_WINDOW)
+            .count()
+    }
+
+    /// Reconnects since startup.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Time since the connection last dropped, if it ever has.
+    pub fn since_last_disconnect(&self, now: Instant) -> Option<Duration> {
+        self.last_disconnect
+            .map(|at| now.saturating_duration_since(at))
+    }
+
+    /// One-line summary, e.g. "12 reconnects in last hour, last disconnect 5m ago".
+    pub fn summary(&self, now: Instant) -> String {
+        let Some(since) = self.since_last_disconnect(now) else {
+            return "No reconnects".to_string();
+        };
+        let count = self.in_last_hour(now);
+        format!(
+            "{} reconnect{} in last hour, last disconnect {} ago",
+            count,
+            if count == 1 { "" } else { "s" },
+            format_age(since)
+        )
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) >= RECONNECT_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+}
+
+/// Coarse age such as "42s", "5m" or "3h".
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_reconnects_in_last_hour() {
+        let mut stats = ReconnectStats::default();
+        let start = Instant::now();
+        assert_eq!(stats.summary(start), "No reconnects");
+
+        stats.record_reconnect(start);
+        for i in 1..=12 {
+            stats.record_reconnect(start + Duration::from_secs(3600 + i));
+        }
+        let now = start + Duration::from_secs(3600 + 12 + 300);
+        assert_eq!(stats.in_last_hour(now), 12);
+        assert_eq!(stats.total(), 13);
+        assert_eq!(stats.since_last_disconnect(now), Some(Duration::from_secs(300)));
+        assert_eq!(stats.summary(now), "12 reconnects in last hour, last disconnect 5m ago");
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(format_age(Duration::from_secs(60)), "1m");
+        assert_eq!(format_age(Duration::from_secs(2 * 3600 + 59)), "2h");
+    }
+}
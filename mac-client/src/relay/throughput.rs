@@ -0,0 +1,93 @@
+//! Terminal throughput for the menu.
+//!
+//! The forwarding paths count the bytes they relay in a shared
+//! [`ByteCounters`]; once a second the counts are taken and fed into a
+//! [`MovingAverage`], which smooths bursty output into a steady reading.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// One-second samples averaged for the display
+pub const THROUGHPUT_SAMPLES: usize = 3;
+
+/// Bytes relayed since the last [`take`](Self::take).
+#[derive(Debug, Default)]
+pub struct ByteCounters {
+    /// Shell output sent to browsers
+    up: AtomicU64,
+    /// Browser input sent to shells
+    down: AtomicU64,
+}
+
+impl ByteCounters {
+    pub fn add_up(&self, bytes: usize) {
+        self.up.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_down(&self, bytes: usize) {
+        self.down.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes (up, down) counted since the last call, resetting the counts.
+    pub fn take(&self) -> (u64, u64) {
+        (self.up.swap(0, Ordering::Relaxed), self.down.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// Average of the last `capacity` per-interval byte counts.
+#[derive(Debug)]
+pub struct MovingAverage {
+    samples: VecDeque<(u64, u64)>,
+    capacity: usize,
+}
+
+impl MovingAverage {
+    pub fn new(capacity: usize) -> Self {
+        Self { samples: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+    }
+
+    /// Add the (up, down) bytes of one interval, dropping the oldest sample
+    /// once full. Returns the average (up, down) bytes per interval.
+    pub fn push(&mut self, up: u64, down: u64) -> (u64, u64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((up, down));
+        let n = self.samples.len() as u64;
+        let (up, down) = self
+            .samples
+            .iter()
+            .fold((0, 0), |(up, down), (u, d)| (up + u, down + d));
+        (up / n, down / n)
+    }
+}
+
+impl Default for MovingAverage {
+    fn default() -> Self {
+        Self::new(THROUGHPUT_SAMPLES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moving_average() {
+        let mut average = MovingAverage::new(3);
+        assert_eq!(average.push(3000, 30), (3000, 30));
+        assert_eq!(average.push(0, 0), (1500, 15));
+        assert_eq!(average.push(6000, 0), (3000, 10));
+        // The first sample drops out
+        assert_eq!(average.push(0, 0), (2000, 0));
+        assert_eq!(average.push(0, 0), (2000, 0));
+        assert_eq!(average.push(0, 0), (0, 0));
+
+        let counters = ByteCounters::default();
+        counters.add_up(10);
+        counters.add_up(5);
+        counters.add_down(2);
+        assert_eq!(counters.take(), (15, 2));
+        assert_eq!(counters.take(), (0, 0));
+    }
+}
@@ -0,0 +1,132 @@
+//! SSH sessions to remote hosts.
+//!
+//! A browser can ask for a new session that connects to a remote host,
+//! making the Mac a jump host. `ssh` runs as the new session's command like
+//! any other browser-created session: in a headless PTY the mac-client spawns
+//! (`PtyCommand::SpawnShell`), or in a new Terminal window captured by
+//! pty-proxy when `OPEN_TERMINAL_WINDOW` is set. Either way it streams through
+//! the usual relay pipeline.
+
+/// Seconds ssh waits for the remote host before giving up
+const CONNECT_TIMEOUT_SECS: u32 = 10;
+
+/// Longest accepted user or host name
+const MAX_NAME_LEN: usize = 253;
+
+/// A validated `[user@]host[:port]` from a browser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshTarget {
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl SshTarget {
+    /// Validate a browser-supplied user and host (`name`, `name:port`,
+    /// `[v6]:port` or a bare IPv6 address). Both end up on a shell command
+    /// line, so only plain names are accepted.
+    pub fn new(user: Option<&str>, host: &str) -> Result<Self, String> {
+        let user = user.map(str::trim).filter(|u| !u.is_empty());
+        if let Some(user) = user {
+            if !is_plain_name(user, |c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+                return Err(format!("Invalid SSH user {:?}", user));
+            }
+        }
+
+        let host = host.trim();
+        let (name, port) = if let Some(rest) = host.strip_prefix('[') {
+            let (name, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid SSH host {:?}", host))?;
+            let port = match rest {
+                "" => None,
+                _ => Some(rest.strip_prefix(':').unwrap_or(rest)),
+            };
+            (name, port)
+        } else {
+            match host.split_once(':') {
+                // One colon: host:port. More: a bare IPv6 address.
+                Some((name, port)) if !port.contains(':') => (name, Some(port)),
+                _ => (host, None),
+            }
+        };
+        if !is_plain_name(name, |c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':')) {
+            return Err(format!("Invalid SSH host {:?}", host));
+        }
+        let port = match port {
+            Some(port) => Some(
+                port.parse::<u16>()
+                    .ok()
+                    .filter(|p| *p > 0)
+                    .ok_or_else(|| format!("Invalid SSH port {:?}", port))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            user: user.map(str::to_string),
+            host: name.to_string(),
+            port,
+        })
+    }
+
+    /// `user@host`, or just the host
+    pub fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Shell command that connects, leaving a note in the terminal if ssh
+    /// fails (unreachable host, refused, auth failure).
+    pub fn command(&self) -> String {
+        let port = self.port.map(|p| format!(" -p {}", p)).unwrap_or_default();
+        let destination = self.destination();
+        format!(
+            "ssh -o ConnectTimeout={}{} -- {} || echo '[ssh to {} failed]'",
+            CONNECT_TIMEOUT_SECS, port, destination, destination
+        )
+    }
+}
+
+/// Non-empty, not an option (leading `-`), and only `allowed` characters
+fn is_plain_name(name: &str, allowed: impl Fn(char) -> bool) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('-')
+        && name.chars().all(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_targets() {
+        let target = SshTarget::new(Some("deploy"), "build-01.example.com").unwrap();
+        assert_eq!(
+            target.command(),
+            "ssh -o ConnectTimeout=10 -- deploy@build-01.example.com || echo '[ssh to deploy@build-01.example.com failed]'"
+        );
+
+        let target = SshTarget::new(None, "10.0.0.5:2222").unwrap();
+        assert_eq!(target.destination(), "10.0.0.5");
+        assert!(target.command().starts_with("ssh -o ConnectTimeout=10 -p 2222 -- 10.0.0.5 "));
+
+        assert_eq!(SshTarget::new(Some(""), "[fe80::1]:22").unwrap().destination(), "fe80::1");
+        assert_eq!(SshTarget::new(None, "::1").unwrap().port, None);
+    }
+
+    #[test]
+    fn test_injection_rejected() {
+        assert!(SshTarget::new(None, "").is_err());
+        assert!(SshTarget::new(None, "-oProxyCommand=sh").is_err());
+        assert!(SshTarget::new(None, "host; rm -rf ~").is_err());
+        assert!(SshTarget::new(None, "host\"").is_err());
+        assert!(SshTarget::new(Some("me$(id)"), "host").is_err());
+        assert!(SshTarget::new(Some("-l"), "host").is_err());
+        assert!(SshTarget::new(None, "host:99999").is_err());
+        assert!(SshTarget::new(None, "[::1]x").is_err());
+    }
+}
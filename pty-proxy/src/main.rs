@@ -7,6 +7,9 @@
 //! is sent to mac-client via Unix socket for remote browser access.
 //!
 //! The terminal emulator sees a normal PTY — no scroll/copy/mouse conflicts.
+//!
+//! `pty-proxy --adopt <fd> [name]` instead hands a PTY master a script set
+//! up itself to the mac-client, which runs it as a session.
 
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
@@ -24,10 +27,12 @@ use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::time::Instant;
 
-const SOCKET_PATH: &str = "/tmp/terminal-remote.sock";
 const BUF_SIZE: usize = 8192;
 const RECONNECT_INTERVAL_SECS: u64 = 5;
 
+/// Shells tried in order when neither DEFAULT_SHELL nor SHELL is usable.
+const FALLBACK_SHELLS: [&str; 3] = ["/bin/zsh", "/bin/bash", "/bin/sh"];
+
 /// Registration message sent to mac-client on connect.
 #[derive(Serialize)]
 struct Registration {
@@ -35,7 +40,11 @@ struct Registration {
     shell: String,
     pid: u32,
     tty: String,
+    /// 2 added fit, print and redraw; mac-client sends them only from there on
     proxy_version: u8,
+    /// Output encoding when the session isn't UTF-8 (mac-client transcodes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
 }
 
 /// Control messages received from mac-client.
@@ -48,6 +57,13 @@ enum ControlMessage {
     Resize { cols: u16, rows: u16 },
     /// Close session — kill child and exit cleanly (code 0)
     Close,
+    /// Text to show in the local terminal as if the shell printed it
+    Print { data: Vec<u8> },
+    /// Fit the session to the local terminal: report its size back and make
+    /// the foreground app redraw
+    Fit,
+    /// Make the foreground app redraw at the current size
+    Redraw,
 }
 
 // Global state for signal handlers
@@ -67,8 +83,19 @@ extern "C" fn handle_sigwinch(_sig: i32) {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--adopt") {
+        std::process::exit(adopt(&args[1..]));
+    }
+
     // Determine shell to exec
-    let shell = detect_shell();
+    let shell = detect_shell().unwrap_or_else(|| {
+        eprintln!(
+            "pty-proxy: no usable shell found (set DEFAULT_SHELL; tried $SHELL, {})",
+            FALLBACK_SHELLS.join(", ")
+        );
+        std::process::exit(1);
+    });
 
     // Save original terminal state for restore on exit
     let orig_termios = termios::tcgetattr(unsafe { BorrowedFd::borrow_raw(STDIN_FILENO) }).ok();
@@ -116,6 +143,9 @@ fn main() {
             // Mark that we're inside the proxy (prevent recursion in .zshrc)
             std::env::set_var("PTY_PROXY_ACTIVE", "1");
 
+            // Let shells and scripts detect that this session is remotely viewable
+            std::env::set_var("TERMINAL_REMOTE", "1");
+
             // Set TERM if not set
             if std::env::var("TERM").is_err() {
                 std::env::set_var("TERM", "xterm-256color");
@@ -124,16 +154,15 @@ fn main() {
             // Exec the shell
             let shell_cstr = CString::new(shell.as_str()).unwrap();
             let args = [shell_cstr.clone()];
-            execvp(&shell_cstr, &args).unwrap_or_else(|e| {
-                eprintln!("pty-proxy: exec {} failed: {}", shell, e);
-                std::process::exit(1);
-            });
+            let Err(e) = execvp(&shell_cstr, &args);
+            eprintln!("pty-proxy: exec {} failed: {}", shell, e);
+            std::process::exit(1);
         }
         Ok(ForkResult::Parent { child }) => {
             // === PARENT: proxy I/O ===
             drop(slave); // close slave in parent
 
-            CHILD_PID.store(child.as_raw() as i32, Ordering::Relaxed);
+            CHILD_PID.store(child.as_raw(), Ordering::Relaxed);
 
             // Also set size on master (belt and suspenders — slave already has it)
             if let Some(size) = get_terminal_size(STDIN_FILENO) {
@@ -377,7 +406,7 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
                                 }
                                 let payload = frame_buf[4..4 + len].to_vec();
                                 frame_buf.drain(..4 + len);
-                                if handle_mac_client_message(&payload, master_fd, child) {
+                                if handle_mac_client_message(&payload, master_fd, sock_raw, child) {
                                     // Close requested — wait for child and exit with 0
                                     reap_child(child);
                                     return 0;
@@ -406,7 +435,7 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
 
 /// Handle a message from mac-client (browser → shell).
 /// Returns true if pty-proxy should exit cleanly (Close message received).
-fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bool {
+fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, sock_fd: RawFd, child: Pid) -> bool {
     // Try JSON parse first
     if let Ok(msg) = serde_json::from_slice::<ControlMessage>(payload) {
         match msg {
@@ -423,11 +452,44 @@ fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bo
                 set_pty_size(master_fd, &size);
             }
             ControlMessage::Close => {
-                // Kill child shell — use SIGHUP, not SIGTERM.
+                // Hang up like a closed terminal would — SIGHUP, not SIGTERM.
                 // zsh ignores SIGTERM in interactive mode, but respects SIGHUP.
-                unsafe { libc::kill(child.as_raw() as i32, libc::SIGHUP); }
+                // The shell leads its own process group (setsid); a running
+                // foreground job has another one, so it gets hung up too.
+                unsafe {
+                    let foreground = libc::tcgetpgrp(master_fd);
+                    if foreground > 0 && foreground != child.as_raw() {
+                        libc::killpg(foreground, libc::SIGHUP);
+                    }
+                    libc::killpg(child.as_raw(), libc::SIGHUP);
+                }
                 return true;
             }
+            ControlMessage::Print { data } => {
+                // Goes to the terminal, not the shell, and is teed back as
+                // output so browsers and scrollback see it too
+                write_all(STDOUT_FILENO, &data);
+                let mut msg = Vec::with_capacity(1 + data.len());
+                msg.push(b'O');
+                msg.extend_from_slice(&data);
+                send_frame(sock_fd, &msg);
+            }
+            ControlMessage::Fit => {
+                // The local window is the size content should fit; a browser
+                // may have resized the PTY away from it
+                if let Some(size) = get_terminal_size(STDIN_FILENO) {
+                    set_pty_size(master_fd, &size);
+                    let resize_msg = format!(
+                        "{{\"type\":\"resize\",\"cols\":{},\"rows\":{}}}",
+                        size.ws_col, size.ws_row
+                    );
+                    send_frame(sock_fd, resize_msg.as_bytes());
+                }
+                // Setting an unchanged size sends no SIGWINCH, so send one
+                // to get the app to redraw regardless
+                signal_redraw(master_fd);
+            }
+            ControlMessage::Redraw => signal_redraw(master_fd),
         }
     }
     // If not JSON, treat as raw input
@@ -437,11 +499,125 @@ fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bo
     false
 }
 
+/// Send SIGWINCH to the PTY's foreground process group, so full-screen apps
+/// repaint without a size change.
+fn signal_redraw(master_fd: RawFd) {
+    unsafe {
+        let foreground = libc::tcgetpgrp(master_fd);
+        if foreground > 0 {
+            libc::killpg(foreground, libc::SIGWINCH);
+        }
+    }
+}
+
+/// Encoding of this session's output if it isn't UTF-8, from
+/// TERMINAL_REMOTE_ENCODING. Browsers can change it per session.
+fn session_encoding() -> Option<String> {
+    std::env::var("TERMINAL_REMOTE_ENCODING").ok().filter(|encoding| !encoding.is_empty())
+}
+
+/// The mac-client's socket, resolved as it does: `terminal-remote.sock` in
+/// `XDG_RUNTIME_DIR`, else `~/.terminal-remote/ipc.sock`.
+fn socket_path() -> std::path::PathBuf {
+    let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(std::path::PathBuf::from);
+    if let Some(dir) = var("XDG_RUNTIME_DIR").filter(|dir| dir.is_absolute()) {
+        return dir.join("terminal-remote.sock");
+    }
+    match var("HOME") {
+        Some(home) => home.join(".terminal-remote").join("ipc.sock"),
+        None => format!("/tmp/terminal-remote-{}.sock", unsafe { nix::libc::getuid() }).into(),
+    }
+}
+
+/// Largest reply accepted from the mac-client to an adopt request
+const MAX_ADOPT_REPLY: usize = 64 * 1024;
+
+/// `--adopt <fd> [name]`: pass the PTY master open as `fd` to the
+/// mac-client over its socket (SCM_RIGHTS) and print the session id it
+/// runs under. Returns the exit code.
+fn adopt(args: &[String]) -> i32 {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    let Some(fd) = args.first().and_then(|arg| arg.parse::<RawFd>().ok()) else {
+        eprintln!("usage: pty-proxy --adopt <fd> [name]");
+        return 2;
+    };
+    let name = args.get(1).cloned().unwrap_or_else(|| "adopted".to_string());
+
+    let mut stream = match UnixStream::connect(socket_path()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("pty-proxy: cannot reach mac-client: {}", e);
+            return 1;
+        }
+    };
+    let payload = serde_json::json!({ "adopt": name }).to_string();
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload.as_bytes());
+    if let Err(e) = send_with_fd(stream.as_raw_fd(), &frame, fd) {
+        eprintln!("pty-proxy: cannot pass fd {}: {}", fd, e);
+        return 1;
+    }
+
+    // Answered with {"session_id": ...} or {"error": ...}
+    let mut len = [0u8; 4];
+    let reply = stream.read_exact(&mut len).ok().and_then(|()| {
+        let len = u32::from_be_bytes(len) as usize;
+        let mut buf = vec![0u8; len.min(MAX_ADOPT_REPLY)];
+        stream.read_exact(&mut buf).ok()?;
+        serde_json::from_slice::<serde_json::Value>(&buf).ok()
+    });
+    match reply {
+        Some(reply) => match reply["session_id"].as_str() {
+            Some(session_id) => {
+                println!("{}", session_id);
+                0
+            }
+            None => {
+                eprintln!("pty-proxy: mac-client refused fd {}: {}", fd, reply["error"].as_str().unwrap_or("unknown error"));
+                1
+            }
+        },
+        None => {
+            eprintln!("pty-proxy: no reply from mac-client");
+            1
+        }
+    }
+}
+
+/// Write `data` to `socket` with `fd` attached to its first byte.
+fn send_with_fd(socket: RawFd, data: &[u8], fd: RawFd) -> std::io::Result<()> {
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut _, iov_len: data.len() };
+    // u64s keep the buffer aligned for cmsghdr
+    let mut control = [0u64; 4];
+    let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) } as usize;
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+        (libc::CMSG_DATA(cmsg) as *mut RawFd).write_unaligned(fd);
+    }
+    let sent = unsafe { libc::sendmsg(socket, &msg, 0) };
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // The descriptor went with the first byte; the rest is plain data
+    write_all(socket, &data[sent as usize..]);
+    Ok(())
+}
+
 /// Connect to mac-client via Unix socket. Returns None on failure (non-fatal).
 fn connect_to_mac_client(shell: &str, child_pid: Pid) -> Option<OwnedFd> {
     use std::os::unix::net::UnixStream;
 
-    let stream = match UnixStream::connect(SOCKET_PATH) {
+    let stream = match UnixStream::connect(socket_path()) {
         Ok(s) => s,
         Err(_) => return None, // mac-client not running, that's OK
     };
@@ -471,7 +647,8 @@ fn connect_to_mac_client(shell: &str, child_pid: Pid) -> Option<OwnedFd> {
         shell: shell.to_string(),
         pid: child_pid.as_raw() as u32,
         tty: tty_name,
-        proxy_version: 1,
+        proxy_version: 2,
+        encoding: session_encoding(),
     };
 
     let json = match serde_json::to_vec(&reg) {
@@ -551,15 +728,32 @@ fn reap_child(child: Pid) -> i32 {
     }
 }
 
-fn detect_shell() -> String {
-    // Check SHELL env var
-    if let Ok(shell) = std::env::var("SHELL") {
-        if !shell.is_empty() {
-            return shell;
+/// Pick the shell to exec. `DEFAULT_SHELL` takes precedence over `$SHELL`
+/// (launchd may leave `$SHELL` unset or stale), then well-known shells.
+/// Every candidate must be an existing executable file.
+fn detect_shell() -> Option<String> {
+    for var in ["DEFAULT_SHELL", "SHELL"] {
+        if let Ok(shell) = std::env::var(var) {
+            if shell.is_empty() {
+                continue;
+            }
+            if is_executable(&shell) {
+                return Some(shell);
+            }
+            eprintln!("pty-proxy: {}={} is not executable, skipping", var, shell);
         }
     }
-    // Fallback
-    "/bin/zsh".to_string()
+    FALLBACK_SHELLS
+        .iter()
+        .find(|shell| is_executable(shell))
+        .map(|shell| shell.to_string())
+}
+
+fn is_executable(path: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
 }
 
 /// If anything goes wrong, just exec the shell directly.
@@ -0,0 +1,208 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Access log in Combined Log Format, enabled with `ACCESS_LOG`
+/// (`stdout`/`-`, or a file path to append to).
+#[derive(Clone)]
+pub struct AccessLog {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AccessLog {
+    /// Open the log configured by `ACCESS_LOG`, or None if unset.
+    pub fn from_env() -> std::io::Result<Option<Self>> {
+        let Ok(target) = std::env::var("ACCESS_LOG") else {
+            return Ok(None);
+        };
+        let out: Box<dyn Write + Send> = match target.as_str() {
+            "" => return Ok(None),
+            "stdout" | "-" => Box::new(std::io::stdout()),
+            path => Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+        };
+        Ok(Some(Self {
+            out: Arc::new(Mutex::new(out)),
+        }))
+    }
+
+    /// Note a raw TCP connection from `client`, which has no request line
+    /// or status of its own.
+    pub fn log_tcp_connection(&self, client: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.write_line(&tcp_line(client, now));
+    }
+
+    fn write_line(&self, line: &str) {
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+/// Middleware writing one combined-format line per request.
+pub async fn access_log(
+    State(log): State<AccessLog>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let client = client_ip(request.headers()).or(peer).unwrap_or_else(|| "-".into());
+    // Path only: query strings can carry session codes or the admin token
+    let request_line = format!(
+        "{} {} {:?}",
+        request.method(),
+        request.uri().path(),
+        request.version()
+    );
+    let referer = header_or_dash(request.headers(), header::REFERER);
+    let user_agent = header_or_dash(request.headers(), header::USER_AGENT);
+
+    let response = next.run(request).await;
+
+    let bytes = header_or_dash(response.headers(), header::CONTENT_LENGTH);
+    log.write_line(&format_line(
+        &client,
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        &request_line,
+        response.status().as_u16(),
+        &bytes,
+        &referer,
+        &user_agent,
+    ));
+    response
+}
+
+/// Real client address when behind a proxy/tunnel (cloudflared sets these).
+pub(crate) fn client_ip(headers: &HeaderMap) -> Option<String> {
+    if let Some(ip) = headers.get("cf-connecting-ip").and_then(|v| v.to_str().ok()) {
+        return Some(ip.trim().to_string());
+    }
+    headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|ip| ip.trim().to_string())
+}
+
+fn header_or_dash(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string()
+}
+
+fn format_line(
+    client: &str,
+    unix_secs: u64,
+    request_line: &str,
+    status: u16,
+    bytes: &str,
+    referer: &str,
+    user_agent: &str,
+) -> String {
+    format!(
+        "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"",
+        client,
+        clf_time(unix_secs),
+        request_line,
+        status,
+        bytes,
+        referer.replace('"', "\\\""),
+        user_agent.replace('"', "\\\"")
+    )
+}
+
+fn tcp_line(client: &str, unix_secs: u64) -> String {
+    format!("{} - - [{}] \"TCP\" - - \"-\" \"-\"", client, clf_time(unix_secs))
+}
+
+/// Format a Unix timestamp as a CLF time (`10/Oct/2000:13:55:36 +0000`, UTC).
+fn clf_time(unix_secs: u64) -> String {
+    let days = (unix_secs / 86_400) as i64;
+    let secs = unix_secs % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clf_time() {
+        assert_eq!(clf_time(0), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(clf_time(971_211_336), "10/Oct/2000:20:55:36 +0000");
+        assert_eq!(clf_time(1_709_208_000), "29/Feb/2024:12:00:00 +0000");
+    }
+
+    #[test]
+    fn test_format_line() {
+        let line = format_line(
+            "203.0.113.7",
+            971_211_336,
+            "GET /ws HTTP/1.1",
+            101,
+            "-",
+            "-",
+            "Mozilla/5.0 \"test\"",
+        );
+        assert_eq!(
+            line,
+            "203.0.113.7 - - [10/Oct/2000:20:55:36 +0000] \"GET /ws HTTP/1.1\" 101 - \"-\" \"Mozilla/5.0 \\\"test\\\"\""
+        );
+    }
+
+    #[test]
+    fn test_tcp_line() {
+        assert_eq!(
+            tcp_line("203.0.113.7", 971_211_336),
+            "203.0.113.7 - - [10/Oct/2000:20:55:36 +0000] \"TCP\" - - \"-\" \"-\""
+        );
+    }
+}
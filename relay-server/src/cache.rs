@@ -0,0 +1,146 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::assets::Assets;
+
+/// Cache-Control for Vite's content-hashed bundles, which never change
+/// under the same name
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Cache-Control for everything else (index.html, SPA routes): revalidate on
+/// every load, so upgrading the relay ships a fresh UI
+const REVALIDATE: &str = "no-cache";
+
+/// Length of the content hash Vite appends to bundle names (`index-B3-Vx0_Y.js`)
+const HASH_LEN: usize = 8;
+
+/// Embedded file served for `path`. Unknown paths get index.html, matching
+/// the SPA fallback.
+fn embedded_name(path: &str) -> String {
+    let name = path.trim_start_matches('/');
+    if !name.is_empty() && Assets::get(name).is_some() {
+        name.to_string()
+    } else {
+        "index.html".to_string()
+    }
+}
+
+/// Whether `name` is a Vite bundle under `assets/` with a content hash.
+fn is_hashed_asset(name: &str) -> bool {
+    let Some(file) = name.strip_prefix("assets/") else {
+        return false;
+    };
+    let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+    let Some(split) = stem.len().checked_sub(HASH_LEN + 1) else {
+        return false;
+    };
+    let (base, hash) = stem.split_at(split);
+    !base.is_empty()
+        && hash.starts_with('-')
+        && hash[1..]
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Cache-Control for the embedded file `name`.
+fn cache_control(name: &str) -> &'static str {
+    if is_hashed_asset(name) {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    }
+}
+
+/// Strong ETag from the embedded file's SHA-256.
+fn etag(name: &str) -> Option<HeaderValue> {
+    let hash = Assets::get(name)?.metadata.sha256_hash();
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    HeaderValue::from_str(&format!("\"{}\"", hex)).ok()
+}
+
+/// Whether the request's If-None-Match lists `etag` (weak or strong).
+fn not_modified(request: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(etag) = etag.to_str().ok() else {
+        return false;
+    };
+    request
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Middleware for the embedded web UI: long-lived caching for hashed
+/// bundles, revalidation for everything else, and ETags so revalidation is
+/// a cheap 304.
+pub async fn cache_headers(request: Request, next: Next) -> Response {
+    let name = embedded_name(request.uri().path());
+    let control = HeaderValue::from_static(cache_control(&name));
+    let etag = etag(&name);
+
+    if let Some(etag) = &etag {
+        if not_modified(request.headers(), etag) {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [(header::ETAG, etag.clone()), (header::CACHE_CONTROL, control)],
+            )
+                .into_response();
+        }
+    }
+
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        let headers = response.headers_mut();
+        headers.insert(header::CACHE_CONTROL, control);
+        if let Some(etag) = etag {
+            headers.entry(header::ETAG).or_insert(etag);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_assets_are_immutable() {
+        assert!(is_hashed_asset("assets/index-Dy_S4WPk.js"));
+        assert!(is_hashed_asset("assets/index-B3-Vx0_Y.css"));
+        assert!(!is_hashed_asset("assets/logo.svg"));
+        assert!(!is_hashed_asset("index-Dy_S4WPk.js"));
+        assert!(!is_hashed_asset("assets/-Dy_S4WPk.js"));
+
+        assert_eq!(cache_control("assets/addon-fit-YJmn1quW.js"), IMMUTABLE);
+        assert_eq!(cache_control("index.html"), REVALIDATE);
+    }
+
+    #[test]
+    fn test_spa_routes_resolve_to_index() {
+        assert_eq!(embedded_name("/"), "index.html");
+        assert_eq!(embedded_name("/session/ABC123"), "index.html");
+        // A missing bundle is answered with index.html, which must not be
+        // cached as if it were the bundle
+        assert_eq!(cache_control(&embedded_name("/assets/gone-AAAAAAAA.js")), REVALIDATE);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = etag("index.html").unwrap();
+        let mut request = HeaderMap::new();
+        assert!(!not_modified(&request, &etag));
+
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"stale\""));
+        assert!(!not_modified(&request, &etag));
+
+        let weak = format!("\"stale\", W/{}", etag.to_str().unwrap());
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&weak).unwrap());
+        assert!(not_modified(&request, &etag));
+    }
+}
@@ -0,0 +1,92 @@
+//! Optional gzip framing for binary frames.
+//!
+//! A client that sends `compression: true` with its Register or Auth is
+//! answered with a `compression` message, after which every binary frame the
+//! relay sends it starts with a flag byte: [`RAW`] for the frame as is,
+//! [`GZIP`] for a gzip stream of it. The client may answer with its own
+//! `compression` message to flag the frames it sends the same way. Frames
+//! sent before either message are never flagged, so the switch can't race
+//! with output already in flight.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Flag of a frame sent uncompressed
+pub const RAW: u8 = 0;
+
+/// Flag of a gzip-compressed frame
+pub const GZIP: u8 = 1;
+
+/// Frames shorter than this are sent raw: keystroke echo and prompts gain
+/// nothing from compression
+pub const MIN_COMPRESSED_SIZE: usize = 512;
+
+/// Largest frame a gzip stream may inflate to, so a small frame can't make
+/// the relay allocate without bound
+const MAX_DECODED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Flag `frame`, compressing it if it is large enough and compresses well.
+pub fn encode(frame: &[u8]) -> Vec<u8> {
+    if frame.len() >= MIN_COMPRESSED_SIZE {
+        // Fast level: output is latency-sensitive, and terminal output
+        // compresses well even so
+        let mut encoder = GzEncoder::new(vec![GZIP], Compression::fast());
+        if let Ok(compressed) = encoder.write_all(frame).and_then(|()| encoder.finish()) {
+            if compressed.len() <= frame.len() {
+                return compressed;
+            }
+        }
+    }
+    let mut flagged = Vec::with_capacity(1 + frame.len());
+    flagged.push(RAW);
+    flagged.extend_from_slice(frame);
+    flagged
+}
+
+/// The frame within a flagged one, or None if it is malformed.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    match data.split_first()? {
+        (&RAW, frame) => Some(frame.to_vec()),
+        (&GZIP, compressed) => {
+            let mut frame = Vec::new();
+            GzDecoder::new(compressed)
+                .take(MAX_DECODED_SIZE as u64 + 1)
+                .read_to_end(&mut frame)
+                .ok()?;
+            (frame.len() <= MAX_DECODED_SIZE).then_some(frame)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // `cat` of a log file: long and repetitive
+        let mut frame = vec![2, b's', b'1'];
+        for i in 0..2000 {
+            frame.extend_from_slice(format!("\x1b[32m{:>5} INFO request handled in 3ms\x1b[0m\r\n", i % 100).as_bytes());
+        }
+        let encoded = encode(&frame);
+        assert_eq!(encoded[0], GZIP);
+        assert!(encoded.len() < frame.len() / 10, "{} of {} bytes", encoded.len(), frame.len());
+        assert_eq!(decode(&encoded).unwrap(), frame);
+
+        // Small frames only gain the flag
+        let echo = b"\x02s1l".to_vec();
+        assert_eq!(encode(&echo), [&[RAW][..], &echo].concat());
+        assert_eq!(decode(&encode(&echo)).unwrap(), echo);
+    }
+
+    #[test]
+    fn test_malformed_frames_rejected() {
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[7, 1, b'x']), None);
+        assert_eq!(decode(&[GZIP, 0x1f, 0x8b, 0x08]), None);
+    }
+}
@@ -0,0 +1,344 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::protocol::{BrowserEntry, ClientEnvironment, SessionInfo};
+use crate::session::mask_code;
+use crate::state::{AdminEvent, AppState, SessionSummary};
+
+#[derive(Deserialize)]
+pub struct AdminQuery {
+    token: Option<String>,
+}
+
+/// Response body for `GET /admin/sessions.json`.
+#[derive(Serialize)]
+struct SessionsResponse {
+    sessions: Vec<SessionJson>,
+}
+
+#[derive(Serialize)]
+struct SessionJson {
+    code: String,
+    client_id: String,
+    age_secs: u64,
+    browser_count: usize,
+    browsers: Vec<BrowserEntry>,
+    terminals: Vec<SessionInfo>,
+    heartbeat: Option<HeartbeatJson>,
+    label: Option<String>,
+    /// Reported only by mac-clients with REPORT_ENVIRONMENT set
+    environment: Option<ClientEnvironment>,
+}
+
+/// Entry of `GET /debug/sessions/detailed`: one line per mac-client.
+#[derive(Serialize)]
+struct SessionBrief {
+    code: String,
+    client_id: String,
+    browser_count: usize,
+    age_secs: u64,
+}
+
+#[derive(Serialize)]
+struct HeartbeatJson {
+    age_secs: u64,
+    sessions: usize,
+    load: f32,
+}
+
+impl From<SessionSummary> for SessionJson {
+    fn from(summary: SessionSummary) -> Self {
+        Self {
+            code: summary.code,
+            client_id: summary.client_id,
+            age_secs: summary.age.as_secs(),
+            browser_count: summary.browser_count,
+            browsers: summary.browsers,
+            terminals: summary.terminals,
+            heartbeat: summary.heartbeat.map(|hb| HeartbeatJson {
+                age_secs: hb.received_at.elapsed().as_secs(),
+                sessions: hb.sessions,
+                load: hb.load,
+            }),
+            label: summary.label,
+            environment: summary.environment,
+        }
+    }
+}
+
+/// Check the `ADMIN_TOKEN`, given as `Authorization: Bearer <token>` or, for
+/// browsers that can't set WebSocket headers, a `?token=` query parameter.
+/// Admin routes 404 when no token is configured.
+fn authorize(state: &AppState, headers: &HeaderMap, query: AdminQuery) -> Result<(), StatusCode> {
+    if !state.admin_enabled() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or(query.token);
+    if !token.is_some_and(|t| state.admin_token_matches(&t)) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+/// Admin WebSocket streaming session lifecycle events as JSON.
+pub async fn admin_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<AdminQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = authorize(&state, &headers, query) {
+        if status == StatusCode::UNAUTHORIZED {
+            tracing::warn!("Admin WebSocket rejected: bad token");
+        }
+        return status.into_response();
+    }
+
+    let events = state.subscribe_admin_events();
+    ws.on_upgrade(move |socket| forward_admin_events(socket, events))
+}
+
+/// Active sessions with full codes, viewers, terminals and ages, for
+/// scripts and dashboards. Same token as the admin WebSocket.
+pub async fn admin_sessions_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = authorize(&state, &headers, query) {
+        if status == StatusCode::UNAUTHORIZED {
+            tracing::warn!("Admin sessions request rejected: bad token");
+        }
+        return status.into_response();
+    }
+
+    let sessions = state
+        .session_summaries()
+        .await
+        .into_iter()
+        .map(SessionJson::from)
+        .collect();
+    Json(SessionsResponse { sessions }).into_response()
+}
+
+/// Plain-text session overview with masked codes and mac-client heartbeats.
+/// It names client ids, so it takes the same token as the other admin routes.
+pub async fn debug_sessions_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = authorize(&state, &headers, query) {
+        if status == StatusCode::UNAUTHORIZED {
+            tracing::warn!("Debug sessions request rejected: bad token");
+        }
+        return status.into_response();
+    }
+
+    let mut out = format!("Active sessions: {}\n", state.session_count());
+    for summary in state.session_summaries().await {
+        // Only show a code prefix - the full code would let anyone join
+        let masked = mask_code(&summary.code);
+        let heartbeat = match summary.heartbeat {
+            Some(hb) => format!(
+                "heartbeat={}s ago sessions={} load={:.2}",
+                hb.received_at.elapsed().as_secs(),
+                hb.sessions,
+                hb.load
+            ),
+            None => "heartbeat=never".to_string(),
+        };
+        out.push_str(&format!(
+            "{} client={} browsers={} {}\n",
+            masked, summary.client_id, summary.browser_count, heartbeat
+        ));
+    }
+    out.into_response()
+}
+
+/// Compact list of active sessions, for monitoring who is connected. Same
+/// token as the other admin routes.
+pub async fn debug_sessions_detailed_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = authorize(&state, &headers, query) {
+        if status == StatusCode::UNAUTHORIZED {
+            tracing::warn!("Detailed sessions request rejected: bad token");
+        }
+        return status.into_response();
+    }
+
+    let sessions: Vec<SessionBrief> = state
+        .session_summaries()
+        .await
+        .into_iter()
+        .map(|summary| SessionBrief {
+            code: summary.code,
+            client_id: summary.client_id,
+            browser_count: summary.browser_count,
+            age_secs: summary.age.as_secs(),
+        })
+        .collect();
+    Json(sessions).into_response()
+}
+
+async fn forward_admin_events(mut socket: WebSocket, mut events: broadcast::Receiver<AdminEvent>) {
+    tracing::info!("Admin connected");
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = serde_json::to_string(&event).unwrap();
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped = skipped, "Admin viewer lagging, events dropped");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+    tracing::info!("Admin disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    async fn sessions_json(state: &AppState, token: Option<&str>) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        }
+        admin_sessions_handler(State(state.clone()), Query(AdminQuery { token: None }), headers).await
+    }
+
+    #[tokio::test]
+    async fn test_sessions_json_requires_token() {
+        let state = AppState::new();
+        assert_eq!(sessions_json(&state, Some("secret")).await.status(), StatusCode::NOT_FOUND);
+
+        let state = AppState::with_admin_token(Some("secret".into()));
+        assert_eq!(sessions_json(&state, None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(sessions_json(&state, Some("wrong")).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_json_lists_sessions() {
+        let state = AppState::with_admin_token(Some("secret".into()));
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        state.terminal_connected(&code, "t1", "zsh").await;
+        state.set_label(&code, "studio-mac").await;
+
+        let response = sessions_json(&state, Some("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let session = &json["sessions"][0];
+        assert_eq!(session["code"], code.as_str());
+        assert_eq!(session["client_id"], "client-1");
+        assert_eq!(session["browser_count"], 0);
+        assert_eq!(session["terminals"][0]["name"], "zsh");
+        assert!(session["heartbeat"].is_null());
+        assert_eq!(session["label"], "studio-mac");
+        assert!(session["environment"].is_null());
+
+        let environment = ClientEnvironment {
+            os_version: Some("14.5\n".into()),
+            shell: Some("/bin/zsh".into()),
+            tmux: false,
+            cloudflared: true,
+            client_version: Some("0.1.0".into()),
+        };
+        state.set_environment(&code, environment).await;
+        let response = sessions_json(&state, Some("secret")).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let environment = &json["sessions"][0]["environment"];
+        assert_eq!(environment["os_version"], "14.5");
+        assert_eq!(environment["shell"], "/bin/zsh");
+        assert_eq!(environment["tmux"], false);
+        assert_eq!(environment["cloudflared"], true);
+    }
+
+    #[tokio::test]
+    async fn test_detailed_sessions() {
+        let state = AppState::with_admin_token(Some("secret".into()));
+        let (tx, _rx) = mpsc::channel(1);
+        let first = state.register_mac_client("client-1", None, tx.clone()).unwrap();
+        let second = state.register_mac_client("client-2", None, tx).unwrap();
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser(&second, "b1".into(), browser_tx, "10.0.0.0/24".into(), false, false);
+
+        let request = |token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            }
+            debug_sessions_detailed_handler(State(state.clone()), Query(AdminQuery { token: None }), headers)
+        };
+        assert_eq!(request(None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(request(Some("wrong")).await.status(), StatusCode::UNAUTHORIZED);
+
+        let response = request(Some("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut sessions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        sessions.sort_by_key(|s| s["client_id"].as_str().unwrap().to_string());
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0]["code"], first.as_str());
+        assert_eq!(sessions[0]["browser_count"], 0);
+        assert_eq!(sessions[1]["code"], second.as_str());
+        assert_eq!(sessions[1]["client_id"], "client-2");
+        assert_eq!(sessions[1]["browser_count"], 1);
+        assert!(sessions[1]["age_secs"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn test_debug_sessions_requires_token() {
+        let state = AppState::with_admin_token(Some("secret".into()));
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        let request = |token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            }
+            debug_sessions_handler(State(state.clone()), Query(AdminQuery { token: None }), headers)
+        };
+        assert_eq!(request(None).await.status(), StatusCode::UNAUTHORIZED);
+
+        let response = request(Some("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("client=client-1"));
+        assert!(!body.contains(&code));
+    }
+}
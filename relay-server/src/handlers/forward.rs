@@ -0,0 +1,111 @@
+use axum::extract::ws::Message;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue};
+
+use crate::protocol::ControlMessage;
+
+/// Relay every connection is passed on to (`UPSTREAM_RELAY`), making this
+/// instance an edge relay: e.g. one in a DMZ in front of a private relay
+/// that mac-clients and browsers can't reach directly.
+#[derive(Clone, Debug)]
+pub struct UpstreamRelay(pub String);
+
+impl UpstreamRelay {
+    /// Base URL from `UPSTREAM_RELAY` (e.g. `wss://home-relay:3000`), if set.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("UPSTREAM_RELAY").ok()?;
+        let url = url.trim().trim_end_matches('/').trim_end_matches("/ws");
+        (!url.is_empty()).then(|| Self(url.to_string()))
+    }
+}
+
+/// Relay a connection to another relay instance: open a WebSocket to
+/// `instance_url`, send the connection's `first` message (a browser's `auth`
+/// or a mac-client's `register`) there, then pass frames through in both
+/// directions until either side closes.
+pub async fn forward<S, R, E>(
+    mut sender: S,
+    mut receiver: R,
+    instance_url: &str,
+    first: ControlMessage,
+    ip: Option<String>,
+) where
+    S: Sink<Message> + Unpin,
+    R: Stream<Item = Result<Message, E>> + Unpin,
+{
+    let role = match first {
+        ControlMessage::Register(_) => "mac-client",
+        _ => "browser",
+    };
+    let mut request = match format!("{}/ws", instance_url).into_client_request() {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!(instance = %instance_url, "Invalid instance URL: {}", e);
+            return;
+        }
+    };
+    // The owning instance shows the browser's address in the viewer list
+    if let Some(ip) = ip.and_then(|ip| HeaderValue::from_str(&ip).ok()) {
+        request.headers_mut().insert("x-forwarded-for", ip);
+    }
+
+    let upstream = match tokio_tungstenite::connect_async(request).await {
+        Ok((upstream, _)) => upstream,
+        Err(e) => {
+            tracing::warn!(instance = %instance_url, "Cannot reach relay instance for {}: {}", role, e);
+            let response = match first {
+                ControlMessage::Auth(_) => ControlMessage::AuthFailed {
+                    reason: "Session is unavailable, try again".into(),
+                },
+                _ => ControlMessage::Error {
+                    message: "Relay is unavailable, try again".into(),
+                },
+            };
+            let _ = sender
+                .send(Message::Text(serde_json::to_string(&response).unwrap().into()))
+                .await;
+            return;
+        }
+    };
+    let (mut up_tx, mut up_rx) = upstream.split();
+    let first = serde_json::to_string(&first).unwrap();
+    if up_tx.send(tungstenite::Message::Text(first.into())).await.is_err() {
+        return;
+    }
+    tracing::info!(instance = %instance_url, "Forwarding {} to relay instance", role);
+
+    let to_upstream = async {
+        while let Some(Ok(msg)) = receiver.next().await {
+            let msg = match msg {
+                Message::Text(text) => tungstenite::Message::Text(text.as_str().into()),
+                Message::Binary(data) => tungstenite::Message::Binary(data),
+                Message::Close(_) => break,
+                // Each hop answers its own pings
+                Message::Ping(_) | Message::Pong(_) => continue,
+            };
+            if up_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = up_tx.send(tungstenite::Message::Close(None)).await;
+    };
+    let to_browser = async {
+        while let Some(Ok(msg)) = up_rx.next().await {
+            let msg = match msg {
+                tungstenite::Message::Text(text) => Message::Text(text.as_str().into()),
+                tungstenite::Message::Binary(data) => Message::Binary(data),
+                tungstenite::Message::Close(_) => break,
+                _ => continue,
+            };
+            if sender.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = sender.send(Message::Close(None)).await;
+    };
+    tokio::select! {
+        _ = to_upstream => {}
+        _ = to_browser => {}
+    }
+    tracing::info!(instance = %instance_url, "Forwarded {} disconnected", role);
+}
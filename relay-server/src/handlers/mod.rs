@@ -1,2 +1,8 @@
+mod admin;
+mod forward;
+mod tcp;
 mod ws;
+pub use forward::UpstreamRelay;
+pub use tcp::{serve_tcp, tcp_port};
+pub use admin::{admin_sessions_handler, admin_ws_handler, debug_sessions_detailed_handler, debug_sessions_handler};
 pub use ws::ws_handler;
@@ -0,0 +1,300 @@
+//! Raw TCP access for browser-role clients that don't speak WebSocket,
+//! such as a native CLI viewer. Enabled with `TCP_PORT`.
+//!
+//! The protocol is the WebSocket one with its own framing:
+//! - a control message is one JSON object on a `\n`-terminated line
+//! - terminal data is a `0x00` byte, a big-endian `u32` length, then the
+//!   same bytes a WebSocket binary frame would carry
+//!
+//! The first line must be an `auth`. Mac-clients keep using WebSocket,
+//! whose pings the relay relies on to notice a dead Mac.
+//!
+//! The stream is plaintext, so the listener binds to loopback unless
+//! `TCP_BIND_ADDR` says otherwise; expose it only behind TLS termination.
+
+use axum::extract::ws::Message;
+use futures_util::{SinkExt, Stream};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use super::forward::{forward, UpstreamRelay};
+use super::ws::{handle_browser, max_input_frame, read_first_message};
+use crate::access_log::AccessLog;
+use crate::protocol::ControlMessage;
+use crate::state::AppState;
+
+/// Marks a length-prefixed terminal data frame
+const BINARY_FRAME: u8 = 0x00;
+
+/// Buffer growth step while reading a frame, so a claimed length only
+/// costs memory once its bytes actually arrive
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Port for raw TCP clients (`TCP_PORT`), if enabled.
+pub fn tcp_port() -> Option<u16> {
+    let value = std::env::var("TCP_PORT").ok()?;
+    let port = value.trim().parse().ok().filter(|port| *port > 0);
+    if port.is_none() {
+        tracing::warn!("Invalid TCP_PORT {:?}, raw TCP disabled", value);
+    }
+    port
+}
+
+/// Accept raw TCP clients on `listener` until the relay stops, noting each
+/// connection in the access log if there is one.
+pub async fn serve_tcp(
+    listener: TcpListener,
+    state: AppState,
+    upstream: Option<UpstreamRelay>,
+    access_log: Option<AccessLog>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Cannot accept TCP connection: {}", e);
+                continue;
+            }
+        };
+        if let Some(log) = &access_log {
+            log.log_tcp_connection(&peer.ip().to_string());
+        }
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        tokio::spawn(handle_tcp(reader, writer, state.clone(), peer, upstream.clone()));
+    }
+}
+
+async fn handle_tcp<R, W>(reader: R, writer: W, state: AppState, peer: SocketAddr, upstream: Option<UpstreamRelay>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let mut sender = Box::pin(frame_sink(writer));
+    let mut receiver = Box::pin(frames(reader));
+    let ip = Some(peer.ip().to_string());
+
+    let Some(first) = read_first_message(&mut sender, &mut receiver).await else {
+        return;
+    };
+    tracing::debug!(peer = %peer, "Raw TCP client connected");
+    match (first, upstream) {
+        // An edge relay passes the browser on over WebSocket
+        (first @ ControlMessage::Auth(_), Some(UpstreamRelay(upstream))) => {
+            forward(sender, receiver, &upstream, first, ip).await;
+        }
+        (ControlMessage::Auth(auth), None) => {
+            handle_browser(sender, receiver, state, auth, ip).await;
+        }
+        _ => {
+            let msg = ControlMessage::Error {
+                message: "First message over TCP must be Auth".into(),
+            };
+            let _ = sender.send(Message::Text(serde_json::to_string(&msg).unwrap().into())).await;
+        }
+    }
+}
+
+/// Messages read from a raw TCP client, ending at EOF or a malformed frame.
+fn frames<R>(reader: R) -> impl Stream<Item = Result<Message, std::io::Error>> + Send
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    futures_util::stream::unfold(BufReader::new(reader), |mut reader| async move {
+        match read_frame(&mut reader).await {
+            Ok(Some(msg)) => Some((Ok(msg), reader)),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), reader)),
+        }
+    })
+}
+
+/// Read one frame, skipping blank lines. Returns None at EOF.
+async fn read_frame<R>(reader: &mut BufReader<R>) -> std::io::Result<Option<Message>>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let kind = match reader.read_u8().await {
+            Ok(kind) => kind,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match kind {
+            BINARY_FRAME => {
+                let len = reader.read_u32().await? as usize;
+                let max = max_input_frame();
+                if len > max {
+                    return Err(invalid(format!("frame of {} bytes exceeds {}", len, max)));
+                }
+                let mut data = Vec::with_capacity(len.min(READ_CHUNK));
+                (&mut *reader).take(len as u64).read_to_end(&mut data).await?;
+                if data.len() < len {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                return Ok(Some(Message::Binary(data.into())));
+            }
+            b'\r' | b'\n' => continue,
+            _ => {
+                let mut line = vec![kind];
+                (&mut *reader).take(max_input_frame() as u64).read_until(b'\n', &mut line).await?;
+                if line.last() != Some(&b'\n') {
+                    return Err(invalid("line too long or unterminated".into()));
+                }
+                let text = String::from_utf8(line).map_err(|_| invalid("line is not UTF-8".into()))?;
+                return Ok(Some(Message::Text(text.trim_end().into())));
+            }
+        }
+    }
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Sink writing messages to a raw TCP client in its framing.
+fn frame_sink<W>(writer: W) -> impl futures_util::Sink<Message, Error = std::io::Error> + Send
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    futures_util::sink::unfold(writer, |mut writer, msg: Message| async move {
+        match msg {
+            // serde_json escapes newlines, so a message is always one line
+            Message::Text(text) => {
+                writer.write_all(text.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+            }
+            Message::Binary(data) => {
+                writer.write_u8(BINARY_FRAME).await?;
+                writer.write_u32(data.len() as u32).await?;
+                writer.write_all(&data).await?;
+            }
+            Message::Close(_) => writer.shutdown().await?,
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+        Ok(writer)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+    /// Read frames from the relay until the next one of the wanted kind.
+    async fn next_frame(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, binary: bool) -> Message {
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), read_frame(reader))
+                .await
+                .unwrap()
+                .unwrap()
+                .expect("connection closed");
+            if matches!(msg, Message::Binary(_)) == binary {
+                return msg;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_client_exchanges_terminal_io() {
+        let state = AppState::new();
+        let ws_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_addr = ws_listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(super::super::ws_handler))
+            .with_state(state.clone());
+        tokio::spawn(async move {
+            axum::serve(ws_listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(tcp_listener, state, None, None));
+
+        // The Mac registers over WebSocket as usual
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", ws_addr)).await.unwrap();
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        mac.send(WsMessage::Text(register.into())).await.unwrap();
+        let code = match mac.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+                ControlMessage::Registered { code } => code,
+                other => panic!("Expected Registered, got {:?}", other),
+            },
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        let (reader, mut writer) = TcpStream::connect(tcp_addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(format!("{{\"type\":\"auth\",\"session_code\":\"{}\"}}\n", code).as_bytes())
+            .await
+            .unwrap();
+        let Message::Text(text) = next_frame(&mut reader, false).await else {
+            unreachable!();
+        };
+        assert!(matches!(serde_json::from_str(&text).unwrap(), ControlMessage::AuthSuccess), "{}", text);
+
+        // Output reaches the TCP client as a length-prefixed frame
+        let output = b"\x02s1hello".to_vec();
+        mac.send(WsMessage::Binary(output.clone().into())).await.unwrap();
+        assert_eq!(next_frame(&mut reader, true).await, Message::Binary(output.into()));
+
+        // And its input reaches the Mac
+        let input = b"\x02s1ls\r";
+        writer.write_u8(BINARY_FRAME).await.unwrap();
+        writer.write_u32(input.len() as u32).await.unwrap();
+        writer.write_all(input).await.unwrap();
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), mac.next()).await.unwrap();
+            if let WsMessage::Binary(data) = msg.unwrap().unwrap() {
+                assert_eq!(&data[..], input);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_register_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(listener, AppState::new(), None, None));
+
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(b"{\"type\":\"register\",\"client_id\":\"550e8400-e29b-41d4-a716-446655440000\"}\n")
+            .await
+            .unwrap();
+        let Message::Text(text) = next_frame(&mut reader, false).await else {
+            unreachable!();
+        };
+        assert!(text.contains("must be Auth"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_closes_connection() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut reader = BufReader::new(server);
+
+        // A claimed length beyond the input limit is refused before any
+        // payload is buffered
+        client.write_u8(BINARY_FRAME).await.unwrap();
+        client.write_u32(max_input_frame() as u32 + 1).await.unwrap();
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // One cut short by EOF is an error too
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut reader = BufReader::new(server);
+        client.write_u8(BINARY_FRAME).await.unwrap();
+        client.write_u32(10).await.unwrap();
+        client.write_all(b"abc").await.unwrap();
+        drop(client);
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
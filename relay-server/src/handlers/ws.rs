@@ -1,32 +1,135 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, State,
     },
+    http::HeaderMap,
     response::IntoResponse,
+    Extension,
 };
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
-use crate::protocol::ControlMessage;
-use crate::state::{AppState, BrowserMessage, MacMessage};
+use super::forward::{forward, UpstreamRelay};
+use crate::password;
+use crate::protocol::{AuthRequest, ControlMessage, RegisterRequest};
+use crate::search;
+use crate::access_log::client_ip;
+use crate::compression;
+use crate::session::{code_length, ip_prefix, looks_like_session_code, requestable_code};
+use crate::state::{AppState, BrowserMessage, MacMessage, HEARTBEAT_TIMEOUT, MAX_SHARE_TTL};
+
+/// How often the relay checks each mac-client's heartbeat freshness
+const HEARTBEAT_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often the relay pings each mac-client
+const MAC_PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Consecutive pings a mac-client may leave unanswered before its
+/// connection is considered dead
+const MAX_UNANSWERED_PINGS: u32 = 2;
+
+/// How long a new connection has to send its Register/Auth message
+const FIRST_MESSAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest browser input frame forwarded to the mac-client unless
+/// `MAX_INPUT_FRAME` is set. Generous for pastes, small enough that a
+/// browser can't make the mac-client buffer megabytes per frame.
+const DEFAULT_MAX_INPUT_FRAME: usize = 256 * 1024;
+
+/// Largest browser input frame in bytes, from `MAX_INPUT_FRAME`.
+pub(super) fn max_input_frame() -> usize {
+    static MAX: OnceLock<usize> = OnceLock::new();
+    *MAX.get_or_init(|| {
+        let Ok(value) = std::env::var("MAX_INPUT_FRAME") else {
+            return DEFAULT_MAX_INPUT_FRAME;
+        };
+        parse_max_input_frame(&value).unwrap_or_else(|| {
+            tracing::warn!("Invalid MAX_INPUT_FRAME {:?}, using {} bytes", value, DEFAULT_MAX_INPUT_FRAME);
+            DEFAULT_MAX_INPUT_FRAME
+        })
+    })
+}
+
+/// `MAX_INPUT_FRAME` as a positive byte count.
+fn parse_max_input_frame(value: &str) -> Option<usize> {
+    value.trim().parse().ok().filter(|max| *max > 0)
+}
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    upstream: Option<Extension<UpstreamRelay>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    let ip = client_ip(&headers)
+        .or_else(|| peer.map(|Extension(ConnectInfo(addr))| addr.ip().to_string()));
+    let upstream = upstream.map(|Extension(upstream)| upstream);
+    ws.on_upgrade(|socket| handle_socket(socket, state, ip, upstream))
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+async fn handle_socket(socket: WebSocket, state: AppState, ip: Option<String>, upstream: Option<UpstreamRelay>) {
     let (mut sender, mut receiver) = socket.split();
 
     // Wait for first message to determine client type
-    let Some(Ok(first_msg)) = receiver.next().await else {
-        tracing::debug!("Client disconnected before sending first message");
+    let Some(control_msg) = read_first_message(&mut sender, &mut receiver).await else {
         return;
     };
 
+    // An edge relay terminates nothing itself
+    if let Some(UpstreamRelay(upstream)) = upstream {
+        if matches!(control_msg, ControlMessage::Register(_) | ControlMessage::Auth(_)) {
+            forward(sender, receiver, &upstream, control_msg, ip).await;
+            return;
+        }
+    }
+
+    match control_msg {
+        ControlMessage::Register(register) => {
+            handle_mac_client(sender, receiver, state, register, MAC_PING_INTERVAL).await;
+        }
+        ControlMessage::Auth(auth) => {
+            handle_browser(sender, receiver, state, auth, ip).await;
+        }
+        _ => {
+            tracing::warn!("Unexpected first message type");
+            let _ = sender
+                .send(Message::Text(
+                    serde_json::to_string(&ControlMessage::Error {
+                        message: "First message must be Register or Auth".into(),
+                    })
+                    .unwrap()
+                    .into(),
+                ))
+                .await;
+        }
+    }
+}
+
+/// Wait for a new connection's first message and parse it, answering with
+/// an error if it isn't a valid control message for the role it claims.
+pub(super) async fn read_first_message<S, R, E>(sender: &mut S, receiver: &mut R) -> Option<ControlMessage>
+where
+    S: Sink<Message> + Unpin,
+    R: Stream<Item = Result<Message, E>> + Unpin,
+{
+    let first_msg = match tokio::time::timeout(FIRST_MESSAGE_TIMEOUT, first_message(receiver)).await {
+        Ok(Some(msg)) => msg,
+        Ok(None) => {
+            tracing::debug!("Client disconnected before sending first message");
+            return None;
+        }
+        Err(_) => {
+            tracing::debug!("Client sent no first message within {:?}", FIRST_MESSAGE_TIMEOUT);
+            return None;
+        }
+    };
+
     // Parse first message as JSON to determine client type
     let Message::Text(text) = first_msg else {
         tracing::warn!("First message must be JSON Text, got binary");
@@ -39,7 +142,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                 .into(),
             ))
             .await;
-        return;
+        return None;
     };
 
     let Ok(control_msg) = serde_json::from_str::<ControlMessage>(&text) else {
@@ -53,43 +156,119 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                 .into(),
             ))
             .await;
-        return;
+        return None;
     };
 
-    match control_msg {
-        ControlMessage::Register { client_id } => {
-            handle_mac_client(sender, receiver, state, client_id).await;
+    if let Err(reason) = validate_role(&control_msg, code_length()) {
+        tracing::warn!("Rejecting first message: {}", reason);
+        let _ = sender
+            .send(Message::Text(
+                serde_json::to_string(&ControlMessage::Error {
+                    message: reason.into(),
+                })
+                .unwrap()
+                .into(),
+            ))
+            .await;
+        let _ = sender.send(Message::Close(None)).await;
+        return None;
+    }
+    Some(control_msg)
+}
+
+/// Read the first meaningful message, skipping pings/pongs and empty frames
+/// a client may send before its Register/Auth. Returns None on close or error.
+async fn first_message<S, E>(receiver: &mut S) -> Option<Message>
+where
+    S: Stream<Item = Result<Message, E>> + Unpin,
+{
+    loop {
+        match receiver.next().await? {
+            Ok(Message::Ping(_) | Message::Pong(_)) => continue,
+            Ok(Message::Text(text)) if text.trim().is_empty() => continue,
+            Ok(Message::Binary(data)) if data.is_empty() => continue,
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(msg) => return Some(msg),
         }
-        ControlMessage::Auth { session_code } => {
-            handle_browser(sender, receiver, state, session_code).await;
+    }
+}
+
+/// Check that the first message's payload matches the role its type claims.
+///
+/// A browser sending `Register` would otherwise become a mac-client and be
+/// handed a fresh code, and a mac-client sending `Auth` would join a session
+/// as a browser. Mac-clients identify with a `client_id`, browsers with a
+/// session code (of `code_length` characters), so a payload carrying the other
+/// role's credential is rejected.
+fn validate_role(msg: &ControlMessage, code_length: usize) -> Result<(), &'static str> {
+    match msg {
+        ControlMessage::Register(RegisterRequest { client_id, password_hash, .. }) => {
+            if client_id.trim().is_empty() {
+                return Err("Register requires a client_id");
+            }
+            if looks_like_session_code(client_id, code_length) {
+                return Err("Register got a session code; browsers must send Auth");
+            }
+            if let Some(hash) = password_hash {
+                password::validate_hash(hash)?;
+            }
         }
-        _ => {
-            tracing::warn!("Unexpected first message type");
-            let _ = sender
-                .send(Message::Text(
-                    serde_json::to_string(&ControlMessage::Error {
-                        message: "First message must be Register or Auth".into(),
-                    })
-                    .unwrap()
-                    .into(),
-                ))
-                .await;
+        ControlMessage::Auth(AuthRequest { session_code, .. }) if looks_like_client_id(session_code) => {
+            return Err("Auth got a client_id; mac-clients must send Register");
         }
+        _ => {}
     }
+    Ok(())
 }
 
-/// Handle a mac-client connection
-async fn handle_mac_client(
-    mut sender: futures_util::stream::SplitSink<WebSocket, Message>,
-    mut receiver: futures_util::stream::SplitStream<WebSocket>,
+/// Returns true if `s` has the shape of a mac-client id (a hyphenated UUID).
+fn looks_like_client_id(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Handle a mac-client connection, pinging it every `ping_interval`
+async fn handle_mac_client<S, R, E>(
+    mut sender: S,
+    mut receiver: R,
     state: AppState,
-    client_id: String,
-) {
+    register: RegisterRequest,
+    ping_interval: Duration,
+) where
+    S: Sink<Message> + Unpin + Send + 'static,
+    R: Stream<Item = Result<Message, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let RegisterRequest { client_id, password_hash, requested_code, compression } = register;
     // Create channel for receiving messages to send to mac-client
     let (mac_tx, mut mac_rx) = mpsc::channel::<MacMessage>(1000);
+    let ping_tx = mac_tx.clone();
 
     // Register and get session code
-    let code = state.register_mac_client(mac_tx);
+    let protected = password_hash.is_some();
+    // An invalid or guessable request falls back to a random code; the
+    // client sees which one it got in Registered
+    let requested = requested_code.as_deref().and_then(|requested| {
+        let code = requestable_code(requested, code_length());
+        if code.is_none() {
+            tracing::info!(client_id = %client_id, "Requested session code not allowed, generating one");
+        }
+        code
+    });
+    let code = match state.register_mac_client_shared(&client_id, requested, password_hash, mac_tx).await {
+        Ok(code) => code,
+        Err(message) => {
+            let response = ControlMessage::Error { message: message.into() };
+            let _ = sender
+                .send(Message::Text(serde_json::to_string(&response).unwrap().into()))
+                .await;
+            let _ = sender.send(Message::Close(None)).await;
+            return;
+        }
+    };
 
     // Send registration confirmation
     let response = ControlMessage::Registered { code: code.clone() };
@@ -104,15 +283,32 @@ async fn handle_mac_client(
         return;
     }
 
-    tracing::info!(code = %code, client_id = %client_id, "Mac-client connected");
+    // Browser input to this mac-client is flagged from here on
+    if compression {
+        let msg = serde_json::to_string(&ControlMessage::Compression).unwrap();
+        if sender.send(Message::Text(msg.into())).await.is_err() {
+            state.remove_session(&code);
+            return;
+        }
+    }
+
+    tracing::info!(code = %code, client_id = %client_id, protected, compression, "Mac-client connected");
 
     // Spawn task to forward messages from browsers to mac-client
     let code_clone = code.clone();
     let send_task = tokio::spawn(async move {
         while let Some(msg) = mac_rx.recv().await {
             let result = match msg {
+                MacMessage::Binary(data) if compression => {
+                    sender.send(Message::Binary(compression::encode(&data).into())).await
+                }
                 MacMessage::Binary(data) => sender.send(Message::Binary(data.into())).await,
                 MacMessage::Text(text) => sender.send(Message::Text(text.into())).await,
+                MacMessage::Ping => sender.send(Message::Ping(Default::default())).await,
+                MacMessage::Close => {
+                    let _ = sender.send(Message::Close(None)).await;
+                    break;
+                }
             };
             if result.is_err() {
                 break;
@@ -120,73 +316,224 @@ async fn handle_mac_client(
         }
     });
 
+    // Periodically check heartbeat freshness as an extra dead-session signal
+    let mut heartbeat_check = tokio::time::interval(HEARTBEAT_CHECK_INTERVAL);
+
+    // Ping so a connection that dropped without a FIN doesn't block the
+    // receive loop forever
+    let mut ping = tokio::time::interval(ping_interval);
+    let mut unanswered_pings = 0;
+
+    // Whether the mac-client has switched to flagged output frames
+    let mut mac_compresses = false;
+
     // Process incoming messages from mac-client (terminal output)
-    while let Some(msg_result) = receiver.next().await {
+    loop {
+        let msg_result = tokio::select! {
+            msg = receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = heartbeat_check.tick() => {
+                if state.heartbeat_expired(&code_clone, HEARTBEAT_TIMEOUT).await {
+                    tracing::warn!(code = %code_clone, "Mac-client heartbeat expired, dropping session");
+                    break;
+                }
+                // Expired while idle: disconnect so the mac-client registers again
+                if !state.validate_session_code(&code_clone) {
+                    tracing::info!(code = %code_clone, "Session expired, disconnecting mac-client");
+                    break;
+                }
+                state.refresh_claim(&code_clone).await;
+                continue;
+            }
+            _ = ping.tick() => {
+                if unanswered_pings >= MAX_UNANSWERED_PINGS {
+                    tracing::warn!(code = %code_clone, "Mac-client stopped answering pings, dropping session");
+                    break;
+                }
+                unanswered_pings += 1;
+                // Never wait on a backed-up queue: a ping that can't be
+                // queued stays unanswered, so a stuck client is still dropped
+                let _ = ping_tx.try_send(MacMessage::Ping);
+                continue;
+            }
+        };
+
         match msg_result {
             Ok(Message::Binary(data)) => {
+                let frame = if mac_compresses {
+                    let Some(frame) = compression::decode(&data) else {
+                        tracing::warn!(code = %code_clone, bytes = data.len(), "Dropping malformed compressed frame");
+                        continue;
+                    };
+                    frame
+                } else {
+                    data.to_vec()
+                };
                 // Forward terminal output to all connected browsers
-                state.broadcast_to_browsers(&code_clone, data.to_vec()).await;
+                state.broadcast_to_browsers(&code_clone, frame).await;
             }
             Ok(Message::Text(text)) => {
                 // Handle control messages from mac-client
                 if let Ok(ctrl) = serde_json::from_str::<ControlMessage>(&text) {
-                    tracing::info!(code = %code_clone, "Mac-client control message: {:?}", ctrl);
+                    if matches!(ctrl, ControlMessage::Heartbeat { .. }) {
+                        tracing::debug!(code = %code_clone, "Mac-client control message: {:?}", ctrl);
+                    } else {
+                        tracing::info!(code = %code_clone, "Mac-client control message: {:?}", ctrl);
+                    }
                     // Forward session messages to browsers
                     match &ctrl {
+                        ControlMessage::Heartbeat { sessions, load } => {
+                            state.record_heartbeat(&code_clone, *sessions, *load).await;
+                        }
+                        ControlMessage::ListBrowsers => {
+                            send_browser_list(&state, &code_clone).await;
+                        }
+                        ControlMessage::Compression if compression => mac_compresses = true,
+                        ControlMessage::Environment { environment } => {
+                            state.set_environment(&code_clone, environment.clone()).await;
+                        }
+                        ControlMessage::SetLabel { label } => {
+                            if let Some(label) = state.set_label(&code_clone, label).await {
+                                tracing::info!(code = %code_clone, label = %label, "Session label changed");
+                                let msg = ControlMessage::Label { label };
+                                let json = serde_json::to_string(&msg).unwrap();
+                                state.broadcast_text_to_browsers(&code_clone, &json).await;
+                            }
+                        }
+                        ControlMessage::CreateShare { ttl_secs } => {
+                            let ttl = Duration::from_secs(*ttl_secs).min(MAX_SHARE_TTL);
+                            if let Some(code) = state.create_share(&code_clone, ttl) {
+                                state.claim_share(&code, ttl).await;
+                                let reply = ControlMessage::ShareCreated { code, ttl_secs: ttl.as_secs() };
+                                let json = serde_json::to_string(&reply).unwrap();
+                                state.send_text_to_mac_client(&code_clone, &json).await;
+                            }
+                        }
                         ControlMessage::SessionList { sessions } => {
                             tracing::info!(code = %code_clone, "Forwarding SessionList ({} sessions) to browsers", sessions.len());
+                            state.set_terminals(&code_clone, sessions.clone()).await;
                             state.broadcast_text_to_browsers(&code_clone, &text).await;
                         }
-                        ControlMessage::SessionConnected { .. } => {
+                        ControlMessage::SessionConnected { session_id, name } => {
+                            state.terminal_connected(&code_clone, session_id, name).await;
                             tracing::info!(code = %code_clone, "Forwarding SessionConnected to browsers");
                             state.broadcast_text_to_browsers(&code_clone, &text).await;
                         }
                         ControlMessage::SessionDisconnected { session_id } => {
                             tracing::info!(code = %code_clone, session_id = %session_id, "Forwarding SessionDisconnected to browsers, purging scrollback");
-                            state.purge_session_scrollback(&code_clone, &session_id).await;
+                            state.purge_session_scrollback(&code_clone, session_id).await;
+                            state.terminal_disconnected(&code_clone, session_id).await;
                             state.broadcast_text_to_browsers(&code_clone, &text).await;
                         }
                         ControlMessage::SessionResize { session_id, cols, rows } => {
                             tracing::debug!(code = %code_clone, session_id = %session_id, cols = cols, rows = rows, "Forwarding SessionResize to browsers");
                             state.broadcast_text_to_browsers(&code_clone, &text).await;
                         }
+                        ControlMessage::Cwd { session_id, .. } => {
+                            tracing::debug!(code = %code_clone, session_id = %session_id, "Forwarding Cwd to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::ProfileList { browser_id, profiles } => {
+                            tracing::debug!(code = %code_clone, "Forwarding ProfileList ({} profiles) to browsers", profiles.len());
+                            match browser_id {
+                                Some(browser_id) => state.send_text_to_browser(&code_clone, browser_id, &text).await,
+                                None => state.broadcast_text_to_browsers(&code_clone, &text).await,
+                            }
+                        }
+                        ControlMessage::State { session_id, browser_id, .. } => {
+                            tracing::debug!(code = %code_clone, session_id = %session_id, "Forwarding State to browsers");
+                            match browser_id {
+                                Some(browser_id) => state.send_text_to_browser(&code_clone, browser_id, &text).await,
+                                None => state.broadcast_text_to_browsers(&code_clone, &text).await,
+                            }
+                        }
+                        ControlMessage::OutputTimestamp { session_id, timestamp_ms, .. } => {
+                            // Precedes the session's next output frame
+                            state.set_mac_timestamp(&code_clone, session_id, *timestamp_ms);
+                        }
+                        ControlMessage::LocalInput { session_id, .. } => {
+                            tracing::trace!(code = %code_clone, session_id = %session_id, "Forwarding LocalInput to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::LockSession { session_id, locked } => {
+                            tracing::info!(code = %code_clone, session_id = %session_id, locked = locked, "Forwarding LockSession to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::SetEncoding { session_id, encoding } => {
+                            tracing::info!(code = %code_clone, session_id = %session_id, encoding = ?encoding, "Forwarding SetEncoding to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::ConfirmPaste { session_id, .. } => {
+                            tracing::debug!(code = %code_clone, session_id = %session_id, "Forwarding ConfirmPaste to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::Notice { message, .. } => {
+                            tracing::debug!(code = %code_clone, "Forwarding Notice to browsers: {}", message);
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
+                        ControlMessage::FlowControl { pause } => {
+                            tracing::info!(code = %code_clone, pause = pause, "Forwarding FlowControl to browsers");
+                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                        }
                         _ => {}
                     }
                 } else {
                     tracing::warn!(code = %code_clone, "Failed to parse mac-client message: {}", text);
                 }
             }
+            Ok(Message::Pong(_)) => unanswered_pings = 0,
             Ok(Message::Close(_)) => break,
             Err(e) => {
                 tracing::debug!(code = %code_clone, "Mac-client error: {}", e);
                 break;
             }
-            _ => {} // Ignore ping/pong
+            _ => {} // Pings are answered by the WebSocket layer
         }
     }
 
+    send_task.abort();
+    // A reconnect of the same mac-client took the session over, browsers
+    // and all, or it was already removed (e.g. expired)
+    if !state.is_mac_client_of(&code_clone, &ping_tx) {
+        tracing::info!(code = %code_clone, "Mac-client disconnected");
+        return;
+    }
+
     // Notify all browsers that the session is gone, then clean up
     let error_msg = serde_json::to_string(&ControlMessage::Error {
         message: "Session disconnected".into(),
     }).unwrap();
     state.broadcast_text_to_browsers(&code_clone, &error_msg).await;
 
-    send_task.abort();
     state.remove_session(&code_clone);
     tracing::info!(code = %code_clone, "Mac-client disconnected");
 }
 
-/// Handle a browser connection
-async fn handle_browser(
-    mut sender: futures_util::stream::SplitSink<WebSocket, Message>,
-    mut receiver: futures_util::stream::SplitStream<WebSocket>,
+/// Handle a browser connection. A `read_only` browser gets all output but
+/// its input, and any request that would change the sessions or read the
+/// Mac's state, is dropped. Browsers joining with a share code are always
+/// read-only; `read_only` only lets others opt in.
+/// With `compression` its binary frames are flagged (see `compression.rs`).
+pub(super) async fn handle_browser<S, R, E>(
+    mut sender: S,
+    mut receiver: R,
     state: AppState,
-    session_code: String,
-) {
-    let code = session_code.to_uppercase();
-
-    // Validate session code
-    if !state.validate_session_code(&code) {
+    auth: AuthRequest,
+    ip: Option<String>,
+) where
+    S: Sink<Message> + Unpin + Send + 'static,
+    R: Stream<Item = Result<Message, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    // Share codes resolve to the session they grant access to, until they expire
+    let Some((code, expires_at)) = state.resolve_code(&auth.session_code.to_uppercase()) else {
+        // The session may be held by another relay instance sharing our codes
+        if let Some(instance_url) = state.remote_owner(&auth.session_code.to_uppercase()).await {
+            forward(sender, receiver, &instance_url, ControlMessage::Auth(auth), ip).await;
+            return;
+        }
         let response = ControlMessage::AuthFailed {
             reason: "Invalid session code".into(),
         };
@@ -195,16 +542,50 @@ async fn handle_browser(
                 serde_json::to_string(&response).unwrap().into(),
             ))
             .await;
-        tracing::info!(code = %code, "Browser auth failed - invalid code");
+        tracing::info!(code = %auth.session_code, "Browser auth failed - invalid code");
+        state.record_auth_failure();
+        state.record_invalid_code(&auth.session_code.to_uppercase());
         return;
+    };
+    let AuthRequest { replay_lines, password, read_only, compression, .. } = auth;
+
+    // Share codes are the owner's way of inviting watchers, so they never
+    // grant input whatever the browser asked for
+    let read_only = read_only || expires_at.is_some();
+
+    // Argon2 is deliberately slow: only a few verifications run at once,
+    // and an address or code that keeps failing has to wait between tries
+    if let Some(hash) = state.password_hash(&code) {
+        let gate = state.password_gate();
+        let mut keys = vec![format!("code:{}", code)];
+        keys.extend(ip.as_deref().map(|ip| format!("ip:{}", ip)));
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let reason = if let Some(wait) = gate.retry_after(&keys, Instant::now()) {
+            Some(format!("Too many failed attempts, try again in {} s", wait.as_secs().max(1)))
+        } else {
+            let authorized = gate.verify(Some(hash), password).await;
+            gate.record(&keys, authorized, Instant::now());
+            (!authorized).then(|| "Invalid password".to_string())
+        };
+        if let Some(reason) = reason {
+            tracing::info!(code = %code, reason = %reason, "Browser auth failed - password");
+            let response = ControlMessage::AuthFailed { reason };
+            let _ = sender
+                .send(Message::Text(
+                    serde_json::to_string(&response).unwrap().into(),
+                ))
+                .await;
+            state.record_auth_failure();
+            return;
+        }
     }
 
     // Create channel for receiving messages to send to browser
     let (browser_tx, mut browser_rx) = mpsc::channel::<BrowserMessage>(1000);
-    let browser_id = nanoid::nanoid!(8);
 
     // Register browser with session
-    state.add_browser(&code, browser_id.clone(), browser_tx);
+    let ip_prefix = ip.as_deref().map_or_else(|| "unknown".to_string(), ip_prefix);
+    let browser_id = state.add_browser(&code, nanoid::nanoid!(8), browser_tx, ip_prefix, read_only, compression);
 
     // Send auth success
     let response = ControlMessage::AuthSuccess;
@@ -219,13 +600,36 @@ async fn handle_browser(
         return;
     }
 
-    tracing::info!(code = %code, browser_id = %browser_id, "Browser connected");
+    // Output to this browser is flagged from here on
+    if compression {
+        let msg = serde_json::to_string(&ControlMessage::Compression).unwrap();
+        if sender.send(Message::Text(msg.into())).await.is_err() {
+            state.remove_browser(&code, &browser_id);
+            return;
+        }
+    }
+
+    tracing::info!(code = %code, browser_id = %browser_id, read_only, compression, "Browser connected");
+
+    if let Some(label) = state.label(&code).await {
+        let msg = ControlMessage::Label { label };
+        if sender
+            .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+            .await
+            .is_err()
+        {
+            state.remove_browser(&code, &browser_id);
+            return;
+        }
+    }
 
     // Replay scrollback so browser gets terminal history immediately.
-    let scrollback = state.get_scrollback(&code).await;
+    // A bandwidth-constrained browser may ask for only the last few lines.
+    let scrollback = state.get_scrollback(&code, replay_lines).await;
     if !scrollback.is_empty() {
         tracing::info!(code = %code, frames = scrollback.len(), "Replaying scrollback to browser");
         for frame in scrollback {
+            let frame = if compression { compression::encode(&frame) } else { frame };
             if sender.send(Message::Binary(frame.into())).await.is_err() {
                 state.remove_browser(&code, &browser_id);
                 return;
@@ -240,6 +644,7 @@ async fn handle_browser(
     let msg_json = serde_json::to_string(&browser_connected_msg).unwrap();
     tracing::info!(code = %code, "Sending BrowserConnected to mac-client: {}", msg_json);
     state.send_text_to_mac_client(&code, &msg_json).await;
+    send_browser_list(&state, &code).await;
 
     // Spawn task to forward messages to browser
     let code_clone = code.clone();
@@ -249,38 +654,176 @@ async fn handle_browser(
             let result = match msg {
                 BrowserMessage::Binary(data) => sender.send(Message::Binary(data.into())).await,
                 BrowserMessage::Text(text) => sender.send(Message::Text(text.into())).await,
+                BrowserMessage::Close => break,
             };
             if result.is_err() {
                 break;
             }
         }
+        let _ = sender.send(Message::Close(None)).await;
     });
 
+    // Browsers that joined with a share code are dropped when it expires
+    let share_expiry = async {
+        match expires_at {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(share_expiry);
+
+    // Whether the browser has switched to flagged input frames
+    let mut browser_compresses = false;
+    // Whether one of this browser's searches is running
+    let searching = Arc::new(AtomicBool::new(false));
+
     // Process incoming messages from browser (keyboard input)
-    while let Some(msg_result) = receiver.next().await {
+    loop {
+        let msg_result = tokio::select! {
+            msg = receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = &mut share_expiry => {
+                tracing::info!(code = %code_clone, browser_id = %browser_id_clone, "Share code expired, disconnecting browser");
+                let notice = ControlMessage::Notice {
+                    session_id: None,
+                    message: "This share link has expired".into(),
+                };
+                let json = serde_json::to_string(&notice).unwrap();
+                state.send_text_to_browser(&code_clone, &browser_id_clone, &json).await;
+                // Dropping the browser's channel lets the send task flush the
+                // notice and close the socket
+                state.remove_browser(&code_clone, &browser_id_clone);
+                let _ = tokio::time::timeout(Duration::from_secs(1), send_task).await;
+                send_browser_list(&state, &code_clone).await;
+                tracing::info!(code = %code_clone, browser_id = %browser_id_clone, "Browser disconnected");
+                return;
+            }
+        };
         match msg_result {
+            Ok(Message::Binary(_)) if read_only => {
+                tracing::debug!(code = %code_clone, browser_id = %browser_id_clone, "Dropping input from read-only browser");
+            }
             Ok(Message::Binary(data)) => {
+                let data = if browser_compresses {
+                    let Some(frame) = compression::decode(&data) else {
+                        tracing::warn!(code = %code_clone, browser_id = %browser_id_clone, "Dropping malformed compressed frame");
+                        continue;
+                    };
+                    frame
+                } else {
+                    data.to_vec()
+                };
+                let max = max_input_frame();
+                if data.len() > max {
+                    tracing::warn!(code = %code_clone, browser_id = %browser_id_clone, bytes = data.len(), "Dropping oversized input frame");
+                    let notice = ControlMessage::Notice {
+                        session_id: None,
+                        message: format!("Input of {} bytes dropped (limit {} bytes)", data.len(), max),
+                    };
+                    let json = serde_json::to_string(&notice).unwrap();
+                    state.send_text_to_browser(&code_clone, &browser_id_clone, &json).await;
+                    continue;
+                }
                 // Forward keyboard input to mac-client
-                state.send_to_mac_client(&code_clone, data.to_vec()).await;
+                state.send_to_mac_client(&code_clone, data).await;
             }
             Ok(Message::Text(text)) => {
                 // Handle control messages from browser
                 if let Ok(ctrl) = serde_json::from_str::<ControlMessage>(&text) {
                     tracing::debug!(code = %code_clone, "Browser control: {:?}", ctrl);
+                    if read_only && denied_to_read_only(&ctrl) {
+                        tracing::debug!(code = %code_clone, browser_id = %browser_id_clone, "Ignoring control from read-only browser");
+                        continue;
+                    }
                     match ctrl {
-                        ControlMessage::CloseSession { session_id } => {
+                        ControlMessage::CloseSession { session_id, force } => {
                             // Forward to mac-client as binary frame:
                             // [session_id_len][session_id][payload]
-                            let payload = b"{\"type\":\"close_session\"}";
+                            let payload: &[u8] = if force {
+                                b"{\"type\":\"close_session\",\"force\":true}"
+                            } else {
+                                b"{\"type\":\"close_session\"}"
+                            };
                             let mut frame = Vec::with_capacity(1 + session_id.len() + payload.len());
                             frame.push(session_id.len() as u8);
                             frame.extend_from_slice(session_id.as_bytes());
                             frame.extend_from_slice(payload);
                             state.send_to_mac_client(&code_clone, frame).await;
                         }
-                        ControlMessage::CreateSession => {
+                        ControlMessage::CreateSession { term, backend, host, user, profile, .. } => {
+                            // Tell the mac-client what this browser's terminal
+                            // is like, so the new session starts out matching it
+                            let msg = ControlMessage::CreateSession {
+                                term,
+                                backend,
+                                host,
+                                user,
+                                capabilities: state.capabilities(&code_clone, &browser_id_clone),
+                                profile,
+                            };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            state.send_text_to_mac_client(&code_clone, &json).await;
+                        }
+                        ControlMessage::GetState { session_id, .. } => {
+                            // Tag with the asking browser so only it gets the snapshot
+                            let msg = ControlMessage::GetState {
+                                session_id,
+                                browser_id: Some(browser_id_clone.clone()),
+                            };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            state.send_text_to_mac_client(&code_clone, &json).await;
+                        }
+                        ControlMessage::ListProfiles { .. } => {
+                            // Tag with the asking browser so only it gets the list
+                            let msg = ControlMessage::ListProfiles {
+                                browser_id: Some(browser_id_clone.clone()),
+                            };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            state.send_text_to_mac_client(&code_clone, &json).await;
+                        }
+                        ControlMessage::BrowserCapabilities { capabilities } => {
+                            state.set_capabilities(&code_clone, &browser_id_clone, capabilities);
+                        }
+                        ControlMessage::Compression if compression => browser_compresses = true,
+                        ControlMessage::Clipboard { .. }
+                        | ControlMessage::GetCwd { .. }
+                        | ControlMessage::AutoFit { .. }
+                        | ControlMessage::ConfirmPasteAck { .. }
+                        | ControlMessage::LockSession { .. }
+                        | ControlMessage::SetEncoding { .. } => {
                             state.send_text_to_mac_client(&code_clone, &text).await;
                         }
+                        ControlMessage::Search { session_id, pattern } => {
+                            // One search per browser at a time, off the async workers
+                            if searching.swap(true, Ordering::AcqRel) {
+                                let reply = ControlMessage::Error { message: "A search is already running".into() };
+                                let json = serde_json::to_string(&reply).unwrap();
+                                state.send_text_to_browser(&code_clone, &browser_id_clone, &json).await;
+                            } else {
+                                let (state, code, browser_id, searching) =
+                                    (state.clone(), code_clone.clone(), browser_id_clone.clone(), searching.clone());
+                                tokio::spawn(async move {
+                                    let reply = search_scrollback(&state, &code, session_id, &pattern).await;
+                                    let json = serde_json::to_string(&reply).unwrap();
+                                    state.send_text_to_browser(&code, &browser_id, &json).await;
+                                    searching.store(false, Ordering::Release);
+                                });
+                            }
+                        }
+                        ControlMessage::Subscribe { session_ids } => {
+                            state.subscribe(&code_clone, &browser_id_clone, session_ids).await;
+                        }
+                        ControlMessage::Unsubscribe { session_ids } => {
+                            state.unsubscribe(&code_clone, &browser_id_clone, &session_ids);
+                        }
+                        ControlMessage::EnableTimestamps { session_id } => {
+                            state.set_timestamps(&code_clone, &browser_id_clone, &session_id, true);
+                        }
+                        ControlMessage::DisableTimestamps { session_id } => {
+                            state.set_timestamps(&code_clone, &browser_id_clone, &session_id, false);
+                        }
                         _ => {}
                     }
                 }
@@ -297,5 +840,502 @@ async fn handle_browser(
     // Cleanup
     send_task.abort();
     state.remove_browser(&code_clone, &browser_id_clone);
+    send_browser_list(&state, &code_clone).await;
     tracing::info!(code = %code_clone, browser_id = %browser_id_clone, "Browser disconnected");
 }
+
+/// Whether a browser message would act on the mac's shells, which a
+/// read-only browser may not do. Typed input and resizes arrive as binary
+/// frames and are dropped separately.
+fn denied_to_read_only(msg: &ControlMessage) -> bool {
+    matches!(
+        msg,
+        ControlMessage::GetCwd { .. }
+            | ControlMessage::CloseSession { .. }
+            | ControlMessage::CreateSession { .. }
+            | ControlMessage::LockSession { .. }
+            | ControlMessage::SetEncoding { .. }
+            | ControlMessage::Clipboard { .. }
+            | ControlMessage::ConfirmPasteAck { .. }
+            | ControlMessage::AutoFit { .. }
+    )
+}
+
+/// Run a browser's regex search over a session's scrollback, answering
+/// with SearchResults or an Error.
+async fn search_scrollback(state: &AppState, code: &str, session_id: String, pattern: &str) -> ControlMessage {
+    let regex = match search::compile(pattern) {
+        Ok(regex) => regex,
+        Err(message) => return ControlMessage::Error { message },
+    };
+    let scrollback = state.scrollback_snapshot(code).await.unwrap_or_default();
+    let sid = session_id.clone();
+    match tokio::task::spawn_blocking(move || search::search_session(&scrollback, &sid, &regex)).await {
+        Ok((matches, truncated)) => {
+            tracing::debug!(code = %code, session_id = %session_id, matches = matches.len(), "Scrollback search");
+            ControlMessage::SearchResults { session_id, matches, truncated }
+        }
+        Err(e) => {
+            tracing::warn!(code = %code, error = %e, "Scrollback search failed");
+            ControlMessage::Error { message: "Search failed".into() }
+        }
+    }
+}
+
+/// Send the session's current viewers to its mac-client.
+async fn send_browser_list(state: &AppState, code: &str) {
+    let msg = ControlMessage::BrowserList {
+        browsers: state.browser_list(code),
+    };
+    state
+        .send_text_to_mac_client(code, &serde_json::to_string(&msg).unwrap())
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_first_message_skips_empty_frames() {
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        let mut stream = futures_util::stream::iter(vec![
+            Ok::<_, ()>(Message::Text("".into())),
+            Ok(Message::Ping(Default::default())),
+            Ok(Message::Binary(Default::default())),
+            Ok(Message::Text(register.into())),
+        ]);
+
+        match first_message(&mut stream).await {
+            Some(Message::Text(text)) => {
+                let msg: ControlMessage = serde_json::from_str(&text).unwrap();
+                assert!(matches!(msg, ControlMessage::Register(_)));
+            }
+            other => panic!("Expected Register text, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_message_none_on_close() {
+        let mut stream = futures_util::stream::iter(vec![
+            Ok::<_, ()>(Message::Text(" ".into())),
+            Ok(Message::Close(None)),
+        ]);
+        assert!(first_message(&mut stream).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unresponsive_mac_client_dropped() {
+        let state = AppState::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let sender = Box::pin(futures_util::sink::unfold(out_tx, |tx, msg: Message| async move {
+            let _ = tx.send(msg);
+            Ok::<_, axum::Error>(tx)
+        }));
+        // Connected, but never sends anything again (not even a pong)
+        let receiver = futures_util::stream::pending::<Result<Message, axum::Error>>();
+
+        let interval = Duration::from_millis(20);
+        let register = RegisterRequest { client_id: "client-1".into(), password_hash: None, requested_code: None, compression: false };
+        let handler = handle_mac_client(sender, receiver, state.clone(), register, interval);
+        tokio::time::timeout(Duration::from_secs(5), handler)
+            .await
+            .expect("receive loop should exit");
+
+        assert_eq!(state.session_count(), 0);
+        assert!(matches!(out_rx.recv().await, Some(Message::Text(text)) if text.contains("registered")));
+        let mut pings = 0;
+        while let Ok(msg) = out_rx.try_recv() {
+            assert!(matches!(msg, Message::Ping(_)), "{:?}", msg);
+            pings += 1;
+        }
+        assert_eq!(pings, MAX_UNANSWERED_PINGS);
+    }
+
+    #[test]
+    fn test_parse_max_input_frame() {
+        assert_eq!(parse_max_input_frame("4096"), Some(4096));
+        assert_eq!(parse_max_input_frame("0"), None);
+        assert_eq!(parse_max_input_frame("64k"), None);
+    }
+
+    #[test]
+    fn test_valid_roles() {
+        let register = ControlMessage::Register(RegisterRequest {
+            client_id: "550e8400-e29b-41d4-a716-446655440000".into(),
+            password_hash: None,
+            requested_code: None,
+            compression: false,
+        });
+        assert!(validate_role(&register, 6).is_ok());
+
+        let auth = ControlMessage::Auth(AuthRequest {
+            session_code: "ABC234".into(),
+            replay_lines: None,
+            password: None,
+            read_only: false,
+            compression: false,
+        });
+        assert!(validate_role(&auth, 6).is_ok());
+
+        // Malformed codes are left to normal auth (AuthFailed), not rejected here
+        let auth = ControlMessage::Auth(AuthRequest {
+            session_code: "BADCODE".into(),
+            replay_lines: None,
+            password: None,
+            read_only: false,
+            compression: false,
+        });
+        assert!(validate_role(&auth, 6).is_ok());
+    }
+
+    #[test]
+    fn test_browser_sending_register_rejected() {
+        let msg = ControlMessage::Register(RegisterRequest {
+            client_id: "abc234".into(),
+            password_hash: None,
+            requested_code: None,
+            compression: false,
+        });
+        assert!(validate_role(&msg, 6).is_err());
+
+        let msg = ControlMessage::Register(RegisterRequest {
+            client_id: "  ".into(),
+            password_hash: None,
+            requested_code: None,
+            compression: false,
+        });
+        assert!(validate_role(&msg, 6).is_err());
+    }
+
+    #[test]
+    fn test_mac_sending_auth_rejected() {
+        let msg = ControlMessage::Auth(AuthRequest {
+            session_code: "550e8400-e29b-41d4-a716-446655440000".into(),
+            replay_lines: None,
+            password: None,
+            read_only: false,
+            compression: false,
+        });
+        assert!(validate_role(&msg, 6).is_err());
+    }
+
+    /// Serve the WebSocket route of a relay instance that shares codes
+    /// through `store`, returning its address.
+    async fn spawn_instance(store: std::sync::Arc<crate::registry::MemoryStore>) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let registry = crate::registry::Registry::new(store, format!("ws://{}", addr));
+        let state = AppState::with_registry(None, Some(registry));
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(ws_handler))
+            .with_state(state);
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    /// Next binary frame on `ws`, skipping control messages
+    async fn next_binary<S>(ws: &mut S) -> Vec<u8>
+    where
+        S: Stream<Item = Result<tokio_tungstenite::tungstenite::Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap();
+            if let tokio_tungstenite::tungstenite::Message::Binary(data) = msg.unwrap().unwrap() {
+                return data.to_vec();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_password() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+        let addr = spawn_instance(std::sync::Arc::new(crate::registry::MemoryStore::default())).await;
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let register = ControlMessage::Register(RegisterRequest {
+            client_id: "550e8400-e29b-41d4-a716-446655440000".into(),
+            password_hash: Some(password::tests::hash("s3cret")),
+            requested_code: None,
+            compression: false,
+        });
+        mac.send(WsMessage::Text(serde_json::to_string(&register).unwrap().into())).await.unwrap();
+        let code = match mac.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+                ControlMessage::Registered { code } => code,
+                other => panic!("Expected Registered, got {:?}", other),
+            },
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        for (password, accepted) in [(None, false), (Some("wrong"), false), (Some("s3cret"), true)] {
+            let (mut browser, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+            let auth = ControlMessage::Auth(AuthRequest {
+                session_code: code.clone(),
+                replay_lines: None,
+                password: password.map(String::from),
+                read_only: false,
+                compression: false,
+            });
+            browser.send(WsMessage::Text(serde_json::to_string(&auth).unwrap().into())).await.unwrap();
+            let WsMessage::Text(text) = browser.next().await.unwrap().unwrap() else {
+                panic!("Expected text");
+            };
+            match serde_json::from_str(&text).unwrap() {
+                ControlMessage::AuthSuccess => assert!(accepted, "{:?} accepted", password),
+                ControlMessage::AuthFailed { reason } => {
+                    assert!(!accepted, "{:?} rejected", password);
+                    assert_eq!(reason, "Invalid password");
+                }
+                other => panic!("Expected auth result, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_with_invalid_hash_rejected() {
+        let msg = ControlMessage::Register(RegisterRequest {
+            client_id: "550e8400-e29b-41d4-a716-446655440000".into(),
+            password_hash: Some("hunter2".into()),
+            requested_code: None,
+            compression: false,
+        });
+        assert!(validate_role(&msg, 6).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_browser_forwarded_to_instance_holding_session() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+        let store = std::sync::Arc::new(crate::registry::MemoryStore::default());
+        let a = spawn_instance(store.clone()).await;
+        let b = spawn_instance(store).await;
+
+        // Mac-client registers with instance A
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", a)).await.unwrap();
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        mac.send(WsMessage::Text(register.into())).await.unwrap();
+        let code = match mac.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+                ControlMessage::Registered { code } => code,
+                other => panic!("Expected Registered, got {:?}", other),
+            },
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        // Browser lands on instance B and is forwarded to A
+        let (mut browser, _) = connect_async(format!("ws://{}/ws", b)).await.unwrap();
+        let auth = serde_json::to_string(&ControlMessage::Auth(AuthRequest { session_code: code, replay_lines: None, password: None, read_only: false, compression: false })).unwrap();
+        browser.send(WsMessage::Text(auth.into())).await.unwrap();
+        match browser.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => {
+                let msg: ControlMessage = serde_json::from_str(&text).unwrap();
+                assert!(matches!(msg, ControlMessage::AuthSuccess), "got {:?}", msg);
+            }
+            other => panic!("Expected text, got {:?}", other),
+        }
+
+        // Output and input cross the two instances
+        let output = b"\x02s1hello".to_vec();
+        mac.send(WsMessage::Binary(output.clone().into())).await.unwrap();
+        assert_eq!(next_binary(&mut browser).await, output);
+
+        let input = b"\x02s1ls\r".to_vec();
+        browser.send(WsMessage::Binary(input.clone().into())).await.unwrap();
+        assert_eq!(next_binary(&mut mac).await, input);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_browser_input_dropped() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+        let addr = spawn_instance(std::sync::Arc::new(crate::registry::MemoryStore::default())).await;
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        mac.send(WsMessage::Text(register.into())).await.unwrap();
+        let code = match mac.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+                ControlMessage::Registered { code } => code,
+                other => panic!("Expected Registered, got {:?}", other),
+            },
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        // A share code makes the browser read-only without it asking
+        let share = r#"{"type":"create_share","ttl_secs":60}"#;
+        mac.send(WsMessage::Text(share.into())).await.unwrap();
+        let code = loop {
+            if let WsMessage::Text(text) = mac.next().await.unwrap().unwrap() {
+                if let ControlMessage::ShareCreated { code: share_code, .. } = serde_json::from_str(&text).unwrap() {
+                    assert_ne!(share_code, code);
+                    break share_code;
+                }
+            }
+        };
+
+        let (mut browser, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let auth = ControlMessage::Auth(AuthRequest { session_code: code, replay_lines: None, password: None, read_only: false, compression: false });
+        browser.send(WsMessage::Text(serde_json::to_string(&auth).unwrap().into())).await.unwrap();
+        match browser.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => {
+                let msg: ControlMessage = serde_json::from_str(&text).unwrap();
+                assert!(matches!(msg, ControlMessage::AuthSuccess), "got {:?}", msg);
+            }
+            other => panic!("Expected text, got {:?}", other),
+        }
+
+        // Output still reaches the read-only browser
+        let output = b"\x02s1hello".to_vec();
+        mac.send(WsMessage::Binary(output.clone().into())).await.unwrap();
+        assert_eq!(next_binary(&mut browser).await, output);
+
+        // Its input and session changes are dropped; messages are handled in
+        // order, so nothing may reach the mac before the profile request
+        browser.send(WsMessage::Binary(b"\x02s1rm -rf ~\r".to_vec().into())).await.unwrap();
+        let close = ControlMessage::CloseSession { session_id: "s1".into(), force: true };
+        browser.send(WsMessage::Text(serde_json::to_string(&close).unwrap().into())).await.unwrap();
+        browser.send(WsMessage::Text(r#"{"type":"get_cwd","session_id":"s1"}"#.into())).await.unwrap();
+        browser.send(WsMessage::Text(r#"{"type":"list_profiles"}"#.into())).await.unwrap();
+        let browser_id = loop {
+            let msg = tokio::time::timeout(Duration::from_secs(5), mac.next()).await.unwrap().unwrap().unwrap();
+            let WsMessage::Text(text) = msg else {
+                assert!(!matches!(msg, WsMessage::Binary(_)), "read-only input forwarded: {:?}", msg);
+                continue;
+            };
+            match serde_json::from_str(&text).unwrap() {
+                // Tagged with the asking browser
+                ControlMessage::ListProfiles { browser_id } => break browser_id.expect("browser_id filled in"),
+                ControlMessage::BrowserList { browsers } => assert!(browsers.iter().all(|b| b.read_only)),
+                ControlMessage::BrowserConnected { .. } => {}
+                other => panic!("Unexpected message to mac: {:?}", other),
+            }
+        };
+
+        // The reply goes back to that browser
+        let reply = ControlMessage::ProfileList { browser_id: Some(browser_id), profiles: vec![] };
+        mac.send(WsMessage::Text(serde_json::to_string(&reply).unwrap().into())).await.unwrap();
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(5), browser.next()).await.unwrap().unwrap().unwrap();
+            if let WsMessage::Text(text) = msg {
+                if text.contains("profile_list") {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_output_relayed() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+        /// Next text message, parsed
+        async fn next_control<S>(ws: &mut S) -> ControlMessage
+        where
+            S: Stream<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap();
+                if let WsMessage::Text(text) = msg.unwrap().unwrap() {
+                    return serde_json::from_str(&text).unwrap();
+                }
+            }
+        }
+
+        let addr = spawn_instance(std::sync::Arc::new(crate::registry::MemoryStore::default())).await;
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000","compression":true}"#;
+        mac.send(WsMessage::Text(register.into())).await.unwrap();
+        let ControlMessage::Registered { code } = next_control(&mut mac).await else {
+            panic!("Expected Registered");
+        };
+        assert!(matches!(next_control(&mut mac).await, ControlMessage::Compression));
+
+        let mut browsers = Vec::new();
+        for compression in [true, false] {
+            let (mut browser, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+            let auth = ControlMessage::Auth(AuthRequest {
+                session_code: code.clone(),
+                replay_lines: None,
+                password: None,
+                read_only: false,
+                compression,
+            });
+            browser.send(WsMessage::Text(serde_json::to_string(&auth).unwrap().into())).await.unwrap();
+            assert!(matches!(next_control(&mut browser).await, ControlMessage::AuthSuccess));
+            if compression {
+                assert!(matches!(next_control(&mut browser).await, ControlMessage::Compression));
+            }
+            browsers.push(browser);
+        }
+
+        // The mac-client switches its own frames over, then sends a large
+        // compressible chunk of output
+        let compression_msg = serde_json::to_string(&ControlMessage::Compression).unwrap();
+        mac.send(WsMessage::Text(compression_msg.into())).await.unwrap();
+        let mut output = b"\x02s1".to_vec();
+        output.extend(b"0123456789abcdef".repeat(1024));
+        let sent = compression::encode(&output);
+        assert_eq!(sent[0], compression::GZIP);
+        mac.send(WsMessage::Binary(sent.into())).await.unwrap();
+
+        let received = next_binary(&mut browsers[0]).await;
+        assert_eq!(received[0], compression::GZIP);
+        assert!(received.len() < output.len() / 10);
+        assert_eq!(compression::decode(&received).unwrap(), output);
+        assert_eq!(next_binary(&mut browsers[1]).await, output);
+
+        // Input from a browser reaches the mac-client flagged
+        let input = b"\x02s1ls\r".to_vec();
+        browsers[1].send(WsMessage::Binary(input.clone().into())).await.unwrap();
+        assert_eq!(compression::decode(&next_binary(&mut mac).await).unwrap(), input);
+    }
+
+    #[tokio::test]
+    async fn test_edge_relay_forwards_to_upstream() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+        let upstream = spawn_instance(std::sync::Arc::new(crate::registry::MemoryStore::default())).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let edge = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(ws_handler))
+            .layer(Extension(UpstreamRelay(format!("ws://{}", upstream))))
+            .with_state(AppState::new());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+
+        // Both the mac-client and the browser only know the edge
+        let (mut mac, _) = connect_async(format!("ws://{}/ws", edge)).await.unwrap();
+        let register = r#"{"type":"register","client_id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        mac.send(WsMessage::Text(register.into())).await.unwrap();
+        let code = match mac.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+                ControlMessage::Registered { code } => code,
+                other => panic!("Expected Registered, got {:?}", other),
+            },
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        let (mut browser, _) = connect_async(format!("ws://{}/ws", edge)).await.unwrap();
+        let auth = serde_json::to_string(&ControlMessage::Auth(AuthRequest { session_code: code, replay_lines: None, password: None, read_only: false, compression: false })).unwrap();
+        browser.send(WsMessage::Text(auth.into())).await.unwrap();
+        match browser.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => {
+                let msg: ControlMessage = serde_json::from_str(&text).unwrap();
+                assert!(matches!(msg, ControlMessage::AuthSuccess), "got {:?}", msg);
+            }
+            other => panic!("Expected text, got {:?}", other),
+        }
+
+        let output = b"\x02s1hello".to_vec();
+        mac.send(WsMessage::Binary(output.clone().into())).await.unwrap();
+        assert_eq!(next_binary(&mut browser).await, output);
+    }
+}
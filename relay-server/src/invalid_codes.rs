@@ -0,0 +1,111 @@
+//! Monitoring of browser auths with codes that match no session.
+//!
+//! A code is only 6 characters, so someone working through the code space
+//! shows up as a stream of auths with codes that don't exist. Attempts are
+//! counted (for `/metrics`) and tallied per code in fixed windows; once a
+//! window goes over the threshold a single warning is raised for it.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Length of a counting window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Invalid-code attempts per window that count as a burst
+const BURST_THRESHOLD: u32 = 20;
+
+/// Distinct codes tallied per window, so a scan can't grow the map unbounded
+const MAX_TRACKED_CODES: usize = 1024;
+
+/// A window that crossed the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Burst {
+    pub attempts: u32,
+    pub distinct_codes: usize,
+    /// Most tried code and its attempts
+    pub top_code: (String, u32),
+}
+
+#[derive(Default)]
+struct Window {
+    started: Option<Instant>,
+    attempts: u32,
+    per_code: HashMap<String, u32>,
+    reported: bool,
+}
+
+#[derive(Default)]
+pub struct InvalidCodeMonitor {
+    /// All invalid-code attempts since startup
+    total: AtomicU64,
+    window: Mutex<Window>,
+}
+
+impl InvalidCodeMonitor {
+    /// Count an attempt with `code`. Returns the burst the first time the
+    /// current window crosses the threshold, and None otherwise.
+    pub fn record(&self, code: &str, now: Instant) -> Option<Burst> {
+        self.total.fetch_add(1, Ordering::Relaxed);
+
+        let mut window = self.window.lock().unwrap();
+        if window.started.is_none_or(|started| now.duration_since(started) >= WINDOW) {
+            *window = Window { started: Some(now), ..Window::default() };
+        }
+        window.attempts += 1;
+        if window.per_code.len() < MAX_TRACKED_CODES || window.per_code.contains_key(code) {
+            *window.per_code.entry(code.to_string()).or_default() += 1;
+        }
+
+        if window.reported || window.attempts < BURST_THRESHOLD {
+            return None;
+        }
+        window.reported = true;
+        let top_code = window
+            .per_code
+            .iter()
+            .max_by_key(|(code, count)| (**count, std::cmp::Reverse(code.as_str())))
+            .map(|(code, count)| (code.clone(), *count))
+            .unwrap_or_default();
+        Some(Burst {
+            attempts: window.attempts,
+            distinct_codes: window.per_code.len(),
+            top_code,
+        })
+    }
+
+    /// Invalid-code attempts since startup.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_reported_once_per_window() {
+        let monitor = InvalidCodeMonitor::default();
+        let start = Instant::now();
+
+        for i in 0..BURST_THRESHOLD - 1 {
+            assert_eq!(monitor.record(&format!("SCAN{:02}", i), start), None);
+        }
+        let burst = monitor.record("SCAN00", start + Duration::from_secs(1)).unwrap();
+        assert_eq!(burst.attempts, BURST_THRESHOLD);
+        assert_eq!(burst.distinct_codes, BURST_THRESHOLD as usize - 1);
+        assert_eq!(burst.top_code, ("SCAN00".to_string(), 2));
+
+        // Throttled for the rest of the window
+        for _ in 0..50 {
+            assert_eq!(monitor.record("SCAN99", start + Duration::from_secs(2)), None);
+        }
+
+        // A new window starts counting afresh
+        let later = start + WINDOW;
+        assert_eq!(monitor.record("SCAN00", later), None);
+        assert_eq!(monitor.total(), BURST_THRESHOLD as u64 + 51);
+    }
+}
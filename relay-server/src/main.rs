@@ -1,23 +1,130 @@
+mod access_log;
 mod assets;
+mod cache;
+mod compression;
 mod handlers;
+mod invalid_codes;
+mod password;
 mod protocol;
+mod registry;
+mod scrollback;
+mod search;
+mod security;
 mod session;
 mod state;
 
-use axum::{extract::State, routing::get, Router};
+use axum::{extract::State, http::HeaderMap, middleware, routing::get, Extension, Json, Router};
 use axum_embed::ServeEmbed;
-use std::net::SocketAddr;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
 use crate::assets::Assets;
+use crate::access_log::AccessLog;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::registry::Registry;
+use crate::security::SecurityHeaders;
 use crate::state::AppState;
 
-async fn debug_sessions(State(state): State<AppState>) -> String {
-    format!("Active sessions: {}", state.session_count())
+/// How long connections get to close on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// Interface listened on unless `BIND_ADDR` is set: all of them
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Response body for `GET /version`.
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    protocol_version: u32,
+    features: Features,
+}
+
+/// Optional protocol features this relay supports.
+#[derive(Serialize)]
+struct Features {
+    /// Gzip-flagged binary frames for clients that ask; every build
+    /// negotiates them (see `compression`)
+    compression: bool,
+    /// This request came in over TLS, terminated in front of the relay
+    tls: bool,
+    /// A reconnecting mac-client gets its session (or, once it is freed,
+    /// its code) back, so browsers keep the code they joined with
+    resume: bool,
+}
+
+impl Features {
+    fn detect(request: &HeaderMap) -> Self {
+        Self {
+            compression: true,
+            tls: security::over_tls(request),
+            resume: !state::CODE_RESERVATION.is_zero(),
+        }
+    }
+}
+
+async fn version(headers: HeaderMap) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        features: Features::detect(&headers),
+    })
+}
+
+/// Output of `relay-server --version`. The mac-client parses the protocol
+/// number to catch a stale bundled binary before starting it.
+fn version_line() -> String {
+    format!(
+        "relay-server {} (protocol {})",
+        env!("CARGO_PKG_VERSION"),
+        PROTOCOL_VERSION
+    )
+}
+
+/// Prometheus text exposition of relay counters.
+async fn metrics(State(state): State<AppState>) -> String {
+    let counters = state.counters();
+    format!(
+        "# HELP relay_sessions Connected mac-clients\n\
+         # TYPE relay_sessions gauge\n\
+         relay_sessions {}\n\
+         # HELP relay_browsers Connected browsers\n\
+         # TYPE relay_browsers gauge\n\
+         relay_browsers {}\n\
+         # HELP relay_sessions_total Mac-clients registered since startup\n\
+         # TYPE relay_sessions_total counter\n\
+         relay_sessions_total {}\n\
+         # HELP relay_bytes_to_browsers_total Bytes relayed to browsers\n\
+         # TYPE relay_bytes_to_browsers_total counter\n\
+         relay_bytes_to_browsers_total {}\n\
+         # HELP relay_bytes_to_mac_total Bytes relayed to mac-clients\n\
+         # TYPE relay_bytes_to_mac_total counter\n\
+         relay_bytes_to_mac_total {}\n\
+         # HELP relay_auth_failures_total Browser auths refused for a bad code or password\n\
+         # TYPE relay_auth_failures_total counter\n\
+         relay_auth_failures_total {}\n\
+         # HELP relay_invalid_code_attempts_total Browser auths with a code matching no session\n\
+         # TYPE relay_invalid_code_attempts_total counter\n\
+         relay_invalid_code_attempts_total {}\n",
+        state.session_count(),
+        state.browser_count(),
+        counters.sessions_total,
+        counters.bytes_to_browsers,
+        counters.bytes_to_mac,
+        counters.auth_failures,
+        state.invalid_code_attempts()
+    )
 }
 
 #[tokio::main]
 async fn main() {
+    if std::env::args().skip(1).any(|arg| arg == "--version" || arg == "-V") {
+        println!("{}", version_line());
+        return;
+    }
+
     // Initialize tracing
     tracing_subscriber::fmt::init();
 
@@ -27,8 +134,18 @@ async fn main() {
         .parse()
         .expect("PORT must be a valid number");
 
-    // Create application state
-    let state = AppState::new();
+    // Listen on every interface unless told otherwise, e.g. 127.0.0.1 behind cloudflared
+    let bind_addr = match std::env::var("BIND_ADDR") {
+        Ok(value) => parse_bind_addr(&value).unwrap_or_else(|| {
+            tracing::warn!("Invalid BIND_ADDR {:?}, using {}", value, DEFAULT_BIND_ADDR);
+            DEFAULT_BIND_ADDR
+        }),
+        Err(_) => DEFAULT_BIND_ADDR,
+    };
+
+    // Create application state. The admin WebSocket is only enabled with a token.
+    let admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+    let state = AppState::with_registry(admin_token, Registry::from_env());
 
     // Create embedded asset server with SPA fallback
     // First param: index file for "/" route, Second: fallback behavior for unknown paths
@@ -37,18 +154,167 @@ async fn main() {
         axum_embed::FallbackBehavior::Ok,
         None,
     );
+    // Cache headers apply to the web UI only, not the API routes below
+    let serve_assets = Router::new()
+        .fallback_service(serve_assets)
+        .layer(middleware::from_fn(cache::cache_headers));
 
     // Build router
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/ws", get(handlers::ws_handler))
-        .route("/debug/sessions", get(debug_sessions))
+        .route("/admin/ws", get(handlers::admin_ws_handler))
+        .route("/admin/sessions.json", get(handlers::admin_sessions_handler))
+        .route("/debug/sessions", get(handlers::debug_sessions_handler))
+        .route("/debug/sessions/detailed", get(handlers::debug_sessions_detailed_handler))
+        .route("/metrics", get(metrics))
+        .route("/version", get(version));
+
+    // Access log covers the WebSocket and API routes and raw TCP
+    // connections, not static assets
+    let access_log = AccessLog::from_env().unwrap_or_else(|e| {
+        tracing::error!("Cannot open ACCESS_LOG: {}", e);
+        None
+    });
+    if let Some(log) = &access_log {
+        app = app.route_layer(middleware::from_fn_with_state(log.clone(), access_log::access_log));
+    }
+
+    let upstream = handlers::UpstreamRelay::from_env();
+    if let Some(upstream) = &upstream {
+        info!("Edge relay: forwarding all connections to {}", upstream.0);
+        app = app.layer(Extension(upstream.clone()));
+    }
+
+    let app = app
         .fallback_service(serve_assets)
-        .with_state(state);
+        .layer(middleware::from_fn_with_state(
+            Arc::new(SecurityHeaders::from_env()),
+            security::security_headers,
+        ))
+        .with_state(state.clone());
 
     // Bind and serve
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::new(bind_addr, port);
     info!("Relay server starting on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+
+    // Raw TCP for clients without WebSocket support
+    if let Some(tcp_port) = handlers::tcp_port() {
+        // Plaintext, so loopback unless TCP_BIND_ADDR says otherwise
+        let tcp_bind_addr = match std::env::var("TCP_BIND_ADDR") {
+            Ok(value) => parse_bind_addr(&value).unwrap_or_else(|| {
+                tracing::warn!("Invalid TCP_BIND_ADDR {:?}, using {}", value, Ipv4Addr::LOCALHOST);
+                Ipv4Addr::LOCALHOST.into()
+            }),
+            Err(_) => Ipv4Addr::LOCALHOST.into(),
+        };
+        let tcp_addr = SocketAddr::new(tcp_bind_addr, tcp_port);
+        let tcp_listener = tokio::net::TcpListener::bind(tcp_addr).await.unwrap();
+        info!("Raw TCP clients accepted on {}", tcp_addr);
+        tokio::spawn(handlers::serve_tcp(tcp_listener, state.clone(), upstream, access_log));
+    }
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                tracing::error!("Cannot listen for Ctrl-C: {}", e);
+                std::future::pending::<()>().await;
+            }
+            state.shutdown(SHUTDOWN_GRACE).await;
+        })
+        .await
+        .unwrap();
+    info!("Relay server stopped");
+}
+
+/// `BIND_ADDR` or `TCP_BIND_ADDR` as an IPv4 or IPv6 address; the IPv6 one may be bracketed.
+fn parse_bind_addr(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    let value = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bind_addr() {
+        assert_eq!(parse_bind_addr("127.0.0.1"), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(parse_bind_addr(" 0.0.0.0 "), Some(DEFAULT_BIND_ADDR));
+        assert_eq!(parse_bind_addr("::1"), Some("::1".parse().unwrap()));
+        assert_eq!(parse_bind_addr("[::]"), Some("::".parse().unwrap()));
+        assert_eq!(parse_bind_addr("localhost"), None);
+        assert_eq!(parse_bind_addr("127.0.0.1:3000"), None);
+        assert_eq!(parse_bind_addr(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_version_json() {
+        let Json(info) = version(HeaderMap::new()).await;
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(json["features"]["compression"], true);
+        assert_eq!(json["features"]["tls"], false);
+        assert_eq!(json["features"]["resume"], true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let Json(info) = version(headers).await;
+        assert!(info.features.tls);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_counts_invalid_codes() {
+        let state = AppState::new();
+        let mut events = state.subscribe_admin_events();
+        for i in 0..30 {
+            state.record_invalid_code(&format!("ZZZ{:03}", i));
+        }
+
+        let body = metrics(State(state)).await;
+        assert!(body.contains("\nrelay_invalid_code_attempts_total 30\n"), "{}", body);
+        assert!(body.contains("\nrelay_sessions 0\n"));
+        // One alert for the burst, not one per attempt
+        assert!(matches!(events.try_recv(), Ok(state::AdminEvent::InvalidCodeBurst { attempts: 20, .. })));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_traffic() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = tokio::sync::mpsc::channel(8);
+        let code = state.register_mac_client("client-1", None, mac_tx).unwrap();
+        let mut browsers = Vec::new();
+        for browser_id in ["b1", "b2"] {
+            let (browser_tx, browser_rx) = tokio::sync::mpsc::channel(8);
+            state.add_browser(&code, browser_id.into(), browser_tx, "127.0.0.0/24".into(), false, false);
+            browsers.push(browser_rx);
+        }
+
+        // A 4-byte output frame reaches both browsers; 2 bytes of input go the other way
+        state.broadcast_to_browsers(&code, vec![2, b't', b'1', b'x']).await;
+        state.send_to_mac_client(&code, b"ls".to_vec()).await;
+        state.record_auth_failure();
+
+        let body = metrics(State(state)).await;
+        for line in [
+            "relay_sessions 1",
+            "relay_browsers 2",
+            "relay_sessions_total 1",
+            "relay_bytes_to_browsers_total 8",
+            "relay_bytes_to_mac_total 2",
+            "relay_auth_failures_total 1",
+        ] {
+            assert!(body.contains(&format!("\n{}\n", line)), "missing {:?} in\n{}", line, body);
+        }
+    }
+
+    #[test]
+    fn test_version_line() {
+        let line = version_line();
+        assert!(line.starts_with(&format!("relay-server {} ", env!("CARGO_PKG_VERSION"))));
+        assert!(line.ends_with(&format!("(protocol {})", PROTOCOL_VERSION)));
+    }
 }
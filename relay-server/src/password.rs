@@ -0,0 +1,211 @@
+//! Optional session passwords.
+//!
+//! A six-character code can be guessed, so a mac-client may register with
+//! the Argon2 hash (PHC string) of a password. Browsers joining that session
+//! must then send the password with `Auth`. The relay only ever stores the
+//! hash; sessions registered without one accept the code alone.
+//!
+//! Every verification costs up to [`MAX_MEMORY_KIB`] and a blocking thread,
+//! so [`PasswordGate`] runs only a few at once and makes a browser address
+//! or session code that keeps failing wait longer before each retry.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use argon2::{Argon2, Params};
+use tokio::sync::Semaphore;
+
+/// Cost limits for registered hashes. Every browser auth pays the cost, so a
+/// mac-client can't pick parameters that tie up the relay.
+const MAX_MEMORY_KIB: u32 = 64 * 1024;
+const MAX_ITERATIONS: u32 = 10;
+const MAX_PARALLELISM: u32 = 4;
+
+/// Verifications running at once; further auths wait their turn
+const MAX_CONCURRENT_VERIFIES: usize = 4;
+
+/// Failures per address or code before retries are delayed
+const FREE_FAILURES: u32 = 3;
+
+/// Longest delay before another attempt, reached after repeated failures
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Addresses and codes with failures remembered at once
+const MAX_TRACKED_KEYS: usize = 4096;
+
+/// Check a hash sent with Register: an Argon2 PHC string within the cost limits.
+pub fn validate_hash(hash: &str) -> Result<(), &'static str> {
+    let parsed = PasswordHash::new(hash).map_err(|_| "password_hash is not a PHC string")?;
+    if !matches!(parsed.algorithm.as_str(), "argon2id" | "argon2i" | "argon2d") {
+        return Err("password_hash must be an Argon2 hash");
+    }
+    if parsed.hash.is_none() {
+        return Err("password_hash has no hash value");
+    }
+    let params = Params::try_from(&parsed).map_err(|_| "password_hash has invalid Argon2 parameters")?;
+    if params.m_cost() > MAX_MEMORY_KIB || params.t_cost() > MAX_ITERATIONS || params.p_cost() > MAX_PARALLELISM {
+        return Err("password_hash parameters are too expensive");
+    }
+    Ok(())
+}
+
+/// Whether a browser may join: always without a registered hash, otherwise
+/// only with the matching password. Slow by design; run off the async runtime.
+pub fn authorize(hash: Option<&str>, password: Option<&str>) -> bool {
+    let Some(hash) = hash else {
+        return true;
+    };
+    let (Some(password), Ok(parsed)) = (password, PasswordHash::new(hash)) else {
+        return false;
+    };
+    Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok()
+}
+
+struct Failures {
+    count: u32,
+    /// No attempt is verified before this
+    retry_at: Instant,
+}
+
+/// Limits password verification: a fixed number at a time, with
+/// exponential backoff for keys (browser address, session code) that keep
+/// sending wrong passwords.
+pub struct PasswordGate {
+    permits: Semaphore,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl Default for PasswordGate {
+    fn default() -> Self {
+        Self {
+            permits: Semaphore::new(MAX_CONCURRENT_VERIFIES),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PasswordGate {
+    /// How long until any of `keys` may try again, if it is backing off.
+    pub fn retry_after(&self, keys: &[&str], now: Instant) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        keys.iter()
+            .filter_map(|key| failures.get(*key))
+            .map(|f| f.retry_at.saturating_duration_since(now))
+            .filter(|wait| !wait.is_zero())
+            .max()
+    }
+
+    /// [`authorize`] on a blocking thread, once a verification slot is free.
+    pub async fn verify(&self, hash: Option<String>, password: Option<String>) -> bool {
+        if hash.is_none() {
+            return true;
+        }
+        let Ok(_permit) = self.permits.acquire().await else {
+            return false;
+        };
+        tokio::task::spawn_blocking(move || authorize(hash.as_deref(), password.as_deref()))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Record the outcome of an attempt by `keys`: success forgets their
+    /// failures, a failure delays their next attempt.
+    pub fn record(&self, keys: &[&str], success: bool, now: Instant) {
+        let mut failures = self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if success {
+            for key in keys {
+                failures.remove(*key);
+            }
+            return;
+        }
+        if failures.len() >= MAX_TRACKED_KEYS {
+            failures.retain(|_, f| f.retry_at > now);
+        }
+        for key in keys {
+            if failures.len() >= MAX_TRACKED_KEYS && !failures.contains_key(*key) {
+                tracing::warn!("Too many failing addresses and codes to track; not delaying {}", key);
+                continue;
+            }
+            let entry = failures.entry(key.to_string()).or_insert(Failures { count: 0, retry_at: now });
+            entry.count += 1;
+            entry.retry_at = now + backoff(entry.count);
+        }
+    }
+}
+
+/// Delay after `failures` consecutive failures: none for the first few,
+/// then doubling from a second up to [`MAX_BACKOFF`].
+fn backoff(failures: u32) -> Duration {
+    match failures.checked_sub(FREE_FAILURES) {
+        None | Some(0) => Duration::ZERO,
+        Some(n) => Duration::from_secs(1u64 << (n - 1).min(6)).min(MAX_BACKOFF),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use argon2::{Algorithm, Version};
+
+    /// Hash with cheap parameters, as verification reuses them
+    pub(crate) fn hash(password: &str) -> String {
+        let salt = SaltString::encode_b64(b"relay-test-salt!").unwrap();
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::new(1024, 1, 1, None).unwrap());
+        argon2.hash_password(password.as_bytes(), &salt).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_authorize() {
+        let hash = hash("correct horse");
+        assert!(authorize(Some(&hash), Some("correct horse")));
+        assert!(!authorize(Some(&hash), Some("battery staple")));
+        assert!(!authorize(Some(&hash), None));
+
+        // No password registered: the code alone is enough
+        assert!(authorize(None, None));
+        assert!(authorize(None, Some("anything")));
+    }
+
+    #[test]
+    fn test_failures_back_off() {
+        let gate = PasswordGate::default();
+        let now = Instant::now();
+        for _ in 0..FREE_FAILURES {
+            gate.record(&["ip:10.0.0.1", "code:ABC123"], false, now);
+        }
+        assert_eq!(gate.retry_after(&["ip:10.0.0.1"], now), None);
+
+        gate.record(&["ip:10.0.0.1", "code:ABC123"], false, now);
+        assert_eq!(gate.retry_after(&["ip:10.0.0.1"], now), Some(Duration::from_secs(1)));
+        gate.record(&["ip:10.0.0.1", "code:ABC123"], false, now);
+        assert_eq!(gate.retry_after(&["ip:10.0.0.2", "code:ABC123"], now), Some(Duration::from_secs(2)));
+        assert_eq!(gate.retry_after(&["ip:10.0.0.1"], now + Duration::from_secs(2)), None);
+
+        // Capped, and forgotten on success
+        for _ in 0..20 {
+            gate.record(&["ip:10.0.0.1"], false, now);
+        }
+        assert_eq!(gate.retry_after(&["ip:10.0.0.1"], now), Some(MAX_BACKOFF));
+        gate.record(&["ip:10.0.0.1"], true, now);
+        assert_eq!(gate.retry_after(&["ip:10.0.0.1"], now), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_without_hash_skips_argon2() {
+        let gate = PasswordGate::default();
+        assert!(gate.verify(None, None).await);
+        assert!(gate.verify(Some(hash("pw")), Some("pw".into())).await);
+        assert!(!gate.verify(Some(hash("pw")), None).await);
+    }
+
+    #[test]
+    fn test_validate_hash() {
+        assert!(validate_hash(&hash("pw")).is_ok());
+        assert!(validate_hash("hunter2").is_err());
+        assert!(validate_hash("$pbkdf2-sha256$i=1000$c2FsdHNhbHQ$aGFzaGhhc2g").is_err());
+        assert!(validate_hash("$argon2id$v=19$m=1048576,t=2,p=1$c2FsdHNhbHRzYWx0$aGFzaGhhc2hoYXNoaGFzaA").is_err());
+    }
+}
@@ -1,52 +1,329 @@
 use serde::{Deserialize, Serialize};
 
+/// Version of the WebSocket control protocol spoken by this relay.
+/// Bump when a change breaks existing mac-clients or browsers.
+/// 2: Register's requested code and compression, labels, share codes,
+/// flow control, timestamps and the other messages added since 1.
+pub const PROTOCOL_VERSION: u32 = 2;
+
 /// Control messages sent as JSON over WebSocket Text frames.
 /// Terminal I/O is sent as Binary frames (not wrapped in ControlMessage).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ControlMessage {
     // Mac-client -> Relay
-    Register { client_id: String },
+    Register(RegisterRequest),
+    Heartbeat { sessions: usize, load: f32 },
+    /// Name browsers show for this Mac (e.g. its computer name); resent
+    /// whenever it changes. Empty clears it.
+    SetLabel { label: String },
+    /// The Mac's OS version, shell and tools (only with REPORT_ENVIRONMENT),
+    /// sent after each Register
+    Environment { environment: ClientEnvironment },
+
+    /// Relay <-> Mac-client/Browser: every binary frame the sender sends
+    /// after this starts with a compression flag byte
+    Compression,
+
+    // Mac-client -> Relay (time-boxed code for sharing the session)
+    CreateShare { ttl_secs: u64 },
+
+    // Mac-client -> Relay (replied to with BrowserList)
+    ListBrowsers,
 
     // Relay -> Mac-client
     Registered { code: String },
+    ShareCreated { code: String, ttl_secs: u64 },
+    /// Connected browsers; also sent whenever one joins or leaves
+    BrowserList { browsers: Vec<BrowserEntry> },
     BrowserConnected { browser_id: String },
     BrowserDisconnected { browser_id: String },
 
     // Browser -> Relay
-    Auth { session_code: String },
+    Auth(AuthRequest),
 
     // Relay -> Browser
     AuthSuccess,
     AuthFailed { reason: String },
+    /// The session's label, after AuthSuccess and whenever it changes
+    Label { label: String },
 
     // Browser -> Relay -> Mac-client
-    CloseSession { session_id: String },
-    CreateSession,
+    CloseSession {
+        session_id: String,
+        /// Close even if the session is locked
+        #[serde(default)]
+        force: bool,
+    },
+    // Browser -> Relay -> Mac-client, echoed back to browsers once applied
+    LockSession { session_id: String, locked: bool },
+    /// Transcode the session's output to UTF-8 from `encoding` (e.g.
+    /// "latin1"), and input back to it; None for UTF-8 passthrough
+    SetEncoding {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+    },
+    CreateSession {
+        /// TERM for the new shell (must be in the mac-client's allowlist)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        term: Option<String>,
+        /// "ssh" for a session on a remote host (default: a local shell)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        backend: Option<String>,
+        /// Remote `host[:port]` for the ssh backend
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<String>,
+        /// Remote user for the ssh backend (default: ssh's own default)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        /// The requesting browser's terminal, filled in by the relay
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capabilities: Option<TerminalCapabilities>,
+        /// Name of a profile from ProfileList to start the session with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+
+    // Browser -> Relay, after AuthSuccess (attached to its CreateSession requests)
+    BrowserCapabilities {
+        #[serde(flatten)]
+        capabilities: TerminalCapabilities,
+    },
+
+    // Browser -> Relay (only receive output for these terminal sessions)
+    Subscribe { session_ids: Vec<String> },
+    Unsubscribe { session_ids: Vec<String> },
+
+    // Browser -> Relay -> Mac-client (base64 clipboard text, delivered via OSC 52)
+    Clipboard { session_id: String, data: String },
+
+    // Browser -> Relay -> Mac-client (replied to with Cwd)
+    GetCwd { session_id: String },
+
+    // Browser -> Relay -> Mac-client (re-fit the session to its terminal;
+    // answered with SessionResize)
+    AutoFit { session_id: String },
+
+    // Browser -> Relay -> Mac-client (replied to with ProfileList; the relay fills in browser_id)
+    ListProfiles {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+    },
+
+    // Browser -> Relay -> Mac-client (replied to with State; the relay fills in browser_id)
+    GetState {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+    },
+
+    // Browser -> Relay -> Mac-client (run a paste held by ConfirmPaste, or drop it)
+    ConfirmPasteAck { token: String, accept: bool },
+
+    // Browser -> Relay (precede this session's output with OutputTimestamp;
+    // for debugging latency, off by default). Relay -> Mac-client while any
+    // browser wants a session's output stamped, and when none does anymore.
+    EnableTimestamps { session_id: String },
+    DisableTimestamps { session_id: String },
+
+    // Browser -> Relay (regex search over a session's scrollback)
+    Search { session_id: String, pattern: String },
+
+    // Relay -> Browser
+    SearchResults {
+        session_id: String,
+        matches: Vec<SearchMatch>,
+        /// True if the search hit its match or time limit
+        truncated: bool,
+    },
 
     // Mac-client -> Relay -> Browser (session list on connect)
     SessionList { sessions: Vec<SessionInfo> },
     SessionConnected { session_id: String, name: String },
     SessionDisconnected { session_id: String },
     SessionResize { session_id: String, cols: u16, rows: u16 },
+    Cwd { session_id: String, path: String },
+    /// Session presets a browser can pass to CreateSession as `profile`,
+    /// for the browser that asked (all browsers if None)
+    ProfileList {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+        profiles: Vec<ProfileInfo>,
+    },
+    /// Unix milliseconds of the binary frame that follows, for browsers that
+    /// sent EnableTimestamps. From the mac-client, `timestamp_ms` is when it
+    /// sent the frame; to browsers, when the relay received it, with the
+    /// mac-client's time as `mac_timestamp_ms` (if it stamped the frame).
+    OutputTimestamp {
+        session_id: String,
+        timestamp_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mac_timestamp_ms: Option<u64>,
+    },
+    /// Snapshot of a session's terminal for the browser that asked (all
+    /// browsers if None)
+    State {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_id: Option<String>,
+        state: TerminalState,
+    },
+    /// Keystrokes typed on the Mac (base64), distinct from output (opt-in)
+    LocalInput { session_id: String, data: String },
+    /// Multiline browser input held until a browser acks `token` (opt-in)
+    ConfirmPaste { session_id: String, token: String, preview: String },
+    Notice {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        message: String,
+    },
+
+    // Mac-client -> Relay -> Browser (pause/resume input while the mac uplink is backed up)
+    FlowControl { pause: bool },
 
     // Bidirectional
     Error { message: String },
 }
 
+/// A mac-client's Register request (the fields of `ControlMessage::Register`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterRequest {
+    pub client_id: String,
+    /// Argon2 hash (PHC string) of a password browsers must send with Auth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Fixed code to use if valid and free; Registered has the code given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_code: Option<String>,
+    /// Can handle flagged, possibly compressed binary frames (answered
+    /// with Compression)
+    #[serde(default)]
+    pub compression: bool,
+}
+
+/// A browser's Auth request (the fields of `ControlMessage::Auth`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthRequest {
+    pub session_code: String,
+    /// Lines of scrollback to replay per terminal session (None = all)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_lines: Option<usize>,
+    /// Required when the mac-client registered with a password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Watch only: the relay drops this browser's input. Browsers joining
+    /// with a share code are read-only regardless
+    #[serde(default)]
+    pub read_only: bool,
+    /// Can handle flagged, possibly compressed binary frames (answered
+    /// with Compression)
+    #[serde(default)]
+    pub compression: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SessionInfo {
     pub id: String,
     pub name: String,
 }
 
+/// A session preset as offered to browsers. `icon` is a hint for the
+/// picker (e.g. "python"); browsers fall back to a generic icon.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProfileInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// What a mac-client runs on, reported (opt-in) so relay operators can
+/// debug a Mac whose sessions behave oddly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ClientEnvironment {
+    /// macOS version, e.g. "14.5"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Shell new sessions start, e.g. "/bin/zsh"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub tmux: bool,
+    #[serde(default)]
+    pub cloudflared: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+}
+
+/// A connected browser as shown to the session owner. Only a network
+/// prefix of its address is included, for the viewer's privacy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BrowserEntry {
+    pub id: String,
+    /// Unix timestamp (seconds) of when the browser joined
+    pub connected_at: u64,
+    pub ip_prefix: String,
+    /// Joined watch-only; its input is dropped by the relay
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// What a browser's terminal can display, used to set up the sessions it
+/// creates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TerminalCapabilities {
+    pub cols: u16,
+    pub rows: u16,
+    /// 24-bit color support
+    #[serde(default)]
+    pub truecolor: bool,
+    /// Unicode version of the browser's character width tables (e.g. 6 or 11)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unicode_version: Option<u32>,
+    /// Browser locale as a BCP 47 tag (e.g. "de-CH")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Browser timezone as an IANA name (e.g. "Europe/Zurich")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// A session's terminal as the mac-client last saw it, for a browser
+/// restoring its view on rejoin: writing `scrollback` then `screen` to a
+/// reset terminal of `cols`x`rows` reproduces it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TerminalState {
+    pub cols: u16,
+    pub rows: u16,
+    /// Cursor position (0-based), also restored by `screen`
+    pub cursor_row: u16,
+    pub cursor_col: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Base64 escape sequences writing the scrollback tail into history
+    pub scrollback: String,
+    /// Base64 escape sequences redrawing the screen, cursor and input modes
+    pub screen: String,
+}
+
+/// A scrollback search hit: line number from the oldest buffered line,
+/// character column, and the (escape-stripped) line text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchMatch {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_serialize_register() {
-        let msg = ControlMessage::Register { client_id: "test".into() };
+        let msg = ControlMessage::Register(RegisterRequest { client_id: "test".into(), password_hash: None, requested_code: None, compression: false });
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
         assert!(json.contains("\"client_id\":\"test\""));
@@ -60,6 +337,68 @@ mod tests {
         assert!(json.contains("\"code\":\"ABC123\""));
     }
 
+    #[test]
+    fn test_deserialize_subscribe() {
+        let json = r#"{"type":"subscribe","session_ids":["a","b"]}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ControlMessage::Subscribe { session_ids } => assert_eq!(session_ids, ["a", "b"]),
+            _ => panic!("Expected Subscribe message"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_close_session_force() {
+        let json = r#"{"type":"close_session","session_id":"s1"}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::CloseSession { force, .. } => assert!(!force),
+            _ => panic!("Expected CloseSession message"),
+        }
+        let json = r#"{"type":"close_session","session_id":"s1","force":true}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::CloseSession { force, .. } => assert!(force),
+            _ => panic!("Expected CloseSession message"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_browser_capabilities() {
+        let json = r#"{"type":"browser_capabilities","cols":120,"rows":40,"truecolor":true,"unicode_version":11,"locale":"de-CH","timezone":"Europe/Zurich"}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::BrowserCapabilities { capabilities } => {
+                assert_eq!(capabilities.cols, 120);
+                assert_eq!(capabilities.rows, 40);
+                assert!(capabilities.truecolor);
+                assert_eq!(capabilities.unicode_version, Some(11));
+                assert_eq!(capabilities.locale.as_deref(), Some("de-CH"));
+                assert_eq!(capabilities.timezone.as_deref(), Some("Europe/Zurich"));
+            }
+            _ => panic!("Expected BrowserCapabilities message"),
+        }
+        let json = r#"{"type":"browser_capabilities","cols":80,"rows":24}"#;
+        match serde_json::from_str(json).unwrap() {
+            ControlMessage::BrowserCapabilities { capabilities } => {
+                assert!(!capabilities.truecolor);
+                assert_eq!(capabilities.unicode_version, None);
+                assert_eq!(capabilities.locale, None);
+            }
+            _ => panic!("Expected BrowserCapabilities message"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_heartbeat() {
+        let json = r#"{"type":"heartbeat","sessions":3,"load":1.5}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ControlMessage::Heartbeat { sessions, load } => {
+                assert_eq!(sessions, 3);
+                assert_eq!(load, 1.5);
+            }
+            _ => panic!("Expected Heartbeat message"),
+        }
+    }
+
     #[test]
     fn test_serialize_auth_success() {
         let msg = ControlMessage::AuthSuccess;
@@ -72,13 +411,53 @@ mod tests {
         let json = r#"{"type":"auth","session_code":"XYZ789"}"#;
         let msg: ControlMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ControlMessage::Auth { session_code } => {
+            ControlMessage::Auth(AuthRequest { session_code, replay_lines, read_only, .. }) => {
                 assert_eq!(session_code, "XYZ789");
+                assert_eq!(replay_lines, None);
+                assert!(!read_only);
             }
             _ => panic!("Expected Auth message"),
         }
     }
 
+    #[test]
+    fn test_deserialize_auth_with_replay_lines() {
+        let json = r#"{"type":"auth","session_code":"XYZ789","replay_lines":50}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ControlMessage::Auth(AuthRequest { replay_lines, .. }) => {
+                assert_eq!(replay_lines, Some(50));
+            }
+            _ => panic!("Expected Auth message"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_notice() {
+        let msg = ControlMessage::Notice {
+            session_id: Some("sess_1".into()),
+            message: "Session ended".into(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"notice\""));
+        assert!(json.contains("\"session_id\":\"sess_1\""));
+
+        // session_id is omitted when not tied to a session
+        let msg = ControlMessage::Notice {
+            session_id: None,
+            message: "hello".into(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, "{\"type\":\"notice\",\"message\":\"hello\"}");
+    }
+
+    #[test]
+    fn test_serialize_flow_control() {
+        let msg = ControlMessage::FlowControl { pause: true };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, "{\"type\":\"flow_control\",\"pause\":true}");
+    }
+
     #[test]
     fn test_session_info() {
         let info = SessionInfo {
@@ -0,0 +1,210 @@
+//! Shared code registry for running several relay instances.
+//!
+//! Sessions (channels, scrollback) live on the instance the mac-client is
+//! connected to. When several instances sit behind one hostname, each
+//! records the codes it owns in a [`SessionStore`]; an instance that gets a
+//! browser for a code it doesn't hold looks up the owner and forwards the
+//! browser's WebSocket there.
+//!
+//! Enabled by setting `RELAY_INSTANCE_URL` to the address other instances
+//! can reach this one at, and `SESSION_STORE` to the shared store.
+
+#[cfg(feature = "redis")]
+mod redis;
+
+use dashmap::DashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a claim on a code lasts without being refreshed. Instances
+/// refresh the claims of connected mac-clients well within this, so codes
+/// of an instance that died free up on their own.
+pub const CLAIM_TTL: Duration = Duration::from_secs(60);
+
+/// Future returned by [`SessionStore`] methods
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Maps codes to the instance holding their session.
+pub trait SessionStore: Send + Sync {
+    /// Record that `instance` holds `code` for `ttl`, unless another
+    /// instance already does. Returns whether the claim succeeded.
+    fn claim<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool>;
+
+    /// Extend (or re-establish, if it lapsed) `instance`'s claim on `code`,
+    /// unless another instance holds it. Returns whether the claim is
+    /// `instance`'s.
+    fn refresh<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool>;
+
+    /// Instance holding `code`, if any.
+    fn lookup<'a>(&'a self, code: &'a str) -> StoreFuture<'a, Option<String>>;
+
+    /// Forget `code`.
+    fn release<'a>(&'a self, code: &'a str) -> StoreFuture<'a, ()>;
+}
+
+/// In-process store. Only shared by instances in the same process, so it
+/// is the single-instance default and a stand-in for tests.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// code -> (instance, claim expiry)
+    claims: DashMap<String, (String, Instant)>,
+}
+
+impl SessionStore for MemoryStore {
+    fn claim<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut entry = self
+                .claims
+                .entry(code.to_string())
+                .or_insert_with(|| (instance.to_string(), now + ttl));
+            let (owner, expires_at) = entry.value_mut();
+            if *owner == instance || *expires_at <= now {
+                *owner = instance.to_string();
+                *expires_at = now + ttl;
+                return Ok(true);
+            }
+            Ok(false)
+        })
+    }
+
+    fn refresh<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool> {
+        // Same rules as a claim: taking over a lapsed claim is fine
+        self.claim(code, instance, ttl)
+    }
+
+    fn lookup<'a>(&'a self, code: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            Ok(self
+                .claims
+                .get(code)
+                .filter(|claim| claim.1 > Instant::now())
+                .map(|claim| claim.0.clone()))
+        })
+    }
+
+    fn release<'a>(&'a self, code: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.claims.remove(code);
+            Ok(())
+        })
+    }
+}
+
+/// This instance's view of the shared registry.
+#[derive(Clone)]
+pub struct Registry {
+    store: Arc<dyn SessionStore>,
+    /// WebSocket base URL other instances reach this one at (e.g. `ws://10.0.0.5:3000`)
+    instance_url: String,
+}
+
+impl Registry {
+    pub fn new(store: Arc<dyn SessionStore>, instance_url: String) -> Self {
+        Self {
+            store,
+            instance_url: instance_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Registry configured by `RELAY_INSTANCE_URL` and `SESSION_STORE`, or
+    /// None to run as a standalone instance.
+    pub fn from_env() -> Option<Self> {
+        let instance_url = std::env::var("RELAY_INSTANCE_URL").ok().filter(|u| !u.is_empty())?;
+        let store: Arc<dyn SessionStore> = match std::env::var("SESSION_STORE").ok().as_deref() {
+            None | Some("") | Some("memory") => {
+                tracing::warn!("RELAY_INSTANCE_URL is set but SESSION_STORE isn't; codes are not shared with other instances");
+                Arc::new(MemoryStore::default())
+            }
+            #[cfg(feature = "redis")]
+            Some(url) if url.starts_with("redis://") => match redis::RedisStore::new(url) {
+                Ok(store) => Arc::new(store),
+                Err(e) => {
+                    tracing::error!("Invalid SESSION_STORE: {}", e);
+                    return None;
+                }
+            },
+            Some(other) => {
+                tracing::error!(
+                    "Unsupported SESSION_STORE {:?} (Redis needs the \"redis\" feature), running standalone",
+                    other
+                );
+                return None;
+            }
+        };
+        tracing::info!(instance = %instance_url, "Sharing session codes with other relay instances");
+        Some(Self::new(store, instance_url))
+    }
+
+    /// Claim `code` for this instance. A store error is logged and treated
+    /// as success, so an unreachable store degrades to standalone behavior.
+    pub async fn claim(&self, code: &str, ttl: Duration) -> bool {
+        match self.store.claim(code, &self.instance_url, ttl).await {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                tracing::warn!("Session store claim failed: {}", e);
+                true
+            }
+        }
+    }
+
+    /// Keep this instance's claim on `code` alive. Another instance's
+    /// claim is left alone.
+    pub async fn refresh(&self, code: &str, ttl: Duration) {
+        match self.store.refresh(code, &self.instance_url, ttl).await {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!("Session store claim is held by another instance, not refreshed"),
+            Err(e) => tracing::warn!("Session store refresh failed: {}", e),
+        }
+    }
+
+    /// Forget `code` (its session or share ended here).
+    pub async fn release(&self, code: &str) {
+        if let Err(e) = self.store.release(code).await {
+            tracing::warn!("Session store release failed: {}", e);
+        }
+    }
+
+    /// URL of the other instance holding `code`. None if no instance does,
+    /// or the claim is this instance's own (a stale one, since the code
+    /// didn't resolve locally).
+    pub async fn remote_owner(&self, code: &str) -> Option<String> {
+        match self.store.lookup(code).await {
+            Ok(owner) => owner.filter(|owner| *owner != self.instance_url),
+            Err(e) => {
+                tracing::warn!("Session store lookup failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claims_are_exclusive_until_expiry() {
+        let store: Arc<dyn SessionStore> = Arc::new(MemoryStore::default());
+        let a = Registry::new(store.clone(), "ws://a:3000/".into());
+        let b = Registry::new(store, "ws://b:3000".into());
+
+        assert!(a.claim("ABC234", CLAIM_TTL).await);
+        assert!(!b.claim("ABC234", CLAIM_TTL).await);
+        assert_eq!(b.remote_owner("ABC234").await.as_deref(), Some("ws://a:3000"));
+        // An instance never forwards to itself
+        assert_eq!(a.remote_owner("ABC234").await, None);
+
+        a.release("ABC234").await;
+        assert_eq!(b.remote_owner("ABC234").await, None);
+
+        assert!(a.claim("XYZ789", Duration::ZERO).await);
+        assert!(b.claim("XYZ789", CLAIM_TTL).await);
+        // A refresh doesn't take the code back from the new holder
+        a.refresh("XYZ789", CLAIM_TTL).await;
+        assert_eq!(a.remote_owner("XYZ789").await.as_deref(), Some("ws://b:3000"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Redis-backed [`SessionStore`] on the `redis` crate, over a
+//! [`ConnectionManager`] that reconnects after errors.
+
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client, Script};
+use std::io;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use super::{SessionStore, StoreFuture};
+
+/// Key prefix for code claims
+const KEY_PREFIX: &str = "relay:code:";
+
+/// Longest a single command may take, so a hung Redis can't stall browser auth
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Extend the claim in KEYS[1] to ARGV[1] for ARGV[2] ms, unless another
+/// instance holds it. Returns 1 if the claim is (now) this instance's.
+const REFRESH_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if owner and owner ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return 1
+"#;
+
+pub struct RedisStore {
+    client: Client,
+    /// Connected on first use, so a relay can start before Redis is up
+    conn: OnceCell<ConnectionManager>,
+    refresh: Script,
+}
+
+impl RedisStore {
+    /// Store at a `redis://` URL, e.g. `redis://[:password@]host[:port][/db]`.
+    pub fn new(url: &str) -> Result<Self, String> {
+        if !url.starts_with("redis://") {
+            return Err(format!("{} is not a redis:// URL", url));
+        }
+        let client = Client::open(url).map_err(|e| e.to_string())?;
+        Ok(Self {
+            client,
+            conn: OnceCell::new(),
+            refresh: Script::new(REFRESH_SCRIPT),
+        })
+    }
+
+    /// The shared connection, connecting first if there isn't one yet.
+    async fn connection(&self) -> redis::RedisResult<ConnectionManager> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| {
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(COMMAND_TIMEOUT)
+                    .set_response_timeout(COMMAND_TIMEOUT);
+                ConnectionManager::new_with_config(self.client.clone(), config)
+            })
+            .await?;
+        Ok(conn.clone())
+    }
+
+    /// Run `command` on the shared connection, bounded by [`COMMAND_TIMEOUT`].
+    async fn run<T, F>(&self, command: impl FnOnce(ConnectionManager) -> F) -> io::Result<T>
+    where
+        F: std::future::Future<Output = redis::RedisResult<T>>,
+    {
+        tokio::time::timeout(COMMAND_TIMEOUT, async { command(self.connection().await?).await })
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "redis command timed out"))?
+            .map_err(io::Error::other)
+    }
+}
+
+fn key(code: &str) -> String {
+    format!("{}{}", KEY_PREFIX, code)
+}
+
+fn ttl_millis(ttl: Duration) -> u64 {
+    ttl.as_millis().clamp(1, u64::MAX as u128) as u64
+}
+
+impl SessionStore for RedisStore {
+    fn claim<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let reply: Option<String> = self
+                .run(|mut conn| async move {
+                    redis::cmd("SET")
+                        .arg(key(code))
+                        .arg(instance)
+                        .arg("NX")
+                        .arg("PX")
+                        .arg(ttl_millis(ttl))
+                        .query_async(&mut conn)
+                        .await
+                })
+                .await?;
+            Ok(reply.is_some())
+        })
+    }
+
+    fn refresh<'a>(&'a self, code: &'a str, instance: &'a str, ttl: Duration) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let kept: i64 = self
+                .run(|mut conn| async move {
+                    self.refresh
+                        .key(key(code))
+                        .arg(instance)
+                        .arg(ttl_millis(ttl))
+                        .invoke_async(&mut conn)
+                        .await
+                })
+                .await?;
+            Ok(kept == 1)
+        })
+    }
+
+    fn lookup<'a>(&'a self, code: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            self.run(|mut conn| async move { redis::cmd("GET").arg(key(code)).query_async(&mut conn).await })
+                .await
+        })
+    }
+
+    fn release<'a>(&'a self, code: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.run(|mut conn| async move { redis::cmd("DEL").arg(key(code)).query_async(&mut conn).await })
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let store = RedisStore::new("redis://:secret@cache.internal/2").unwrap();
+        let info = store.client.get_connection_info();
+        assert_eq!(info.addr.to_string(), "cache.internal:6379");
+        assert_eq!(info.redis.password.as_deref(), Some("secret"));
+        assert_eq!(info.redis.db, 2);
+
+        let store = RedisStore::new("redis://127.0.0.1:6380").unwrap();
+        let info = store.client.get_connection_info();
+        assert_eq!(info.addr.to_string(), "127.0.0.1:6380");
+        assert_eq!(info.redis.password, None);
+        assert!(RedisStore::new("http://cache").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Per-session scrollback kept for replay to browsers that (re)connect.
+//!
+//! Frames are stored as received. With `SCROLLBACK_COMPRESSION=on`, frames
+//! older than the most recent window are packed into LZ4 blocks and only
+//! unpacked on replay, so the same memory holds several times more output
+//! (terminal output typically compresses 4-10x). Live broadcasting never
+//! touches compressed data.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+/// Default memory budget per mac-client session (1 MB)
+const DEFAULT_SCROLLBACK_SIZE: usize = 1024 * 1024;
+
+/// Most recent output kept uncompressed, in bytes (at most a quarter of the
+/// budget). Once twice this much is pending, the older half is packed into
+/// one block.
+const RECENT_WINDOW: usize = 64 * 1024;
+
+/// Scrollback settings, from the environment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollbackConfig {
+    /// Memory budget in bytes, counting compressed blocks at their packed size
+    pub max_bytes: usize,
+    /// Whether older frames are compressed
+    pub compress: bool,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self { max_bytes: DEFAULT_SCROLLBACK_SIZE, compress: false }
+    }
+}
+
+/// Settings from `SCROLLBACK_SIZE` and `SCROLLBACK_COMPRESSION`, read once.
+pub fn config() -> ScrollbackConfig {
+    static CONFIG: OnceLock<ScrollbackConfig> = OnceLock::new();
+    *CONFIG.get_or_init(|| {
+        let config = parse_config(
+            std::env::var("SCROLLBACK_SIZE").ok().as_deref(),
+            std::env::var("SCROLLBACK_COMPRESSION").ok().as_deref(),
+        );
+        tracing::debug!(?config, "Scrollback settings");
+        config
+    })
+}
+
+/// Invalid values fall back to the defaults with a warning.
+fn parse_config(size: Option<&str>, compression: Option<&str>) -> ScrollbackConfig {
+    let mut config = ScrollbackConfig::default();
+    if let Some(size) = size {
+        match size.trim().parse().ok().filter(|max: &usize| *max > 0) {
+            Some(max) => config.max_bytes = max,
+            None => tracing::warn!("Invalid SCROLLBACK_SIZE {:?}, using {} bytes", size, DEFAULT_SCROLLBACK_SIZE),
+        }
+    }
+    if let Some(compression) = compression {
+        match compression.trim().to_ascii_lowercase().as_str() {
+            "on" | "true" | "1" | "lz4" => config.compress = true,
+            "off" | "false" | "0" | "" => config.compress = false,
+            _ => tracing::warn!("Invalid SCROLLBACK_COMPRESSION {:?}, leaving it off", compression),
+        }
+    }
+    config
+}
+
+/// Consecutive frames packed together: each frame as a 4-byte little-endian
+/// length followed by its bytes, LZ4-compressed as a whole.
+#[derive(Clone)]
+struct Block {
+    data: Vec<u8>,
+    frames: usize,
+}
+
+impl Block {
+    fn pack(frames: impl Iterator<Item = Vec<u8>>) -> Self {
+        let mut raw = Vec::new();
+        let mut count = 0;
+        for frame in frames {
+            raw.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            raw.extend_from_slice(&frame);
+            count += 1;
+        }
+        Self { data: lz4_flex::compress_prepend_size(&raw), frames: count }
+    }
+
+    fn unpack(&self, out: &mut Vec<Vec<u8>>) {
+        // Only ever decompressing our own output, so failure is a bug
+        let raw = lz4_flex::decompress_size_prepended(&self.data).expect("corrupt scrollback block");
+        let mut rest = raw.as_slice();
+        while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+            let len = u32::from_le_bytes(*len) as usize;
+            out.push(tail[..len].to_vec());
+            rest = &tail[len..];
+        }
+    }
+}
+
+/// Output frames of one mac-client session, oldest first, capped at the
+/// configured memory budget by dropping the oldest output.
+#[derive(Clone)]
+pub struct Scrollback {
+    config: ScrollbackConfig,
+    /// Older output, compressed (empty unless compression is on)
+    blocks: VecDeque<Block>,
+    /// Newest output as received
+    recent: VecDeque<Vec<u8>>,
+    recent_bytes: usize,
+    block_bytes: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(config())
+    }
+}
+
+impl Scrollback {
+    pub fn new(config: ScrollbackConfig) -> Self {
+        Self {
+            config,
+            blocks: VecDeque::new(),
+            recent: VecDeque::new(),
+            recent_bytes: 0,
+            block_bytes: 0,
+        }
+    }
+
+    /// Append a frame, compressing and dropping older output as needed.
+    pub fn push(&mut self, frame: Vec<u8>) {
+        self.recent_bytes += frame.len();
+        self.recent.push_back(frame);
+
+        let window = RECENT_WINDOW.min(self.config.max_bytes / 4);
+        if self.config.compress && self.recent_bytes >= 2 * window {
+            let mut take = 0;
+            let mut packed = 0;
+            while packed < self.recent_bytes - window {
+                packed += self.recent[take].len();
+                take += 1;
+            }
+            let block = Block::pack(self.recent.drain(..take));
+            self.recent_bytes -= packed;
+            self.block_bytes += block.data.len();
+            self.blocks.push_back(block);
+        }
+
+        // Drop whole blocks first; they are always older than `recent`
+        while self.memory_bytes() > self.config.max_bytes {
+            if let Some(block) = self.blocks.pop_front() {
+                self.block_bytes -= block.data.len();
+            } else if let Some(frame) = self.recent.pop_front() {
+                self.recent_bytes -= frame.len();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// All frames, oldest first, decompressing older ones.
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        let mut frames = Vec::with_capacity(self.len());
+        for block in &self.blocks {
+            block.unpack(&mut frames);
+        }
+        frames.extend(self.recent.iter().cloned());
+        frames
+    }
+
+    /// Keep only the frames matching `keep`, returning how many were removed.
+    pub fn retain(&mut self, keep: impl Fn(&[u8]) -> bool) -> usize {
+        let before = self.len();
+        let frames = self.frames();
+        *self = Self::new(self.config);
+        for frame in frames.into_iter().filter(|frame| keep(frame)) {
+            self.push(frame);
+        }
+        before - self.len()
+    }
+
+    /// Number of frames held.
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(|block| block.frames).sum::<usize>() + self.recent.len()
+    }
+
+    /// Bytes held, counting compressed blocks at their packed size.
+    pub fn memory_bytes(&self) -> usize {
+        self.block_bytes + self.recent_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame of plausible build output for terminal session "t1".
+    fn frame(i: usize) -> Vec<u8> {
+        let mut frame = vec![2, b't', b'1'];
+        frame.extend_from_slice(
+            format!("\x1b[32m   Compiling\x1b[0m crate-{} v0.{}.0 (/home/dev/project/crates/crate-{})\r\n", i % 50, i % 7, i % 50)
+                .as_bytes(),
+        );
+        frame
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(parse_config(None, None), ScrollbackConfig::default());
+        assert_eq!(
+            parse_config(Some("8388608"), Some("on")),
+            ScrollbackConfig { max_bytes: 8 * 1024 * 1024, compress: true }
+        );
+        assert_eq!(parse_config(Some("0"), Some("maybe")), ScrollbackConfig::default());
+        assert!(!parse_config(None, Some("off")).compress);
+    }
+
+    #[test]
+    fn test_uncompressed_cap() {
+        let mut scrollback = Scrollback::new(ScrollbackConfig { max_bytes: 1000, compress: false });
+        for i in 0..100 {
+            scrollback.push(frame(i));
+        }
+        assert!(scrollback.memory_bytes() <= 1000);
+        let frames = scrollback.frames();
+        assert_eq!(frames.len(), scrollback.len());
+        assert_eq!(frames.last(), Some(&frame(99)));
+        assert_eq!(frames.iter().map(Vec::len).sum::<usize>(), scrollback.memory_bytes());
+    }
+
+    #[test]
+    fn test_compression_holds_more_output() {
+        let config = ScrollbackConfig { max_bytes: 4 * 1024 * 1024, compress: true };
+        let mut scrollback = Scrollback::new(config);
+        let count = 40_000;
+        let raw: usize = (0..count).map(|i| frame(i).len()).sum();
+        for i in 0..count {
+            scrollback.push(frame(i));
+        }
+
+        // Nothing was dropped, yet far less memory is used than the raw output
+        assert!(raw < config.max_bytes);
+        assert_eq!(scrollback.len(), count);
+        assert!(scrollback.memory_bytes() * 3 < raw, "{} of {}", scrollback.memory_bytes(), raw);
+        // The newest output stays uncompressed
+        assert!(scrollback.recent_bytes >= RECENT_WINDOW);
+
+        let frames = scrollback.frames();
+        assert!(frames.iter().enumerate().all(|(i, f)| *f == frame(i)));
+    }
+
+    #[test]
+    fn test_compressed_cap_drops_oldest_blocks() {
+        let mut scrollback = Scrollback::new(ScrollbackConfig { max_bytes: 100 * 1024, compress: true });
+        for i in 0..50_000 {
+            scrollback.push(frame(i));
+        }
+        assert!(scrollback.memory_bytes() <= 100 * 1024);
+        let frames = scrollback.frames();
+        assert_eq!(frames.last(), Some(&frame(49_999)));
+        assert!(frames.len() > 100 * 1024 / frame(0).len());
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut scrollback = Scrollback::new(ScrollbackConfig { max_bytes: 1024 * 1024, compress: true });
+        for i in 0..3000 {
+            let mut f = frame(i);
+            f[2] = if i % 2 == 0 { b'1' } else { b'2' };
+            scrollback.push(f);
+        }
+        assert_eq!(scrollback.retain(|f| f[2] == b'1'), 1500);
+        assert_eq!(scrollback.len(), 1500);
+        assert!(scrollback.frames().iter().all(|f| f[2] == b'1'));
+    }
+}
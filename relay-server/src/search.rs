@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::protocol::SearchMatch;
+use crate::scrollback::Scrollback;
+use crate::state::split_frame;
+
+/// Longest pattern a browser may search for
+const MAX_PATTERN_LEN: usize = 256;
+
+/// Cap on compiled regex size, so a pathological pattern can't eat memory
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Most matches returned for one search
+pub const MAX_MATCHES: usize = 100;
+
+/// Stop scanning after this long and return what was found
+const TIME_BUDGET: Duration = Duration::from_millis(200);
+
+/// Longest snippet returned per match
+const MAX_SNIPPET: usize = 200;
+
+/// Compile a browser-supplied search pattern with size limits.
+/// The regex crate matches in linear time, so there's no catastrophic
+/// backtracking to guard against beyond bounding the pattern itself.
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    if pattern.is_empty() {
+        return Err("Empty search pattern".into());
+    }
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(format!("Search pattern longer than {} bytes", MAX_PATTERN_LEN));
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Invalid search pattern: {}", e))
+}
+
+/// Search one terminal session's output in `scrollback`, returning the
+/// matches and whether the search stopped early. Blocking: decompressing a
+/// full scrollback takes a while, so it counts against the time budget too.
+pub fn search_session(scrollback: &Scrollback, terminal_session_id: &str, regex: &Regex) -> (Vec<SearchMatch>, bool) {
+    let started = Instant::now();
+    let mut output = Vec::new();
+    for frame in scrollback.frames() {
+        if let Some((sid, payload)) = split_frame(&frame) {
+            if sid == terminal_session_id.as_bytes() {
+                output.extend_from_slice(payload);
+            }
+        }
+    }
+    search_output(&output, regex, started)
+}
+
+/// Search terminal output line by line, within the time budget counted
+/// from `started`. Lines are numbered from the oldest line in the buffer;
+/// columns are character offsets within the line. Returns the matches and
+/// whether the search stopped early.
+fn search_output(output: &[u8], regex: &Regex, started: Instant) -> (Vec<SearchMatch>, bool) {
+    if started.elapsed() > TIME_BUDGET {
+        return (Vec::new(), true);
+    }
+    let text = strip_escapes(output);
+    let mut matches = Vec::new();
+
+    for (line_no, line) in text.split('\n').enumerate() {
+        if started.elapsed() > TIME_BUDGET {
+            return (matches, true);
+        }
+        let line = line.trim_end_matches('\r');
+        for m in regex.find_iter(line) {
+            if matches.len() == MAX_MATCHES {
+                return (matches, true);
+            }
+            matches.push(SearchMatch {
+                line: line_no,
+                column: line[..m.start()].chars().count(),
+                text: line.chars().take(MAX_SNIPPET).collect(),
+            });
+        }
+    }
+    (matches, false)
+}
+
+/// Remove terminal escape sequences (CSI, OSC, and two-byte escapes) so
+/// patterns match the text as displayed.
+fn strip_escapes(output: &[u8]) -> String {
+    let text = String::from_utf8_lossy(output);
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters then a final byte in @..~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: until BEL or ST (ESC \)
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_finds_lines_and_columns() {
+        let output = b"$ make\r\nerror: \x1b[31mbuild failed\x1b[0m\r\nok\r\nanother error\n";
+        let regex = compile("error").unwrap();
+        let (matches, truncated) = search_output(output, &regex, Instant::now());
+
+        assert!(!truncated);
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].line, matches[0].column), (1, 0));
+        assert_eq!(matches[0].text, "error: build failed");
+        assert_eq!((matches[1].line, matches[1].column), (3, 8));
+    }
+
+    #[test]
+    fn test_search_bounded() {
+        let output = "x\n".repeat(MAX_MATCHES * 2);
+        let (matches, truncated) = search_output(output.as_bytes(), &compile("x").unwrap(), Instant::now());
+        assert_eq!(matches.len(), MAX_MATCHES);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_search_session_skips_other_sessions() {
+        let mut scrollback = Scrollback::default();
+        scrollback.push(b"\x02t1error in t1\r\n".to_vec());
+        scrollback.push(b"\x02t2error in t2\r\n".to_vec());
+        let (matches, truncated) = search_session(&scrollback, "t2", &compile("error").unwrap());
+        assert!(!truncated);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].text, "error in t2");
+    }
+
+    #[test]
+    fn test_compile_rejects_bad_patterns() {
+        assert!(compile("").is_err());
+        assert!(compile("(unclosed").is_err());
+        assert!(compile(&"a".repeat(MAX_PATTERN_LEN + 1)).is_err());
+    }
+}
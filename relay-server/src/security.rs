@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// Default CSP for the embedded web UI. xterm.js sets inline styles, so
+/// `style-src` needs `'unsafe-inline'`; everything else stays same-origin.
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data:; font-src 'self' data:; \
+    connect-src 'self'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'";
+
+/// HSTS policy sent when the request reached us over TLS (e.g. via cloudflared)
+const HSTS: &str = "max-age=31536000; includeSubDomains";
+
+/// Security headers added to every HTTP response.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    csp: HeaderValue,
+}
+
+impl SecurityHeaders {
+    /// Build from `CONTENT_SECURITY_POLICY`, falling back to [`DEFAULT_CSP`].
+    pub fn from_env() -> Self {
+        let csp = std::env::var("CONTENT_SECURITY_POLICY")
+            .ok()
+            .and_then(|value| match HeaderValue::from_str(&value) {
+                Ok(csp) => Some(csp),
+                Err(_) => {
+                    tracing::warn!("Invalid CONTENT_SECURITY_POLICY, using default");
+                    None
+                }
+            })
+            .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_CSP));
+        Self { csp }
+    }
+
+    /// Set the security headers on `response`. HSTS is only sent when the
+    /// request came in over HTTPS, since the relay itself serves plain HTTP.
+    fn apply(&self, request: &HeaderMap, response: &mut HeaderMap) {
+        response.insert(header::CONTENT_SECURITY_POLICY, self.csp.clone());
+        response.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        response.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+        response.insert(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        );
+
+        if over_tls(request) {
+            response.insert(
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static(HSTS),
+            );
+        }
+    }
+}
+
+/// Whether the request reached the proxy in front of us (e.g. cloudflared)
+/// over HTTPS. The relay itself only serves plain HTTP.
+pub fn over_tls(request: &HeaderMap) -> bool {
+    request
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            csp: HeaderValue::from_static(DEFAULT_CSP),
+        }
+    }
+}
+
+/// Middleware that adds [`SecurityHeaders`] to responses.
+pub async fn security_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_headers = request.headers().clone();
+    let mut response = next.run(request).await;
+    headers.apply(&request_headers, response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_over_http() {
+        let mut response = HeaderMap::new();
+        SecurityHeaders::default().apply(&HeaderMap::new(), &mut response);
+
+        assert_eq!(response[header::CONTENT_SECURITY_POLICY], DEFAULT_CSP);
+        assert_eq!(response[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(response[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(!response.contains_key(header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[test]
+    fn test_hsts_when_forwarded_over_https() {
+        let mut request = HeaderMap::new();
+        request.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        let mut response = HeaderMap::new();
+        SecurityHeaders::default().apply(&request, &mut response);
+
+        assert_eq!(response[header::STRICT_TRANSPORT_SECURITY], HSTS);
+    }
+}
@@ -1,4 +1,6 @@
 use nanoid::nanoid;
+use std::net::IpAddr;
+use std::sync::OnceLock;
 
 /// Characters for session codes - excludes 0/O/1/I/L to avoid confusion
 const CODE_ALPHABET: [char; 31] = [
@@ -7,9 +9,122 @@ const CODE_ALPHABET: [char; 31] = [
     'X', 'Y', 'Z', '2', '3', '4', '5', '6', '7', '8', '9',
 ];
 
-/// Generate a 6-character session code
-pub fn generate_session_code() -> String {
-    nanoid!(6, &CODE_ALPHABET)
+/// Length of generated session codes unless `SESSION_CODE_LENGTH` is set
+const DEFAULT_CODE_LENGTH: usize = 6;
+
+/// Shortest configurable code length (for kiosk demos)
+const MIN_CODE_LENGTH: usize = 4;
+
+/// Longest configurable code length
+const MAX_CODE_LENGTH: usize = 16;
+
+/// Length of session codes, from `SESSION_CODE_LENGTH`, read once.
+pub fn code_length() -> usize {
+    static LENGTH: OnceLock<usize> = OnceLock::new();
+    *LENGTH.get_or_init(|| {
+        let value = std::env::var("SESSION_CODE_LENGTH").ok();
+        let length = parse_code_length(value.as_deref());
+        if length != DEFAULT_CODE_LENGTH {
+            tracing::info!("Session codes are {} characters", length);
+        }
+        length
+    })
+}
+
+/// A code length clamped to [`MIN_CODE_LENGTH`]..=[`MAX_CODE_LENGTH`];
+/// the default if unset or not a number.
+fn parse_code_length(value: Option<&str>) -> usize {
+    let Some(value) = value else {
+        return DEFAULT_CODE_LENGTH;
+    };
+    match value.trim().parse::<usize>() {
+        Ok(length) => length.clamp(MIN_CODE_LENGTH, MAX_CODE_LENGTH),
+        Err(_) => {
+            tracing::warn!("Invalid SESSION_CODE_LENGTH {:?}, using {}", value, DEFAULT_CODE_LENGTH);
+            DEFAULT_CODE_LENGTH
+        }
+    }
+}
+
+/// Generate a session code of `length` characters
+pub fn generate_session_code(length: usize) -> String {
+    nanoid!(length, &CODE_ALPHABET)
+}
+
+/// Returns true if `code` has the shape of a session code of `length`
+/// characters (case-insensitive).
+pub fn looks_like_session_code(code: &str, length: usize) -> bool {
+    code.chars().count() == length
+        && code
+            .chars()
+            .all(|c| CODE_ALPHABET.contains(&c.to_ascii_uppercase()))
+}
+
+/// Codes a mac-client may not request because they are among the first a
+/// guesser would try (runs and repeats are rejected separately)
+const RESERVED_CODES: &[&str] = &[
+    "QWERTY", "ASDFGH", "ZXCVBN", "SECRET", "PASSWD", "ACCESS", "MASTER", "GUEST2", "TESTER", "ABC234",
+];
+
+/// A code requested by a mac-client, uppercased, if it is a valid code of
+/// `length` characters that isn't easy to guess: no reserved words, single repeated characters,
+/// repeated pairs or triples (ABABAB, XYZXYZ), or runs through the code
+/// alphabet in either direction (ABCDEF, 987654).
+pub fn requestable_code(code: &str, length: usize) -> Option<String> {
+    if !looks_like_session_code(code, length) {
+        return None;
+    }
+    let code = code.to_ascii_uppercase();
+    let chars: Vec<char> = code.chars().collect();
+    let repeats = |period: usize| chars.iter().skip(period).zip(&chars).all(|(a, b)| a == b);
+    // Positions in the code alphabet, so GHJKMN counts as a run
+    let index = |c: char| CODE_ALPHABET.iter().position(|&a| a == c).unwrap_or_default() as i32;
+    let step = |a: char, b: char| index(b) - index(a);
+    let run = chars.windows(2).all(|w| step(w[0], w[1]) == 1) || chars.windows(2).all(|w| step(w[0], w[1]) == -1);
+    if RESERVED_CODES.contains(&code.as_str()) || repeats(1) || repeats(2) || repeats(3) || run {
+        return None;
+    }
+    Some(code)
+}
+
+/// Mask a session code for logs and monitoring (the full code grants access).
+pub fn mask_code(code: &str) -> String {
+    let prefix: String = code.chars().take(2).collect();
+    format!("{}****", prefix)
+}
+
+/// Longest session label kept, in characters
+const MAX_LABEL_LEN: usize = 64;
+
+/// Clean up a mac-client's label for display (the mac-client sends it as
+/// is, so this is the one place labels are normalized): trimmed, without control
+/// characters and at most [`MAX_LABEL_LEN`] characters. None if empty.
+pub fn sanitize_label(label: &str) -> Option<String> {
+    let label: String = label
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_LABEL_LEN)
+        .collect();
+    Some(label.trim_end().to_string()).filter(|l| !l.is_empty())
+}
+
+/// Coarse network prefix of a client address (/24 for IPv4, /48 for IPv6),
+/// enough for the session owner to tell viewers apart without the full address.
+pub fn ip_prefix(ip: &str) -> String {
+    let v4 = match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => v4,
+        Ok(IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+            Some(v4) => v4,
+            None => {
+                let s = v6.segments();
+                return format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2]);
+            }
+        },
+        Err(_) => return "unknown".to_string(),
+    };
+    let [a, b, c, _] = v4.octets();
+    format!("{}.{}.{}.0/24", a, b, c)
 }
 
 #[cfg(test)]
@@ -18,23 +133,73 @@ mod tests {
 
     #[test]
     fn test_code_length() {
-        let code = generate_session_code();
+        let code = generate_session_code(DEFAULT_CODE_LENGTH);
         assert_eq!(code.len(), 6);
     }
 
+    #[test]
+    fn test_parse_code_length() {
+        assert_eq!(parse_code_length(None), DEFAULT_CODE_LENGTH);
+        assert_eq!(parse_code_length(Some("10")), 10);
+        assert_eq!(parse_code_length(Some("1")), MIN_CODE_LENGTH);
+        assert_eq!(parse_code_length(Some("0")), MIN_CODE_LENGTH);
+        assert_eq!(parse_code_length(Some("64")), MAX_CODE_LENGTH);
+        assert_eq!(parse_code_length(Some("eight")), DEFAULT_CODE_LENGTH);
+    }
+
     #[test]
     fn test_code_alphabet() {
-        let code = generate_session_code();
-        for c in code.chars() {
-            assert!(CODE_ALPHABET.contains(&c), "Invalid char: {}", c);
+        for length in MIN_CODE_LENGTH..=MAX_CODE_LENGTH {
+            let code = generate_session_code(length);
+            assert_eq!(code.chars().count(), length);
+            for c in code.chars() {
+                assert!(CODE_ALPHABET.contains(&c), "Invalid char: {}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_looks_like_session_code() {
+        assert!(looks_like_session_code(&generate_session_code(8), 8));
+        assert!(looks_like_session_code("abc234", 6));
+        assert!(!looks_like_session_code("ABC12", 6));
+        assert!(!looks_like_session_code("ABC1230", 6));
+        assert!(!looks_like_session_code("ABCDE0", 6));
+        assert!(!looks_like_session_code("550e8400-e29b-41d4-a716-446655440000", 6));
+    }
+
+    #[test]
+    fn test_requestable_code() {
+        assert_eq!(requestable_code("desk42", 6).as_deref(), Some("DESK42"));
+        assert_eq!(requestable_code("HQ2TRM", 6).as_deref(), Some("HQ2TRM"));
+        assert_eq!(requestable_code("DESK4", 6), None);
+        assert_eq!(requestable_code("KIOSK7", 6), None);
+        for guessable in ["qwerty", "AAAAAA", "ABABAB", "XYZXYZ", "ABCDEF", "987654", "GHJKMN", "XYZ234"] {
+            assert_eq!(requestable_code(guessable, 6), None, "{}", guessable);
         }
     }
 
+    #[test]
+    fn test_ip_prefix() {
+        assert_eq!(ip_prefix("203.0.113.77"), "203.0.113.0/24");
+        assert_eq!(ip_prefix("::ffff:203.0.113.77"), "203.0.113.0/24");
+        assert_eq!(ip_prefix("2001:db8:1:2::5"), "2001:db8:1::/48");
+        assert_eq!(ip_prefix("not-an-ip"), "unknown");
+    }
+
+    #[test]
+    fn test_sanitize_label() {
+        assert_eq!(sanitize_label("  Jane's MacBook Pro \n").as_deref(), Some("Jane's MacBook Pro"));
+        assert_eq!(sanitize_label("build\x1b[31m-box").as_deref(), Some("build[31m-box"));
+        assert_eq!(sanitize_label(" \t "), None);
+        assert_eq!(sanitize_label(&"x".repeat(100)).unwrap().len(), MAX_LABEL_LEN);
+    }
+
     #[test]
     fn test_no_confusing_chars() {
         // Generate many codes and verify none contain confusing chars
-        for _ in 0..100 {
-            let code = generate_session_code();
+        for i in 0..100 {
+            let code = generate_session_code(MIN_CODE_LENGTH + i % (MAX_CODE_LENGTH - MIN_CODE_LENGTH + 1));
             assert!(!code.contains('0'));
             assert!(!code.contains('O'));
             assert!(!code.contains('1'));
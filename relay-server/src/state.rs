@@ -1,17 +1,70 @@
-use dashmap::DashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use dashmap::{DashMap, DashSet};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, Mutex};
 
-use crate::session::generate_session_code;
+use crate::compression;
+use crate::invalid_codes::InvalidCodeMonitor;
+use crate::password::PasswordGate;
+use crate::protocol::{BrowserEntry, ClientEnvironment, ControlMessage, SessionInfo, TerminalCapabilities};
+use crate::registry::{Registry, CLAIM_TTL};
+use crate::scrollback::Scrollback;
+use crate::session::{code_length, generate_session_code, mask_code, sanitize_label};
 
-/// Maximum scrollback buffer size (1 MB)
-const MAX_SCROLLBACK: usize = 1024 * 1024;
+/// Upper bound on lines a browser can request per terminal session on replay
+pub const MAX_REPLAY_LINES: usize = 10_000;
+
+/// A mac-client that has sent heartbeats before but goes this long without
+/// one is considered dead (three missed 30s heartbeats).
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Sessions without traffic for this long are removed, so a code left
+/// behind by a Mac that slept or crashed stops working
+pub const DEFAULT_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Random codes tried before giving up on finding an unused one
+const MAX_CODE_ATTEMPTS: usize = 100;
+
+/// How long a freed session code stays reserved for the mac-client that
+/// held it, so a reconnect gets the same code back
+pub const CODE_RESERVATION: Duration = Duration::from_secs(5 * 60);
+
+/// Longest lifetime a mac-client can give a share code
+pub const MAX_SHARE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often expired share codes and code reservations are dropped, so
+/// codes nobody uses again don't pile up
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Capacity of the admin event channel; slow admin viewers skip events past this
+const ADMIN_EVENT_CAPACITY: usize = 256;
+
+/// Largest frame a subscription replay merges a session's output into
+const REPLAY_FRAME_BYTES: usize = 64 * 1024;
+
+/// Session lifecycle events streamed to the admin WebSocket.
+/// Session codes are masked since the full code grants access.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminEvent {
+    MacRegistered { code: String },
+    BrowserConnected { code: String, browser_id: String },
+    BrowserDisconnected { code: String, browser_id: String },
+    SessionRemoved { code: String },
+    /// Many browser auths with nonexistent codes in a short window
+    InvalidCodeBurst { attempts: u32, distinct_codes: usize },
+}
 
 /// Message types that can be sent to browsers
 #[derive(Debug, Clone)]
 pub enum BrowserMessage {
     Binary(Vec<u8>),
     Text(String),
+    /// Close the WebSocket
+    Close,
 }
 
 /// Message types that can be sent to mac-client
@@ -19,19 +72,134 @@ pub enum BrowserMessage {
 pub enum MacMessage {
     Binary(Vec<u8>),
     Text(String),
+    /// WebSocket ping, to detect a connection that died without a FIN
+    Ping,
+    /// Close the WebSocket
+    Close,
+}
+
+/// Relay-wide counters since startup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Counters {
+    pub sessions_total: u64,
+    pub bytes_to_browsers: u64,
+    pub bytes_to_mac: u64,
+    pub auth_failures: u64,
+}
+
+/// Latest liveness report received from a mac-client.
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    /// When the relay received the heartbeat
+    pub received_at: Instant,
+    /// Number of terminal sessions the mac-client reported
+    pub sessions: usize,
+    /// 1-minute load average the mac-client reported
+    pub load: f32,
+}
+
+/// Summary of a session for the debug and admin endpoints.
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub code: String,
+    /// Mac-client's stable id from Register
+    pub client_id: String,
+    /// Time since the mac-client registered
+    pub age: Duration,
+    pub browser_count: usize,
+    /// Connected browsers, oldest first
+    pub browsers: Vec<BrowserEntry>,
+    /// Terminal sessions the mac-client last reported
+    pub terminals: Vec<SessionInfo>,
+    pub heartbeat: Option<Heartbeat>,
+    pub label: Option<String>,
+    pub environment: Option<ClientEnvironment>,
 }
 
 /// A connected mac-client session
 pub struct Session {
+    /// Stable id the mac-client registered with
+    client_id: String,
+    /// Argon2 hash of the password browsers must send, if the mac-client set one
+    password_hash: Option<String>,
     /// Channel to send messages to the mac-client
     pub mac_tx: mpsc::Sender<MacMessage>,
     /// Connected browsers: browser_id -> sender channel
     pub browsers: DashMap<String, mpsc::Sender<BrowserMessage>>,
+    /// When and from where each browser joined, for the owner's viewer list
+    viewers: DashMap<String, BrowserEntry>,
+    /// Terminal capabilities each browser reported after auth
+    capabilities: DashMap<String, TerminalCapabilities>,
+    /// Browsers whose output frames are flagged and compressed
+    compressing: DashSet<String>,
+    /// Terminal sessions each browser has subscribed to: browser_id -> session ids.
+    /// Browsers without an entry receive output for every terminal session.
+    subscriptions: DashMap<String, HashSet<String>>,
+    /// Terminal sessions whose output each browser wants timestamped:
+    /// browser_id -> session ids
+    timestamps: DashMap<String, HashSet<String>>,
+    /// The mac-client's OutputTimestamp for the next output frame of each
+    /// terminal session: session id -> Unix milliseconds
+    mac_timestamps: DashMap<String, u64>,
     /// Accumulated terminal output frames for replay on browser reconnect.
     /// Each entry is a complete binary frame (with session ID prefix).
-    scrollback_frames: Mutex<Vec<Vec<u8>>>,
-    /// Total byte count of all frames in scrollback (for cap enforcement).
-    scrollback_bytes: Mutex<usize>,
+    scrollback: Mutex<Scrollback>,
+    /// Most recent heartbeat (None until the mac-client sends one).
+    heartbeat: Mutex<Option<Heartbeat>>,
+    /// When the mac-client registered
+    created_at: Instant,
+    /// Milliseconds after `created_at` of the last output, input or heartbeat
+    last_activity: AtomicU64,
+    /// Terminal sessions as last reported by the mac-client
+    terminals: Mutex<Vec<SessionInfo>>,
+    /// Name the mac-client gave itself (SetLabel)
+    label: Mutex<Option<String>>,
+    /// What the mac-client runs on, if it reported it
+    environment: Mutex<Option<ClientEnvironment>>,
+}
+
+impl Session {
+    /// Record traffic, postponing idle expiry.
+    fn touch(&self) {
+        let millis = self.created_at.elapsed().as_millis() as u64;
+        self.last_activity.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    /// Time since the last traffic.
+    fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_activity.load(Ordering::Relaxed));
+        self.created_at.elapsed().saturating_sub(last)
+    }
+
+    /// Whether any browser wants `session_id`'s output timestamped.
+    fn timestamps_wanted(&self, session_id: &str) -> bool {
+        self.timestamps.iter().any(|entry| entry.value().contains(session_id))
+    }
+
+    /// Terminal sessions some browser wants timestamped.
+    fn timestamped_sessions(&self) -> HashSet<String> {
+        self.timestamps.iter().flat_map(|entry| entry.value().clone()).collect()
+    }
+
+    /// Ask the mac-client to start or stop stamping `session_id`'s output.
+    /// Queued without waiting; a missed stop only costs the stamps' overhead.
+    fn send_timestamps_to_mac(&self, session_id: &str, enabled: bool) {
+        let session_id = session_id.to_string();
+        let msg = if enabled {
+            ControlMessage::EnableTimestamps { session_id }
+        } else {
+            self.mac_timestamps.remove(&session_id);
+            ControlMessage::DisableTimestamps { session_id }
+        };
+        let _ = self.mac_tx.try_send(MacMessage::Text(serde_json::to_string(&msg).unwrap()));
+    }
+}
+
+/// A time-boxed code granting browser access to another session
+struct Share {
+    /// Permanent code of the mac-client session being shared
+    owner: String,
+    expires_at: Instant,
 }
 
 /// Shared application state
@@ -43,40 +211,274 @@ pub struct AppState {
 struct AppStateInner {
     /// Session code -> Session data
     sessions: DashMap<String, Session>,
+    /// Share code -> session it grants access to, until it expires
+    shares: DashMap<String, Share>,
+    /// Freed session code -> client_id it is held for, and until when
+    reserved: DashMap<String, (String, Instant)>,
+    /// Lifecycle events for admin monitoring
+    admin_events: broadcast::Sender<AdminEvent>,
+    /// Token required for the admin WebSocket (None = admin disabled)
+    admin_token: Option<String>,
+    /// Codes shared with other relay instances (None = standalone)
+    registry: Option<Registry>,
+    /// Auths with codes matching no session, for spotting code scans
+    invalid_codes: InvalidCodeMonitor,
+    /// Bounds and backs off browser password checks
+    passwords: PasswordGate,
+    /// Mac-clients registered since startup
+    sessions_total: AtomicU64,
+    /// Bytes delivered to browsers (output and JSON), counted per browser
+    bytes_to_browsers: AtomicU64,
+    /// Bytes delivered to mac-clients (input and JSON)
+    bytes_to_mac: AtomicU64,
+    /// Browser auths refused for a bad code or password
+    auth_failures: AtomicU64,
 }
 
 impl AppState {
     pub fn new() -> Self {
-        Self {
+        Self::with_admin_token(None)
+    }
+
+    /// Create state with the admin WebSocket enabled for `admin_token`.
+    pub fn with_admin_token(admin_token: Option<String>) -> Self {
+        Self::with_registry(admin_token, None)
+    }
+
+    /// Create state that shares its codes with other instances through
+    /// `registry`.
+    pub fn with_registry(admin_token: Option<String>, registry: Option<Registry>) -> Self {
+        Self::with_idle_ttl(admin_token, registry, idle_ttl_from_env())
+    }
+
+    /// Create state that removes sessions idle longer than `idle_ttl`. The
+    /// reaper runs on the current tokio runtime, if there is one.
+    fn with_idle_ttl(admin_token: Option<String>, registry: Option<Registry>, idle_ttl: Option<Duration>) -> Self {
+        let (admin_events, _) = broadcast::channel(ADMIN_EVENT_CAPACITY);
+        let state = Self {
             inner: Arc::new(AppStateInner {
                 sessions: DashMap::new(),
+                shares: DashMap::new(),
+                reserved: DashMap::new(),
+                admin_events,
+                admin_token,
+                registry,
+                invalid_codes: InvalidCodeMonitor::default(),
+                passwords: PasswordGate::default(),
+                sessions_total: AtomicU64::new(0),
+                bytes_to_browsers: AtomicU64::new(0),
+                bytes_to_mac: AtomicU64::new(0),
+                auth_failures: AtomicU64::new(0),
             }),
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(prune_expired_codes(Arc::downgrade(&state.inner)));
+            if let Some(ttl) = idle_ttl {
+                runtime.spawn(reap_idle_sessions(Arc::downgrade(&state.inner), ttl));
+            }
         }
+        state
     }
 
-    /// Register a new mac-client, returns unique session code
-    pub fn register_mac_client(&self, mac_tx: mpsc::Sender<MacMessage>) -> String {
-        // Generate code with collision check
-        let code = loop {
-            let candidate = generate_session_code();
-            if !self.inner.sessions.contains_key(&candidate) {
-                break candidate;
+    /// Whether the admin WebSocket is enabled.
+    pub fn admin_enabled(&self) -> bool {
+        self.inner.admin_token.is_some()
+    }
+
+    /// Check a token presented to the admin WebSocket.
+    pub fn admin_token_matches(&self, token: &str) -> bool {
+        match &self.inner.admin_token {
+            // Compare every byte so timing doesn't reveal the matching prefix
+            Some(expected) => {
+                expected.len() == token.len()
+                    && expected
+                        .bytes()
+                        .zip(token.bytes())
+                        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                        == 0
             }
-            tracing::debug!("Session code collision, regenerating");
+            None => false,
+        }
+    }
+
+    /// Subscribe to session lifecycle events.
+    pub fn subscribe_admin_events(&self) -> broadcast::Receiver<AdminEvent> {
+        self.inner.admin_events.subscribe()
+    }
+
+    fn publish(&self, event: AdminEvent) {
+        // Err only means no admin is listening
+        let _ = self.inner.admin_events.send(event);
+    }
+
+    /// Register a new mac-client with a random code, without the shared
+    /// registry. Fails if no unused code turned up within a bounded number
+    /// of attempts.
+    #[cfg(test)]
+    pub fn register_mac_client(
+        &self,
+        client_id: &str,
+        password_hash: Option<String>,
+        mac_tx: mpsc::Sender<MacMessage>,
+    ) -> Result<String, &'static str> {
+        self.register_with(client_id, password_hash, mac_tx, || generate_session_code(code_length()))
+    }
+
+    fn register_with(
+        &self,
+        client_id: &str,
+        password_hash: Option<String>,
+        mac_tx: mpsc::Sender<MacMessage>,
+        generate: impl FnMut() -> String,
+    ) -> Result<String, &'static str> {
+        let Some(code) = self.unused_code(client_id, generate) else {
+            tracing::error!("No unused session code after {} attempts", MAX_CODE_ATTEMPTS);
+            return Err("No codes available");
         };
+        self.insert_session(code.clone(), client_id, password_hash, mac_tx);
+        Ok(code)
+    }
 
+    /// Like [`register_mac_client`](Self::register_mac_client), but also
+    /// claims the code in the shared registry, skipping codes another
+    /// instance holds. A `requested_code` (already checked with
+    /// [`requestable_code`](crate::session::requestable_code)) is tried first, falling back to random codes
+    /// if it is taken. A session already on that code with the same
+    /// `client_id` is taken over, keeping its browsers.
+    pub async fn register_mac_client_shared(
+        &self,
+        client_id: &str,
+        requested_code: Option<String>,
+        password_hash: Option<String>,
+        mac_tx: mpsc::Sender<MacMessage>,
+    ) -> Result<String, &'static str> {
+        if let Some(code) = &requested_code {
+            if self.take_over(code, client_id, &password_hash, &mac_tx) {
+                return Ok(code.clone());
+            }
+        }
+        let mut requested_code = requested_code;
+        let mut generate = move || requested_code.take().unwrap_or_else(|| generate_session_code(code_length()));
+        let Some(registry) = &self.inner.registry else {
+            return self.register_with(client_id, password_hash, mac_tx, generate);
+        };
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let Some(code) = self.unused_code(client_id, &mut generate) else {
+                break;
+            };
+            if registry.claim(&code, CLAIM_TTL).await {
+                self.insert_session(code.clone(), client_id, password_hash, mac_tx);
+                return Ok(code);
+            }
+            tracing::debug!("Session code held by another instance, regenerating");
+        }
+        tracing::error!("No unused session code after {} attempts", MAX_CODE_ATTEMPTS);
+        Err("No codes available")
+    }
+
+    /// Hand the session on `code` to a reconnecting mac-client, if it
+    /// registered with the same `client_id`. The old connection is closed
+    /// without removing the session.
+    fn take_over(
+        &self,
+        code: &str,
+        client_id: &str,
+        password_hash: &Option<String>,
+        mac_tx: &mpsc::Sender<MacMessage>,
+    ) -> bool {
+        let Some(mut session) = self.inner.sessions.get_mut(code) else {
+            return false;
+        };
+        if session.client_id != client_id {
+            return false;
+        }
+        let old_tx = std::mem::replace(&mut session.mac_tx, mac_tx.clone());
+        session.password_hash = password_hash.clone();
+        // The new connection starts without stamping output
+        session.mac_timestamps.clear();
+        for session_id in session.timestamped_sessions() {
+            session.send_timestamps_to_mac(&session_id, true);
+        }
+        // The old connection's last heartbeat says nothing about this one
+        if let Ok(mut heartbeat) = session.heartbeat.try_lock() {
+            *heartbeat = None;
+        }
+        session.touch();
+        drop(session);
+        let _ = old_tx.try_send(MacMessage::Close);
+        tracing::info!(code = %code, client_id = %client_id, "Mac-client took over its session");
+        true
+    }
+
+    /// Whether `mac_tx` is the current connection of the session on `code`,
+    /// i.e. it hasn't been taken over by a reconnect.
+    pub fn is_mac_client_of(&self, code: &str, mac_tx: &mpsc::Sender<MacMessage>) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.mac_tx.same_channel(mac_tx))
+    }
+
+    fn insert_session(
+        &self,
+        code: String,
+        client_id: &str,
+        password_hash: Option<String>,
+        mac_tx: mpsc::Sender<MacMessage>,
+    ) {
         self.inner.sessions.insert(
             code.clone(),
             Session {
+                client_id: client_id.to_string(),
+                password_hash,
                 mac_tx,
                 browsers: DashMap::new(),
-                scrollback_frames: Mutex::new(Vec::new()),
-                scrollback_bytes: Mutex::new(0),
+                viewers: DashMap::new(),
+                capabilities: DashMap::new(),
+                compressing: DashSet::new(),
+                timestamps: DashMap::new(),
+                mac_timestamps: DashMap::new(),
+                subscriptions: DashMap::new(),
+                scrollback: Mutex::new(Scrollback::default()),
+                heartbeat: Mutex::new(None),
+                created_at: Instant::now(),
+                last_activity: AtomicU64::new(0),
+                terminals: Mutex::new(Vec::new()),
+                label: Mutex::new(None),
+                environment: Mutex::new(None),
             },
         );
 
-        tracing::info!(code = %code, "Mac-client registered");
-        code
+        self.inner.sessions_total.fetch_add(1, Ordering::Relaxed);
+        tracing::info!(code = %code, client_id = %client_id, "Mac-client registered");
+        self.publish(AdminEvent::MacRegistered { code: mask_code(&code) });
+    }
+
+    /// Generate a code not in use as a session or share code, nor reserved
+    /// for a client other than `client_id`, giving up after
+    /// [`MAX_CODE_ATTEMPTS`] collisions (e.g. a nearly full code space)
+    fn unused_code(&self, client_id: &str, mut generate: impl FnMut() -> String) -> Option<String> {
+        let now = Instant::now();
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let candidate = generate();
+            let reserved = self.inner.reserved.get(&candidate).is_some_and(|entry| {
+                let (owner, until) = entry.value();
+                owner != client_id && *until > now
+            });
+            if !reserved
+                && !self.inner.sessions.contains_key(&candidate)
+                && !self.inner.shares.contains_key(&candidate)
+            {
+                return Some(candidate);
+            }
+            tracing::debug!("Session code collision, regenerating");
+        }
+        None
+    }
+
+    /// The password hash browsers joining a session are checked against.
+    pub fn password_hash(&self, code: &str) -> Option<String> {
+        self.inner.sessions.get(code)?.password_hash.clone()
     }
 
     /// Validate a session code, returns true if valid
@@ -84,54 +486,532 @@ impl AppState {
         self.inner.sessions.contains_key(code)
     }
 
-    /// Remove a session (when mac-client disconnects)
+    /// Create a share code for `owner` that stops working after `ttl`
+    /// (capped at [`MAX_SHARE_TTL`]). Returns None if `owner` isn't a session.
+    pub fn create_share(&self, owner: &str, ttl: Duration) -> Option<String> {
+        if !self.validate_session_code(owner) {
+            return None;
+        }
+        let code = self.unused_code("", || generate_session_code(code_length()))?;
+        self.inner.shares.insert(
+            code.clone(),
+            Share {
+                owner: owner.to_string(),
+                expires_at: Instant::now() + ttl.min(MAX_SHARE_TTL),
+            },
+        );
+        tracing::info!(code = %mask_code(owner), share = %mask_code(&code), ttl_secs = ttl.as_secs(), "Share code created");
+        Some(code)
+    }
+
+    /// Resolve a code a browser authenticated with to the session it grants
+    /// access to, plus the deadline if it's a share code. Expired share
+    /// codes are removed and resolve to None.
+    pub fn resolve_code(&self, code: &str) -> Option<(String, Option<Instant>)> {
+        if self.validate_session_code(code) {
+            return Some((code.to_string(), None));
+        }
+        let share = self.inner.shares.get(code)?;
+        if share.expires_at <= Instant::now() {
+            drop(share);
+            self.inner.shares.remove(code);
+            return None;
+        }
+        Some((share.owner.clone(), Some(share.expires_at)))
+    }
+
+    /// Drop share codes past their deadline and lapsed code reservations.
+    /// Returns the number of share codes dropped.
+    pub fn prune_expired_codes(&self) -> usize {
+        let now = Instant::now();
+        let before = self.inner.shares.len();
+        self.inner.shares.retain(|_, share| share.expires_at > now);
+        self.inner.reserved.retain(|_, (_, until)| *until > now);
+        before - self.inner.shares.len()
+    }
+
+    /// Remove a session (when mac-client disconnects). Its code stays
+    /// reserved for the same client_id for [`CODE_RESERVATION`].
     pub fn remove_session(&self, code: &str) {
-        if self.inner.sessions.remove(code).is_some() {
+        let mut released = vec![code.to_string()];
+        self.inner.shares.retain(|share_code, share| {
+            let keep = share.owner != code;
+            if !keep {
+                released.push(share_code.clone());
+            }
+            keep
+        });
+        if let Some((_, session)) = self.inner.sessions.remove(code) {
+            let now = Instant::now();
+            self.inner.reserved.retain(|_, (_, until)| *until > now);
+            self.inner.reserved.insert(code.to_string(), (session.client_id, now + CODE_RESERVATION));
             tracing::info!(code = %code, "Session removed");
+            self.publish(AdminEvent::SessionRemoved { code: mask_code(code) });
+        }
+        if let Some(registry) = self.inner.registry.clone() {
+            tokio::spawn(async move {
+                for code in released {
+                    registry.release(&code).await;
+                }
+            });
+        }
+    }
+
+    /// Claim a new share code in the shared registry, so other instances
+    /// forward browsers using it here.
+    pub async fn claim_share(&self, code: &str, ttl: Duration) {
+        if let Some(registry) = &self.inner.registry {
+            if !registry.claim(code, ttl.min(MAX_SHARE_TTL)).await {
+                tracing::warn!(share = %mask_code(code), "Share code is held by another instance");
+            }
         }
     }
 
+    /// Keep the registry claim on a connected mac-client's code alive.
+    pub async fn refresh_claim(&self, code: &str) {
+        if let Some(registry) = &self.inner.registry {
+            registry.refresh(code, CLAIM_TTL).await;
+        }
+    }
+
+    /// URL of another relay instance holding `code`, when sharing codes.
+    pub async fn remote_owner(&self, code: &str) -> Option<String> {
+        self.inner.registry.as_ref()?.remote_owner(code).await
+    }
+
+    /// Count a browser auth with a code matching no session, warning (and
+    /// telling admins) once per window when they come in bursts.
+    pub fn record_invalid_code(&self, code: &str) {
+        let Some(burst) = self.inner.invalid_codes.record(code, Instant::now()) else {
+            return;
+        };
+        tracing::warn!(
+            attempts = burst.attempts,
+            distinct_codes = burst.distinct_codes,
+            top_code = %burst.top_code.0,
+            top_code_attempts = burst.top_code.1,
+            "Burst of invalid session codes, possible code scan"
+        );
+        self.publish(AdminEvent::InvalidCodeBurst {
+            attempts: burst.attempts,
+            distinct_codes: burst.distinct_codes,
+        });
+    }
+
+    /// Gate for verifying browser passwords.
+    pub fn password_gate(&self) -> &PasswordGate {
+        &self.inner.passwords
+    }
+
+    /// Browser auths with codes matching no session since startup.
+    pub fn invalid_code_attempts(&self) -> u64 {
+        self.inner.invalid_codes.total()
+    }
+
     /// Get count of active sessions (for debugging)
     pub fn session_count(&self) -> usize {
         self.inner.sessions.len()
     }
 
-    /// Add a browser to a session
-    pub fn add_browser(&self, code: &str, browser_id: String, tx: mpsc::Sender<BrowserMessage>) {
+    /// Browsers connected across all sessions.
+    pub fn browser_count(&self) -> usize {
+        self.inner.sessions.iter().map(|session| session.browsers.len()).sum()
+    }
+
+    /// Count a browser auth refused for a bad code or password.
+    pub fn record_auth_failure(&self) {
+        self.inner.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Traffic and auth counters since startup, for `/metrics`.
+    pub fn counters(&self) -> Counters {
+        let inner = &self.inner;
+        Counters {
+            sessions_total: inner.sessions_total.load(Ordering::Relaxed),
+            bytes_to_browsers: inner.bytes_to_browsers.load(Ordering::Relaxed),
+            bytes_to_mac: inner.bytes_to_mac.load(Ordering::Relaxed),
+            auth_failures: inner.auth_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    fn count_to_browsers(&self, bytes: usize) {
+        self.inner.bytes_to_browsers.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn count_to_mac(&self, bytes: usize) {
+        self.inner.bytes_to_mac.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record a heartbeat from a mac-client
+    pub async fn record_heartbeat(&self, code: &str, sessions: usize, load: f32) {
         if let Some(session) = self.inner.sessions.get(code) {
-            session.browsers.insert(browser_id, tx);
+            session.touch();
+            *session.heartbeat.lock().await = Some(Heartbeat {
+                received_at: Instant::now(),
+                sessions,
+                load,
+            });
         }
     }
 
+    /// Store a mac-client's label (sanitized). Returns the new label if it
+    /// changed, with an empty string for a cleared label.
+    pub async fn set_label(&self, code: &str, label: &str) -> Option<String> {
+        let session = self.inner.sessions.get(code)?;
+        let label = sanitize_label(label);
+        let mut current = session.label.lock().await;
+        if *current == label {
+            return None;
+        }
+        *current = label.clone();
+        Some(label.unwrap_or_default())
+    }
+
+    /// Store the environment a mac-client reported, with its strings cleaned
+    /// up like labels.
+    pub async fn set_environment(&self, code: &str, environment: ClientEnvironment) {
+        let Some(session) = self.inner.sessions.get(code) else {
+            return;
+        };
+        let clean = |value: Option<String>| value.as_deref().and_then(sanitize_label);
+        *session.environment.lock().await = Some(ClientEnvironment {
+            os_version: clean(environment.os_version),
+            shell: clean(environment.shell),
+            client_version: clean(environment.client_version),
+            ..environment
+        });
+    }
+
+    /// A mac-client's current label, if it set one.
+    pub async fn label(&self, code: &str) -> Option<String> {
+        let session = self.inner.sessions.get(code)?;
+        let label = session.label.lock().await.clone();
+        label
+    }
+
+    /// Replace the terminal sessions reported by a mac-client (SessionList).
+    pub async fn set_terminals(&self, code: &str, terminals: Vec<SessionInfo>) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            *session.terminals.lock().await = terminals;
+        }
+    }
+
+    /// Add or rename a terminal session (SessionConnected).
+    pub async fn terminal_connected(&self, code: &str, id: &str, name: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            let mut terminals = session.terminals.lock().await;
+            match terminals.iter_mut().find(|t| t.id == id) {
+                Some(terminal) => terminal.name = name.to_string(),
+                None => terminals.push(SessionInfo {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                }),
+            }
+        }
+    }
+
+    /// Forget a terminal session (SessionDisconnected).
+    pub async fn terminal_disconnected(&self, code: &str, id: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.terminals.lock().await.retain(|t| t.id != id);
+        }
+    }
+
+    /// Returns true if the mac-client has sent heartbeats before but none
+    /// within `timeout`. Clients that never sent one are not considered expired.
+    pub async fn heartbeat_expired(&self, code: &str, timeout: Duration) -> bool {
+        if let Some(session) = self.inner.sessions.get(code) {
+            if let Some(hb) = *session.heartbeat.lock().await {
+                return hb.received_at.elapsed() > timeout;
+            }
+        }
+        false
+    }
+
+    /// Summaries of all active sessions (for debugging)
+    pub async fn session_summaries(&self) -> Vec<SessionSummary> {
+        let mut summaries = Vec::with_capacity(self.inner.sessions.len());
+        for entry in self.inner.sessions.iter() {
+            let mut browsers: Vec<BrowserEntry> =
+                entry.viewers.iter().map(|v| v.value().clone()).collect();
+            browsers.sort_by_key(|b| b.connected_at);
+            summaries.push(SessionSummary {
+                code: entry.key().clone(),
+                client_id: entry.client_id.clone(),
+                age: entry.created_at.elapsed(),
+                browser_count: entry.browsers.len(),
+                browsers,
+                terminals: entry.terminals.lock().await.clone(),
+                heartbeat: *entry.heartbeat.lock().await,
+                label: entry.label.lock().await.clone(),
+                environment: entry.environment.lock().await.clone(),
+            });
+        }
+        summaries
+    }
+
+    /// Add a browser to a session. `ip_prefix` is the coarse address shown
+    /// in the owner's viewer list, `read_only` whether the browser only
+    /// watches, `compression` whether its output frames are compressed
+    /// (see `compression.rs`). If `browser_id` is already taken in the
+    /// session a new one is generated, so the other browser keeps its
+    /// channel; returns the id the browser was added with.
+    pub fn add_browser(
+        &self,
+        code: &str,
+        browser_id: String,
+        tx: mpsc::Sender<BrowserMessage>,
+        ip_prefix: String,
+        read_only: bool,
+        compression: bool,
+    ) -> String {
+        let mut browser_id = browser_id;
+        if let Some(session) = self.inner.sessions.get(code) {
+            loop {
+                match session.browsers.entry(browser_id.clone()) {
+                    dashmap::Entry::Vacant(entry) => {
+                        // Flagged before it can get any output
+                        if compression {
+                            session.compressing.insert(browser_id.clone());
+                        }
+                        entry.insert(tx);
+                        break;
+                    }
+                    dashmap::Entry::Occupied(_) => {
+                        tracing::debug!(code = %code, "Browser id collision, regenerating");
+                        browser_id = nanoid::nanoid!(8);
+                    }
+                }
+            }
+            let connected_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            session.viewers.insert(
+                browser_id.clone(),
+                BrowserEntry {
+                    id: browser_id.clone(),
+                    connected_at,
+                    ip_prefix,
+                    read_only,
+                },
+            );
+            self.publish(AdminEvent::BrowserConnected {
+                code: mask_code(code),
+                browser_id: browser_id.clone(),
+            });
+        }
+        browser_id
+    }
+
     /// Remove a browser from a session
     pub fn remove_browser(&self, code: &str, browser_id: &str) {
         if let Some(session) = self.inner.sessions.get(code) {
-            session.browsers.remove(browser_id);
+            session.subscriptions.remove(browser_id);
+            if let Some((_, ids)) = session.timestamps.remove(browser_id) {
+                for session_id in ids {
+                    if !session.timestamps_wanted(&session_id) {
+                        session.send_timestamps_to_mac(&session_id, false);
+                    }
+                }
+            }
+            session.viewers.remove(browser_id);
+            session.capabilities.remove(browser_id);
+            if session.browsers.remove(browser_id).is_some() {
+                session.compressing.remove(browser_id);
+                self.publish(AdminEvent::BrowserDisconnected {
+                    code: mask_code(code),
+                    browser_id: browser_id.to_string(),
+                });
+            }
         }
     }
 
-    /// Broadcast terminal output (binary) to all browsers in a session
-    pub async fn broadcast_to_browsers(&self, code: &str, data: Vec<u8>) {
+    /// Browsers connected to a session, oldest first.
+    pub fn browser_list(&self, code: &str) -> Vec<BrowserEntry> {
+        let mut browsers: Vec<BrowserEntry> = self
+            .inner
+            .sessions
+            .get(code)
+            .map(|session| session.viewers.iter().map(|e| e.value().clone()).collect())
+            .unwrap_or_default();
+        browsers.sort_by_key(|b| b.connected_at);
+        browsers
+    }
+
+    /// Record the terminal capabilities a browser reported.
+    pub fn set_capabilities(&self, code: &str, browser_id: &str, capabilities: TerminalCapabilities) {
         if let Some(session) = self.inner.sessions.get(code) {
-            // Append frame to scrollback, dropping oldest frames if over cap
-            {
-                let frame_len = data.len();
-                let mut frames = session.scrollback_frames.lock().await;
-                let mut total = session.scrollback_bytes.lock().await;
+            if session.browsers.contains_key(browser_id) {
+                session.capabilities.insert(browser_id.to_string(), capabilities);
+            }
+        }
+    }
+
+    /// Terminal capabilities a browser reported, if it has.
+    pub fn capabilities(&self, code: &str, browser_id: &str) -> Option<TerminalCapabilities> {
+        let session = self.inner.sessions.get(code)?;
+        let capabilities = session.capabilities.get(browser_id)?;
+        Some(capabilities.clone())
+    }
 
-                frames.push(data.clone());
-                *total += frame_len;
+    /// Limit a browser's terminal output to the given sessions (in addition
+    /// to any it already subscribed to). Scrollback for newly subscribed
+    /// sessions is replayed so the browser isn't left with a blank terminal.
+    pub async fn subscribe(&self, code: &str, browser_id: &str, session_ids: Vec<String>) {
+        let mut stalled = Vec::new();
+        if let Some(session) = self.inner.sessions.get(code) {
+            let Some(tx) = session.browsers.get(browser_id).map(|tx| tx.clone()) else {
+                return;
+            };
+
+            // Hold the scrollback lock so no live frame slips in between
+            // the replay and the subscription taking effect. The replay is
+            // merged into a few large frames and queued without waiting, so
+            // the lock isn't held for long.
+            let scrollback = session.scrollback.lock().await;
+            let added: HashSet<String> = {
+                let mut subscribed = session.subscriptions.entry(browser_id.to_string()).or_default();
+                session_ids
+                    .into_iter()
+                    .filter(|id| subscribed.insert(id.clone()))
+                    .collect()
+            };
+
+            let replay = scrollback.frames().into_iter().filter(|frame| {
+                split_frame(frame).is_some_and(|(sid, _)| added.iter().any(|id| id.as_bytes() == sid))
+            });
+            for frame in merge_frames(replay, REPLAY_FRAME_BYTES) {
+                let frame = if session.compressing.contains(browser_id) {
+                    compression::encode(&frame)
+                } else {
+                    frame
+                };
+                if !queue_for_browser(&tx, BrowserMessage::Binary(frame)) {
+                    stalled.push(browser_id.to_string());
+                    break;
+                }
+            }
+        }
+        self.drop_stalled_browsers(code, stalled);
+    }
 
-                // Drop oldest frames until we're under the cap
-                while *total > MAX_SCROLLBACK && !frames.is_empty() {
-                    let removed = frames.remove(0);
-                    *total -= removed.len();
+    /// Stop sending output for the given sessions to a browser. A browser
+    /// that never subscribed receives everything and is left unchanged.
+    pub fn unsubscribe(&self, code: &str, browser_id: &str, session_ids: &[String]) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            match session.subscriptions.get_mut(browser_id) {
+                Some(mut subscribed) => {
+                    for id in session_ids {
+                        subscribed.remove(id);
+                    }
                 }
+                None => tracing::debug!(code = %code, browser_id = %browser_id, "Unsubscribe without subscription ignored"),
+            }
+        }
+    }
+
+    /// Start or stop preceding a browser's output frames for a terminal
+    /// session with an OutputTimestamp. The mac-client stamps a session's
+    /// output while any browser wants it.
+    pub fn set_timestamps(&self, code: &str, browser_id: &str, session_id: &str, enabled: bool) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            if !session.browsers.contains_key(browser_id) {
+                return;
+            }
+            let wanted = session.timestamps_wanted(session_id);
+            if enabled {
+                session.timestamps.entry(browser_id.to_string()).or_default().insert(session_id.to_string());
+            } else {
+                session.timestamps.remove_if_mut(browser_id, |_, ids| {
+                    ids.remove(session_id);
+                    ids.is_empty()
+                });
+            }
+            if session.timestamps_wanted(session_id) != wanted {
+                session.send_timestamps_to_mac(session_id, !wanted);
+            }
+        }
+    }
+
+    /// Record the mac-client's OutputTimestamp for the next output frame of
+    /// `session_id`, passed on to browsers along with the relay's own.
+    pub fn set_mac_timestamp(&self, code: &str, session_id: &str, timestamp_ms: u64) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            if session.timestamps_wanted(session_id) {
+                session.mac_timestamps.insert(session_id.to_string(), timestamp_ms);
             }
+        }
+    }
+
+    /// Broadcast terminal output (binary) to all browsers in a session.
+    /// Frames are queued without waiting, so a browser that stopped reading
+    /// can't hold up the others; it is disconnected once its queue is full.
+    pub async fn broadcast_to_browsers(&self, code: &str, data: Vec<u8>) {
+        let mut stalled = Vec::new();
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.touch();
+            // Append frame to scrollback, dropping oldest frames if over cap.
+            // The lock is held while queuing so `subscribe` sees a consistent
+            // boundary between replayed and live frames.
+            let mut scrollback = session.scrollback.lock().await;
+            scrollback.push(data.clone());
 
+            let sid = split_frame(&data).map(|(sid, _)| sid);
+            // Compressed once, for all the browsers that asked for it
+            let mut encoded: Option<Vec<u8>> = None;
+            // Taken on arrival, before any browser's send can hold us up
+            let received = SystemTime::now();
+            let mac_timestamp = match sid.map(std::str::from_utf8) {
+                Some(Ok(sid)) if !session.mac_timestamps.is_empty() => {
+                    session.mac_timestamps.remove(sid).map(|(_, ms)| ms)
+                }
+                _ => None,
+            };
+            let mut timestamp: Option<String> = None;
             for entry in session.browsers.iter() {
-                let _ = entry.value().send(BrowserMessage::Binary(data.clone())).await;
+                let wanted = match (session.subscriptions.get(entry.key()), sid) {
+                    (Some(subscribed), Some(sid)) => subscribed.iter().any(|id| id.as_bytes() == sid),
+                    _ => true,
+                };
+                if !wanted {
+                    continue;
+                }
+                if let Some(sid) = sid {
+                    let timestamped = session
+                        .timestamps
+                        .get(entry.key())
+                        .is_some_and(|ids| ids.iter().any(|id| id.as_bytes() == sid));
+                    if timestamped {
+                        let json = timestamp.get_or_insert_with(|| output_timestamp(sid, received, mac_timestamp));
+                        if !queue_for_browser(entry.value(), BrowserMessage::Text(json.clone())) {
+                            stalled.push(entry.key().clone());
+                            continue;
+                        }
+                    }
+                }
+                let frame = if session.compressing.contains(entry.key()) {
+                    encoded.get_or_insert_with(|| compression::encode(&data)).clone()
+                } else {
+                    data.clone()
+                };
+                let len = frame.len();
+                match entry.value().try_send(BrowserMessage::Binary(frame)) {
+                    Ok(()) => self.count_to_browsers(len),
+                    Err(mpsc::error::TrySendError::Full(_)) => stalled.push(entry.key().clone()),
+                    Err(mpsc::error::TrySendError::Closed(_)) => {}
+                }
             }
+            drop(scrollback);
+        }
+        self.drop_stalled_browsers(code, stalled);
+    }
+
+    /// Disconnect browsers whose queue filled up: they stopped reading, and
+    /// catch up from a replay when they reconnect.
+    fn drop_stalled_browsers(&self, code: &str, stalled: Vec<String>) {
+        for browser_id in stalled {
+            tracing::warn!(code = %code, browser_id = %browser_id, "Browser stopped reading output, disconnecting it");
+            self.remove_browser(code, &browser_id);
         }
     }
 
@@ -139,33 +1019,20 @@ impl AppState {
     /// Binary frame format: [1 byte session_id_len][session_id][payload]
     pub async fn purge_session_scrollback(&self, code: &str, terminal_session_id: &str) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let mut frames = session.scrollback_frames.lock().await;
-            let mut total = session.scrollback_bytes.lock().await;
+            let mut scrollback = session.scrollback.lock().await;
 
             let tid = terminal_session_id.as_bytes();
-            let before = frames.len();
-            frames.retain(|frame| {
-                if frame.is_empty() {
-                    return false;
-                }
-                let id_len = frame[0] as usize;
-                if frame.len() < 1 + id_len {
-                    return false;
-                }
-                let frame_sid = &frame[1..1 + id_len];
-                frame_sid != tid
+            let purged = scrollback.retain(|frame| match split_frame(frame) {
+                Some((frame_sid, _)) => frame_sid != tid,
+                None => false,
             });
-            let after = frames.len();
-
-            // Recalculate total bytes
-            *total = frames.iter().map(|f| f.len()).sum();
 
-            if before != after {
+            if purged > 0 {
                 tracing::info!(
                     code = %code,
                     terminal_session_id = %terminal_session_id,
-                    purged = before - after,
-                    remaining = after,
+                    purged,
+                    remaining = scrollback.len(),
                     "Purged scrollback frames for dead session"
                 );
             }
@@ -173,20 +1040,50 @@ impl AppState {
     }
 
     /// Get scrollback frames for replay to a newly connected browser.
-    pub async fn get_scrollback(&self, code: &str) -> Vec<Vec<u8>> {
+    ///
+    /// With `max_lines`, only the last lines of each terminal session are
+    /// returned (clamped to `MAX_REPLAY_LINES`).
+    pub async fn get_scrollback(&self, code: &str, max_lines: Option<usize>) -> Vec<Vec<u8>> {
         if let Some(session) = self.inner.sessions.get(code) {
-            let frames = session.scrollback_frames.lock().await;
-            frames.clone()
+            let frames = session.scrollback.lock().await.frames();
+            match max_lines {
+                Some(n) => tail_lines(&frames, n.min(MAX_REPLAY_LINES)),
+                None => frames,
+            }
         } else {
             Vec::new()
         }
     }
 
+    /// Copy of a session's scrollback, older output still compressed, to
+    /// read without holding its lock.
+    pub async fn scrollback_snapshot(&self, code: &str) -> Option<Scrollback> {
+        let session = self.inner.sessions.get(code)?;
+        let scrollback = session.scrollback.lock().await.clone();
+        Some(scrollback)
+    }
+
+    /// Send a text message (JSON) to one browser
+    pub async fn send_text_to_browser(&self, code: &str, browser_id: &str, text: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.touch();
+            let tx = session.browsers.get(browser_id).map(|tx| tx.clone());
+            if let Some(tx) = tx {
+                if tx.send(BrowserMessage::Text(text.to_string())).await.is_ok() {
+                    self.count_to_browsers(text.len());
+                }
+            }
+        }
+    }
+
     /// Broadcast text message (JSON) to all browsers in a session
     pub async fn broadcast_text_to_browsers(&self, code: &str, text: &str) {
         if let Some(session) = self.inner.sessions.get(code) {
+            session.touch();
             for entry in session.browsers.iter() {
-                let _ = entry.value().send(BrowserMessage::Text(text.to_string())).await;
+                if entry.value().send(BrowserMessage::Text(text.to_string())).await.is_ok() {
+                    self.count_to_browsers(text.len());
+                }
             }
         }
     }
@@ -194,16 +1091,234 @@ impl AppState {
     /// Send keyboard input (binary) to mac-client
     pub async fn send_to_mac_client(&self, code: &str, data: Vec<u8>) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let _ = session.mac_tx.send(MacMessage::Binary(data)).await;
+            session.touch();
+            let len = data.len();
+            if session.mac_tx.send(MacMessage::Binary(data)).await.is_ok() {
+                self.count_to_mac(len);
+            }
         }
     }
 
     /// Send text message (JSON) to mac-client
     pub async fn send_text_to_mac_client(&self, code: &str, text: &str) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let _ = session.mac_tx.send(MacMessage::Text(text.to_string())).await;
+            session.touch();
+            if session.mac_tx.send(MacMessage::Text(text.to_string())).await.is_ok() {
+                self.count_to_mac(text.len());
+            }
+        }
+    }
+
+    /// Remove sessions without traffic for longer than `ttl`, telling their
+    /// browsers first and closing their mac-clients. A browser or mac-client
+    /// too backed up to take the message just has its channel dropped.
+    /// Returns the removed codes.
+    pub async fn expire_idle_sessions(&self, ttl: Duration) -> Vec<String> {
+        let idle: Vec<String> = self
+            .inner
+            .sessions
+            .iter()
+            .filter(|entry| entry.idle() > ttl)
+            .map(|entry| entry.key().clone())
+            .collect();
+        let notice = serde_json::to_string(&ControlMessage::Error { message: "Session expired".into() }).unwrap();
+        for code in &idle {
+            let Some(session) = self.inner.sessions.get(code) else {
+                continue;
+            };
+            for entry in session.browsers.iter() {
+                let _ = entry.value().try_send(BrowserMessage::Text(notice.clone()));
+            }
+            let _ = session.mac_tx.try_send(MacMessage::Close);
+            drop(session);
+            tracing::info!(code = %code, idle_ttl_secs = ttl.as_secs(), "Idle session expired");
+            // Dropping the session drops the browsers' senders, closing them
+            self.remove_session(code);
+        }
+        idle
+    }
+
+    /// Tell every browser and mac-client the relay is going away and close
+    /// their connections, then wait for the sessions to go. Gives up after
+    /// `grace`, e.g. if a client stopped reading. The notices are queued
+    /// without waiting, so one client with a full channel can't hold up the
+    /// rest; it misses the notice and is dropped at exit.
+    pub async fn shutdown(&self, grace: Duration) {
+        let notice = serde_json::to_string(&ControlMessage::Error { message: "Relay shutting down".into() }).unwrap();
+        let clients: Vec<_> = self
+            .inner
+            .sessions
+            .iter()
+            .map(|session| {
+                let browsers: Vec<_> = session.browsers.iter().map(|entry| entry.value().clone()).collect();
+                (session.mac_tx.clone(), browsers)
+            })
+            .collect();
+        tracing::info!(sessions = clients.len(), "Relay shutting down, closing connections");
+
+        for (mac_tx, browsers) in clients {
+            for tx in browsers {
+                let _ = tx.try_send(BrowserMessage::Text(notice.clone()));
+                let _ = tx.try_send(BrowserMessage::Close);
+            }
+            let _ = mac_tx.try_send(MacMessage::Text(notice.clone()));
+            let _ = mac_tx.try_send(MacMessage::Close);
+        }
+
+        let close_all = async {
+            // Each mac-client's handler removes its session once it has gone
+            while self.session_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        if tokio::time::timeout(grace, close_all).await.is_err() {
+            tracing::warn!(sessions = self.session_count(), "Connections still open after shutdown grace period");
+        }
+    }
+}
+
+/// `SESSION_IDLE_TTL` in seconds, [`DEFAULT_IDLE_TTL`] if unset or invalid.
+/// 0 keeps idle sessions forever.
+fn idle_ttl_from_env() -> Option<Duration> {
+    let Ok(value) = std::env::var("SESSION_IDLE_TTL") else {
+        return Some(DEFAULT_IDLE_TTL);
+    };
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            tracing::warn!("Invalid SESSION_IDLE_TTL {:?}, using {}s", value, DEFAULT_IDLE_TTL.as_secs());
+            Some(DEFAULT_IDLE_TTL)
+        }
+    }
+}
+
+/// Periodically drop expired share codes and reservations, until the
+/// state is dropped.
+async fn prune_expired_codes(inner: Weak<AppStateInner>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let pruned = AppState { inner }.prune_expired_codes();
+        if pruned > 0 {
+            tracing::debug!(pruned, "Expired share codes dropped");
+        }
+    }
+}
+
+/// Periodically expire idle sessions, until the state is dropped.
+async fn reap_idle_sessions(inner: Weak<AppStateInner>, ttl: Duration) {
+    let mut interval = tokio::time::interval((ttl / 2).min(Duration::from_secs(60)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        AppState { inner }.expire_idle_sessions(ttl).await;
+    }
+}
+
+/// OutputTimestamp for output of `session_id` that arrived at `received`,
+/// having been sent by the mac-client at `mac_timestamp_ms`.
+fn output_timestamp(session_id: &[u8], received: SystemTime, mac_timestamp_ms: Option<u64>) -> String {
+    let msg = ControlMessage::OutputTimestamp {
+        session_id: String::from_utf8_lossy(session_id).into_owned(),
+        timestamp_ms: received
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64),
+        mac_timestamp_ms,
+    };
+    serde_json::to_string(&msg).unwrap()
+}
+
+/// Queue a message for a browser without waiting. False if its queue is
+/// full, meaning it has stopped reading.
+fn queue_for_browser(tx: &mpsc::Sender<BrowserMessage>, msg: BrowserMessage) -> bool {
+    !matches!(tx.try_send(msg), Err(mpsc::error::TrySendError::Full(_)))
+}
+
+/// Join consecutive frames of the same terminal session into frames of up
+/// to `max_bytes` (a larger frame is left whole), so a replay takes few of
+/// a browser's queue slots.
+fn merge_frames(frames: impl IntoIterator<Item = Vec<u8>>, max_bytes: usize) -> Vec<Vec<u8>> {
+    let mut merged: Vec<Vec<u8>> = Vec::new();
+    for frame in frames {
+        let joins = match (merged.last(), split_frame(&frame)) {
+            (Some(last), Some((sid, payload))) => {
+                split_frame(last).is_some_and(|(last_sid, _)| last_sid == sid) && last.len() + payload.len() <= max_bytes
+            }
+            _ => false,
+        };
+        match merged.last_mut() {
+            Some(last) if joins => last.extend_from_slice(&frame[1 + frame[0] as usize..]),
+            _ => merged.push(frame),
         }
     }
+    merged
+}
+
+/// Split a binary frame into (session_id, payload).
+/// Binary frame format: [1 byte session_id_len][session_id][payload]
+pub(crate) fn split_frame(frame: &[u8]) -> Option<(&[u8], &[u8])> {
+    let id_len = *frame.first()? as usize;
+    if frame.len() < 1 + id_len {
+        return None;
+    }
+    Some((&frame[1..1 + id_len], &frame[1 + id_len..]))
+}
+
+/// Keep at most the last `max_lines` lines of output per terminal session.
+///
+/// Walks frames newest-first, counting newlines per session; the frame that
+/// crosses the limit is trimmed to the bytes after the cut. A newline ending
+/// the output closes the last line rather than starting another.
+fn tail_lines(frames: &[Vec<u8>], max_lines: usize) -> Vec<Vec<u8>> {
+    // Newlines still allowed per terminal session
+    let mut remaining: HashMap<&[u8], usize> = HashMap::new();
+    let mut tail = Vec::new();
+
+    for frame in frames.iter().rev() {
+        let Some((sid, payload)) = split_frame(frame) else {
+            continue;
+        };
+        let left = remaining
+            .entry(sid)
+            .or_insert_with(|| if payload.ends_with(b"\n") { max_lines + 1 } else { max_lines });
+        if *left == 0 {
+            continue;
+        }
+
+        let mut cut = None;
+        for (i, &b) in payload.iter().enumerate().rev() {
+            if b == b'\n' {
+                *left -= 1;
+                if *left == 0 {
+                    cut = Some(i + 1);
+                    break;
+                }
+            }
+        }
+
+        match cut {
+            Some(start) if start < payload.len() => {
+                let mut trimmed = Vec::with_capacity(1 + sid.len() + payload.len() - start);
+                trimmed.push(sid.len() as u8);
+                trimmed.extend_from_slice(sid);
+                trimmed.extend_from_slice(&payload[start..]);
+                tail.push(trimmed);
+            }
+            Some(_) => {}
+            None => tail.push(frame.clone()),
+        }
+    }
+
+    tail.reverse();
+    tail
 }
 
 impl Default for AppState {
@@ -211,3 +1326,518 @@ impl Default for AppState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requested_code() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client_shared("client-1", Some("DESK42".into()), None, tx.clone()).await;
+        assert_eq!(code.as_deref(), Ok("DESK42"));
+
+        // Taken: a random code instead
+        let code = state.register_mac_client_shared("client-2", Some("DESK42".into()), None, tx.clone()).await.unwrap();
+        assert_ne!(code, "DESK42");
+        assert!(state.validate_session_code(&code));
+
+        // Free again once its mac-client is gone, as after a reconnect
+        state.remove_session("DESK42");
+        let code = state.register_mac_client_shared("client-1", Some("DESK42".into()), None, tx).await;
+        assert_eq!(code.as_deref(), Ok("DESK42"));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_takes_over_session() {
+        let state = AppState::new();
+        let (old_tx, mut old_rx) = mpsc::channel(1);
+        state.register_mac_client_shared("client-1", Some("DESK42".into()), None, old_tx.clone()).await.unwrap();
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser("DESK42", "browser-1".into(), browser_tx, "127.0.0.x".into(), false, false);
+
+        // The old connection hasn't noticed it is dead yet
+        let (new_tx, _new_rx) = mpsc::channel(1);
+        let code = state.register_mac_client_shared("client-1", Some("DESK42".into()), None, new_tx.clone()).await;
+        assert_eq!(code.as_deref(), Ok("DESK42"));
+        assert!(matches!(old_rx.recv().await, Some(MacMessage::Close)));
+        assert!(state.is_mac_client_of("DESK42", &new_tx));
+        assert!(!state.is_mac_client_of("DESK42", &old_tx));
+        assert_eq!(state.browser_count(), 1);
+
+        // Another client doesn't get it
+        let code = state.register_mac_client_shared("client-2", Some("DESK42".into()), None, new_tx).await.unwrap();
+        assert_ne!(code, "DESK42");
+    }
+
+    #[tokio::test]
+    async fn test_freed_code_reserved_for_its_client() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        state.register_mac_client_shared("client-1", Some("DESK42".into()), None, tx.clone()).await.unwrap();
+        state.remove_session("DESK42");
+
+        let code = state.register_mac_client_shared("client-2", Some("DESK42".into()), None, tx.clone()).await.unwrap();
+        assert_ne!(code, "DESK42");
+        let code = state.register_mac_client_shared("client-1", Some("DESK42".into()), None, tx.clone()).await;
+        assert_eq!(code.as_deref(), Ok("DESK42"));
+
+        // Free for anyone once the reservation lapses
+        state.remove_session("DESK42");
+        state.inner.reserved.alter("DESK42", |_, (owner, _)| (owner, Instant::now()));
+        let code = state.register_mac_client_shared("client-2", Some("DESK42".into()), None, tx).await;
+        assert_eq!(code.as_deref(), Ok("DESK42"));
+    }
+
+    #[tokio::test]
+    async fn test_client_id_stored() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("550e8400-e29b-41d4-a716-446655440000", None, tx).unwrap();
+
+        let summaries = state.session_summaries().await;
+        assert_eq!(summaries[0].code, code);
+        assert_eq!(summaries[0].client_id, "550e8400-e29b-41d4-a716-446655440000");
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_recorded() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        let summaries = state.session_summaries().await;
+        assert!(summaries[0].heartbeat.is_none());
+
+        state.record_heartbeat(&code, 4, 0.75).await;
+
+        let summaries = state.session_summaries().await;
+        let hb = summaries[0].heartbeat.expect("heartbeat recorded");
+        assert_eq!(hb.sessions, 4);
+        assert_eq!(hb.load, 0.75);
+    }
+
+    #[tokio::test]
+    async fn test_share_code_expires() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let owner = state.register_mac_client("client-1", None, tx).unwrap();
+
+        let share = state.create_share(&owner, Duration::from_millis(20)).unwrap();
+        assert_ne!(share, owner);
+        let (resolved, deadline) = state.resolve_code(&share).unwrap();
+        assert_eq!(resolved, owner);
+        assert!(deadline.is_some());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(state.resolve_code(&share).is_none());
+        // The owner's own code is unaffected
+        assert_eq!(state.resolve_code(&owner), Some((owner.clone(), None)));
+
+        // Shares nobody resolves again are pruned all the same
+        state.create_share(&owner, Duration::from_millis(20)).unwrap();
+        let live = state.create_share(&owner, Duration::from_secs(60)).unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(state.prune_expired_codes(), 1);
+        assert!(state.resolve_code(&live).is_some());
+    }
+
+    #[test]
+    fn test_code_space_exhaustion_is_bounded() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let taken = state.register_mac_client("client-1", None, tx).unwrap();
+
+        // Every candidate collides: give up instead of looping forever
+        let mut attempts = 0;
+        let code = state.unused_code("client-1", || {
+            attempts += 1;
+            taken.clone()
+        });
+        assert_eq!(code, None);
+        assert_eq!(attempts, MAX_CODE_ATTEMPTS);
+
+        // The last attempt still finds a free code
+        let mut attempts = 0;
+        let code = state.unused_code("client-1", || {
+            attempts += 1;
+            if attempts < MAX_CODE_ATTEMPTS { taken.clone() } else { "FREE23".into() }
+        });
+        assert_eq!(code.as_deref(), Some("FREE23"));
+    }
+
+    #[test]
+    fn test_browser_list() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser(&code, "b1".into(), browser_tx, "203.0.113.0/24".into(), false, false);
+
+        let list = state.browser_list(&code);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, "b1");
+        assert_eq!(list[0].ip_prefix, "203.0.113.0/24");
+        assert!(list[0].connected_at > 0);
+
+        state.remove_browser(&code, "b1");
+        assert!(state.browser_list(&code).is_empty());
+    }
+
+    #[test]
+    fn test_share_removed_with_session() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let owner = state.register_mac_client("client-1", None, tx).unwrap();
+        assert!(state.create_share("NOSUCH", Duration::from_secs(60)).is_none());
+
+        let share = state.create_share(&owner, Duration::from_secs(60)).unwrap();
+        state.remove_session(&owner);
+        assert!(state.resolve_code(&share).is_none());
+    }
+
+    fn frame(session_id: &str, payload: &[u8]) -> Vec<u8> {
+        let mut f = vec![session_id.len() as u8];
+        f.extend_from_slice(session_id.as_bytes());
+        f.extend_from_slice(payload);
+        f
+    }
+
+    fn replayed_payload(frames: &[Vec<u8>], session_id: &str) -> Vec<u8> {
+        frames
+            .iter()
+            .filter_map(|f| split_frame(f))
+            .filter(|(sid, _)| *sid == session_id.as_bytes())
+            .flat_map(|(_, payload)| payload.to_vec())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_scrollback_replay_limit() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        let mut output = Vec::new();
+        for i in 0..100 {
+            output.extend_from_slice(format!("line {}\r\n", i).as_bytes());
+        }
+        // Split across frames so the cut lands mid-frame
+        for chunk in output.chunks(37) {
+            state.broadcast_to_browsers(&code, frame("s1", chunk)).await;
+        }
+        state.broadcast_to_browsers(&code, frame("s2", b"a\nb\nc\n")).await;
+
+        // Full replay by default
+        let all = state.get_scrollback(&code, None).await;
+        assert_eq!(replayed_payload(&all, "s1"), output);
+
+        // Limited replay keeps only the tail of each session
+        let tail = state.get_scrollback(&code, Some(50)).await;
+        let s1 = replayed_payload(&tail, "s1");
+        assert_eq!(s1.iter().filter(|&&b| b == b'\n').count(), 50);
+        assert!(s1.starts_with(b"line 50\r\n"));
+        assert!(s1.ends_with(b"line 99\r\n"));
+        assert_eq!(replayed_payload(&tail, "s2"), b"a\nb\nc\n");
+
+        // Zero lines replays nothing
+        assert!(state.get_scrollback(&code, Some(0)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_colliding_browser_id_regenerated() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        let (first_tx, mut first_rx) = mpsc::channel(16);
+        let (second_tx, mut second_rx) = mpsc::channel(16);
+        assert_eq!(state.add_browser(&code, "same".into(), first_tx, "unknown".into(), false, false), "same");
+        let second = state.add_browser(&code, "same".into(), second_tx, "unknown".into(), false, false);
+        assert_ne!(second, "same");
+        assert_eq!(state.browser_list(&code).len(), 2);
+
+        // Both still get output
+        state.broadcast_to_browsers(&code, frame("s1", b"out")).await;
+        assert!(matches!(first_rx.try_recv(), Ok(BrowserMessage::Binary(_))));
+        assert!(matches!(second_rx.try_recv(), Ok(BrowserMessage::Binary(_))));
+    }
+
+    #[tokio::test]
+    async fn test_output_timestamps() {
+        let state = AppState::new();
+        let (tx, mut mac_rx) = mpsc::channel(4);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        let (browser_tx, mut browser_rx) = mpsc::channel(16);
+        state.add_browser(&code, "b1".into(), browser_tx, "unknown".into(), false, false);
+        let (other_tx, _other_rx) = mpsc::channel(16);
+        state.add_browser(&code, "b2".into(), other_tx, "unknown".into(), false, false);
+        let mac_text = |msg: Option<MacMessage>| match msg {
+            Some(MacMessage::Text(json)) => serde_json::from_str::<ControlMessage>(&json).unwrap(),
+            other => panic!("Expected text to the mac-client, got {:?}", other),
+        };
+
+        // Off by default
+        state.broadcast_to_browsers(&code, frame("s1", b"before")).await;
+        assert!(matches!(browser_rx.try_recv(), Ok(BrowserMessage::Binary(_))));
+
+        // Only the enabled session's frames are preceded by a timestamp, and
+        // the mac-client is asked to stamp it once
+        state.set_timestamps(&code, "b1", "s1", true);
+        state.set_timestamps(&code, "b2", "s1", true);
+        assert!(matches!(mac_text(mac_rx.try_recv().ok()), ControlMessage::EnableTimestamps { session_id } if session_id == "s1"));
+        assert!(mac_rx.try_recv().is_err());
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        state.set_mac_timestamp(&code, "s1", 1_000);
+        state.broadcast_to_browsers(&code, frame("s1", b"tagged")).await;
+        state.broadcast_to_browsers(&code, frame("s2", b"untagged")).await;
+        match browser_rx.try_recv() {
+            Ok(BrowserMessage::Text(json)) => match serde_json::from_str(&json).unwrap() {
+                ControlMessage::OutputTimestamp { session_id, timestamp_ms, mac_timestamp_ms } => {
+                    assert_eq!(session_id, "s1");
+                    assert!(timestamp_ms >= before && timestamp_ms < before + 5000, "{}", timestamp_ms);
+                    assert_eq!(mac_timestamp_ms, Some(1_000));
+                }
+                other => panic!("Expected OutputTimestamp, got {:?}", other),
+            },
+            other => panic!("Expected timestamp, got {:?}", other),
+        }
+        assert!(matches!(browser_rx.try_recv(), Ok(BrowserMessage::Binary(data)) if data == frame("s1", b"tagged")));
+        assert!(matches!(browser_rx.try_recv(), Ok(BrowserMessage::Binary(data)) if data == frame("s2", b"untagged")));
+
+        // The mac-client stops stamping once no browser wants it
+        state.set_timestamps(&code, "b1", "s1", false);
+        assert!(mac_rx.try_recv().is_err());
+        state.broadcast_to_browsers(&code, frame("s1", b"after")).await;
+        assert!(matches!(browser_rx.try_recv(), Ok(BrowserMessage::Binary(_))));
+        assert!(browser_rx.try_recv().is_err());
+        state.remove_browser(&code, "b2");
+        assert!(matches!(mac_text(mac_rx.try_recv().ok()), ControlMessage::DisableTimestamps { session_id } if session_id == "s1"));
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_filter_output() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        state.broadcast_to_browsers(&code, frame("s1", b"old1")).await;
+        state.broadcast_to_browsers(&code, frame("s2", b"old2")).await;
+
+        let (all_tx, all_rx) = mpsc::channel(16);
+        let (one_tx, mut one_rx) = mpsc::channel(16);
+        state.add_browser(&code, "all".into(), all_tx, "unknown".into(), false, false);
+        state.add_browser(&code, "one".into(), one_tx, "unknown".into(), false, false);
+
+        // Subscribing replays the scrollback of just that session
+        state.subscribe(&code, "one", vec!["s2".into()]).await;
+        match one_rx.try_recv() {
+            Ok(BrowserMessage::Binary(data)) => assert_eq!(data, frame("s2", b"old2")),
+            other => panic!("Expected replayed frame, got {:?}", other),
+        }
+
+        state.broadcast_to_browsers(&code, frame("s1", b"new1")).await;
+        state.broadcast_to_browsers(&code, frame("s2", b"new2")).await;
+
+        // Unsubscribed browsers still get everything
+        assert_eq!(all_rx.len(), 2);
+        match one_rx.try_recv() {
+            Ok(BrowserMessage::Binary(data)) => assert_eq!(data, frame("s2", b"new2")),
+            other => panic!("Expected s2 frame, got {:?}", other),
+        }
+        assert!(one_rx.try_recv().is_err());
+
+        state.unsubscribe(&code, "one", &["s2".into()]);
+        state.broadcast_to_browsers(&code, frame("s2", b"more")).await;
+        assert!(one_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_browser_does_not_hold_up_output() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        // One browser stopped reading with a full queue, one keeps up
+        let (stalled_tx, _stalled_rx) = mpsc::channel(1);
+        let (live_tx, mut live_rx) = mpsc::channel(16);
+        state.add_browser(&code, "stalled".into(), stalled_tx, "unknown".into(), false, false);
+        state.add_browser(&code, "live".into(), live_tx, "unknown".into(), false, false);
+
+        for chunk in [&b"one"[..], b"two", b"three"] {
+            tokio::time::timeout(Duration::from_secs(1), state.broadcast_to_browsers(&code, frame("s1", chunk)))
+                .await
+                .expect("broadcast waited on a stalled browser");
+        }
+        assert_eq!(live_rx.len(), 3);
+        while let Ok(BrowserMessage::Binary(_)) = live_rx.try_recv() {}
+
+        // The stalled browser was dropped, and the scrollback isn't left locked
+        assert_eq!(state.browser_count(), 1);
+        tokio::time::timeout(Duration::from_secs(1), state.subscribe(&code, "live", vec!["s1".into()]))
+            .await
+            .expect("subscribe waited on the scrollback");
+        match live_rx.try_recv() {
+            Ok(BrowserMessage::Binary(data)) => assert_eq!(data, frame("s1", b"onetwothree")),
+            other => panic!("Expected one merged replay frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_capabilities_per_browser() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser(&code, "b1".into(), browser_tx, "unknown".into(), false, false);
+
+        let capabilities = TerminalCapabilities {
+            cols: 132,
+            rows: 43,
+            truecolor: true,
+            unicode_version: Some(11),
+            locale: None,
+            timezone: None,
+        };
+        state.set_capabilities(&code, "b1", capabilities.clone());
+        state.set_capabilities(&code, "gone", capabilities.clone());
+        assert_eq!(state.capabilities(&code, "b1"), Some(capabilities));
+        assert_eq!(state.capabilities(&code, "gone"), None);
+
+        state.remove_browser(&code, "b1");
+        assert_eq!(state.capabilities(&code, "b1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_label_changes_reported_once() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        assert_eq!(state.label(&code).await, None);
+
+        assert_eq!(state.set_label(&code, " studio-mac ").await.as_deref(), Some("studio-mac"));
+        assert_eq!(state.set_label(&code, "studio-mac").await, None);
+        assert_eq!(state.label(&code).await.as_deref(), Some("studio-mac"));
+
+        // Clearing is reported as an empty label
+        assert_eq!(state.set_label(&code, "").await.as_deref(), Some(""));
+        assert_eq!(state.label(&code).await, None);
+        assert_eq!(state.set_label("NOSUCH", "x").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_admin_events_published() {
+        let state = AppState::new();
+        let mut events = state.subscribe_admin_events();
+
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+        let masked = mask_code(&code);
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser(&code, "b1".into(), browser_tx, "unknown".into(), false, false);
+        state.remove_browser(&code, "b1");
+        state.remove_session(&code);
+
+        assert_eq!(events.try_recv().unwrap(), AdminEvent::MacRegistered { code: masked.clone() });
+        assert_eq!(
+            events.try_recv().unwrap(),
+            AdminEvent::BrowserConnected { code: masked.clone(), browser_id: "b1".into() }
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            AdminEvent::BrowserDisconnected { code: masked.clone(), browser_id: "b1".into() }
+        );
+        assert_eq!(events.try_recv().unwrap(), AdminEvent::SessionRemoved { code: masked });
+    }
+
+    #[test]
+    fn test_admin_token() {
+        assert!(!AppState::new().admin_token_matches(""));
+
+        let state = AppState::with_admin_token(Some("secret".into()));
+        assert!(state.admin_token_matches("secret"));
+        assert!(!state.admin_token_matches("secreT"));
+        assert!(!state.admin_token_matches("secret2"));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_expiry() {
+        let state = AppState::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let code = state.register_mac_client("client-1", None, tx).unwrap();
+
+        // Never sent a heartbeat: not expired
+        assert!(!state.heartbeat_expired(&code, Duration::ZERO).await);
+
+        state.record_heartbeat(&code, 1, 0.0).await;
+        assert!(!state.heartbeat_expired(&code, HEARTBEAT_TIMEOUT).await);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(state.heartbeat_expired(&code, Duration::from_millis(1)).await);
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire() {
+        let state = AppState::with_idle_ttl(None, None, Some(Duration::from_millis(200)));
+        let (idle_tx, mut idle_rx) = mpsc::channel(8);
+        let idle = state.register_mac_client("client-1", None, idle_tx).unwrap();
+        let (browser_tx, mut browser_rx) = mpsc::channel(8);
+        state.add_browser(&idle, "b1".into(), browser_tx, "127.0.0.0/24".into(), false, false);
+        let (mac_tx, _mac_rx) = mpsc::channel(8);
+        let active = state.register_mac_client("client-2", None, mac_tx).unwrap();
+
+        // Keep one session busy while the reaper runs
+        for _ in 0..8 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            state.broadcast_to_browsers(&active, vec![2, b't', b'1', b'x']).await;
+        }
+
+        assert!(!state.validate_session_code(&idle));
+        assert!(state.validate_session_code(&active));
+        match browser_rx.recv().await {
+            Some(BrowserMessage::Text(text)) => assert!(text.contains("Session expired"), "{}", text),
+            other => panic!("expected expiry notice, got {:?}", other),
+        }
+        // The browser's channel closes with the session
+        assert!(browser_rx.recv().await.is_none());
+        // The mac-client is disconnected right away, not on its next check
+        assert!(matches!(idle_rx.recv().await, Some(MacMessage::Close)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_notifies_all_clients() {
+        let state = AppState::new();
+        let mut receivers = Vec::new();
+        for client_id in ["client-1", "client-2"] {
+            let (mac_tx, mac_rx) = mpsc::channel(8);
+            let code = state.register_mac_client(client_id, None, mac_tx).unwrap();
+            let (browser_tx, browser_rx) = mpsc::channel(8);
+            state.add_browser(&code, "b1".into(), browser_tx, "127.0.0.0/24".into(), false, false);
+            receivers.push((mac_rx, browser_rx));
+        }
+        // A browser that stopped reading doesn't hold up the others
+        let (mac_tx, _stalled_mac_rx) = mpsc::channel(8);
+        let code = state.register_mac_client("client-3", None, mac_tx).unwrap();
+        let (stalled_tx, _stalled_rx) = mpsc::channel(1);
+        stalled_tx.try_send(BrowserMessage::Close).unwrap();
+        state.add_browser(&code, "b1".into(), stalled_tx, "127.0.0.0/24".into(), false, false);
+
+        // No handlers are running to remove the sessions, so this waits out
+        // the grace period
+        state.shutdown(Duration::from_millis(100)).await;
+
+        for (mut mac_rx, mut browser_rx) in receivers {
+            match browser_rx.recv().await {
+                Some(BrowserMessage::Text(text)) => assert!(text.contains("Relay shutting down"), "{}", text),
+                other => panic!("expected shutdown notice, got {:?}", other),
+            }
+            assert!(matches!(browser_rx.recv().await, Some(BrowserMessage::Close)));
+            match mac_rx.recv().await {
+                Some(MacMessage::Text(text)) => assert!(text.contains("Relay shutting down"), "{}", text),
+                other => panic!("expected shutdown notice, got {:?}", other),
+            }
+            assert!(matches!(mac_rx.recv().await, Some(MacMessage::Close)));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+import { useState, type ReactNode } from 'react';
+import { useTabs } from '../context/TabsContext';
+import './NewSessionButton.css';
+
+/** Icons for the profile hints the picker knows; others get the default */
+const profileIcons: Record<string, string> = {
+  python: '\u{1F40D}',
+  node: '\u{2B22}',
+  rust: '\u{2699}',
+  docker: '\u{1F433}',
+  database: '\u{1F5C4}',
+  ssh: '\u{1F511}',
+  shell: '\u{276F}',
+};
+const DEFAULT_ICON = '\u{276F}';
+
+interface NewSessionButtonProps {
+  className: string;
+  title?: string;
+  'aria-label'?: string;
+  children: ReactNode;
+}
+
+/**
+ * Creates a session. When the Mac offers profiles, it opens a picker with
+ * each profile's icon and description first, plus the plain shell.
+ */
+export default function NewSessionButton({ className, title, 'aria-label': ariaLabel, children }: NewSessionButtonProps) {
+  const { profiles, createTab } = useTabs();
+  const [picking, setPicking] = useState(false);
+
+  function handleClick() {
+    if (profiles.length === 0) {
+      createTab();
+    } else {
+      setPicking(true);
+    }
+  }
+
+  function choose(profile?: string) {
+    setPicking(false);
+    createTab(profile);
+  }
+
+  return (
+    <>
+      <button className={className} onClick={handleClick} title={title} aria-label={ariaLabel}>
+        {children}
+      </button>
+      {picking && (
+        <div className="profile-picker-backdrop" onClick={() => setPicking(false)}>
+          <div
+            className="profile-picker"
+            role="dialog"
+            aria-labelledby="profile-picker-title"
+            onClick={(e) => e.stopPropagation()}
+            onKeyDown={(e) => {
+              if (e.key === 'Escape') setPicking(false);
+            }}
+          >
+            <h3 id="profile-picker-title">New session</h3>
+            <ul className="profile-list">
+              <li>
+                <button className="profile-option" onClick={() => choose()} autoFocus>
+                  <span className="profile-icon">{DEFAULT_ICON}</span>
+                  <span className="profile-text">
+                    <span className="profile-name">Shell</span>
+                    <span className="profile-description">Your default login shell</span>
+                  </span>
+                </button>
+              </li>
+              {profiles.map((profile) => (
+                <li key={profile.name}>
+                  <button className="profile-option" onClick={() => choose(profile.name)}>
+                    <span className="profile-icon">
+                      {(profile.icon && profileIcons[profile.icon.toLowerCase()]) || DEFAULT_ICON}
+                    </span>
+                    <span className="profile-text">
+                      <span className="profile-name">{profile.name}</span>
+                      {profile.description && (
+                        <span className="profile-description">{profile.description}</span>
+                      )}
+                    </span>
+                  </button>
+                </li>
+              ))}
+            </ul>
+          </div>
+        </div>
+      )}
+    </>
+  );
+}
@@ -0,0 +1,76 @@
+import { useEffect, useRef, useState } from 'react';
+import { useConnection } from '../context/ConnectionContext';
+import { useTabs } from '../context/TabsContext';
+import type { NoticeMessage } from '../../shared/protocol';
+import './Notices.css';
+
+/** How long a notice stays up (ms) */
+const NOTICE_DURATION_MS = 6000;
+
+/** Most notices shown at once; older ones make room */
+const MAX_NOTICES = 3;
+
+interface Notice {
+  id: number;
+  sessionId?: string;
+  message: string;
+}
+
+/**
+ * Shows notice messages from the Mac or relay (e.g. "Session ended") as
+ * toasts that dismiss themselves, or on click.
+ */
+export default function Notices() {
+  const { registerMessageHandler } = useConnection();
+  const { sessions } = useTabs();
+  const [notices, setNotices] = useState<Notice[]>([]);
+  const nextIdRef = useRef(0);
+
+  useEffect(() => {
+    const timers = new Set<ReturnType<typeof setTimeout>>();
+    const dismiss = (id: number) => setNotices((prev) => prev.filter((n) => n.id !== id));
+
+    const unregister = registerMessageHandler((data) => {
+      switch (data.type) {
+        case 'notice': {
+          const msg = data as unknown as NoticeMessage;
+          const id = nextIdRef.current++;
+          setNotices((prev) => [...prev, { id, sessionId: msg.session_id, message: msg.message }].slice(-MAX_NOTICES));
+          const timer = setTimeout(() => {
+            timers.delete(timer);
+            dismiss(id);
+          }, NOTICE_DURATION_MS);
+          timers.add(timer);
+          break;
+        }
+        case '__disconnect':
+          setNotices([]);
+          break;
+      }
+    });
+    return () => {
+      unregister();
+      for (const timer of timers) clearTimeout(timer);
+    };
+  }, [registerMessageHandler]);
+
+  if (notices.length === 0) return null;
+
+  return (
+    <div className="notices" role="status" aria-live="polite">
+      {notices.map((notice) => {
+        const session = notice.sessionId ? sessions.find((s) => s.id === notice.sessionId) : undefined;
+        return (
+          <div
+            key={notice.id}
+            className="notice"
+            onClick={() => setNotices((prev) => prev.filter((n) => n.id !== notice.id))}
+          >
+            {session && <span className="notice-session">{session.name || 'Terminal'}</span>}
+            <span className="notice-message">{notice.message}</span>
+          </div>
+        );
+      })}
+    </div>
+  );
+}
@@ -0,0 +1,62 @@
+import { useEffect, useState } from 'react';
+import { useConnection } from '../context/ConnectionContext';
+import { useTabs } from '../context/TabsContext';
+import type { ConfirmPasteAckMessage, ConfirmPasteMessage } from '../../shared/protocol';
+import './PasteConfirm.css';
+
+/**
+ * Asks before running multiline input the Mac is holding (CONFIRM_PASTE).
+ * Pastes are confirmed one at a time, oldest first; unanswered ones are
+ * dropped by the Mac after a minute.
+ */
+export default function PasteConfirm() {
+  const { registerMessageHandler, sendMessage } = useConnection();
+  const { sessions } = useTabs();
+  const [pending, setPending] = useState<ConfirmPasteMessage[]>([]);
+
+  useEffect(() => {
+    return registerMessageHandler((data) => {
+      switch (data.type) {
+        case 'confirm_paste': {
+          const msg = data as unknown as ConfirmPasteMessage;
+          setPending((prev) => [...prev, msg]);
+          break;
+        }
+        case '__disconnect':
+          setPending([]);
+          break;
+      }
+    });
+  }, [registerMessageHandler]);
+
+  const current = pending[0];
+  if (!current) return null;
+
+  function answer(accept: boolean) {
+    const ack: ConfirmPasteAckMessage = { type: 'confirm_paste_ack', token: current.token, accept };
+    sendMessage(ack);
+    setPending((prev) => prev.slice(1));
+  }
+
+  const session = sessions.find((s) => s.id === current.session_id);
+
+  return (
+    <div className="paste-confirm-backdrop">
+      <div className="paste-confirm" role="alertdialog" aria-labelledby="paste-confirm-title">
+        <h3 id="paste-confirm-title">Run multiline paste?</h3>
+        <p className="paste-confirm-detail">
+          Each line runs as a command in {session?.name || 'this session'}.
+        </p>
+        <pre className="paste-confirm-preview">{current.preview}</pre>
+        <div className="paste-confirm-actions">
+          <button className="btn-secondary" onClick={() => answer(false)}>
+            Cancel
+          </button>
+          <button className="btn-warning" onClick={() => answer(true)} autoFocus>
+            Paste
+          </button>
+        </div>
+      </div>
+    </div>
+  );
+}
@@ -1,26 +1,56 @@
-import { useTabs } from '../context/TabsContext';
+import { useTabs, type SessionInfo } from '../context/TabsContext';
+import NewSessionButton from './NewSessionButton';
 import './TerminalTabs.css';
 
+/** Encodings offered for a session, by the name the Mac echoes back ('' = UTF-8) */
+const ENCODINGS: { value: string; label: string }[] = [
+  { value: '', label: 'UTF-8' },
+  { value: 'windows-1252', label: 'Latin-1' },
+  { value: 'ISO-8859-2', label: 'Latin-2' },
+  { value: 'KOI8-R', label: 'KOI8-R' },
+  { value: 'Shift_JIS', label: 'Shift_JIS' },
+  { value: 'EUC-JP', label: 'EUC-JP' },
+  { value: 'EUC-KR', label: 'EUC-KR' },
+  { value: 'GBK', label: 'GBK' },
+  { value: 'Big5', label: 'Big5' },
+];
+
 export default function TerminalTabs() {
-  const { sessions, activeSessionId, switchSession, createTab, closeTab } = useTabs();
+  const { sessions, activeSessionId, switchSession, closeTab, setLocked, setEncoding } = useTabs();
+
+  /** Unlocking asks first, since it makes the session closable again. */
+  function confirmUnlock(session: SessionInfo): boolean {
+    return window.confirm(`Unlock "${session.name || 'Terminal'}"? It can then be closed.`);
+  }
 
-  function handleCloseSession(event: React.MouseEvent, sessionId: string) {
+  function handleToggleLock(event: React.MouseEvent, session: SessionInfo) {
     event.stopPropagation();
-    closeTab(sessionId);
+    if (!session.locked) {
+      setLocked(session.id, true);
+    } else if (confirmUnlock(session)) {
+      setLocked(session.id, false);
+    }
+  }
+
+  function handleCloseSession(event: React.MouseEvent, session: SessionInfo) {
+    event.stopPropagation();
+    if (session.locked) {
+      // Closing waits for the Mac to confirm the unlock
+      if (confirmUnlock(session)) {
+        setLocked(session.id, false);
+      }
+      return;
+    }
+    closeTab(session.id);
   }
 
   return (
     <aside className="tab-sidebar">
       <div className="tab-header">
         <span className="tab-header-label">Sessions</span>
-        <button
-          className="btn-new-tab"
-          onClick={createTab}
-          title="New session"
-          aria-label="Create new session"
-        >
+        <NewSessionButton className="btn-new-tab" title="New session" aria-label="Create new session">
           +
-        </button>
+        </NewSessionButton>
       </div>
 
       <div className="tab-list" role="tablist" aria-label="Terminal sessions">
@@ -38,11 +68,45 @@ export default function TerminalTabs() {
             title={session.name}
           >
             <span className="tab-title">{session.name || 'Terminal'}</span>
+            {session.typingOnMac && (
+              <span className="typing-badge" title="Someone is typing on the Mac" aria-label="Typing on the Mac">
+                &#x2328;
+              </span>
+            )}
             {!session.connected && <span className="disconnected-badge">offline</span>}
+            {session.id === activeSessionId && session.connected && (
+              <select
+                className="tab-encoding"
+                value={session.encoding ?? ''}
+                onClick={(e) => e.stopPropagation()}
+                onKeyDown={(e) => e.stopPropagation()}
+                onChange={(e) => setEncoding(session.id, e.target.value || undefined)}
+                title="Encoding of this session's programs"
+                aria-label={`Encoding of session ${session.name}`}
+              >
+                {ENCODINGS.map((encoding) => (
+                  <option key={encoding.value} value={encoding.value}>
+                    {encoding.label}
+                  </option>
+                ))}
+                {session.encoding && !ENCODINGS.some((e) => e.value === session.encoding) && (
+                  <option value={session.encoding}>{session.encoding}</option>
+                )}
+              </select>
+            )}
+            <button
+              className={`btn-lock-tab${session.locked ? ' locked' : ''}`}
+              onClick={(e) => handleToggleLock(e, session)}
+              title={session.locked ? 'Locked against closing (click to unlock)' : 'Lock against closing'}
+              aria-label={`${session.locked ? 'Unlock' : 'Lock'} session ${session.name}`}
+              aria-pressed={session.locked}
+            >
+              {session.locked ? '\u{1F512}' : '\u{1F513}'}
+            </button>
             <button
               className="btn-close-tab"
-              onClick={(e) => handleCloseSession(e, session.id)}
-              title="Close session"
+              onClick={(e) => handleCloseSession(e, session)}
+              title={session.locked ? 'Locked; unlock to close' : 'Close session'}
               aria-label={`Close session ${session.name}`}
             >
               &times;
@@ -13,13 +13,21 @@ import ReconnectingWebSocket from 'reconnecting-websocket';
 import type {
   AuthMessage,
   AuthSuccessMessage,
+  BrowserCapabilitiesMessage,
   AuthFailedMessage,
   ErrorMessage,
   SessionConnectedMessage,
   SessionDisconnectedMessage,
   ConfigMessage,
+  FlowControlMessage,
+  LabelMessage,
+  EnableTimestampsMessage,
+  OutputTimestampMessage,
+  SessionListMessage,
 } from '../../shared/protocol';
-import { decodeBinaryFrame, encodeInputMessage } from '../protocol/binary';
+import { compressionSupported, decodeBinaryFrame, encodeInputMessage, GZIP_FRAME, inflateFrame } from '../protocol/binary';
+import { FLOW_CONTROL_BULK_BYTES, TERMINAL_MIN_COLS, TERMINAL_MIN_ROWS } from '../../shared/constants';
+import { defaultTerminalOptions } from '../iterm-theme';
 
 // =============================================================================
 // Connection State Types
@@ -62,6 +70,92 @@ function clearStoredSessionCode(): void {
   }
 }
 
+// =============================================================================
+// Scrollback Replay Preference
+// =============================================================================
+
+const REPLAY_LINES_STORAGE_KEY = 'terminal-replay-lines';
+
+/**
+ * Lines of scrollback the relay replays per session on the first auth of a
+ * connect() (null = all of it). Chosen on the login page and kept in
+ * localStorage so a reload replays the same amount.
+ */
+export function getStoredReplayLines(): number | null {
+  try {
+    const stored = localStorage.getItem(REPLAY_LINES_STORAGE_KEY);
+    const lines = stored === null ? NaN : Number(stored);
+    return Number.isInteger(lines) && lines >= 0 ? lines : null;
+  } catch {
+    return null;
+  }
+}
+
+function storeReplayLines(lines: number | null): void {
+  try {
+    if (lines === null) {
+      localStorage.removeItem(REPLAY_LINES_STORAGE_KEY);
+    } else {
+      localStorage.setItem(REPLAY_LINES_STORAGE_KEY, String(lines));
+    }
+  } catch {
+    // Ignore storage errors
+  }
+}
+
+// =============================================================================
+// Output Timestamps (debugging latency)
+// =============================================================================
+
+const OUTPUT_TIMESTAMPS_STORAGE_KEY = 'terminal-output-timestamps';
+
+/**
+ * Output timestamps are requested for every session when the
+ * `terminal-output-timestamps` localStorage key is '1' (set it from the
+ * console and reload).
+ */
+function outputTimestampsEnabled(): boolean {
+  try {
+    return localStorage.getItem(OUTPUT_TIMESTAMPS_STORAGE_KEY) === '1';
+  } catch {
+    return false;
+  }
+}
+
+/** Session ids a session_list/session_connected message announces. */
+function announcedSessions(data: Record<string, unknown>): string[] {
+  switch (data.type) {
+    case 'session_list':
+      return (data as unknown as SessionListMessage).sessions.map((s) => s.id);
+    case 'session_connected':
+      return [(data as unknown as SessionConnectedMessage).session_id];
+    default:
+      return [];
+  }
+}
+
+// =============================================================================
+// Browser Capabilities
+// =============================================================================
+
+/**
+ * This browser's terminal, for the sessions it creates. No terminal is open
+ * yet when it is sent, so the size is estimated from the window and font.
+ */
+function browserCapabilities(): BrowserCapabilitiesMessage {
+  const fontSize = defaultTerminalOptions.fontSize ?? 14;
+  return {
+    type: 'browser_capabilities',
+    cols: Math.max(TERMINAL_MIN_COLS, Math.floor(window.innerWidth / (fontSize * 0.6))),
+    rows: Math.max(TERMINAL_MIN_ROWS, Math.floor(window.innerHeight / (fontSize * 1.2))),
+    // xterm.js renders 24-bit color, and Terminal loads the Unicode 11 widths
+    truecolor: true,
+    unicode_version: 11,
+    locale: navigator.language || undefined,
+    timezone: Intl.DateTimeFormat().resolvedOptions().timeZone || undefined,
+  };
+}
+
 // =============================================================================
 // Handler Types
 // =============================================================================
@@ -80,8 +174,14 @@ interface ConnectionContextValue {
   state: ConnectionState;
   error: string | null;
   sessionCode: string | null;
+  /** Name of the Mac, from its label message (null until one arrives) */
+  label: string | null;
   isConnected: boolean;
-  connect: (sessionCode: string, onConnected?: () => void) => void;
+  /**
+   * Connect with a session code, and the password if the Mac set one.
+   * `replayLines`, when given, replaces the stored replay preference.
+   */
+  connect: (sessionCode: string, onConnected?: () => void, password?: string, replayLines?: number | null) => void;
   disconnect: () => void;
   /** Send a JSON control message */
   sendMessage: (message: object) => void;
@@ -111,9 +211,12 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   const [state, setState] = useState<ConnectionState>('disconnected');
   const [error, setError] = useState<string | null>(null);
   const [sessionCode, setSessionCode] = useState<string | null>(null);
+  const [label, setLabel] = useState<string | null>(null);
 
   const wsRef = useRef<ReconnectingWebSocket | null>(null);
   const currentCodeRef = useRef<string | null>(null);
+  // Kept in memory only (never in sessionStorage), resent on reconnect
+  const currentPasswordRef = useRef<string | null>(null);
   const onConnectedCallbackRef = useRef<(() => void) | null>(null);
   const messageHandlersRef = useRef<Set<MessageHandler>>(new Set());
   const binaryHandlersRef = useRef<Set<BinaryHandler>>(new Set());
@@ -121,6 +224,11 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   // Refs for state values that event handlers need to read (avoids stale closures)
   const stateRef = useRef<ConnectionState>('disconnected');
 
+  // Set by flow_control while the Mac's uplink is backed up. Bulk input,
+  // and anything typed after it, waits here so input stays in order.
+  const inputPausedRef = useRef(false);
+  const heldInputRef = useRef<Uint8Array[]>([]);
+
   // ---------------------------------------------------------------------------
   // Handler Registration
   // ---------------------------------------------------------------------------
@@ -149,10 +257,28 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   const sendBinary = useCallback((frame: Uint8Array) => {
     const ws = wsRef.current;
     if (stateRef.current === 'connected' && ws && ws.readyState === WebSocket.OPEN) {
+      const held = heldInputRef.current;
+      if (held.length > 0 || (inputPausedRef.current && frame.length >= FLOW_CONTROL_BULK_BYTES)) {
+        held.push(frame);
+        return;
+      }
       ws.send(frame);
     }
   }, []);
 
+  /** Resume input after flow_control, sending what was held back. */
+  const resumeInput = useCallback(() => {
+    inputPausedRef.current = false;
+    const held = heldInputRef.current;
+    heldInputRef.current = [];
+    const ws = wsRef.current;
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      for (const frame of held) {
+        ws.send(frame);
+      }
+    }
+  }, []);
+
   const sendTerminalInput = useCallback((termSessionId: string, payload: string) => {
     const frame = encodeInputMessage(termSessionId, payload);
     sendBinary(frame);
@@ -171,7 +297,11 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     stateRef.current = 'disconnected';
     setError(null);
     setSessionCode(null);
+    setLabel(null);
     currentCodeRef.current = null;
+    currentPasswordRef.current = null;
+    inputPausedRef.current = false;
+    heldInputRef.current = [];
     clearStoredSessionCode();
     // Notify handlers of disconnect
     for (const handler of messageHandlersRef.current) {
@@ -183,7 +313,7 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   // Connect
   // ---------------------------------------------------------------------------
 
-  const connect = useCallback((code: string, onConnected?: () => void) => {
+  const connect = useCallback((code: string, onConnected?: () => void, password?: string, replayLines?: number | null) => {
     // Close existing connection if any
     if (wsRef.current) {
       wsRef.current.close();
@@ -195,6 +325,10 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     setError(null);
     setSessionCode(null);
     currentCodeRef.current = code;
+    currentPasswordRef.current = password || null;
+    if (replayLines !== undefined) {
+      storeReplayLines(replayLines);
+    }
     onConnectedCallbackRef.current = onConnected ?? null;
 
     // Derive relay URL: use env var in dev, or derive from current location in production
@@ -211,26 +345,45 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     // Enable binary message handling
     ws.binaryType = 'arraybuffer';
 
+    // Whether the relay flags (and may compress) its binary frames on the
+    // current socket. Compressed frames inflate asynchronously; messages
+    // arriving meanwhile wait behind them so output stays in order.
+    let compressed = false;
+    let inflating: Promise<void> | null = null;
+    // Set once this connect() has authenticated. Later sockets are rejoins:
+    // the browser still shows its terminals, so it skips the scrollback
+    // replay and restores each session from a state snapshot instead.
+    let joined = false;
+    const timestamps = outputTimestampsEnabled();
+
     ws.addEventListener('open', () => {
       setState('authenticating');
       stateRef.current = 'authenticating';
+      compressed = false;
 
       if (currentCodeRef.current) {
         // Send auth message with session code
         const authMessage: AuthMessage = {
           type: 'auth',
           session_code: currentCodeRef.current,
+          compression: compressionSupported,
         };
+        if (currentPasswordRef.current) {
+          authMessage.password = currentPasswordRef.current;
+        }
+        const replayLines = joined ? 0 : getStoredReplayLines();
+        if (replayLines !== null) {
+          authMessage.replay_lines = replayLines;
+        }
         ws.send(JSON.stringify(authMessage));
       }
     });
 
-    ws.addEventListener('message', (event: MessageEvent) => {
+    const handleMessage = (message: Uint8Array | string) => {
       // Binary frame: decode and dispatch to binary handlers
-      if (event.data instanceof ArrayBuffer) {
+      if (message instanceof Uint8Array) {
         try {
-          const frame = new Uint8Array(event.data);
-          const { sessionId, payload } = decodeBinaryFrame(frame);
+          const { sessionId, payload } = decodeBinaryFrame(message);
           for (const handler of binaryHandlersRef.current) {
             handler(sessionId, payload);
           }
@@ -242,7 +395,14 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
 
       // Text frame: JSON control message
       try {
-        const data = JSON.parse(event.data);
+        const data = JSON.parse(message);
+
+        if (timestamps) {
+          for (const session_id of announcedSessions(data)) {
+            const msg: EnableTimestampsMessage = { type: 'enable_timestamps', session_id };
+            ws.send(JSON.stringify(msg));
+          }
+        }
 
         switch (data.type) {
           case 'auth_success': {
@@ -253,6 +413,9 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
             if (currentCodeRef.current) {
               storeSessionCode(currentCodeRef.current);
             }
+            ws.send(JSON.stringify(browserCapabilities()));
+            // A pause from before a reconnect may never be lifted
+            resumeInput();
             // Fire one-time connected callback
             if (onConnectedCallbackRef.current) {
               const cb = onConnectedCallbackRef.current;
@@ -260,8 +423,10 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
               cb();
             }
             // Notify handlers
+            const rejoin = joined;
+            joined = true;
             for (const handler of messageHandlersRef.current) {
-              handler(data);
+              handler({ ...data, rejoin });
             }
             break;
           }
@@ -300,8 +465,19 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
           case 'session_list':
           case 'session_connected':
           case 'session_disconnected':
+          case 'lock_session':
+          case 'set_encoding':
+          case 'profile_list':
           // Session resize (mac -> browser)
           case 'session_resize':
+          // Multiline input held by the Mac until confirmed
+          case 'confirm_paste':
+          // Informational messages shown to the user
+          case 'notice':
+          // Keystrokes typed on the Mac
+          case 'local_input':
+          // Terminal snapshot answering get_state
+          case 'state':
           // Config message
           case 'config':
           // Legacy tab messages (if any)
@@ -315,12 +491,75 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
             break;
           }
 
+          case 'compression':
+            compressed = true;
+            break;
+
+          case 'label': {
+            const msg = data as LabelMessage;
+            setLabel(msg.label || null);
+            break;
+          }
+
+          case 'flow_control': {
+            const msg = data as FlowControlMessage;
+            if (msg.pause) {
+              inputPausedRef.current = true;
+            } else {
+              resumeInput();
+            }
+            break;
+          }
+
+          case 'output_timestamp': {
+            const { session_id, timestamp_ms, mac_timestamp_ms } = data as OutputTimestampMessage;
+            const now = Date.now();
+            console.debug(
+              `[Connection] Output for ${session_id} reached the relay at`,
+              new Date(timestamp_ms).toISOString(),
+              `(${now - timestamp_ms} ms ago)`,
+              mac_timestamp_ms !== undefined
+                ? `after leaving the Mac at ${new Date(mac_timestamp_ms).toISOString()} (${now - mac_timestamp_ms} ms ago; clocks may differ)`
+                : '',
+            );
+            break;
+          }
+
           default:
             console.log('[Connection] Unhandled message type:', data.type);
         }
       } catch (e) {
         console.error('[Connection] Failed to parse message:', e);
       }
+    };
+
+    ws.addEventListener('message', (event: MessageEvent) => {
+      const data: ArrayBuffer | string = event.data;
+      let message: Uint8Array | string | Promise<Uint8Array> = data;
+      if (data instanceof ArrayBuffer) {
+        const frame = new Uint8Array(data);
+        if (!compressed) {
+          message = frame;
+        } else if (frame[0] === GZIP_FRAME) {
+          message = inflateFrame(data.slice(1));
+        } else {
+          message = frame.subarray(1);
+        }
+      }
+      if (message instanceof Promise || inflating) {
+        const next = (inflating ?? Promise.resolve())
+          .then(() => message)
+          .then(handleMessage)
+          .catch((e) => console.error('[Connection] Failed to inflate frame:', e))
+          .finally(() => {
+            if (inflating === next) {
+              inflating = null;
+            }
+          });
+        inflating = next;
+        return;
+      }
+      handleMessage(message);
     });
 
     ws.addEventListener('close', () => {
@@ -340,7 +579,7 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     });
 
     wsRef.current = ws;
-  }, []);
+  }, [resumeInput]);
 
   // Auto-reconnect on mount if we have a stored session code
   useEffect(() => {
@@ -354,6 +593,7 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     state,
     error,
     sessionCode,
+    label,
     isConnected: state === 'connected',
     connect,
     disconnect,
@@ -10,6 +10,8 @@
  * - Auto-switch to first session (per phase context decision)
  * - Sessions marked as disconnected after session_disconnected message
  * - Disconnected sessions removed after 5 seconds
+ * - Only the active session's output is streamed (subscribe); switching tabs
+ *   replays the new session's scrollback into its cleared terminal
  */
 
 import {
@@ -21,7 +23,16 @@ import {
   useRef,
   type ReactNode,
 } from 'react';
-import type { SessionConnectedMessage, SessionDisconnectedMessage, SessionListMessage } from '../../shared/protocol';
+import type {
+  LocalInputMessage,
+  LockSessionMessage,
+  ProfileInfoSchema,
+  ProfileListMessage,
+  SessionConnectedMessage,
+  SessionDisconnectedMessage,
+  SessionListMessage,
+  SetEncodingMessage,
+} from '../../shared/protocol';
 import { useConnection } from './ConnectionContext';
 import { useTerminal } from './TerminalContext';
 
@@ -33,6 +44,12 @@ export interface SessionInfo {
   id: string;
   name: string;
   connected: boolean;
+  /** Locked against closing; the Mac refuses a close until it is unlocked */
+  locked: boolean;
+  /** Encoding the Mac transcodes this session from (undefined = UTF-8) */
+  encoding?: string;
+  /** Someone is typing in this session on the Mac itself */
+  typingOnMac: boolean;
   lastActivity: number; // timestamp
 }
 
@@ -45,10 +62,16 @@ interface TabsContextValue {
   activeSessionId: string | null;
   activeSession: SessionInfo | undefined;
   switchSession: (sessionId: string) => void;
-  /** Create new tab - sends create_session to server */
-  createTab: () => void;
-  /** Close tab - currently no-op (shell sessions managed by user) */
+  /** Profiles the Mac offers for new sessions (empty if it has none) */
+  profiles: ProfileInfoSchema[];
+  /** Create new tab - sends create_session to server, with a profile name if given */
+  createTab: (profile?: string) => void;
+  /** Close tab - sends close_session; locked sessions are left alone */
   closeTab: (sessionId: string) => void;
+  /** Lock or unlock a session against closing - sends lock_session */
+  setLocked: (sessionId: string, locked: boolean) => void;
+  /** Set the encoding a session is transcoded from (undefined = UTF-8) - sends set_encoding */
+  setEncoding: (sessionId: string, encoding?: string) => void;
   // Legacy aliases for compatibility
   tabs: SessionInfo[];
   activeTabId: string | null;
@@ -71,17 +94,24 @@ export function useTabs(): TabsContextValue {
 // Remove disconnected sessions after this delay (ms)
 const DISCONNECTED_REMOVAL_DELAY_MS = 5000;
 
+// Clear the typing-on-Mac indicator after this long without local input (ms)
+const LOCAL_TYPING_IDLE_MS = 2000;
+
 export function TabsProvider({ children }: { children: ReactNode }) {
   const [sessions, setSessions] = useState<SessionInfo[]>([]);
   const [activeSessionId, setActiveSessionId] = useState<string | null>(null);
+  const [profiles, setProfiles] = useState<ProfileInfoSchema[]>([]);
 
   // Refs for immediate sync access (avoids stale closures)
   const activeSessionIdRef = useRef<string | null>(null);
   const sessionsRef = useRef<SessionInfo[]>([]);
   const removalTimersRef = useRef<Map<string, ReturnType<typeof setTimeout>>>(new Map());
+  const typingTimersRef = useRef<Map<string, ReturnType<typeof setTimeout>>>(new Map());
 
   const { registerMessageHandler, registerBinaryHandler, sendMessage } = useConnection();
-  const { setActiveSession } = useTerminal();
+  const { setActiveSession, clearTerminal } = useTerminal();
+  // Session whose output the relay streams to us (subscribe)
+  const subscribedRef = useRef<string | null>(null);
 
   // Keep refs in sync
   useEffect(() => {
@@ -118,6 +148,8 @@ export function TabsProvider({ children }: { children: ReactNode }) {
         id: sessionId,
         name: name ?? sessionId, // Use sessionId as fallback name
         connected: true,
+        locked: false,
+        typingOnMac: false,
         lastActivity: Date.now(),
       };
 
@@ -179,6 +211,25 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     removalTimersRef.current.set(sessionId, timer);
   }, [setActiveSession]);
 
+  /**
+   * Show that the session is being typed in on the Mac, until it goes quiet.
+   */
+  const markTypingOnMac = useCallback((sessionId: string) => {
+    const setTyping = (typingOnMac: boolean) =>
+      setSessions((prev) =>
+        prev.map((s) => (s.id === sessionId && s.typingOnMac !== typingOnMac ? { ...s, typingOnMac } : s))
+      );
+    setTyping(true);
+
+    const existing = typingTimersRef.current.get(sessionId);
+    if (existing) clearTimeout(existing);
+    const timer = setTimeout(() => {
+      typingTimersRef.current.delete(sessionId);
+      setTyping(false);
+    }, LOCAL_TYPING_IDLE_MS);
+    typingTimersRef.current.set(sessionId, timer);
+  }, []);
+
   /**
    * Reset all sessions (on disconnect).
    */
@@ -186,12 +237,18 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     setSessions([]);
     setActiveSessionId(null);
     activeSessionIdRef.current = null;
+    // The relay forgets subscriptions with the connection
+    subscribedRef.current = null;
 
     // Clear all removal timers
     for (const timer of removalTimersRef.current.values()) {
       clearTimeout(timer);
     }
     removalTimersRef.current.clear();
+    for (const timer of typingTimersRef.current.values()) {
+      clearTimeout(timer);
+    }
+    typingTimersRef.current.clear();
   }, []);
 
   // ---------------------------------------------------------------------------
@@ -231,14 +288,61 @@ export function TabsProvider({ children }: { children: ReactNode }) {
           markSessionDisconnected(msg.session_id);
           break;
         }
+        case 'auth_success': {
+          // Asked on every (re)connect, since the Mac's profiles may have changed
+          sendMessage({ type: 'list_profiles' });
+          break;
+        }
+        case 'profile_list': {
+          const msg = data as unknown as ProfileListMessage;
+          setProfiles(msg.profiles);
+          break;
+        }
+        case 'lock_session': {
+          const msg = data as unknown as LockSessionMessage;
+          setSessions((prev) =>
+            prev.map((s) => (s.id === msg.session_id ? { ...s, locked: msg.locked } : s))
+          );
+          break;
+        }
+        case 'set_encoding': {
+          const msg = data as unknown as SetEncodingMessage;
+          setSessions((prev) =>
+            prev.map((s) => (s.id === msg.session_id ? { ...s, encoding: msg.encoding } : s))
+          );
+          break;
+        }
+        case 'local_input': {
+          const msg = data as unknown as LocalInputMessage;
+          markTypingOnMac(msg.session_id);
+          break;
+        }
         case '__disconnect': {
           reset();
+          setProfiles([]);
           break;
         }
       }
     });
     return unregister;
-  }, [registerMessageHandler, addOrUpdateSession, markSessionDisconnected, reset]);
+  }, [registerMessageHandler, sendMessage, addOrUpdateSession, markSessionDisconnected, markTypingOnMac, reset]);
+
+  // ---------------------------------------------------------------------------
+  // Subscription - only the active session's output is streamed
+  // ---------------------------------------------------------------------------
+
+  useEffect(() => {
+    if (!activeSessionId || activeSessionId === subscribedRef.current) return;
+    const previous = subscribedRef.current;
+    if (previous) {
+      sendMessage({ type: 'unsubscribe', session_ids: [previous] });
+    }
+    // The relay replays the session's scrollback on subscribing, so start
+    // from an empty terminal rather than writing it twice
+    clearTerminal(activeSessionId);
+    sendMessage({ type: 'subscribe', session_ids: [activeSessionId] });
+    subscribedRef.current = activeSessionId;
+  }, [activeSessionId, sendMessage, clearTerminal]);
 
   // ---------------------------------------------------------------------------
   // Cleanup timers on unmount
@@ -249,6 +353,9 @@ export function TabsProvider({ children }: { children: ReactNode }) {
       for (const timer of removalTimersRef.current.values()) {
         clearTimeout(timer);
       }
+      for (const timer of typingTimersRef.current.values()) {
+        clearTimeout(timer);
+      }
     };
   }, []);
 
@@ -265,11 +372,14 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     setActiveSession(sessionId);
   }, [setActiveSession]);
 
-  const createTabAction = useCallback(() => {
-    sendMessage({ type: 'create_session' });
+  const createTabAction = useCallback((profile?: string) => {
+    sendMessage(profile ? { type: 'create_session', profile } : { type: 'create_session' });
   }, [sendMessage]);
 
   const closeTabAction = useCallback((sessionId: string) => {
+    // The Mac refuses to close a locked session, so keep its tab
+    if (sessionsRef.current.find((s) => s.id === sessionId)?.locked) return;
+
     // Send close_session message to server
     sendMessage({ type: 'close_session', session_id: sessionId });
 
@@ -302,6 +412,17 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     }
   }, [sendMessage, setActiveSession]);
 
+  const setLockedAction = useCallback((sessionId: string, locked: boolean) => {
+    // The tab shows the new state once the Mac echoes it back
+    sendMessage({ type: 'lock_session', session_id: sessionId, locked });
+  }, [sendMessage]);
+
+  const setEncodingAction = useCallback((sessionId: string, encoding?: string) => {
+    // The tab shows the new encoding once the Mac echoes it back. An
+    // undefined encoding is left out of the JSON, meaning UTF-8.
+    sendMessage({ type: 'set_encoding', session_id: sessionId, encoding });
+  }, [sendMessage]);
+
   // ---------------------------------------------------------------------------
   // Context Value
   // ---------------------------------------------------------------------------
@@ -311,8 +432,11 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     activeSessionId,
     activeSession: sessions.find((s) => s.id === activeSessionId),
     switchSession,
+    profiles,
     createTab: createTabAction,
     closeTab: closeTabAction,
+    setLocked: setLockedAction,
+    setEncoding: setEncodingAction,
     // Legacy aliases
     tabs: sessions,
     activeTabId: activeSessionId,
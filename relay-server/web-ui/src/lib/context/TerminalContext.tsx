@@ -10,6 +10,7 @@
  * - Data buffering before terminal mounts
  * - Per-session terminal instances (one xterm per session)
  * - Session resize forwarding (mac -> UI, one-way)
+ * - State restore on rejoin (get_state -> state snapshot, no replay)
  */
 
 import {
@@ -22,7 +23,8 @@ import {
   type ReactNode,
 } from 'react';
 import type { Terminal, ITerminalOptions } from '@xterm/xterm';
-import type { ConfigMessage } from '../../shared/protocol';
+import type { ConfigMessage, SessionListMessage, StateMessage } from '../../shared/protocol';
+import { decodeBase64 } from '../protocol/binary';
 import { defaultTerminalOptions, configToXtermOptions } from '../iterm-theme';
 import { useConnection } from './ConnectionContext';
 
@@ -49,6 +51,8 @@ interface TerminalContextValue {
   /** Write binary data to terminal (used internally by binary handler) */
   writeBinaryData: (sessionId: string, data: Uint8Array) => void;
   getTerminal: (sessionId: string) => Terminal | undefined;
+  /** Empty a session's terminal, dropping buffered data (before its output is replayed) */
+  clearTerminal: (sessionId: string) => void;
   /** Subscribe to resize events for a specific session (mac -> browser). Returns unsubscribe fn. */
   onSessionResize: (sessionId: string, callback: SessionResizeCallback) => () => void;
 }
@@ -75,8 +79,10 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
   const readyRef = useRef<Set<string>>(new Set());
   // Session resize listeners (mac -> browser)
   const resizeListenersRef = useRef<Map<string, Set<SessionResizeCallback>>>(new Map());
+  // Set after a rejoin until the session list arrives and snapshots are requested
+  const restoringRef = useRef(false);
 
-  const { registerMessageHandler, registerBinaryHandler } = useConnection();
+  const { registerMessageHandler, registerBinaryHandler, sendMessage } = useConnection();
 
   const setActiveSession = useCallback((sessionId: string | null) => {
     setActiveSessionId(sessionId);
@@ -157,6 +163,11 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
     return terminalsRef.current.get(sessionId);
   }, []);
 
+  const clearTerminal = useCallback((sessionId: string) => {
+    terminalsRef.current.get(sessionId)?.reset();
+    pendingDataRef.current.delete(sessionId);
+  }, []);
+
   /**
    * Subscribe to resize events for a specific session.
    * Returns an unsubscribe function.
@@ -182,8 +193,26 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
     return unregister;
   }, [registerBinaryHandler, writeBinaryData]);
 
+  /**
+   * Replace a session's terminal contents with a snapshot from the Mac.
+   * Output buffered before the snapshot is already part of it, so it's dropped.
+   */
+  const restoreState = useCallback((sessionId: string, state: StateMessage['state']) => {
+    log('restoreState:', sessionId, state.cols, state.rows);
+    const listeners = resizeListenersRef.current.get(sessionId);
+    if (listeners) {
+      for (const cb of listeners) {
+        cb(state.cols, state.rows);
+      }
+    }
+    terminalsRef.current.get(sessionId)?.reset();
+    pendingDataRef.current.delete(sessionId);
+    writeBinaryData(sessionId, decodeBase64(state.scrollback));
+    writeBinaryData(sessionId, decodeBase64(state.screen));
+  }, [writeBinaryData]);
+
   // ---------------------------------------------------------------------------
-  // Message Handler - config + session_resize messages
+  // Message Handler - config, session_resize and state messages
   // ---------------------------------------------------------------------------
 
   useEffect(() => {
@@ -207,16 +236,35 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
           }
           break;
         }
+        case 'auth_success': {
+          restoringRef.current = data.rejoin === true;
+          break;
+        }
+        case 'session_list': {
+          if (!restoringRef.current) break;
+          restoringRef.current = false;
+          const msg = data as unknown as SessionListMessage;
+          for (const session of msg.sessions) {
+            sendMessage({ type: 'get_state', session_id: session.id });
+          }
+          break;
+        }
+        case 'state': {
+          const msg = data as unknown as StateMessage;
+          restoreState(msg.session_id, msg.state);
+          break;
+        }
         case '__disconnect': {
           setActiveSession(null);
           pendingDataRef.current.clear();
           readyRef.current.clear();
+          restoringRef.current = false;
           break;
         }
       }
     });
     return unregister;
-  }, [registerMessageHandler, setActiveSession, applyConfig]);
+  }, [registerMessageHandler, sendMessage, setActiveSession, applyConfig, restoreState]);
 
   const value: TerminalContextValue = {
     activeSessionId,
@@ -228,6 +276,7 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
     markTerminalReady,
     writeBinaryData,
     getTerminal,
+    clearTerminal,
     onSessionResize,
   };
 
@@ -14,6 +14,27 @@
 const textEncoder = new TextEncoder();
 const textDecoder = new TextDecoder();
 
+/**
+ * After the relay sends a `compression` message, each binary frame starts
+ * with a flag byte: 0 for the frame as is, GZIP_FRAME for a gzip stream of
+ * it (see relay-server/src/compression.rs).
+ */
+export const GZIP_FRAME = 1;
+
+/** Whether this browser can inflate compressed frames. */
+export const compressionSupported = typeof DecompressionStream !== 'undefined';
+
+/**
+ * Inflate the gzip stream of a compressed frame.
+ *
+ * @param data - The frame after its flag byte
+ * @returns The frame it was compressed from
+ */
+export async function inflateFrame(data: ArrayBuffer): Promise<Uint8Array> {
+  const stream = new Blob([data]).stream().pipeThrough(new DecompressionStream('gzip'));
+  return new Uint8Array(await new Response(stream).arrayBuffer());
+}
+
 /**
  * Encode a binary frame with session ID prefix.
  *
@@ -86,6 +107,21 @@ export function encodeInputMessage(sessionId: string, input: string): Uint8Array
   return encodeBinaryFrame(sessionId, payload);
 }
 
+/**
+ * Decode base64 (as used for bytes in JSON control messages) to raw bytes.
+ *
+ * @param data - Standard base64 string
+ * @returns Decoded bytes
+ */
+export function decodeBase64(data: string): Uint8Array {
+  const binary = atob(data);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}
+
 // =============================================================================
 // Self-test (runs when imported in development)
 // =============================================================================
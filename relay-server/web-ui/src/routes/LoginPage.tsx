@@ -1,10 +1,24 @@
 import { useState, useEffect } from 'react';
 import { useNavigate } from 'react-router-dom';
-import { useConnection } from '../lib/context/ConnectionContext';
+import { getStoredReplayLines, useConnection } from '../lib/context/ConnectionContext';
+import { SESSION_CODE_MAX_LENGTH, SESSION_CODE_MIN_LENGTH } from '../shared/protocol';
 import './LoginPage.css';
 
+/** Scrollback the relay replays per session on joining ('' = all) */
+const REPLAY_OPTIONS = [
+  { value: '', label: 'Load all scrollback' },
+  { value: '1000', label: 'Load last 1000 lines' },
+  { value: '100', label: 'Load last 100 lines' },
+  { value: '0', label: 'Skip scrollback' },
+];
+
 export default function LoginPage() {
-  const [sessionCode, setSessionCode] = useState('');
+  // The Mac's QR code links here with the code filled in
+  const [sessionCode, setSessionCode] = useState(
+    () => new URLSearchParams(window.location.search).get('code')?.toUpperCase() ?? ''
+  );
+  const [password, setPassword] = useState('');
+  const [replayLines, setReplayLines] = useState(() => String(getStoredReplayLines() ?? ''));
   const [isSubmitting, setIsSubmitting] = useState(false);
   const navigate = useNavigate();
   const { state, error, isConnected, connect } = useConnection();
@@ -27,12 +41,12 @@ export default function LoginPage() {
     e.preventDefault();
 
     const code = sessionCode.toUpperCase().replace(/\s/g, '');
-    if (code.length !== 6) return;
+    if (code.length < SESSION_CODE_MIN_LENGTH) return;
 
     setIsSubmitting(true);
     connect(code, () => {
       navigate('/');
-    });
+    }, password, replayLines === '' ? null : Number(replayLines));
   }
 
   // Show reconnecting spinner while auto-reconnect is in progress
@@ -63,7 +77,7 @@ export default function LoginPage() {
               value={sessionCode}
               onChange={(e) => setSessionCode(e.target.value)}
               placeholder="ABC123"
-              maxLength={6}
+              maxLength={SESSION_CODE_MAX_LENGTH}
               autoComplete="off"
               autoCapitalize="characters"
               spellCheck={false}
@@ -72,6 +86,35 @@ export default function LoginPage() {
             />
           </div>
 
+          <div className="input-wrapper">
+            <label htmlFor="password" className="sr-only">Session Password</label>
+            <input
+              id="password"
+              type="password"
+              value={password}
+              onChange={(e) => setPassword(e.target.value)}
+              placeholder="Password (if set on the Mac)"
+              autoComplete="current-password"
+              className="password-input"
+              disabled={isSubmitting}
+            />
+          </div>
+
+          <div className="input-wrapper">
+            <label htmlFor="replay" className="sr-only">Scrollback</label>
+            <select
+              id="replay"
+              value={replayLines}
+              onChange={(e) => setReplayLines(e.target.value)}
+              className="replay-select"
+              disabled={isSubmitting}
+            >
+              {REPLAY_OPTIONS.map((option) => (
+                <option key={option.value} value={option.value}>{option.label}</option>
+              ))}
+            </select>
+          </div>
+
           {error && (
             <div className="error-box">
               {error}
@@ -81,7 +124,7 @@ export default function LoginPage() {
           <button
             type="submit"
             className="btn-primary"
-            disabled={sessionCode.length !== 6 || isSubmitting}
+            disabled={sessionCode.trim().length < SESSION_CODE_MIN_LENGTH || isSubmitting}
           >
             {isSubmitting ? 'Connecting...' : 'Connect'}
           </button>
@@ -7,13 +7,16 @@ import Terminal from '../lib/components/Terminal';
 import TerminalTabs from '../lib/components/TerminalTabs';
 import MobileControlBar from '../lib/components/MobileControlBar';
 import ConnectionStatus from '../lib/components/ConnectionStatus';
+import PasteConfirm from '../lib/components/PasteConfirm';
+import NewSessionButton from '../lib/components/NewSessionButton';
+import Notices from '../lib/components/Notices';
 import './TerminalPage.css';
 
 export default function TerminalPage() {
   const navigate = useNavigate();
-  const { state, isConnected, disconnect, sendTerminalInput } = useConnection();
+  const { state, isConnected, label, disconnect, sendTerminalInput } = useConnection();
   const { activeSessionId, options } = useTerminal();
-  const { tabs, createTab } = useTabs();
+  const { tabs } = useTabs();
 
   // Redirect to login if disconnected
   useEffect(() => {
@@ -26,9 +29,17 @@ export default function TerminalPage() {
     disconnect();
   }
 
+  // Input only goes to live sessions; once the Mac reports a session gone,
+  // keystrokes in its lingering tab are dropped here
+  function sendInput(sessionId: string, data: string) {
+    if (tabs.some(tab => tab.id === sessionId && tab.connected)) {
+      sendTerminalInput(sessionId, data);
+    }
+  }
+
   function handleMobileKey(data: string) {
     if (activeSessionId) {
-      sendTerminalInput(activeSessionId, data);
+      sendInput(activeSessionId, data);
     }
   }
 
@@ -38,6 +49,7 @@ export default function TerminalPage() {
     <div className="terminal-page">
       <header className="header-bar">
         <ConnectionStatus />
+        {label && <span className="session-label" title={label}>{label}</span>}
         <div className="header-spacer" />
         <button className="btn-disconnect" onClick={handleDisconnect}>
           Disconnect
@@ -56,8 +68,8 @@ export default function TerminalPage() {
                 <Terminal
                   sessionId={tab.id}
                   options={options}
-                  onInput={(data) => sendTerminalInput(tab.id, data)}
-                  onBinaryInput={(data) => sendTerminalInput(tab.id, data)}
+                  onInput={(data) => sendInput(tab.id, data)}
+                  onBinaryInput={(data) => sendInput(tab.id, data)}
                 />
               </div>
             ))}
@@ -71,12 +83,14 @@ export default function TerminalPage() {
             <p className="waiting-detail">
               Create a new session to get started.
             </p>
-            <button className="btn-create-session" onClick={createTab}>
+            <NewSessionButton className="btn-create-session">
               + New Session
-            </button>
+            </NewSessionButton>
           </div>
         </main>
       )}
+      <PasteConfirm />
+      <Notices />
     </div>
   );
 }
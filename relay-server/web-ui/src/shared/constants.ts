@@ -6,3 +6,7 @@
 export const TERMINAL_MIN_COLS = 20;
 export const TERMINAL_MIN_ROWS = 5;
 export const TERMINAL_DEFAULT_SCROLLBACK = 50000;
+
+// Input frames at least this large (pastes) are held while the Mac asks
+// browsers to pause (flow_control)
+export const FLOW_CONTROL_BULK_BYTES = 256;
@@ -5,6 +5,10 @@
 
 import { z } from 'zod';
 
+/** Session code length range the relay can be configured for (SESSION_CODE_LENGTH) */
+export const SESSION_CODE_MIN_LENGTH = 4;
+export const SESSION_CODE_MAX_LENGTH = 16;
+
 // =============================================================================
 // Auth Protocol Messages (Rust Relay v2)
 // =============================================================================
@@ -16,7 +20,13 @@ import { z } from 'zod';
  */
 export const AuthMessage = z.object({
   type: z.literal('auth'),
-  session_code: z.string().length(6),
+  session_code: z.string().min(SESSION_CODE_MIN_LENGTH).max(SESSION_CODE_MAX_LENGTH),
+  /** Required when the Mac set a session password (SESSION_PASSWORD) */
+  password: z.string().optional(),
+  /** Ask for compressed output; the relay answers with a compression message */
+  compression: z.boolean().optional(),
+  /** Lines of scrollback to replay on join (0 when restoring with get_state) */
+  replay_lines: z.number().int().nonnegative().optional(),
 });
 export type AuthMessage = z.infer<typeof AuthMessage>;
 
@@ -37,6 +47,35 @@ export const AuthFailedMessage = z.object({
 });
 export type AuthFailedMessage = z.infer<typeof AuthFailedMessage>;
 
+/**
+ * What the browser's terminal can display, sent after auth_success. The
+ * relay attaches it to this browser's create_session requests, so new
+ * shells start at its size, with COLORTERM and (if the Mac forwards them)
+ * its locale and timezone.
+ */
+export const BrowserCapabilitiesMessage = z.object({
+  type: z.literal('browser_capabilities'),
+  cols: z.number().int(),
+  rows: z.number().int(),
+  truecolor: z.boolean(),
+  unicode_version: z.number().int().optional(),
+  /** BCP 47 tag, e.g. "de-CH" */
+  locale: z.string().optional(),
+  /** IANA name, e.g. "Europe/Zurich" */
+  timezone: z.string().optional(),
+});
+export type BrowserCapabilitiesMessage = z.infer<typeof BrowserCapabilitiesMessage>;
+
+/**
+ * Name of the Mac (e.g. its computer name), after auth_success and whenever
+ * it changes. Empty when the Mac has none.
+ */
+export const LabelMessage = z.object({
+  type: z.literal('label'),
+  label: z.string(),
+});
+export type LabelMessage = z.infer<typeof LabelMessage>;
+
 // =============================================================================
 // Session Event Messages (Mac Client -> Browser via Relay)
 // =============================================================================
@@ -81,6 +120,231 @@ export const SessionDisconnectedMessage = z.object({
 });
 export type SessionDisconnectedMessage = z.infer<typeof SessionDisconnectedMessage>;
 
+/**
+ * A session preset the Mac offers (profiles.json). icon is a hint such as
+ * "python"; unknown hints get a generic icon.
+ */
+export const ProfileInfoSchema = z.object({
+  name: z.string(),
+  description: z.string().optional(),
+  icon: z.string().optional(),
+});
+export type ProfileInfoSchema = z.infer<typeof ProfileInfoSchema>;
+
+/**
+ * The Mac's profiles, in answer to this browser's list_profiles. Pass a
+ * name as profile in create_session to start a session with it.
+ */
+export const ProfileListMessage = z.object({
+  type: z.literal('profile_list'),
+  profiles: z.array(ProfileInfoSchema),
+});
+export type ProfileListMessage = z.infer<typeof ProfileListMessage>;
+
+/**
+ * Lock a session against closing, or unlock it (browser -> Mac). The Mac
+ * echoes it to every browser once applied, and sends the locked sessions
+ * to browsers that join. A locked session is only closed with force.
+ */
+export const LockSessionMessage = z.object({
+  type: z.literal('lock_session'),
+  session_id: z.string(),
+  locked: z.boolean(),
+});
+export type LockSessionMessage = z.infer<typeof LockSessionMessage>;
+
+/**
+ * Transcode a session's output to UTF-8 from `encoding`, and input back to
+ * it; omitted for UTF-8 passthrough (browser -> Mac). The Mac echoes the
+ * encoding it applied, by its canonical name (e.g. "windows-1252" for
+ * "latin1"), to every browser.
+ */
+export const SetEncodingMessage = z.object({
+  type: z.literal('set_encoding'),
+  session_id: z.string(),
+  encoding: z.string().optional(),
+});
+export type SetEncodingMessage = z.infer<typeof SetEncodingMessage>;
+
+/**
+ * Keystrokes typed on the Mac (base64), kept apart from the session's output
+ * so browsers can tell input from program output. Only sent when the Mac
+ * enables FORWARD_LOCAL_INPUT.
+ */
+export const LocalInputMessage = z.object({
+  type: z.literal('local_input'),
+  session_id: z.string(),
+  data: z.string(),
+});
+export type LocalInputMessage = z.infer<typeof LocalInputMessage>;
+
+// =============================================================================
+// Terminal State (reconnect without replaying raw output)
+// =============================================================================
+
+/**
+ * Ask the Mac for a snapshot of a session's terminal (browser -> Mac).
+ */
+export const GetStateMessage = z.object({
+  type: z.literal('get_state'),
+  session_id: z.string(),
+});
+export type GetStateMessage = z.infer<typeof GetStateMessage>;
+
+/**
+ * A session's terminal as the Mac sees it. Writing `scrollback` then `screen`
+ * to a reset terminal of the same size restores the view, cursor included.
+ */
+export const TerminalStateSchema = z.object({
+  cols: z.number(),
+  rows: z.number(),
+  cursor_row: z.number(),
+  cursor_col: z.number(),
+  title: z.string().optional(),
+  /** Base64 escape sequences writing the scrollback tail into history */
+  scrollback: z.string(),
+  /** Base64 escape sequences redrawing the screen, cursor and input modes */
+  screen: z.string(),
+});
+export type TerminalStateSchema = z.infer<typeof TerminalStateSchema>;
+
+/**
+ * Snapshot answering get_state (Mac -> browser)
+ */
+export const StateMessage = z.object({
+  type: z.literal('state'),
+  session_id: z.string(),
+  state: TerminalStateSchema,
+});
+export type StateMessage = z.infer<typeof StateMessage>;
+
+// =============================================================================
+// Output Timestamps (debugging latency, off by default)
+// =============================================================================
+
+/**
+ * Ask the relay to precede a session's output frames with an
+ * output_timestamp (disable_timestamps stops it).
+ */
+export const EnableTimestampsMessage = z.object({
+  type: z.enum(['enable_timestamps', 'disable_timestamps']),
+  session_id: z.string(),
+});
+export type EnableTimestampsMessage = z.infer<typeof EnableTimestampsMessage>;
+
+/**
+ * When the relay received the binary frame that follows (Unix milliseconds),
+ * and when the Mac sent it (by the Mac's clock), if it stamped the frame.
+ */
+export const OutputTimestampMessage = z.object({
+  type: z.literal('output_timestamp'),
+  session_id: z.string(),
+  timestamp_ms: z.number(),
+  mac_timestamp_ms: z.number().optional(),
+});
+export type OutputTimestampMessage = z.infer<typeof OutputTimestampMessage>;
+
+// =============================================================================
+// Subscriptions (Browser -> Relay)
+// =============================================================================
+
+/**
+ * Only stream output of the given sessions (subscribe), or stop streaming
+ * them (unsubscribe). Subscribing replays the session's scrollback. A
+ * browser that never subscribes gets every session's output.
+ */
+export const SubscribeMessage = z.object({
+  type: z.enum(['subscribe', 'unsubscribe']),
+  session_ids: z.array(z.string()),
+});
+export type SubscribeMessage = z.infer<typeof SubscribeMessage>;
+
+// =============================================================================
+// Scrollback Search (Browser <-> Relay; the bundled UI doesn't search yet)
+// =============================================================================
+
+/**
+ * Regex search over a session's scrollback. One search per browser runs at
+ * a time; another sent meanwhile is answered with an error.
+ */
+export const SearchMessage = z.object({
+  type: z.literal('search'),
+  session_id: z.string(),
+  pattern: z.string(),
+});
+export type SearchMessage = z.infer<typeof SearchMessage>;
+
+/** A matching line: numbered from the oldest line held, column in characters */
+export const SearchMatchSchema = z.object({
+  line: z.number(),
+  column: z.number(),
+  text: z.string(),
+});
+export type SearchMatchSchema = z.infer<typeof SearchMatchSchema>;
+
+/** Answer to search; truncated when it hit the match or time limit */
+export const SearchResultsMessage = z.object({
+  type: z.literal('search_results'),
+  session_id: z.string(),
+  matches: z.array(SearchMatchSchema),
+  truncated: z.boolean(),
+});
+export type SearchResultsMessage = z.infer<typeof SearchResultsMessage>;
+
+// =============================================================================
+// Flow Control (Mac Client -> Browser via Relay)
+// =============================================================================
+
+/**
+ * The Mac's uplink to the relay is backed up (pause) or has caught up.
+ * Bulk input is held back while paused.
+ */
+export const FlowControlMessage = z.object({
+  type: z.literal('flow_control'),
+  pause: z.boolean(),
+});
+export type FlowControlMessage = z.infer<typeof FlowControlMessage>;
+
+// =============================================================================
+// Paste Confirmation (CONFIRM_PASTE on the Mac, off by default)
+// =============================================================================
+
+/**
+ * Multiline input the Mac is holding until a browser confirms it.
+ */
+export const ConfirmPasteMessage = z.object({
+  type: z.literal('confirm_paste'),
+  session_id: z.string(),
+  token: z.string(),
+  preview: z.string(),
+});
+export type ConfirmPasteMessage = z.infer<typeof ConfirmPasteMessage>;
+
+/**
+ * Run (accept) or drop the paste held under token.
+ */
+export const ConfirmPasteAckMessage = z.object({
+  type: z.literal('confirm_paste_ack'),
+  token: z.string(),
+  accept: z.boolean(),
+});
+export type ConfirmPasteAckMessage = z.infer<typeof ConfirmPasteAckMessage>;
+
+// =============================================================================
+// Notices (Mac Client or Relay -> Browser)
+// =============================================================================
+
+/**
+ * Something the user should know that doesn't end the connection, e.g.
+ * "Session ended" after input to a closed session, or dropped input.
+ */
+export const NoticeMessage = z.object({
+  type: z.literal('notice'),
+  session_id: z.string().optional(),
+  message: z.string(),
+});
+export type NoticeMessage = z.infer<typeof NoticeMessage>;
+
 // =============================================================================
 // Error Messages (Relay -> Any Client)
 // =============================================================================
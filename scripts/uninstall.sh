@@ -116,10 +116,21 @@ fi
 
 # ── Remove Unix socket ────────────────────────────────────────
 echo -e "${BLUE}> Removing Unix socket...${NC}"
-if [ -e /tmp/terminal-remote.sock ]; then
-    rm -f /tmp/terminal-remote.sock
-    echo -e "${GREEN}  Removed /tmp/terminal-remote.sock${NC}"
-else
+# ~/.terminal-remote/ipc.sock went with the install directory; also clear
+# the XDG_RUNTIME_DIR socket and the one older versions kept in /tmp
+SOCKETS=(/tmp/terminal-remote.sock)
+if [[ "${XDG_RUNTIME_DIR:-}" == /* ]]; then
+    SOCKETS+=("$XDG_RUNTIME_DIR/terminal-remote.sock")
+fi
+REMOVED=0
+for SOCKET in "${SOCKETS[@]}"; do
+    if [ -e "$SOCKET" ]; then
+        rm -f "$SOCKET"
+        echo -e "${GREEN}  Removed $SOCKET${NC}"
+        REMOVED=1
+    fi
+done
+if [ "$REMOVED" = 0 ]; then
     echo "  No Unix socket found (skipped)"
 fi
 
@@ -39,8 +39,11 @@ _terminal_remote_init() {
   local proxy
   proxy=$(_terminal_remote_find_proxy) || return 0  # silently skip if not found
 
-  # Check if mac-client is running (socket exists)
-  [[ -S /tmp/terminal-remote.sock ]] || return 0  # silently skip
+  # Check if mac-client is running (socket exists). Same path as mac-client:
+  # in XDG_RUNTIME_DIR if set, else in ~/.terminal-remote
+  local sock="$HOME/.terminal-remote/ipc.sock"
+  [[ "$XDG_RUNTIME_DIR" == /* ]] && sock="$XDG_RUNTIME_DIR/terminal-remote.sock"
+  [[ -S "$sock" ]] || return 0  # silently skip
 
   exec "$proxy"
 }
@@ -42,8 +42,13 @@ function _terminal_remote_init
     set -l proxy (_terminal_remote_find_proxy)
     or return 0  # silently skip if not found
 
-    # Check if mac-client is running (socket exists)
-    if not test -S /tmp/terminal-remote.sock
+    # Check if mac-client is running (socket exists). Same path as mac-client:
+    # in XDG_RUNTIME_DIR if set, else in ~/.terminal-remote
+    set -l sock "$HOME/.terminal-remote/ipc.sock"
+    if string match -q '/*' -- "$XDG_RUNTIME_DIR"
+        set sock "$XDG_RUNTIME_DIR/terminal-remote.sock"
+    end
+    if not test -S "$sock"
         return 0  # silently skip
     end
 